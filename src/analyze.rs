@@ -27,6 +27,7 @@ pub struct AnnotatedMark {
 
 impl AnnotatedMark {
   pub fn new(id: ProjectId, name: String, mark: String) -> AnnotatedMark { AnnotatedMark { id, name, mark } }
+  pub fn id(&self) -> &ProjectId { &self.id }
   pub fn name(&self) -> &str { &self.name }
   pub fn mark(&self) -> &str { &self.mark }
 }
@@ -0,0 +1,66 @@
+//! The stable library interface to Versio, for tools that want to embed it instead of running the executable.
+//!
+//! Everything that other crates should rely on is re-exported here; the signatures of these items only change with
+//! a major version of Versio. New options are added to builders such as `MonoBuilder` and `ConfigBuilder`, instead
+//! of to existing functions. Other public items in the crate are used by the `versio` executable, and may change at
+//! any time.
+//!
+//! # Examples
+//!
+//! Plan the next release of the repository in the current directory:
+//!
+//! ```no_run
+//! # #[cfg(feature = "cli")]
+//! # mod example {
+//! use versio::api::{Mono, Result, VcsLevel, VcsState};
+//!
+//! async fn print_plan() -> Result<()> {
+//!   let mono = Mono::here(VcsState::new(VcsLevel::Local, false))?;
+//!   let plan = mono.build_plan().await?;
+//!   for (id, (size, _changelog)) in plan.incrs() {
+//!     println!("{}: {}", id, size);
+//!   }
+//!   Ok(())
+//! }
+//! # }
+//! ```
+//!
+//! Find a version with one of the built-in scanners:
+//!
+//! ```
+//! use versio::api::{JsonScanner, Part, Scanner};
+//!
+//! let scanner = JsonScanner::build(vec![Part::Map("version".into())]);
+//! let mark = scanner.find(r#"{ "version": "1.2.3" }"#).unwrap();
+//! assert_eq!("1.2.3", mark.value());
+//! ```
+//!
+//! Size some commit messages, without a repository:
+//!
+//! ```
+//! use versio::api::{angular_sizes, MessagePlan};
+//!
+//! let plan = MessagePlan::new(&angular_sizes(), vec!["fix: a bug", "feat: a thing"]).unwrap();
+//! assert_eq!("1.3.0", plan.next_version("1.2.3").unwrap());
+//! ```
+//!
+//! Only the scanners and sizing are built without the default `cli` feature.
+
+pub use crate::errors::{Error, Result};
+pub use crate::mark::{Mark, MarkedData, NamedData};
+pub use crate::scan::parts::Part;
+pub use crate::scan::{JsonScanner, Scanner, ScannerFactory, Scanners, TomlScanner, XmlScanner, YamlScanner};
+pub use crate::sizes::{angular_sizes, extract_kind, MessagePlan, Size, SizedMessage};
+
+#[cfg(feature = "cli")]
+pub use crate::cancel::{Cancel, Phase};
+#[cfg(feature = "cli")]
+pub use crate::config::{Config, ConfigBuilder, ConfigFile, Project, ProjectBuilder, ProjectId};
+#[cfg(feature = "cli")]
+pub use crate::mono::{Changelog, Mono, MonoBuilder, Plan};
+#[cfg(feature = "cli")]
+pub use crate::observe::{NoObserver, Observer};
+#[cfg(feature = "cli")]
+pub use crate::state::{CurrentState, FilesRead, MemoryState, StateRead, StateWrite};
+#[cfg(feature = "cli")]
+pub use crate::vcs::{VcsLevel, VcsRange, VcsState};
@@ -0,0 +1,141 @@
+//! Cooperative cancellation of a run, and timeouts for its slower phases.
+//!
+//! Cancellation is checked between steps: a fetch is aborted at its next progress report, plan building stops
+//! before the next PR, and an API call is abandoned when its timeout passes. Nothing is cancelled once a release
+//! starts to commit, so a cancelled run never leaves a half-tagged repository.
+
+use crate::errors::{Error, Result};
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A phase of a run that can take an unbounded amount of time.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Phase {
+  Fetch,
+  Api,
+  Plan
+}
+
+impl Phase {
+  pub fn all() -> &'static [Phase] { &[Phase::Fetch, Phase::Api, Phase::Plan] }
+}
+
+impl fmt::Display for Phase {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Phase::Fetch => write!(f, "fetch"),
+      Phase::Api => write!(f, "API call"),
+      Phase::Plan => write!(f, "plan building")
+    }
+  }
+}
+
+/// Cancels a run, and limits the time spent in its slower phases. A `Mono` is opened with one of these (see
+/// `MonoBuilder::cancel`); its clones share the same cancellation, so a clone can cancel the run from another thread.
+#[derive(Clone, Default)]
+pub struct Cancel {
+  cancelled: Arc<AtomicBool>,
+  timeouts: Vec<(Phase, Duration)>
+}
+
+impl Cancel {
+  pub fn new() -> Cancel { Default::default() }
+
+  /// Limit the time spent in each run of a phase, or remove the limit if `timeout` is `None`.
+  pub fn with_timeout(mut self, phase: Phase, timeout: Option<Duration>) -> Cancel {
+    self.timeouts.retain(|(p, _)| *p != phase);
+    if let Some(timeout) = timeout {
+      self.timeouts.push((phase, timeout));
+    }
+    self
+  }
+
+  /// Ask the run to stop at its next opportunity.
+  pub fn cancel(&self) { self.cancelled.store(true, Ordering::SeqCst) }
+
+  pub fn is_cancelled(&self) -> bool { self.cancelled.load(Ordering::SeqCst) }
+
+  pub fn timeout(&self, phase: Phase) -> Option<Duration> {
+    self.timeouts.iter().find(|(p, _)| *p == phase).map(|(_, t)| *t)
+  }
+
+  /// Start a single run of a phase.
+  pub fn start(&self, phase: Phase) -> Deadline {
+    Deadline { phase, cancelled: self.cancelled.clone(), end: self.timeout(phase).map(|t| Instant::now() + t) }
+  }
+
+  /// Run a future as a phase, failing if the run is cancelled or the phase times out.
+  pub async fn guard<T, F: Future<Output = Result<T>>>(&self, phase: Phase, fut: F) -> Result<T> {
+    let deadline = self.start(phase);
+    deadline.check()?;
+    let result = match deadline.remaining() {
+      Some(remaining) => match tokio::time::timeout(remaining, fut).await {
+        Ok(result) => result?,
+        Err(_) => bail!(Error::cancelled(format!("{} timed out", phase)))
+      },
+      None => fut.await?
+    };
+    if self.is_cancelled() {
+      bail!(Error::cancelled(format!("{} was cancelled", phase)));
+    }
+    Ok(result)
+  }
+}
+
+/// A single run of a phase, which is done when the run is cancelled or the phase's timeout passes.
+pub struct Deadline {
+  phase: Phase,
+  cancelled: Arc<AtomicBool>,
+  end: Option<Instant>
+}
+
+impl Deadline {
+  pub fn is_cancelled(&self) -> bool { self.cancelled.load(Ordering::SeqCst) }
+  pub fn is_expired(&self) -> bool { self.end.map(|end| Instant::now() >= end).unwrap_or(false) }
+  pub fn is_done(&self) -> bool { self.is_cancelled() || self.is_expired() }
+  pub fn remaining(&self) -> Option<Duration> { self.end.map(|end| end.saturating_duration_since(Instant::now())) }
+
+  pub fn check(&self) -> Result<()> {
+    if self.is_cancelled() {
+      bail!(Error::cancelled(format!("{} was cancelled", self.phase)));
+    }
+    if self.is_expired() {
+      bail!(Error::cancelled(format!("{} timed out", self.phase)));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Cancel, Phase};
+  use std::time::Duration;
+  use tokio::runtime::Runtime;
+
+  #[test]
+  fn test_cancel_is_shared_by_clones_only() {
+    let cancel = Cancel::new();
+    let other = Cancel::new();
+    let deadline = cancel.start(Phase::Plan);
+    cancel.clone().cancel();
+    assert!(deadline.is_done());
+    assert!(cancel.start(Phase::Fetch).check().is_err());
+    assert!(!other.is_cancelled());
+    assert!(other.start(Phase::Fetch).check().is_ok());
+  }
+
+  #[test]
+  fn test_guard_times_out() {
+    let cancel = Cancel::new().with_timeout(Phase::Api, Some(Duration::from_millis(10)));
+    let slow = async {
+      tokio::time::sleep(Duration::from_secs(5)).await;
+      Ok(())
+    };
+    let err = Runtime::new().unwrap().block_on(cancel.guard(Phase::Api, slow)).unwrap_err();
+    assert_eq!(6, err.exit_code());
+    assert!(Runtime::new().unwrap().block_on(cancel.guard(Phase::Plan, async { Ok(1) })).is_ok());
+  }
+}
@@ -1,16 +1,33 @@
 //! The command-line options for the executable.
 
-use clap::{crate_version, App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
+use clap::{crate_version, App, AppSettings, Arg, ArgGroup, ArgMatches, ErrorKind as ClapErrorKind, SubCommand};
+use std::iter::once;
+use std::path::Path;
 use versio::commands::*;
 use versio::err;
 use versio::errors::Result;
 use versio::init::init;
 use versio::vcs::{VcsLevel, VcsRange};
 
+/// Subcommands that get their own man page.
+const MAN_PAGES: &[&str] = &[
+  "check", "show", "get", "set", "diff", "files", "affected", "owners", "changes", "plan", "status", "next", "exec",
+  "why", "release", "run", "resume", "abort", "revert", "init", "info", "template", "lint", "hook", "history", "prev"
+];
+
 pub async fn execute(info: &EarlyInfo) -> Result<()> {
   let id_required = info.project_count() != 1;
+  let m = app(id_required).get_matches();
+  let timings = m.is_present("timings");
+  let result = parse_matches(m, info).await;
+  if timings {
+    write_timings();
+  }
+  result
+}
 
-  let m = App::new("versio")
+fn app(id_required: bool) -> App<'static, 'static> {
+  App::new("versio")
     .setting(AppSettings::UnifiedHelpMessage)
     .author("Charlie Ozinga, ozchaz@gmail.com")
     .version(concat!(crate_version!(), " (", env!("GIT_SHORT_HASH"), " ", env!("DATE_DASH"), ")"))
@@ -56,10 +73,74 @@ pub async fn execute(info: &EarlyInfo) -> Result<()> {
         .display_order(1)
         .help("Accept local repo changes")
     )
+    .arg(
+      Arg::with_name("maxhistory")
+        .long("max-history")
+        .takes_value(true)
+        .value_name("n")
+        .display_order(1)
+        .help("Scan at most n commits if the prev tag is missing")
+    )
+    .arg(
+      Arg::with_name("timeout")
+        .long("timeout")
+        .takes_value(true)
+        .value_name("secs")
+        .display_order(1)
+        .help("Fail if a fetch, API call, or plan takes longer than secs")
+    )
+    .arg(
+      Arg::with_name("date")
+        .long("date")
+        .takes_value(true)
+        .value_name("date")
+        .display_order(1)
+        .help("Use this time (epoch secs or RFC 3339) for dates, tags, and commits")
+    )
+    .arg(
+      Arg::with_name("quiet")
+        .short("q")
+        .long("quiet")
+        .takes_value(false)
+        .display_order(1)
+        .help("Print only the command's result")
+    )
+    .arg(
+      Arg::with_name("offline")
+        .long("offline")
+        .takes_value(false)
+        .display_order(1)
+        .help("Never fetch, push, or call any API")
+    )
+    .arg(
+      Arg::with_name("timings")
+        .long("timings")
+        .takes_value(false)
+        .display_order(1)
+        .help("Report time spent in each phase")
+    )
     .subcommand(
       SubCommand::with_name("check")
         .setting(AppSettings::UnifiedHelpMessage)
         .about("Check current config")
+        .arg(
+          Arg::with_name("denywarnings")
+            .short("s")
+            .long("deny-warnings")
+            .alias("strict")
+            .takes_value(false)
+            .display_order(1)
+            .help("Fail if there are any warnings")
+        )
+        .arg(
+          Arg::with_name("format")
+            .long("format")
+            .takes_value(true)
+            .value_name("format")
+            .possible_values(&["tap", "junit"])
+            .display_order(1)
+            .help("Write the results as test cases for CI")
+        )
         .display_order(1)
     )
     .subcommand(
@@ -82,6 +163,14 @@ pub async fn execute(info: &EarlyInfo) -> Result<()> {
             .display_order(1)
             .help("Wide output shows IDs")
         )
+        .arg(
+          Arg::with_name("report")
+            .long("report")
+            .takes_value(true)
+            .value_name("url")
+            .display_order(1)
+            .help("Print the results through a report template.")
+        )
         .display_order(1)
     )
     .subcommand(
@@ -119,8 +208,10 @@ pub async fn execute(info: &EarlyInfo) -> Result<()> {
             .long("name")
             .takes_value(true)
             .value_name("name")
+            .multiple(true)
+            .number_of_values(1)
             .display_order(1)
-            .help("The name to get")
+            .help("The name or name glob to get; can be given more than once")
         )
         .arg(
           Arg::with_name("id")
@@ -167,6 +258,14 @@ pub async fn execute(info: &EarlyInfo) -> Result<()> {
             .required(true)
             .help("The value to set to")
         )
+        .arg(
+          Arg::with_name("force")
+            .short("f")
+            .long("force")
+            .takes_value(false)
+            .display_order(2)
+            .help("Set the value even if it moves backwards or to another major")
+        )
         .display_order(1)
     )
     .subcommand(
@@ -179,6 +278,41 @@ pub async fn execute(info: &EarlyInfo) -> Result<()> {
       SubCommand::with_name("files")
         .setting(AppSettings::UnifiedHelpMessage)
         .about("Stream changed files")
+        .arg(
+          Arg::with_name("byproject")
+            .long("by-project")
+            .takes_value(false)
+            .display_order(1)
+            .help("Show the project of each file, instead of its kind")
+        )
+        .display_order(1)
+    )
+    .subcommand(
+      SubCommand::with_name("affected")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .about("List the projects touched since a commit, as JSON")
+        .arg(
+          Arg::with_name("since")
+            .long("since")
+            .takes_value(true)
+            .value_name("ref")
+            .required(true)
+            .display_order(1)
+            .help("The commit, branch, or tag to compare HEAD with")
+        )
+        .display_order(1)
+    )
+    .subcommand(
+      SubCommand::with_name("owners")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .about("Write a CODEOWNERS file from the projects' coverage")
+        .arg(
+          Arg::with_name("json")
+            .long("json")
+            .takes_value(false)
+            .display_order(1)
+            .help("Write each project's coverage and owners as JSON instead")
+        )
         .display_order(1)
     )
     .subcommand(
@@ -208,6 +342,42 @@ pub async fn execute(info: &EarlyInfo) -> Result<()> {
             .display_order(1)
             .help("Plan only a single project.")
         )
+        .arg(
+          Arg::with_name("save")
+            .short("s")
+            .long("save")
+            .takes_value(true)
+            .value_name("file")
+            .display_order(1)
+            .help("Save the plan to a file.")
+        )
+        .arg(
+          Arg::with_name("compare")
+            .long("compare")
+            .takes_value(true)
+            .number_of_values(2)
+            .value_names(&["refA", "refB"])
+            .conflicts_with_all(&["template", "save"])
+            .display_order(1)
+            .help("Show how the plan differs between two refs.")
+        )
+        .arg(
+          Arg::with_name("report")
+            .long("report")
+            .takes_value(true)
+            .value_name("url")
+            .conflicts_with_all(&["template", "compare"])
+            .display_order(1)
+            .help("Print the results through a report template.")
+        )
+        .arg(
+          Arg::with_name("denyineffective")
+            .long("deny-ineffective")
+            .takes_value(false)
+            .conflicts_with("compare")
+            .display_order(1)
+            .help("Fail if any PR's changes aren't covered by a project.")
+        )
         .display_order(1)
     )
     .subcommand(
@@ -268,6 +438,246 @@ pub async fn execute(info: &EarlyInfo) -> Result<()> {
             .display_order(1)
             .help("Don't forward project tags")
         )
+        .arg(
+          Arg::with_name("fromplan")
+            .long("from-plan")
+            .takes_value(true)
+            .value_name("file")
+            .conflicts_with_all(&["resume", "abort"])
+            .display_order(1)
+            .help("Release a previously saved plan")
+        )
+        .arg(
+          Arg::with_name("publishdry")
+            .long("publish-dry-run")
+            .takes_value(false)
+            .conflicts_with_all(&["resume", "abort", "dry", "changelogonly"])
+            .display_order(1)
+            .help("Don't write anything, but check that projects would publish")
+        )
+        .arg(
+          Arg::with_name("imagetags")
+            .long("image-tags")
+            .takes_value(true)
+            .value_name("file")
+            .conflicts_with_all(&["resume", "abort"])
+            .display_order(1)
+            .help("Write the new tag of each image to a file")
+        )
+        .arg(
+          Arg::with_name("cioutput")
+            .long("ci-output")
+            .takes_value(true)
+            .number_of_values(2)
+            .value_names(&["format", "file"])
+            .conflicts_with_all(&["resume", "abort"])
+            .display_order(1)
+            .help("Write versions for CI: gitlab-dotenv, jenkins-properties, or env-file")
+        )
+        .arg(
+          Arg::with_name("summary")
+            .long("summary")
+            .takes_value(true)
+            .value_name("file")
+            .conflicts_with_all(&["resume", "abort"])
+            .display_order(1)
+            .help("Write a JSON summary of the release to a file")
+        )
+        .arg(
+          Arg::with_name("allowdirty")
+            .long("allow-dirty")
+            .takes_value(false)
+            .conflicts_with_all(&["resume", "abort"])
+            .display_order(1)
+            .help("Release even if there are uncommitted changes")
+        )
+        .arg(
+          Arg::with_name("forcetags")
+            .long("force-tags")
+            .takes_value(false)
+            .conflicts_with_all(&["resume", "abort", "dry", "changelogonly"])
+            .display_order(1)
+            .help("Overwrite tags that were changed on the remote")
+        )
+        .arg(
+          Arg::with_name("report")
+            .long("report")
+            .takes_value(true)
+            .value_name("url")
+            .conflicts_with_all(&["resume", "abort"])
+            .display_order(1)
+            .help("Print the results through a report template.")
+        )
+        .arg(
+          Arg::with_name("skipci")
+            .long("skip-ci")
+            .takes_value(true)
+            .value_name("marker")
+            .possible_values(&["skip-ci", "ci-skip", "off"])
+            .conflicts_with_all(&["resume", "abort"])
+            .display_order(1)
+            .help("Mark the release commit to skip CI, or not")
+        )
+        .display_order(1)
+    )
+    .subcommand(
+      SubCommand::with_name("run")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .about("Release a previously saved plan")
+        .arg(
+          Arg::with_name("fromplan")
+            .long("from-plan")
+            .takes_value(true)
+            .value_name("file")
+            .required(true)
+            .display_order(1)
+            .help("The saved plan to release")
+        )
+        .display_order(1)
+    )
+    .subcommand(
+      SubCommand::with_name("status")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .about("Summarize versions and pending changes")
+        .display_order(1)
+    )
+    .subcommand(
+      SubCommand::with_name("next")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .about("Get the next version of one or more projects")
+        .arg(
+          Arg::with_name("versiononly")
+            .short("v")
+            .long("version-only")
+            .takes_value(false)
+            .display_order(1)
+            .help("Only show the version number")
+        )
+        .arg(
+          Arg::with_name("wide")
+            .short("w")
+            .long("wide")
+            .takes_value(false)
+            .display_order(1)
+            .help("Wide output shows IDs")
+        )
+        .arg(
+          Arg::with_name("name")
+            .short("n")
+            .long("name")
+            .takes_value(true)
+            .value_name("name")
+            .display_order(1)
+            .help("The name to get")
+        )
+        .arg(
+          Arg::with_name("id")
+            .short("i")
+            .long("id")
+            .takes_value(true)
+            .value_name("id")
+            .display_order(1)
+            .help("The id to get")
+        )
+        .group(ArgGroup::with_name("ident").args(&["id", "name"]))
+        .display_order(1)
+    )
+    .subcommand(
+      SubCommand::with_name("exec")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .about("Run a command for each project with a pending release")
+        .arg(
+          Arg::with_name("cmd")
+            .takes_value(true)
+            .multiple(true)
+            .last(true)
+            .required(true)
+            .value_name("cmd")
+            .help("The command to run")
+        )
+        .display_order(1)
+    )
+    .subcommand(
+      SubCommand::with_name("why")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .about("Explain the planned size of a project")
+        .arg(
+          Arg::with_name("name")
+            .short("n")
+            .long("name")
+            .takes_value(true)
+            .value_name("name")
+            .display_order(1)
+            .help("The name to explain")
+        )
+        .arg(
+          Arg::with_name("id")
+            .short("i")
+            .long("id")
+            .takes_value(true)
+            .value_name("id")
+            .display_order(1)
+            .help("The id to explain")
+        )
+        .arg(
+          Arg::with_name("project")
+            .short("p")
+            .long("project")
+            .takes_value(true)
+            .value_name("project")
+            .display_order(1)
+            .help("The name or id to explain")
+        )
+        .group(ArgGroup::with_name("ident").args(&["id", "name", "project"]).required(id_required))
+        .display_order(1)
+    )
+    .subcommand(
+      SubCommand::with_name("resume")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .about("Finish an interrupted or paused release")
+        .display_order(1)
+    )
+    .subcommand(
+      SubCommand::with_name("abort")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .about("Roll back an interrupted or paused release")
+        .display_order(1)
+    )
+    .subcommand(
+      SubCommand::with_name("revert")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .about("Roll back the last release")
+        .display_order(1)
+    )
+    .subcommand(
+      SubCommand::with_name("prev")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .about("Inspect or move the prev tag")
+        .subcommand(
+          SubCommand::with_name("show")
+            .setting(AppSettings::UnifiedHelpMessage)
+            .about("Show where the prev tag points, and the versions it records")
+        )
+        .subcommand(
+          SubCommand::with_name("set")
+            .setting(AppSettings::UnifiedHelpMessage)
+            .about("Move the prev tag to a commit, locally and on the remote")
+            .arg(
+              Arg::with_name("ref")
+                .takes_value(true)
+                .value_name("ref")
+                .display_order(1)
+                .required(true)
+                .help("The commit, branch, or tag to move to.")
+            )
+        )
+        .display_order(1)
+    )
+    .subcommand(
+      SubCommand::with_name("history")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .about("Show recorded release runs")
         .display_order(1)
     )
     .subcommand(
@@ -408,42 +818,193 @@ pub async fn execute(info: &EarlyInfo) -> Result<()> {
         )
         .display_order(1)
     )
-    .get_matches();
+    .subcommand(
+      SubCommand::with_name("lint")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .about("Check a commit message's type")
+        .arg(
+          Arg::with_name("file")
+            .takes_value(true)
+            .value_name("file")
+            .display_order(1)
+            .required(true)
+            .help("The file that holds the commit message.")
+        )
+        .arg(
+          Arg::with_name("format")
+            .long("format")
+            .takes_value(true)
+            .value_name("format")
+            .possible_values(&["tap", "junit"])
+            .display_order(1)
+            .help("Write the result as a test case for CI")
+        )
+        .display_order(1)
+    )
+    .subcommand(
+      SubCommand::with_name("hook")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .about("Manage git hooks")
+        .subcommand(
+          SubCommand::with_name("install")
+            .setting(AppSettings::UnifiedHelpMessage)
+            .about("Install a commit-msg hook that runs \"versio lint\"")
+            .arg(
+              Arg::with_name("prepush")
+                .long("pre-push")
+                .takes_value(false)
+                .display_order(1)
+                .help("Also install a pre-push hook that runs \"versio check\"")
+            )
+        )
+        .display_order(1)
+    )
+    .subcommand(
+      SubCommand::with_name("man").setting(AppSettings::Hidden).about("Generate man pages").arg(
+        Arg::with_name("dir")
+          .short("d")
+          .long("dir")
+          .takes_value(true)
+          .value_name("dir")
+          .default_value(".")
+          .help("The directory to write man pages to")
+      )
+    )
+}
+
+/// Write a roff man page for the top-level command and for each subcommand into the given directory.
+fn man(id_required: bool, dir: &Path) -> Result<()> {
+  write_man_page(&dir.join("versio.1"), "versio", &help_text(id_required, None)?)?;
+  for sub in MAN_PAGES {
+    let name = format!("versio-{}", sub);
+    write_man_page(&dir.join(format!("{}.1", name)), &name, &help_text(id_required, Some(sub))?)?;
+  }
+  Ok(())
+}
+
+fn help_text(id_required: bool, sub: Option<&str>) -> Result<String> {
+  let args = once("versio").chain(sub).chain(once("--help"));
+  match app(id_required).get_matches_from_safe(args) {
+    Err(e) if e.kind == ClapErrorKind::HelpDisplayed => Ok(e.message),
+    _ => err!("Unable to get help for {}.", sub.unwrap_or("versio"))
+  }
+}
 
-  parse_matches(m, info).await
+fn write_man_page(path: &Path, name: &str, help: &str) -> Result<()> {
+  let mut page = format!(".TH {} 1 \"\" \"versio {}\"\n", name.to_uppercase(), crate_version!());
+  page.push_str(&format!(".SH NAME\n{}\n.SH DESCRIPTION\n.nf\n", name));
+  for line in help.lines() {
+    let line = line.replace('\\', "\\e");
+    if line.starts_with('.') || line.starts_with('\'') {
+      page.push_str("\\&");
+    }
+    page.push_str(&line);
+    page.push('\n');
+  }
+  page.push_str(".fi\n");
+  std::fs::write(path, page)?;
+  Ok(())
 }
 
 async fn parse_matches(m: ArgMatches<'_>, early_info: &EarlyInfo) -> Result<()> {
   match m.subcommand() {
     ("release", Some(m)) if m.is_present("abort") => (),
     ("release", Some(m)) if m.is_present("resume") => (),
+    ("resume", _) | ("abort", _) => (),
+    ("man", _) => (),
     _ => sanity_check()?
   }
 
-  let pref_vcs = parse_vcs(&m)?;
+  if m.is_present("quiet") {
+    quiet();
+  } else {
+    show_progress();
+  }
+  if m.is_present("offline") {
+    offline();
+  }
+  let pref_vcs = PrefVcs::new(parse_vcs(&m)?).limit_history(m.value_of("maxhistory"))?;
+  let mut pref_vcs = pref_vcs.limit_time(m.value_of("timeout"))?;
+  if let Some(command) = m.subcommand_name() {
+    pref_vcs = pref_vcs.for_command(early_info, command)?;
+  }
   let ignore_current = m.is_present("ignorecurrent");
+  pin_date(m.value_of("date"))?;
 
   match m.subcommand() {
-    ("check", _) => check(pref_vcs, ignore_current)?,
+    ("check", Some(m)) => check(pref_vcs, m.is_present("denywarnings"), m.value_of("format"), ignore_current)?,
     ("get", Some(m)) => get(
       pref_vcs,
       m.is_present("wide"),
       m.is_present("versiononly"),
       m.is_present("prev"),
       m.value_of("id"),
-      m.value_of("name"),
+      &m.values_of("name").map(|v| v.collect::<Vec<_>>()).unwrap_or_default(),
       ignore_current
     )?,
-    ("show", Some(m)) => show(pref_vcs, m.is_present("wide"), m.is_present("prev"), ignore_current)?,
-    ("set", Some(m)) => set(pref_vcs, m.value_of("id"), m.value_of("name"), m.value_of("value").unwrap())?,
+    ("show", Some(m)) => {
+      let (wide, prev) = (m.is_present("wide"), m.is_present("prev"));
+      show(early_info, pref_vcs, wide, prev, m.value_of("report"), ignore_current).await?
+    }
+    ("set", Some(m)) => {
+      set(pref_vcs, m.value_of("id"), m.value_of("name"), m.value_of("value").unwrap(), m.is_present("force"))?
+    }
     ("diff", Some(_)) => diff(pref_vcs, ignore_current)?,
-    ("files", Some(_)) => files(pref_vcs, ignore_current).await?,
+    ("files", Some(m)) => files(pref_vcs, m.is_present("byproject"), ignore_current).await?,
+    ("affected", Some(m)) => affected(pref_vcs, m.value_of("since").unwrap(), ignore_current)?,
+    ("owners", Some(m)) => owners(pref_vcs, m.is_present("json"), ignore_current)?,
     ("changes", Some(_)) => changes(pref_vcs, ignore_current).await?,
-    ("plan", Some(m)) => plan(early_info, pref_vcs, m.value_of("id"), m.value_of("template"), ignore_current).await?,
-    ("release", Some(m)) if m.is_present("abort") => abort()?,
-    ("release", Some(m)) if m.is_present("resume") => resume(pref_vcs)?,
+    ("plan", Some(m)) if m.is_present("compare") => {
+      let mut refs = m.values_of("compare").unwrap();
+      plan_compare(pref_vcs, m.value_of("id"), refs.next().unwrap(), refs.next().unwrap(), ignore_current).await?
+    }
+    ("plan", Some(m)) => {
+      plan(
+        early_info,
+        pref_vcs,
+        m.value_of("id"),
+        m.value_of("template"),
+        m.value_of("save"),
+        m.value_of("report"),
+        m.is_present("denyineffective"),
+        ignore_current
+      )
+      .await?
+    }
+    ("status", Some(_)) => status(pref_vcs, ignore_current).await?,
+    ("next", Some(m)) => {
+      next(
+        pref_vcs,
+        m.is_present("wide"),
+        m.is_present("versiononly"),
+        m.value_of("id"),
+        m.value_of("name"),
+        ignore_current
+      )
+      .await?
+    }
+    ("exec", Some(m)) => {
+      let cmd = m.values_of("cmd").map(|v| v.collect::<Vec<_>>()).unwrap_or_default();
+      exec(pref_vcs, &cmd, ignore_current).await?
+    }
+    ("why", Some(m)) => {
+      why(pref_vcs, m.value_of("id"), m.value_of("name"), m.value_of("project"), ignore_current).await?
+    }
+    ("revert", Some(_)) => revert(pref_vcs)?,
+    ("history", Some(_)) => history(pref_vcs)?,
+    ("prev", Some(m)) => match m.subcommand() {
+      ("show", Some(_)) => prev_show(pref_vcs)?,
+      ("set", Some(m)) => prev_set(pref_vcs, m.value_of("ref").unwrap())?,
+      (c, _) => unknown_cmd(c)?
+    },
+    ("resume", Some(_)) => resume(pref_vcs).await?,
+    ("abort", Some(_)) => abort(pref_vcs)?,
+    ("release", Some(m)) if m.is_present("abort") => abort(pref_vcs)?,
+    ("release", Some(m)) if m.is_present("resume") => resume(pref_vcs).await?,
     ("release", Some(m)) => {
-      let dry = if m.is_present("dry") {
+      // A publish dry run is a dry run that also checks the publish of each released project.
+      let dry = if m.is_present("dry") || m.is_present("publishdry") {
         Engagement::Dry
       } else if m.is_present("changelogonly") {
         Engagement::Changelog
@@ -451,8 +1012,28 @@ async fn parse_matches(m: ArgMatches<'_>, early_info: &EarlyInfo) -> Result<()>
         Engagement::Full
       };
 
-      release(pref_vcs, m.is_present("all"), &dry, m.is_present("locktags"), m.is_present("pause")).await?
+      let opts = ReleaseOptions {
+        all: m.is_present("all"),
+        dry,
+        locktags: m.is_present("locktags"),
+        pause: m.is_present("pause"),
+        from_plan: m.value_of("fromplan"),
+        publish_dry: m.is_present("publishdry"),
+        image_file: m.value_of("imagetags"),
+        ci_output: m.values_of("cioutput").map(|mut v| (v.next().unwrap(), v.next().unwrap())),
+        summary: m.value_of("summary"),
+        allow_dirty: m.is_present("allowdirty"),
+        force_tags: m.is_present("forcetags"),
+        report: m.value_of("report"),
+        skip_ci: m.value_of("skipci")
+      };
+      release(early_info, pref_vcs, &opts).await?
+    }
+    ("run", Some(m)) => {
+      let opts = ReleaseOptions { from_plan: m.value_of("fromplan"), ..Default::default() };
+      release(early_info, pref_vcs, &opts).await?
     }
+    ("man", Some(m)) => man(early_info.project_count() != 1, &early_info.orig_dir().join(m.value_of("dir").unwrap()))?,
     ("init", Some(m)) => init(m.value_of("maxdepth").map(|d| d.parse().unwrap()).unwrap_or(5))?,
     ("info", Some(m)) => {
       let names = m.values_of("name").map(|v| v.collect::<Vec<_>>()).unwrap_or_default();
@@ -475,6 +1056,11 @@ async fn parse_matches(m: ArgMatches<'_>, early_info: &EarlyInfo) -> Result<()>
       info(pref_vcs, ids, names, labels, show, ignore_current)?
     }
     ("template", Some(m)) => template(early_info, m.value_of("template").unwrap()).await?,
+    ("lint", Some(m)) => lint(early_info, m.value_of("file").unwrap(), m.value_of("format"))?,
+    ("hook", Some(m)) => match m.subcommand() {
+      ("install", Some(m)) => hook_install(early_info, m.is_present("prepush"))?,
+      (c, _) => unknown_cmd(c)?
+    },
     ("", _) => empty_cmd()?,
     (c, _) => unknown_cmd(c)?
   }
@@ -0,0 +1,31 @@
+//! The time of a run, which can be pinned so that changelog dates, tags, and commits are reproducible.
+
+use crate::errors::{Result, ResultExt};
+use chrono::{DateTime, TimeZone, Utc};
+use std::sync::RwLock;
+
+/// The environment variable of the reproducible-builds convention, as seconds since the epoch.
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+static PINNED: RwLock<Option<DateTime<Utc>>> = RwLock::new(None);
+
+/// Use `at` as the time of this run, or the actual time if `at` is `None`.
+pub fn pin_time(at: Option<DateTime<Utc>>) -> Result<()> {
+  *PINNED.write().map_err(|_| bad!("Pinned time is poisoned."))? = at;
+  Ok(())
+}
+
+/// The time of this run: the pinned time if there is one, else the current time.
+pub fn now() -> DateTime<Utc> { PINNED.read().ok().and_then(|p| *p).unwrap_or_else(Utc::now) }
+
+pub fn is_pinned() -> bool { PINNED.read().map(|p| p.is_some()).unwrap_or(false) }
+
+/// Parse a pinned time, given either as seconds since the epoch or as an RFC 3339 date-time.
+pub fn parse_time(date: &str) -> Result<DateTime<Utc>> {
+  let date = date.trim();
+  if let Ok(secs) = date.parse::<i64>() {
+    return Utc.timestamp_opt(secs, 0).single().ok_or_else(|| bad!("Bad date \"{}\".", date));
+  }
+  let time = DateTime::parse_from_rfc3339(date).chain_err(|| format!("Bad date \"{}\".", date))?;
+  Ok(time.with_timezone(&Utc))
+}
@@ -1,18 +1,31 @@
 //! The command-line options for the executable.
 
-use crate::config::{Config, ConfigFile, ProjectId, Size};
-use crate::errors::{Result, ResultExt};
-use crate::git::Repo;
-use crate::mono::{Mono, Plan};
-use crate::output::{Output, ProjLine};
-use crate::state::{CommitState, StateRead};
-use crate::template::read_template;
-use crate::vcs::{VcsLevel, VcsRange, VcsState};
-use error_chain::bail;
-use std::collections::HashMap;
+use crate::cancel::{Cancel, Phase};
+use crate::clock::{parse_time, pin_time, SOURCE_DATE_EPOCH};
+use crate::config::{tag_sanitize, CommandVcs, CommitsMode, Config, ConfigFile, FirstRelease, OffBranch, ProjectId,
+                    SkipCi};
+use crate::deploy::deploy_all;
+use crate::errors::{Error, Result, ResultExt};
+use crate::git::{FromTag, FromTagBuf, Repo};
+use crate::mono::{read_prev_message, ChangelogEntry, Mono, Plan};
+use crate::notify::{notify_all, Released};
+use crate::observe::NoObserver;
+use crate::output::{set_quiet, Output, ProjLine, RevertOutput};
+use crate::progress::enable_progress;
+use crate::sizes::{extract_kind, Size};
+use crate::state::{CommitState, OldTags, PrevState, PrevTagMessage, StateRead};
+use crate::template::{read_template, render_report};
+use crate::timing::totals;
+use crate::vcs::{set_offline, VcsLevel, VcsRange, VcsState};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
 use std::fs::{remove_file, File};
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const MAX_HISTORY_VAR: &str = "VERSIO_MAX_HISTORY";
 
 pub fn early_info() -> Result<EarlyInfo> {
   let vcs = VcsRange::detect()?.max();
@@ -22,7 +35,53 @@ pub fn early_info() -> Result<EarlyInfo> {
   let orig_dir = std::env::current_dir()?;
   assert_ok!(orig_dir.is_absolute(), "Couldn't find current working directory.");
 
-  Ok(EarlyInfo::new(project_count, root, orig_dir))
+  Ok(EarlyInfo::new(project_count, root, orig_dir, file.vcs_levels().clone()))
+}
+
+/// The VCS levels, history limit, and timeout that the command line and the config ask of the running command.
+#[derive(Clone, Copy, Default)]
+pub struct PrefVcs {
+  user: Option<VcsRange>,
+  conf_pref: Option<VcsRange>,
+  conf_reqd: Option<VcsRange>,
+  max_history: Option<usize>,
+  timeout: Option<Duration>
+}
+
+impl PrefVcs {
+  /// Prefer the `user` VCS levels from the command line, if any.
+  pub fn new(user: Option<VcsRange>) -> PrefVcs { PrefVcs { user, ..Default::default() } }
+
+  /// Use the `vcs_levels` from the config for `command` in place of its built-in levels. A preferred level from the
+  /// command line still wins over the config.
+  pub fn for_command(self, early_info: &EarlyInfo, command: &str) -> Result<PrefVcs> {
+    let (conf_pref, conf_reqd) = match early_info.vcs_levels().get(command) {
+      Some(levels) => (levels.preferred()?, levels.required()?),
+      None => (None, None)
+    };
+    Ok(PrefVcs { conf_pref, conf_reqd, ..self })
+  }
+
+  /// Override the config's `max_history` with `max`, or else with `VERSIO_MAX_HISTORY` if it's set.
+  pub fn limit_history(self, max: Option<&str>) -> Result<PrefVcs> {
+    let env_max = std::env::var(MAX_HISTORY_VAR).ok().filter(|m| !m.trim().is_empty());
+    let max_history = match (max, env_max) {
+      (Some(max), _) => Some(max.parse().chain_err(|| format!("Bad max history \"{}\".", max))?),
+      (None, Some(max)) => Some(max.parse().chain_err(|| format!("Bad {} \"{}\".", MAX_HISTORY_VAR, max))?),
+      (None, None) => None
+    };
+    Ok(PrefVcs { max_history, ..self })
+  }
+
+  /// Limit each fetch, API call, and plan building to `secs` seconds.
+  pub fn limit_time(self, secs: Option<&str>) -> Result<PrefVcs> {
+    let secs = secs.map(|s| s.parse::<u64>().chain_err(|| format!("Bad timeout \"{}\".", s))).transpose()?;
+    Ok(PrefVcs { timeout: secs.map(Duration::from_secs), ..self })
+  }
+
+  fn cancel(&self) -> Cancel {
+    Phase::all().iter().fold(Cancel::new(), |cancel, phase| cancel.with_timeout(*phase, self.timeout))
+  }
 }
 
 pub enum Engagement {
@@ -35,45 +94,60 @@ pub enum Engagement {
 pub struct EarlyInfo {
   project_count: usize,
   working_dir: PathBuf,
-  orig_dir: PathBuf
+  orig_dir: PathBuf,
+  vcs_levels: HashMap<String, CommandVcs>
 }
 
 impl EarlyInfo {
-  pub fn new(project_count: usize, working_dir: PathBuf, orig_dir: PathBuf) -> EarlyInfo {
-    EarlyInfo { project_count, working_dir, orig_dir }
+  pub fn new(
+    project_count: usize, working_dir: PathBuf, orig_dir: PathBuf, vcs_levels: HashMap<String, CommandVcs>
+  ) -> EarlyInfo {
+    EarlyInfo { project_count, working_dir, orig_dir, vcs_levels }
   }
 
   pub fn project_count(&self) -> usize { self.project_count }
   pub fn working_dir(&self) -> &Path { &self.working_dir }
   pub fn orig_dir(&self) -> &Path { &self.orig_dir }
+  pub fn vcs_levels(&self) -> &HashMap<String, CommandVcs> { &self.vcs_levels }
 }
 
-pub fn check(pref_vcs: Option<VcsRange>, ignore_current: bool) -> Result<()> {
+pub fn check(pref_vcs: PrefVcs, deny_warnings: bool, format: Option<&str>, ignore_current: bool) -> Result<()> {
   let mono = with_opts(pref_vcs, VcsLevel::None, VcsLevel::Local, VcsLevel::None, VcsLevel::Smart, ignore_current)?;
   let output = Output::new();
-  let mut output = output.check();
+  let mut output = output.check(format);
+
+  // Overlapping files are reported against the first of the projects that cover them.
+  let mut overlaps: HashMap<ProjectId, Vec<String>> = HashMap::new();
+  for (ids, example, count) in mono.overlaps()? {
+    let names: Vec<_> =
+      ids.iter().map(|id| mono.get_project(id).map(|p| format!("{} ({})", p.name(), id))).collect::<Result<_>>()?;
+    let warning = format!("{} file(s) covered by {}, e.g. \"{}\".", count, names.join(", "), example);
+    overlaps.entry(ids[0].clone()).or_default().push(warning);
+  }
 
-  mono.check()?;
+  for (project, result) in mono.check()? {
+    let extra = overlaps.remove(project.id()).unwrap_or_default();
+    output.write_project(project.name(), result.map(|warnings| warnings.into_iter().chain(extra).collect()))?;
+  }
   output.write_done()?;
 
-  output.commit()
+  output.commit(deny_warnings)
 }
 
 pub fn get(
-  pref_vcs: Option<VcsRange>, wide: bool, versonly: bool, prev: bool, id: Option<&str>, name: Option<&str>,
-  ignore_current: bool
+  pref_vcs: PrefVcs, wide: bool, versonly: bool, prev: bool, id: Option<&str>, names: &[&str], ignore_current: bool
 ) -> Result<()> {
   let mono = with_opts(pref_vcs, VcsLevel::None, VcsLevel::Local, VcsLevel::None, VcsLevel::Smart, ignore_current)?;
 
   if prev {
-    get_using_cfg(&mono.config().slice_to_prev(mono.repo())?, wide, versonly, id, name)
+    get_using_cfg(&mono.config().slice_to_prev(mono.repo())?, wide, versonly, id, names)
   } else {
-    get_using_cfg(mono.config(), wide, versonly, id, name)
+    get_using_cfg(mono.config(), wide, versonly, id, names)
   }
 }
 
 fn get_using_cfg<R: StateRead>(
-  cfg: &Config<R>, wide: bool, versonly: bool, id: Option<&str>, name: Option<&str>
+  cfg: &Config<R>, wide: bool, versonly: bool, id: Option<&str>, names: &[&str]
 ) -> Result<()> {
   let output = Output::new();
   let mut output = output.projects(wide, versonly);
@@ -84,9 +158,10 @@ fn get_using_cfg<R: StateRead>(
   if let Some(id) = id {
     let id = id.parse()?;
     output.write_project(ProjLine::from(cfg.get_project(&id).ok_or_else(&ensure)?, reader)?)?;
-  } else if let Some(name) = name {
-    let id = cfg.find_unique(name)?;
-    output.write_project(ProjLine::from(cfg.get_project(id).ok_or_else(&ensure)?, reader)?)?;
+  } else if !names.is_empty() {
+    for id in matching_ids(cfg, names)? {
+      output.write_project(ProjLine::from(cfg.get_project(id).ok_or_else(&ensure)?, reader)?)?;
+    }
   } else {
     if cfg.projects().len() != 1 {
       bail!("No solo project.");
@@ -98,9 +173,31 @@ fn get_using_cfg<R: StateRead>(
   output.commit()
 }
 
-pub fn show(pref_vcs: Option<VcsRange>, wide: bool, prev: bool, ignore_current: bool) -> Result<()> {
+/// The projects that match any of the names or globs. Each project is listed once, in the order first matched, even
+/// if several names match it.
+fn matching_ids<'c, R: StateRead>(cfg: &'c Config<R>, names: &[&str]) -> Result<Vec<&'c ProjectId>> {
+  let mut ids: Vec<&ProjectId> = Vec::new();
+  for name in names {
+    for id in cfg.find_matching(name)? {
+      if !ids.contains(&id) {
+        ids.push(id);
+      }
+    }
+  }
+  Ok(ids)
+}
+
+pub async fn show(
+  early_info: &EarlyInfo, pref_vcs: PrefVcs, wide: bool, prev: bool, report: Option<&str>, ignore_current: bool
+) -> Result<()> {
   let mono = with_opts(pref_vcs, VcsLevel::None, VcsLevel::Local, VcsLevel::None, VcsLevel::Smart, ignore_current)?;
 
+  if let Some(report) = report {
+    let projects =
+      if prev { proj_lines(&mono.config().slice_to_prev(mono.repo())?)? } else { proj_lines(mono.config())? };
+    return print_report(early_info, report, &json!({ "projects": projects })).await;
+  }
+
   if prev {
     show_using_cfg(&mono.config().slice_to_prev(mono.repo())?, wide)
   } else {
@@ -108,6 +205,68 @@ pub fn show(pref_vcs: Option<VcsRange>, wide: bool, prev: bool, ignore_current:
   }
 }
 
+fn proj_lines<R: StateRead>(cfg: &Config<R>) -> Result<Vec<ProjLine>> {
+  cfg.projects().iter().map(|p| ProjLine::from(p, cfg.state_read())).collect()
+}
+
+/// Print a report of a command's results, rendered with the user's template at `url`.
+async fn print_report<T: Serialize>(early_info: &EarlyInfo, url: &str, vars: &T) -> Result<()> {
+  let tmpl = read_template(url, Some(early_info.orig_dir()), false).await?;
+  println!("{}", render_report(&tmpl, vars)?);
+  Ok(())
+}
+
+/// The projects of a plan, as given to a report template.
+fn plan_report(mono: &Mono, plan: &Plan, id: Option<&ProjectId>) -> Result<serde_json::Value> {
+  let mut projects = Vec::new();
+  for proj in mono.config().projects().iter().filter(|p| id.map(|id| p.id() == id).unwrap_or(true)) {
+    let (size, changelog) = match plan.incrs().get(proj.id()) {
+      Some(incr) => incr,
+      None => continue
+    };
+
+    let mut prs = Vec::new();
+    let mut deps = Vec::new();
+    for entry in changelog.entries() {
+      match entry {
+        ChangelogEntry::Pr(pr, size) => {
+          let commits = pr
+            .commits()
+            .iter()
+            .filter(|c| c.included())
+            .map(|c| json!({ "oid": c.oid(), "summary": c.summary(), "size": c.size().to_string() }))
+            .collect::<Vec<_>>();
+          prs.push(json!({ "number": pr.number(), "title": pr.title(), "size": size.to_string(), "commits": commits }));
+        }
+        ChangelogEntry::Dep(dep_id, name) => deps.push(json!({ "id": dep_id, "name": name }))
+      }
+    }
+
+    projects.push(json!({
+      "id": proj.id(),
+      "name": proj.name(),
+      "version": proj.get_value(mono.config().state_read())?,
+      "size": size.to_string(),
+      "prs": prs,
+      "deps": deps
+    }));
+  }
+
+  let ineffective = plan
+    .ineffective()
+    .iter()
+    .map(|pr| {
+      let commits = pr
+        .commits()
+        .iter()
+        .map(|c| json!({ "oid": c.oid(), "summary": c.summary(), "files": c.files() }))
+        .collect::<Vec<_>>();
+      json!({ "number": pr.number(), "title": pr.title(), "commits": commits })
+    })
+    .collect::<Vec<_>>();
+  Ok(json!({ "projects": projects, "ineffective": ineffective }))
+}
+
 fn show_using_cfg<R: StateRead>(cfg: &Config<R>, wide: bool) -> Result<()> {
   let output = Output::new();
   let mut output = output.projects(wide, false);
@@ -116,41 +275,83 @@ fn show_using_cfg<R: StateRead>(cfg: &Config<R>, wide: bool) -> Result<()> {
   output.commit()
 }
 
-pub fn set(pref_vcs: Option<VcsRange>, id: Option<&str>, name: Option<&str>, value: &str) -> Result<()> {
+pub fn set(pref_vcs: PrefVcs, id: Option<&str>, name: Option<&str>, value: &str, force: bool) -> Result<()> {
   let mut mono = build(pref_vcs, VcsLevel::None, VcsLevel::None, VcsLevel::None, VcsLevel::Smart)?;
 
-  if let Some(id) = id {
-    mono.set_by_id(&id.parse()?, value)?;
+  let cfg = mono.config();
+  let id = if let Some(id) = id {
+    id.parse()?
   } else if let Some(name) = name {
-    mono.set_by_name(name, value)?;
+    cfg.find_unique(name)?.clone()
   } else {
-    mono.set_by_only(value)?;
-  }
+    if cfg.projects().len() != 1 {
+      bail!("No solo project.");
+    }
+    cfg.projects().get(0).unwrap().id().clone()
+  };
 
-  mono.commit(false, false)
+  if !force {
+    mono.verify_set(&id, value)?;
+  }
+  mono.set_by_id(&id, value)?;
+  mono.set_commit_message(vec![(&id, value)], None)?;
+  mono.commit(false, false, false, &mut NoObserver)
 }
 
-pub fn diff(pref_vcs: Option<VcsRange>, ignore_current: bool) -> Result<()> {
+pub fn diff(pref_vcs: PrefVcs, ignore_current: bool) -> Result<()> {
   let mono = with_opts(pref_vcs, VcsLevel::None, VcsLevel::Local, VcsLevel::Local, VcsLevel::Smart, ignore_current)?;
   let output = Output::new();
   let mut output = output.diff();
 
   let analysis = mono.diff()?;
 
-  output.write_analysis(analysis)?;
+  output.write_analysis(analysis, mono.config().file().retire_removed())?;
   output.commit()
 }
 
-pub async fn files(pref_vcs: Option<VcsRange>, ignore_current: bool) -> Result<()> {
+pub async fn files(pref_vcs: PrefVcs, by_project: bool, ignore_current: bool) -> Result<()> {
   let mono = with_opts(pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart, ignore_current)?;
   let output = Output::new();
   let mut output = output.files();
 
-  output.write_files(mono.keyed_files().await?)?;
+  if by_project {
+    let mut named = Vec::new();
+    for (id, files) in mono.files_by_project().await? {
+      let name = mono.get_project(&id)?.name().to_string();
+      named.extend(files.into_iter().map(|file| Ok((name.clone(), file))));
+    }
+    output.write_files(named.into_iter())?;
+  } else {
+    output.write_files(mono.keyed_files().await?)?;
+  }
   output.commit()
 }
 
-pub async fn changes(pref_vcs: Option<VcsRange>, ignore_current: bool) -> Result<()> {
+pub fn affected(pref_vcs: PrefVcs, since: &str, ignore_current: bool) -> Result<()> {
+  let mono = with_opts(pref_vcs, VcsLevel::Local, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart, ignore_current)?;
+  let output = Output::new();
+  let mut output = output.affected();
+
+  let ids = mono.affected_since(since)?;
+  output.write_projects(ids.into_iter().map(|id| Ok((id.clone(), mono.get_project(&id)?.name().to_string()))))?;
+  output.commit()
+}
+
+pub fn owners(pref_vcs: PrefVcs, json: bool, ignore_current: bool) -> Result<()> {
+  let mono = with_opts(pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::None, VcsLevel::Smart, ignore_current)?;
+  let output = Output::new();
+  let mut output = output.owners(json);
+
+  let file = mono.config().file();
+  if json {
+    output.write_projects(file.projects().iter().filter(|p| !p.umbrella()));
+  } else {
+    output.write_codeowners(file.codeowners());
+  }
+  output.commit()
+}
+
+pub async fn changes(pref_vcs: PrefVcs, ignore_current: bool) -> Result<()> {
   let mono = with_opts(pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart, ignore_current)?;
   let output = Output::new();
   let mut output = output.changes();
@@ -160,18 +361,201 @@ pub async fn changes(pref_vcs: Option<VcsRange>, ignore_current: bool) -> Result
   Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn plan(
-  early_info: &EarlyInfo, pref_vcs: Option<VcsRange>, id: Option<&str>, template: Option<&str>, ignore_current: bool
+  early_info: &EarlyInfo, pref_vcs: PrefVcs, id: Option<&str>, template: Option<&str>, save: Option<&str>,
+  report: Option<&str>, deny_ineffective: bool, ignore_current: bool
 ) -> Result<()> {
   let mono = with_opts(pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart, ignore_current)?;
   let output = Output::new();
   let mut output = output.plan();
-  let plan = mono.build_plan().await?;
+  let mut plan = mono.build_plan().await?;
   let id = id.map(|i| i.parse()).transpose()?;
   let orig_dir = early_info.orig_dir();
 
-  output.write_plan(plan, id, template, orig_dir)?;
-  output.commit(&mono).await
+  if let Some(save) = save {
+    plan = mono.save_plan(plan, &orig_dir.join(save))?;
+  }
+
+  let ineffective: Vec<String> = plan
+    .ineffective()
+    .iter()
+    .map(|pr| if pr.number() == 0 { "unattached commits".to_string() } else { format!("PR {}", pr.number()) })
+    .collect();
+
+  if let Some(report) = report {
+    print_report(early_info, report, &plan_report(&mono, &plan, id.as_ref())?).await?;
+  } else {
+    output.write_plan(plan, id, template, orig_dir)?;
+    output.commit(&mono).await?;
+  }
+
+  if deny_ineffective && !ineffective.is_empty() {
+    bail!(Error::plan(format!("no project covers the changes of {}", ineffective.join(", "))));
+  }
+  Ok(())
+}
+
+/// Compare the plans at two refs, e.g. before and after a rebase, showing the projects whose size or changelog
+/// differs.
+pub async fn plan_compare(
+  pref_vcs: PrefVcs, id: Option<&str>, ref_a: &str, ref_b: &str, ignore_current: bool
+) -> Result<()> {
+  let mono = with_opts(pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart, ignore_current)?;
+  let id = id.map(|i| i.parse()).transpose()?;
+  for spec in &[ref_a, ref_b] {
+    if mono.repo().tag_oid(spec).is_none() {
+      bail!("Unable to find commit \"{}\".", spec);
+    }
+  }
+
+  let plan_a = mono.build_plan_at(ref_a).await?;
+  let plan_b = mono.build_plan_at(ref_b).await?;
+
+  let output = Output::new();
+  let mut output = output.compare(ref_a.to_string(), ref_b.to_string());
+  output.write_compare(mono.config().file(), &plan_a, &plan_b, id.as_ref());
+  output.commit()
+}
+
+pub async fn status(pref_vcs: PrefVcs, ignore_current: bool) -> Result<()> {
+  let mono = with_opts(pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart, ignore_current)?;
+  let output = Output::new();
+  let mut output = output.status();
+  let plan = mono.build_plan().await?;
+
+  let curt_config = mono.config();
+  let prev_config = curt_config.slice_to_prev(mono.repo())?;
+  let reader = curt_config.state_read();
+
+  for proj in curt_config.projects() {
+    let id = proj.id();
+    let curt_vers = proj.get_value(reader)?;
+    let prev_vers = prev_config.get_value(id).chain_err(|| format!("Unable to find prev {} value.", id))?;
+    let (size, logged) = plan
+      .incrs()
+      .get(id)
+      .map(|(size, changelog)| (*size, proj.changelog().is_some() && !changelog.is_empty()))
+      .unwrap_or((Size::Empty, false));
+    output.write_status(proj.name().to_string(), curt_vers, prev_vers, size, logged);
+  }
+
+  output.commit()
+}
+
+pub async fn why(
+  pref_vcs: PrefVcs, id: Option<&str>, name: Option<&str>, project: Option<&str>, ignore_current: bool
+) -> Result<()> {
+  let mono = with_opts(pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart, ignore_current)?;
+  let output = Output::new();
+  let mut output = output.why();
+
+  let cfg = mono.config();
+  let id = if let Some(id) = id {
+    id.parse()?
+  } else if let Some(name) = name {
+    cfg.find_unique(name)?.clone()
+  } else if let Some(project) = project {
+    // A project is named by its ID if it has one, and otherwise by its name.
+    match project.parse().ok().filter(|id| cfg.get_project(id).is_some()) {
+      Some(id) => id,
+      None => cfg.find_unique(project)?.clone()
+    }
+  } else {
+    if cfg.projects().len() != 1 {
+      bail!("No solo project.");
+    }
+    cfg.projects().get(0).unwrap().id().clone()
+  };
+  mono.get_project(&id)?;
+
+  output.write_why(mono.build_plan().await?, id);
+  output.commit(&mono)
+}
+
+pub async fn next(
+  pref_vcs: PrefVcs, wide: bool, versonly: bool, id: Option<&str>, name: Option<&str>, ignore_current: bool
+) -> Result<()> {
+  let mono = with_opts(pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart, ignore_current)?;
+  let output = Output::new();
+  let mut output = output.projects(wide, versonly);
+  let plan = mono.build_plan().await?;
+
+  let cfg = mono.config();
+  let ids = if let Some(id) = id {
+    vec![id.parse()?]
+  } else if let Some(name) = name {
+    vec![cfg.find_unique(name)?.clone()]
+  } else {
+    cfg.projects().iter().map(|p| p.id().clone()).collect()
+  };
+
+  let mut lines = Vec::new();
+  for (id, _, next_vers) in next_versions(&mono, &plan, ids)? {
+    lines.push(ProjLine::from_version(mono.get_project(&id)?, next_vers));
+  }
+
+  output.write_projects(lines.into_iter())?;
+  output.commit()
+}
+
+pub async fn exec(pref_vcs: PrefVcs, cmd: &[&str], ignore_current: bool) -> Result<()> {
+  use std::process::Command;
+
+  let mono = with_opts(pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart, ignore_current)?;
+  let plan = mono.build_plan().await?;
+  let (program, args) = cmd.split_first().ok_or_else(|| bad!("No command to execute."))?;
+
+  let ids = mono.config().projects().iter().map(|p| p.id().clone()).collect();
+  for (id, prev_vers, next_vers) in next_versions(&mono, &plan, ids)? {
+    if prev_vers.as_ref() == Some(&next_vers) {
+      continue;
+    }
+
+    let proj = mono.get_project(&id)?;
+    let status = Command::new(program)
+      .args(args)
+      .envs(proj.version_vars(prev_vers.as_deref(), &next_vers))
+      .status()
+      .chain_err(|| format!("Unable to run {} for {}.", program, proj.name()))?;
+    if !status.success() {
+      bail!("Command {} failed for {}.", program, proj.name());
+    }
+  }
+
+  Ok(())
+}
+
+/// Find the prev version and the version after the next release for each of the given projects.
+fn next_versions(mono: &Mono, plan: &Plan, ids: Vec<ProjectId>) -> Result<Vec<(ProjectId, Option<String>, String)>> {
+  let curt_config = mono.config();
+  let prev_config = curt_config.slice_to_prev(mono.repo())?;
+
+  let mut versions = Vec::new();
+  for id in ids {
+    let proj = mono.get_project(&id)?;
+    let size = plan.incrs().get(&id).map(|(s, _)| *s).unwrap_or(Size::Empty);
+    let curt_vers = proj.get_value(curt_config.state_read())?;
+    let prev_vers = prev_config.get_value(&id).chain_err(|| format!("Unable to find prev {} value.", id))?;
+
+    let next_vers = match &prev_vers {
+      Some(prev_vers) if size != Size::Empty => {
+        if size.is_failure() {
+          bail!(Error::plan(format!("couldn't parse conventional commit(s) {}", failed_hashes(plan))));
+        }
+        let target = size.apply(prev_vers)?;
+        if Size::less_than(&curt_vers, &target)? {
+          target
+        } else {
+          curt_vers
+        }
+      }
+      _ => curt_vers
+    };
+    versions.push((id, prev_vers, next_vers));
+  }
+
+  Ok(versions)
 }
 
 pub async fn template(early_info: &EarlyInfo, template: &str) -> Result<()> {
@@ -182,8 +566,7 @@ pub async fn template(early_info: &EarlyInfo, template: &str) -> Result<()> {
 }
 
 pub fn info(
-  pref_vcs: Option<VcsRange>, ids: Vec<ProjectId>, names: Vec<&str>, labels: Vec<&str>, show: InfoShow,
-  ignore_current: bool
+  pref_vcs: PrefVcs, ids: Vec<ProjectId>, names: Vec<&str>, labels: Vec<&str>, show: InfoShow, ignore_current: bool
 ) -> Result<()> {
   let mono = with_opts(pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::None, VcsLevel::Smart, ignore_current)?;
   let output = Output::new();
@@ -284,28 +667,121 @@ impl InfoShow {
   }
 }
 
-pub async fn release(
-  pref_vcs: Option<VcsRange>, all: bool, dry: &Engagement, locktags: bool, pause: bool
-) -> Result<()> {
+/// The options of a release, as given on the command line.
+pub struct ReleaseOptions<'a> {
+  pub all: bool,
+  pub dry: Engagement,
+  pub locktags: bool,
+  pub pause: bool,
+  pub from_plan: Option<&'a str>,
+  pub publish_dry: bool,
+  pub image_file: Option<&'a str>,
+  pub ci_output: Option<(&'a str, &'a str)>,
+  pub summary: Option<&'a str>,
+  pub allow_dirty: bool,
+  pub force_tags: bool,
+  pub report: Option<&'a str>,
+  pub skip_ci: Option<&'a str>
+}
+
+impl<'a> Default for ReleaseOptions<'a> {
+  fn default() -> ReleaseOptions<'a> {
+    ReleaseOptions {
+      all: false,
+      dry: Engagement::Full,
+      locktags: false,
+      pause: false,
+      from_plan: None,
+      publish_dry: false,
+      image_file: None,
+      ci_output: None,
+      summary: None,
+      allow_dirty: false,
+      force_tags: false,
+      report: None,
+      skip_ci: None
+    }
+  }
+}
+
+pub async fn release(early_info: &EarlyInfo, pref_vcs: PrefVcs, opts: &ReleaseOptions<'_>) -> Result<()> {
+  let ReleaseOptions {
+    all,
+    ref dry,
+    locktags,
+    pause,
+    from_plan,
+    publish_dry,
+    image_file,
+    ci_output,
+    summary,
+    allow_dirty,
+    force_tags,
+    report,
+    skip_ci
+  } = *opts;
+  let skip_ci: Option<SkipCi> = skip_ci.map(str::parse).transpose()?;
   let mut mono = build(pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart)?;
+  if mono.repo().journal_file().map(|j| j.exists()).unwrap_or(false) {
+    bail!("An earlier release was interrupted: run `versio resume` to finish it, or `versio abort` to roll it back.");
+  }
+  if !allow_dirty && matches!(dry, Engagement::Full) {
+    // Files that versio writes outside of the release commit, which are allowed to be dirty.
+    let ours: Vec<&str> =
+      from_plan.into_iter().chain(image_file).chain(ci_output.map(|(_, file)| file)).chain(summary).collect();
+    verify_clean(mono.repo(), early_info.orig_dir(), &ours)?;
+  }
   let output = Output::new();
   let mut output = output.release();
-  let plan = mono.build_plan().await?;
+  let plan = match from_plan {
+    Some(from_plan) => mono.load_plan(&early_info.orig_dir().join(from_plan), &mut output)?,
+    None => mono.build_plan_with(&mut output).await?
+  };
 
-  if let Err((should, is)) = mono.check_branch() {
-    bail!("Branch name \"{}\"\" doesn't match \"{}\".", is, should);
+  let (dry, prerelease) = match mono.check_branch() {
+    Ok(()) => (dry, None),
+    Err((should, is)) => match mono.config().off_branch() {
+      OffBranch::Fail => bail!("Branch name \"{}\"\" doesn't match \"{}\".", is, should),
+      OffBranch::DryRun => (&Engagement::Dry, None),
+      OffBranch::Prerelease => (dry, Some(is))
+    }
+  };
+  if matches!(dry, Engagement::Full) && prerelease.is_none() {
+    mono.check_upstream()?;
   }
 
   if plan.incrs().is_empty() {
+    if let Some((format, file)) = ci_output {
+      write_ci_output(format, &early_info.orig_dir().join(file), &[])?;
+    }
+    if let Some(file) = summary {
+      write_summary(&early_info.orig_dir().join(file), &release_summary(&[], &[], &[], &[], None, dry, pause))?;
+    }
+    if let Some(report) = report {
+      return print_report(early_info, report, &release_report(&[], dry, pause)).await;
+    }
     output.write_empty()?;
     output.commit();
     return Ok(());
   }
 
+  if let Err((_, is)) = mono.check_branch() {
+    output.write_off_branch(is, prerelease.is_some());
+  }
+
   let mut final_sizes = HashMap::new();
+  let mut released = Vec::new();
+  let mut ci_vars = Vec::new();
+  let mut changelogs = Vec::new();
+  let mut held = HashSet::new();
   for (id, (size, changelog)) in plan.incrs() {
     let proj = mono.get_project(id)?;
     let name = proj.name().to_string();
+    let below_min = proj.min_size().map(|min| size > &Size::None && size < &min).unwrap_or(false);
+    let first_release = proj.first_release();
+    if let Some(orig) = plan.info().clamped().get(id) {
+      output.write_clamped(name.clone(), *orig, *size);
+    }
     let curt_config = mono.config();
     let prev_config = curt_config.slice_to_prev(mono.repo())?;
     let curt_vers = curt_config
@@ -314,20 +790,41 @@ pub async fn release(
       .unwrap_or_else(|| panic!("No such project {}.", id));
     let prev_vers = prev_config.get_value(id).chain_err(|| format!("Unable to find prev {} value.", id))?;
 
+    let mut changed = false;
     let new_vers = if size == &Size::Empty {
       output.write_no_change(all, false, name.clone(), prev_vers.clone(), curt_vers.clone());
       curt_vers
     } else if let Some(prev_vers) = prev_vers {
       if size.is_failure() {
-        bail!("Couldn't parse conventional commit(s): {}", failed_hashes(&plan));
+        bail!(Error::plan(format!("couldn't parse conventional commit(s) {}", failed_hashes(&plan))));
+      }
+      if below_min {
+        // Hold the changes until they add up to the project's `min_size`.
+        mono.hold(id, *size, changelog);
+        output.write_held(name.clone(), curt_vers.clone(), *size);
+        held.insert(id.clone());
+        ci_vars.push((name, curt_vers.clone(), false));
+        final_sizes.insert(id.clone(), curt_vers);
+        continue;
       }
       let target = size.apply(&prev_vers)?;
+      let target = match &prerelease {
+        Some(branch) => format!("{}-{}", target, tag_sanitize(branch)),
+        None => target
+      };
       if Size::less_than(&curt_vers, &target)? {
-        proj.verify_restrictions(&target)?;
-        mono.set_by_id(id, &target)?;
-        output.write_changed(name.clone(), prev_vers.clone(), curt_vers.clone(), target.clone());
+        mono.verify_restrictions(id, &target, Some(&prev_vers), changelog)?;
+        if let Engagement::Full = dry {
+          mono.pre_bump(id, Some(&prev_vers), &target)?;
+        }
+        mono.bump_by_id(id, Some(&prev_vers), &target, &mut output)?;
+        if prerelease.is_none() && (size == &Size::Major || size == &Size::Minor) {
+          mono.branch_by_id(id, &target)?;
+        }
+        released.push(Released::new(id.clone(), name.clone(), Some(prev_vers.clone()), target.clone(), changelog));
+        changed = true;
       } else {
-        proj.verify_restrictions(&curt_vers)?;
+        mono.verify_restrictions(id, &curt_vers, Some(&prev_vers), changelog)?;
         if locktags {
           output.write_no_change(all, true, name.clone(), Some(prev_vers.clone()), curt_vers.clone());
         } else {
@@ -336,8 +833,23 @@ pub async fn release(
         }
       }
       target
+    } else if let Some(start) = first_release.start() {
+      mono.verify_restrictions(id, start, None, changelog)?;
+      if let Engagement::Full = dry {
+        mono.pre_bump(id, None, start)?;
+      }
+      mono.bump_by_id(id, None, start, &mut output)?;
+      released.push(Released::new(id.clone(), name.clone(), None, start.to_string(), changelog));
+      changed = true;
+      start.to_string()
     } else {
-      proj.verify_restrictions(&curt_vers)?;
+      if first_release == FirstRelease::Explicit && mono.config().old_tags().latest(id).is_none() {
+        bail!(Error::plan(format!(
+          "project {} has never been released: give its first version with `versio set --id {} --value <version>`",
+          name, id
+        )));
+      }
+      mono.verify_restrictions(id, &curt_vers, None, changelog)?;
       if locktags {
         output.write_no_change(all, true, name.clone(), prev_vers.clone(), curt_vers.clone());
       } else {
@@ -347,21 +859,61 @@ pub async fn release(
       curt_vers
     };
 
-    if let Some(wrote) = mono.write_changelog(id, changelog, &new_vers).await? {
-      output.write_logged(wrote);
+    ci_vars.push((name, new_vers.clone(), changed));
+    final_sizes.insert(id.clone(), new_vers);
+  }
+
+  if let Some((format, file)) = ci_output {
+    write_ci_output(format, &early_info.orig_dir().join(file), &ci_vars)?;
+  }
+
+  // Changelogs are written once all new versions are known, so that they can list new dependency versions.
+  for (id, (_, changelog)) in plan.incrs() {
+    if let Some(new_vers) = final_sizes.get(id) {
+      if let Some(wrote) = mono.write_changelog(id, changelog, new_vers, held.contains(id), &final_sizes).await? {
+        changelogs.push(wrote.clone());
+        output.write_logged(wrote);
+      }
     }
+  }
 
-    final_sizes.insert(id.clone(), new_vers);
+  // A prerelease doesn't advance the prev tag, so removed projects are only retired by a full release.
+  if mono.config().file().retire_removed() && prerelease.is_none() {
+    for retired in mono.retire_removed().await? {
+      if let Some(wrote) = retired.changelog() {
+        changelogs.push(wrote.to_path_buf());
+        output.write_logged(wrote.to_path_buf());
+      }
+      output.write_retired(retired.name().to_string(), retired.version().to_string(), retired.tag().map(|t| t.into()));
+    }
   }
 
   mono.write_chains(plan.chain_writes(), &final_sizes)?;
+  mono.sync_workspace(&final_sizes)?;
+  mono.sync_maven(&final_sizes)?;
+
+  let image_tags = mono.image_tags(&final_sizes);
+  mono.write_image_tags(&image_tags)?;
+  if let Some(image_file) = image_file {
+    let lines: String = image_tags.iter().map(|(image, tag)| format!("{}:{}\n", image, tag)).collect();
+    let image_file = early_info.orig_dir().join(image_file);
+    std::fs::write(&image_file, lines).chain_err(|| format!("Unable to write {}.", image_file.display()))?;
+  }
+  output.write_images(image_tags);
+  mono.write_build_info(&released, &final_sizes)?;
 
   match dry {
     Engagement::Full => {
-      mono.commit(true, pause)?;
+      mono.record_plan(&plan)?;
+      mono.set_commit_message(released.iter().map(|r| (r.id(), r.new_version())), skip_ci)?;
+      mono.write_note(&plan)?;
+      mono.update_formulas(&released)?;
+      mono.set_released(released.clone());
+      mono.commit(prerelease.is_none(), pause, force_tags, &mut output)?;
       if pause {
         output.write_pause();
       } else {
+        announce(&mono, &released).await?;
         output.write_commit();
         output.write_done();
       }
@@ -371,30 +923,116 @@ pub async fn release(
       output.write_wrote_changelogs();
     }
     Engagement::Dry => {
+      if publish_dry {
+        for r in &released {
+          mono.check_publish(r.id())?;
+        }
+      }
       output.write_dry();
     }
   }
 
+  if let Some(file) = summary {
+    let commit = match dry {
+      Engagement::Full if !pause => mono.repo().revparse_oid(FromTag::new("HEAD", false)).ok(),
+      _ => None
+    };
+    let summary = release_summary(&ci_vars, &released, output.tags(), &changelogs, commit, dry, pause);
+    write_summary(&early_info.orig_dir().join(file), &summary)?;
+  }
+
+  if let Some(report) = report {
+    return print_report(early_info, report, &release_report(&ci_vars, dry, pause)).await;
+  }
   output.commit();
   Ok(())
 }
 
-pub fn resume(user_pref_vcs: Option<VcsRange>) -> Result<()> {
-  let vcs = combine_vcs(user_pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart)?;
-  let repo = Repo::open(".", VcsState::new(vcs.max(), false))?;
+/// Notify the webhooks and trigger the GitHub deploys of the released projects, once their release is pushed.
+async fn announce(mono: &Mono, released: &[Released]) -> Result<()> {
+  notify_all(mono.config().file().notify(), released, mono.cancel()).await;
+  if let Some(deploy) = mono.config().file().github_deploy() {
+    let head = mono.repo().revparse_oid(FromTag::new("HEAD", false))?;
+    deploy_all(deploy, mono.github_info(), &head, released, mono.cancel()).await;
+  }
+  Ok(())
+}
+
+/// The results of a release, as given to a report template.
+fn release_report(vars: &[(String, String, bool)], dry: &Engagement, pause: bool) -> serde_json::Value {
+  let projects = vars
+    .iter()
+    .map(|(name, vers, changed)| json!({ "name": name, "version": vers, "changed": changed }))
+    .collect::<Vec<_>>();
+  let engagement = match dry {
+    Engagement::Dry => "dry",
+    Engagement::Changelog => "changelog",
+    Engagement::Full => "full"
+  };
+  json!({ "projects": projects, "engagement": engagement, "paused": pause })
+}
+
+/// The results of a release, as written to its `--summary` file for later jobs.
+fn release_summary(
+  vars: &[(String, String, bool)], released: &[Released], tags: &[String], changelogs: &[PathBuf],
+  commit: Option<String>, dry: &Engagement, pause: bool
+) -> serde_json::Value {
+  let mut summary = release_report(vars, dry, pause);
+  let projects = vars
+    .iter()
+    .map(|(name, vers, changed)| {
+      let prev = released.iter().find(|r| r.name() == name).and_then(|r| r.old());
+      json!({ "name": name, "prev": prev, "version": vers, "changed": changed })
+    })
+    .collect::<Vec<_>>();
+  let changelogs = changelogs.iter().map(|p| p.to_string_lossy().into_owned()).collect::<Vec<_>>();
+  summary["projects"] = json!(projects);
+  summary["tags"] = json!(tags);
+  summary["changelogs"] = json!(changelogs);
+  summary["commit"] = json!(commit);
+  summary
+}
+
+fn write_summary(file: &Path, summary: &serde_json::Value) -> Result<()> {
+  let data = serde_json::to_string_pretty(summary)?;
+  std::fs::write(file, data + "\n").chain_err(|| format!("Unable to write {}.", file.display()))
+}
+
+/// Refuse to release if there are local changes that would be swept into the release commit.
+fn verify_clean(repo: &Repo, orig_dir: &Path, ours: &[&str]) -> Result<()> {
+  let root = repo.working_dir()?;
+  let dirty: Vec<_> =
+    repo.dirty_paths()?.into_iter().filter(|p| !ours.iter().any(|o| orig_dir.join(o) == root.join(p))).collect();
+  if !dirty.is_empty() {
+    bail!("Uncommitted changes in {}: commit or stash them, or use --allow-dirty.", dirty.join(", "));
+  }
+  Ok(())
+}
+
+pub async fn resume(pref_vcs: PrefVcs) -> Result<()> {
+  let vcs = combine_vcs(pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart)?;
+  let repo = Repo::open(".", VcsState::new(vcs.max(), false))?.with_cancel(pref_vcs.cancel());
   let output = Output::new();
   let mut output = output.resume();
 
-  let mut commit: CommitState = {
-    let file = File::open(".versio-paused")?;
-    let reader = BufReader::new(file);
-    let commit: CommitState = serde_json::from_reader(reader)?;
+  let journal = repo.journal_file();
+  let mut commit = match journal.as_ref().filter(|j| j.exists()) {
+    Some(journal) => read_commit_state(journal)?,
+    None => {
+      let commit = read_commit_state(Path::new(".versio-paused"))?;
 
-    // We must remove the pausefile before resuming, or else it will be committed.
-    remove_file(".versio-paused")?;
-    commit
+      // We must remove the pausefile before resuming, or else it will be committed.
+      remove_file(".versio-paused")?;
+      commit
+    }
   };
-  commit.resume(&repo)?;
+  commit.resume(&repo, &mut output, journal.as_deref())?;
+
+  // The webhooks and deploys are configured at the release commit, which is now HEAD.
+  if !commit.released().is_empty() {
+    let mono = build(pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart)?;
+    announce(&mono, commit.released()).await?;
+  }
 
   output.write_done()?;
   output.commit()?;
@@ -402,12 +1040,261 @@ pub fn resume(user_pref_vcs: Option<VcsRange>) -> Result<()> {
   Ok(())
 }
 
-pub fn abort() -> Result<()> {
-  remove_file(".versio-paused")?;
-  println!("Release aborted. You may need to rollback your VCS \n(i.e `git checkout -- .`)");
+pub fn abort(pref_vcs: PrefVcs) -> Result<()> {
+  let vcs = combine_vcs(pref_vcs, VcsLevel::None, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart)?;
+  let repo = Repo::open(".", VcsState::new(vcs.max(), false))?.with_cancel(pref_vcs.cancel());
+
+  match repo.journal_file().filter(|j| j.exists()) {
+    Some(journal) => {
+      read_commit_state(&journal)?.rollback(&repo)?;
+      remove_file(&journal)?;
+      println!("Interrupted release rolled back.");
+    }
+    None => {
+      remove_file(".versio-paused")?;
+      println!("Release aborted. You may need to rollback your VCS \n(i.e `git checkout -- .`)");
+    }
+  }
   Ok(())
 }
 
+fn read_commit_state(path: &Path) -> Result<CommitState> {
+  let file = File::open(path).chain_err(|| format!("Unable to open {}.", path.display()))?;
+  let reader = BufReader::new(file);
+  Ok(serde_json::from_reader(reader)?)
+}
+
+pub fn history(pref_vcs: PrefVcs) -> Result<()> {
+  let mono = build(pref_vcs, VcsLevel::Local, VcsLevel::Local, VcsLevel::Local, VcsLevel::Smart)?;
+  let output = Output::new();
+  let mut output = output.history();
+  output.write_notes(mono.history()?);
+  output.commit()
+}
+
+pub fn revert(pref_vcs: PrefVcs) -> Result<()> {
+  let mono = build(pref_vcs, VcsLevel::Local, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart)?;
+  let output = Output::new();
+  let mut output = output.revert();
+  revert_release(&mono, &mut output)?;
+  output.commit()
+}
+
+/// Undo the release that the prev tag points to: revert its commit, delete the tags it created, and move the prev tag
+/// back to the release before it.
+fn revert_release(mono: &Mono, output: &mut RevertOutput) -> Result<()> {
+  let repo = mono.repo();
+  let prev_tag = mono.config().prev_tag();
+  let message = read_prev_message(repo, prev_tag)?;
+  let last = message.last().as_ref().ok_or_else(|| bad!("No earlier release is recorded in \"{}\".", prev_tag))?;
+  let release_oid = repo.tag_oid(prev_tag).ok_or_else(|| bad!("Unable to find tag \"{}\".", prev_tag))?;
+
+  // The prev tag may not point to a release commit, if the last release didn't write any files.
+  if repo.is_release_commit(&release_oid)? {
+    repo.revert(&release_oid, &format!("build(deploy): Versio revert release {}", &release_oid[.. 7]))?;
+    output.write_reverted(release_oid.clone());
+  }
+
+  // A release tag may have been deleted locally, but still be on the remote.
+  let remote_tags = repo.remote_tags()?;
+  for (id, vers) in message.versions() {
+    if last.versions().get(id) == Some(vers) {
+      continue;
+    }
+    if let Some(tag) = mono.config().get_project(id).and_then(|p| p.full_version(vers)) {
+      if repo.tag_oid(&tag).is_some() || remote_tags.contains_key(&tag) {
+        repo.delete_tag(&tag)?;
+        output.write_deleted(tag);
+      }
+    }
+  }
+
+  let msg = serde_json::to_string(&PrevTagMessage::new(last.versions().clone()))?;
+  repo.update_tag_anno(prev_tag, last.oid(), &msg)?;
+  output.write_restored(prev_tag.to_string(), last.oid().to_string());
+  Ok(())
+}
+
+/// Show where the prev tag points, locally and on the remote, and the snapshot of project versions that it holds.
+pub fn prev_show(pref_vcs: PrefVcs) -> Result<()> {
+  let vcs = combine_vcs(pref_vcs, VcsLevel::Local, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart)?;
+  let repo = Repo::open(".", VcsState::new(vcs.max(), false))?.with_cancel(pref_vcs.cancel());
+  let file = ConfigFile::from_dir(repo.working_dir()?)?;
+  let prev_tag = file.prev_tag();
+  let output = Output::new();
+  let mut output = output.prev(prev_tag.to_string());
+
+  if let Some(oid) = repo.tag_oid(prev_tag) {
+    output.write_local(oid.clone(), repo.in_history(&oid)?);
+    if repo.is_remote() {
+      output.write_remote(repo.remote_tags()?.remove(prev_tag));
+    }
+    output.write_message(&file, &read_prev_message(&repo, prev_tag)?);
+  }
+
+  output.commit()
+}
+
+/// Point the prev tag at `spec`, locally and on the remote, for when history was rewritten or the tag was lost.
+///
+/// The snapshot is rebuilt from the versions at `spec`: projects whose versions are kept in tags retain the versions
+/// already in the snapshot. Held changes are kept, but the record of the last release is dropped, since it no
+/// longer describes the commit that the tag points to.
+pub fn prev_set(pref_vcs: PrefVcs, spec: &str) -> Result<()> {
+  let vcs = combine_vcs(pref_vcs, VcsLevel::Local, VcsLevel::Smart, VcsLevel::Local, VcsLevel::Smart)?;
+  let repo = Repo::open(".", VcsState::new(vcs.max(), false))?.with_cancel(pref_vcs.cancel());
+  let file = ConfigFile::from_dir(repo.working_dir()?)?;
+  let prev_tag = file.prev_tag();
+  let output = Output::new();
+  let mut output = output.prev(prev_tag.to_string());
+
+  let oid = repo.tag_oid(spec).ok_or_else(|| bad!("Unable to find commit \"{}\".", spec))?;
+  if !repo.in_history(&oid)? {
+    bail!("Commit {} isn't in the history of HEAD: the prev tag must mark a commit that has been released.", spec);
+  }
+
+  let old_oid = repo.tag_oid(prev_tag);
+  let message = read_prev_message(&repo, prev_tag)?;
+  let tags = OldTags::new(message.versions().clone(), HashMap::new());
+  let config = Config::from_state(PrevState::new(repo.slice(FromTagBuf::new(oid.clone(), false)), tags))?;
+  let versions = config
+    .projects()
+    .iter()
+    .map(|p| Ok((p.id().clone(), p.get_value(config.state_read())?)))
+    .collect::<Result<HashMap<_, _>>>()?;
+
+  let message = PrevTagMessage::new(versions).with_held(message.into_held());
+  repo.update_tag_anno(prev_tag, &oid, &serde_json::to_string(&message)?)?;
+
+  output.write_moved(old_oid);
+  output.write_local(oid, true);
+  output.write_message(&file, &message);
+  output.commit()
+}
+
+/// Write each project's version, and whether it changed, as variables in a file that a CI system reads: `PROJ_VERSION`
+/// and `PROJ_CHANGED`, where `PROJ` is the project's name in upper snake case.
+fn write_ci_output(format: &str, file: &Path, vars: &[(String, String, bool)]) -> Result<()> {
+  let escape: fn(&str) -> String = match format {
+    "gitlab-dotenv" | "env-file" => |v| v.to_string(),
+    "jenkins-properties" => |v| v.replace('\\', "\\\\").replace('=', "\\=").replace(':', "\\:"),
+    other => bail!("Unknown CI output format \"{}\".", other)
+  };
+
+  let mut data = String::new();
+  for (name, vers, changed) in vars {
+    let key: String =
+      name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect();
+    data.push_str(&format!("{}_VERSION={}\n", key, escape(vers)));
+    data.push_str(&format!("{}_CHANGED={}\n", key, changed));
+  }
+  std::fs::write(file, data).chain_err(|| format!("Unable to write {}.", file.display()))?;
+  Ok(())
+}
+
+/// Check that a commit message has a conventional commit type that the config knows about, and that doesn't fail a
+/// release. Lines starting with `#` are ignored, as git strips them from the final message.
+pub fn lint(early_info: &EarlyInfo, file: &str, format: Option<&str>) -> Result<()> {
+  let path = early_info.orig_dir().join(file);
+  let message = std::fs::read_to_string(&path).chain_err(|| format!("Unable to read {}.", path.display()))?;
+  let message: Vec<_> = message.lines().filter(|line| !line.starts_with('#')).collect();
+  let message = message.join("\n");
+
+  let kind = extract_kind(message.trim());
+  let config = ConfigFile::from_dir(early_info.working_dir())?;
+  let result = lint_kind(&config, &kind);
+
+  match format {
+    Some(format) => {
+      let mut output = Output::new().tests("lint", format);
+      let summary = message.trim().lines().next().unwrap_or_default();
+      output.write_case(summary, result.map(|()| Vec::new()));
+      output.commit(false)
+    }
+    None => result
+  }
+}
+
+fn lint_kind(config: &ConfigFile, kind: &str) -> Result<()> {
+  if config.commits() == CommitsMode::Strict && !config.knows_kind(kind) {
+    bail!(Error::plan(format!("unknown commit type \"{}\"", kind)));
+  }
+  let sizes = config.sizes();
+  if config.is_security_kind(kind) && !sizes.contains_key(kind) {
+    // An unsized security type is a patch, whatever the catch-all is.
+    return Ok(());
+  }
+  match sizes.get(kind).or_else(|| sizes.get("*")) {
+    Some(size) if size.is_failure() => bail!(Error::plan(format!("commit type \"{}\" is not allowed", kind))),
+    Some(_) => Ok(()),
+    None => bail!(Error::plan(format!("unknown commit type \"{}\"", kind)))
+  }
+}
+
+const HOOK_MARKER: &str = "# Installed by versio";
+
+/// Install a `commit-msg` hook that runs `versio lint`, and optionally a `pre-push` hook that runs `versio check`.
+/// Hooks are written where git will run them, respecting `core.hooksPath`; an existing hook that versio didn't
+/// install is never overwritten.
+pub fn hook_install(early_info: &EarlyInfo, pre_push: bool) -> Result<()> {
+  let hooks_dir = Repo::find_hooks_dir(early_info.working_dir())?;
+  std::fs::create_dir_all(&hooks_dir).chain_err(|| format!("Unable to create {}.", hooks_dir.display()))?;
+
+  write_hook(&hooks_dir.join("commit-msg"), "versio lint \"$1\"")?;
+  if pre_push {
+    write_hook(&hooks_dir.join("pre-push"), "versio check")?;
+  }
+  Ok(())
+}
+
+fn write_hook(path: &Path, command: &str) -> Result<()> {
+  if let Ok(existing) = std::fs::read_to_string(path) {
+    if !existing.contains(HOOK_MARKER) {
+      bail!("A hook already exists at {}: not overwriting it.", path.display());
+    }
+  }
+
+  let script = format!("#!/bin/sh\n{}\nexec {}\n", HOOK_MARKER, command);
+  std::fs::write(path, script).chain_err(|| format!("Unable to write {}.", path.display()))?;
+
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt as _;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+  }
+
+  println!("Installed {}", path.display());
+  Ok(())
+}
+
+/// Report the time spent in each phase of this run. This is written to stderr, so that it doesn't mix with the
+/// command's output.
+pub fn write_timings() {
+  for (phase, total) in totals() {
+    eprintln!("{:>16}: {:.3}s", phase, total.as_secs_f64());
+  }
+}
+
+/// Print only each command's primary result: see `output::set_quiet`.
+pub fn quiet() { set_quiet(); }
+
+/// Show progress spinners on stderr for the slower phases of the run, if it's a terminal.
+pub fn show_progress() { enable_progress(); }
+
+/// Run without the network: see `vcs::set_offline`.
+pub fn offline() { set_offline(); }
+
+/// Pin the time used for changelog dates, tags, and commits to `date`, or else to `SOURCE_DATE_EPOCH` if it's set.
+pub fn pin_date(date: Option<&str>) -> Result<()> {
+  let env_date = std::env::var(SOURCE_DATE_EPOCH).ok().filter(|d| !d.trim().is_empty());
+  let at = match (date, env_date) {
+    (Some(date), _) => Some(parse_time(date)?),
+    (None, Some(date)) => Some(parse_time(&date).chain_err(|| format!("Bad {}.", SOURCE_DATE_EPOCH))?),
+    (None, None) => None
+  };
+  pin_time(at)
+}
+
 pub fn sanity_check() -> Result<()> {
   if Path::new(".versio-paused").exists() {
     bail!("versio is paused: use `release --resume` or `--abort`.")
@@ -417,26 +1304,36 @@ pub fn sanity_check() -> Result<()> {
 }
 
 fn with_opts(
-  user_pref_vcs: Option<VcsRange>, my_pref_lo: VcsLevel, my_pref_hi: VcsLevel, my_reqd_lo: VcsLevel,
-  my_reqd_hi: VcsLevel, ignore_current: bool
+  pref_vcs: PrefVcs, my_pref_lo: VcsLevel, my_pref_hi: VcsLevel, my_reqd_lo: VcsLevel, my_reqd_hi: VcsLevel,
+  ignore_current: bool
 ) -> Result<Mono> {
-  let vcs = combine_vcs(user_pref_vcs, my_pref_lo, my_pref_hi, my_reqd_lo, my_reqd_hi)?;
-  Mono::here(VcsState::new(vcs.max(), ignore_current))
+  let vcs = combine_vcs(pref_vcs, my_pref_lo, my_pref_hi, my_reqd_lo, my_reqd_hi)?;
+  let vcs = VcsState::new(vcs.max(), ignore_current);
+  Mono::builder(".", vcs).max_history(pref_vcs.max_history).cancel(pref_vcs.cancel()).open()
 }
 
 fn build(
-  user_pref_vcs: Option<VcsRange>, my_pref_lo: VcsLevel, my_pref_hi: VcsLevel, my_reqd_lo: VcsLevel,
-  my_reqd_hi: VcsLevel
+  pref_vcs: PrefVcs, my_pref_lo: VcsLevel, my_pref_hi: VcsLevel, my_reqd_lo: VcsLevel, my_reqd_hi: VcsLevel
 ) -> Result<Mono> {
-  with_opts(user_pref_vcs, my_pref_lo, my_pref_hi, my_reqd_lo, my_reqd_hi, false)
+  with_opts(pref_vcs, my_pref_lo, my_pref_hi, my_reqd_lo, my_reqd_hi, false)
 }
 
 fn combine_vcs(
-  user_pref_vcs: Option<VcsRange>, my_pref_lo: VcsLevel, my_pref_hi: VcsLevel, my_reqd_lo: VcsLevel,
-  my_reqd_hi: VcsLevel
+  pref_vcs: PrefVcs, my_pref_lo: VcsLevel, my_pref_hi: VcsLevel, my_reqd_lo: VcsLevel, my_reqd_hi: VcsLevel
 ) -> Result<VcsRange> {
-  let pref_vcs = user_pref_vcs.unwrap_or_else(move || VcsRange::new(my_pref_lo, my_pref_hi));
-  let reqd_vcs = VcsRange::new(my_reqd_lo, my_reqd_hi);
+  let PrefVcs { user, conf_pref, conf_reqd, .. } = pref_vcs;
+  let pref_vcs = user.or(conf_pref).unwrap_or_else(move || VcsRange::new(my_pref_lo, my_pref_hi));
+  let mut reqd_vcs = VcsRange::new(my_reqd_lo, my_reqd_hi);
+
+  // The config can only narrow what a command requires: it can't run a command at a level it doesn't support.
+  if let Some(conf_reqd) = conf_reqd {
+    let narrowed = reqd_vcs.intersect(&conf_reqd);
+    if narrowed.is_empty() {
+      bail!("Configured VCS {:?} is outside of the command's required {:?}.", conf_reqd, reqd_vcs);
+    }
+    reqd_vcs = narrowed;
+  }
+
   VcsRange::detect_and_combine(&pref_vcs, &reqd_vcs)
 }
 
@@ -453,3 +1350,187 @@ pub fn failed_hashes(plan: &Plan) -> String {
 
   commits
 }
+
+#[cfg(test)]
+mod test {
+  use super::{matching_ids, plan, revert_release, EarlyInfo, Engagement, PrefVcs, ReleaseOptions};
+  use crate::config::ProjectId;
+  use crate::fixture::Fixture;
+  use crate::mono::read_prev_message;
+  use crate::observe::NoObserver;
+  use crate::output::RevertOutput;
+  use crate::vcs::{VcsLevel, VcsRange};
+  use std::collections::HashMap;
+  use std::fs::read_to_string;
+  use tokio::runtime::Runtime;
+
+  const CONFIG: &str = r#"
+projects:
+  - name: proj
+    id: 1
+    tag_prefix: "proj"
+    version:
+      file: "VERSION"
+      pattern: "([0-9]+\\.[0-9]+\\.[0-9]+)"
+"#;
+
+  const ROOTED_CONFIG: &str = r#"
+projects:
+  - name: proj
+    id: 1
+    root: "proj"
+    tag_prefix: "proj"
+    version:
+      file: "VERSION"
+      pattern: "([0-9]+\\.[0-9]+\\.[0-9]+)"
+
+sizes:
+  use_angular: true
+  fail: ["*"]
+"#;
+
+  fn fixture() -> Fixture { Fixture::new(&[(".versio.yaml", CONFIG), ("VERSION", "1.0.0\n")]) }
+
+  fn local() -> PrefVcs { PrefVcs::new(Some(VcsRange::exact(VcsLevel::Local))) }
+
+  fn early_info(fixture: &Fixture) -> EarlyInfo {
+    EarlyInfo::new(1, fixture.path().to_path_buf(), fixture.path().to_path_buf(), HashMap::new())
+  }
+
+  fn release(fixture: &Fixture, vers: &str) -> String {
+    let mut mono = fixture.mono();
+    mono.set_by_id(&ProjectId::from_id(1), vers).unwrap();
+    mono.commit(true, false, false, &mut NoObserver).unwrap();
+    mono.repo().tag_oid(mono.config().prev_tag()).unwrap()
+  }
+
+  #[test]
+  fn test_revert_release() {
+    let fixture = fixture();
+    let first = release(&fixture, "1.1.0");
+    let second = release(&fixture, "1.2.0");
+    assert_ne!(first, second);
+    assert!(fixture.repo().find_reference("refs/tags/proj-v1.2.0").is_ok());
+
+    let mono = fixture.mono();
+    revert_release(&mono, &mut RevertOutput::new()).unwrap();
+
+    assert_eq!(read_to_string(fixture.path().join("VERSION")).unwrap(), "1.1.0\n");
+    assert!(fixture.repo().find_reference("refs/tags/proj-v1.2.0").is_err());
+    assert!(fixture.repo().find_reference("refs/tags/proj-v1.1.0").is_ok());
+
+    let prev_tag = mono.config().prev_tag();
+    assert_eq!(mono.repo().tag_oid(prev_tag), Some(first));
+    let message = read_prev_message(mono.repo(), prev_tag).unwrap();
+    assert_eq!(message.versions().get(&ProjectId::from_id(1)).map(|v| v.as_str()), Some("1.1.0"));
+    assert!(message.last().is_none());
+  }
+
+  #[test]
+  fn test_revert_needs_earlier_release() {
+    let fixture = fixture();
+    release(&fixture, "1.1.0");
+    assert!(revert_release(&fixture.mono(), &mut RevertOutput::new()).is_err());
+  }
+
+  #[test]
+  fn test_deny_ineffective() {
+    let fixture = Fixture::new(&[(".versio.yaml", ROOTED_CONFIG), ("proj/VERSION", "1.0.0\n")]);
+    fixture.tag_prev("versio-prev", HashMap::new());
+    fixture.write("other.txt", "other\n");
+    let outside = fixture.commit("fix: outside");
+
+    let rt = Runtime::new().unwrap();
+    let built = rt.block_on(fixture.mono().build_plan()).unwrap();
+    assert_eq!(built.ineffective().len(), 1);
+    assert_eq!(built.ineffective()[0].number(), 0);
+    assert!(built.ineffective()[0].commits().iter().any(|c| c.oid() == outside.to_string()));
+
+    let info = early_info(&fixture);
+    rt.block_on(plan(&info, local(), None, None, None, None, false, false)).unwrap();
+    let err = rt.block_on(plan(&info, local(), None, None, None, None, true, false)).unwrap_err();
+    assert!(err.to_string().contains("no project covers the changes of unattached commits"), "{}", err);
+  }
+
+  #[test]
+  fn test_get_names() {
+    let config = r#"
+projects:
+  - name: app-web
+    id: 1
+    root: "web"
+    version: { file: "VERSION", pattern: "(.*)" }
+  - name: app-api
+    id: 2
+    root: "api"
+    version: { file: "VERSION", pattern: "(.*)" }
+  - name: lib
+    id: 3
+    root: "lib"
+    aliases: ["core"]
+    version: { file: "VERSION", pattern: "(.*)" }
+"#;
+    let fixture = Fixture::new(&[
+      (".versio.yaml", config),
+      ("web/VERSION", "1.0.0"),
+      ("api/VERSION", "1.0.0"),
+      ("lib/VERSION", "1.0.0")
+    ]);
+    let mono = fixture.mono();
+    let ids = |names: &[&str]| matching_ids(mono.config(), names).unwrap().into_iter().cloned().collect::<Vec<_>>();
+    let expect = |ids: &[u32]| ids.iter().copied().map(ProjectId::from_id).collect::<Vec<_>>();
+
+    assert_eq!(ids(&["app-*"]), expect(&[1, 2]));
+    assert_eq!(ids(&["lib", "app-*", "app-api"]), expect(&[3, 1, 2]));
+    assert_eq!(ids(&["app-api", "app-?eb"]), expect(&[2, 1]));
+    assert_eq!(ids(&["co*"]), expect(&[3]));
+    assert!(matching_ids(mono.config(), &["nothing-*"]).is_err());
+    assert!(matching_ids(mono.config(), &["nothing"]).is_err());
+  }
+
+  #[test]
+  fn test_release_summary() {
+    let fixture = Fixture::new(&[(".versio.yaml", ROOTED_CONFIG), ("proj/VERSION", "1.0.0\n")]);
+    fixture.tag_prev("versio-prev", vec![(ProjectId::from_id(1), "1.0.0".to_string())].into_iter().collect());
+    fixture.write("proj/one.txt", "one\n");
+    fixture.commit("feat: add one");
+
+    let rt = Runtime::new().unwrap();
+    let read = || serde_json::from_str::<serde_json::Value>(&read_to_string("summary.json").unwrap()).unwrap();
+
+    let opts = ReleaseOptions { summary: Some("summary.json"), dry: Engagement::Dry, ..Default::default() };
+    rt.block_on(super::release(&early_info(&fixture), local(), &opts)).unwrap();
+    let summary = read();
+    assert_eq!(summary["engagement"], "dry");
+    assert_eq!(summary["projects"][0]["version"], "1.1.0");
+    assert_eq!(summary["commit"], serde_json::Value::Null);
+    std::fs::remove_file("summary.json").unwrap();
+
+    let opts = ReleaseOptions { summary: Some("summary.json"), ..Default::default() };
+    rt.block_on(super::release(&early_info(&fixture), local(), &opts)).unwrap();
+    let summary = read();
+    let head = fixture.repo().head().unwrap().target().unwrap().to_string();
+    assert_eq!(
+      summary,
+      serde_json::json!({
+        "projects": [{ "name": "proj", "prev": "1.0.0", "version": "1.1.0", "changed": true }],
+        "engagement": "full",
+        "paused": false,
+        "tags": ["proj-v1.1.0"],
+        "changelogs": [],
+        "commit": head
+      })
+    );
+  }
+
+  #[test]
+  fn test_set_force() {
+    let _fixture = fixture();
+    let none = PrefVcs::new(Some(VcsRange::exact(VcsLevel::None)));
+
+    assert!(super::set(none, None, None, "0.9.0", false).is_err());
+    assert_eq!(read_to_string("VERSION").unwrap(), "1.0.0\n");
+    super::set(none, None, None, "0.9.0", true).unwrap();
+    assert_eq!(read_to_string("VERSION").unwrap(), "0.9.0\n");
+  }
+}
@@ -2,31 +2,47 @@
 
 use crate::analyze::AnnotatedMark;
 use crate::either::IterEither2 as E2;
-use crate::errors::{Result, ResultExt};
-use crate::git::{FromTagBuf, Repo, Slice};
-use crate::mark::{FilePicker, LinePicker, Picker, ScanningPicker};
+use crate::errors::{Error, Result, ResultExt};
+use crate::formula::Formula;
+use crate::git::{FetchOpts, FetchTags, FromTagBuf, Repo, Slice};
+use crate::mark::{CustomPicker, FilePicker, LinePicker, Mark, Picker, ScanningPicker};
 use crate::mono::Changelog;
 use crate::output::ProjLine;
-use crate::scan::parts::{deserialize_parts, Part};
+use crate::publish::Publish;
+use crate::scan::parts::{deserialize_parts, IntoPartVec, Part};
+use crate::scan::{JsonScanner, Scanner, Scanners, TomlScanner, XmlScanner, YamlScanner};
+use crate::sizes::{insert_angular, Size};
 use crate::state::{CurrentFiles, CurrentState, FilesRead, OldTags, PickPath, PrevFiles, PrevState, StateRead,
                    StateWrite};
-use crate::template::{construct_changelog_html, extract_old_content, read_template};
-use error_chain::bail;
-use glob::{glob_with, MatchOptions, Pattern};
+use crate::template::{construct_changelog_html, extract_old_content, read_template, section_hash, DateStyle,
+                      ReleaseKind};
+use crate::timing::Timer;
+use crate::vcs::VcsRange;
+use glob::Pattern;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use liquid::ParserBuilder;
-use log::trace;
-use path_slash::PathBufExt as _;
+use log::{info, trace};
+use path_slash::{PathBufExt as _, PathExt as _};
 use regex::{escape, Regex};
 use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Unexpected, Visitor};
 use serde::ser::Serializer;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
-use std::cmp::{Ord, Ordering};
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::iter::once;
+use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+mod builder;
+mod parse;
+
+pub use self::builder::{ConfigBuilder, ProjectBuilder};
+use self::parse::parse_yaml;
 
 pub const CONFIG_FILENAME: &str = ".versio.yaml";
 
@@ -119,7 +135,8 @@ impl<'de> Deserialize<'de> for ProjectId {
 
 pub struct Config<S: StateRead> {
   state: S,
-  file: ConfigFile
+  file: ConfigFile,
+  scanners: Scanners
 }
 
 impl Config<CurrentState> {
@@ -129,7 +146,7 @@ impl Config<CurrentState> {
     let spec = FromTagBuf::new(self.prev_tag().to_string(), true);
     let old_tags = self.state.old_tags().slice_to_prev()?;
     let prev_state = PrevState::new(repo.slice(spec), old_tags);
-    Config::from_state(prev_state)
+    Config::from_state(prev_state)?.with_scanners(self.scanners.clone())
   }
 
   pub fn old_tags(&self) -> &OldTags { self.state.old_tags() }
@@ -138,7 +155,14 @@ impl Config<CurrentState> {
 }
 
 impl<S: StateRead> Config<S> {
-  pub fn new(state: S, file: ConfigFile) -> Config<S> { Config { state, file } }
+  pub fn new(state: S, file: ConfigFile) -> Config<S> { Config { state, file, scanners: Scanners::new() } }
+
+  /// Use the custom scanners for the version locations of this config: each must name a registered scanner.
+  pub fn with_scanners(mut self, scanners: Scanners) -> Result<Config<S>> {
+    self.file.bind_scanners(&scanners)?;
+    self.scanners = scanners;
+    Ok(self)
+  }
 
   pub fn from_state(state: S) -> Result<Config<S>> {
     let file = ConfigFile::from_read(&state)?;
@@ -149,10 +173,22 @@ impl<S: StateRead> Config<S> {
   pub fn state_read(&self) -> &S { &self.state }
   pub fn projects(&self) -> &[Project] { self.file.projects() }
   pub fn get_project(&self, id: &ProjectId) -> Option<&Project> { self.file.get_project(id) }
-  pub fn branch(&self) -> &Option<String> { self.file.branch() }
+  pub fn branch(&self) -> &[String] { self.file.branch() }
+  pub fn off_branch(&self) -> OffBranch { self.file.off_branch() }
 
+  /// Find the project named by `name`: an exact alias is preferred, otherwise `name` must be part of exactly one
+  /// project's name or aliases.
   pub fn find_unique(&self, name: &str) -> Result<&ProjectId> {
-    let mut iter = self.file.projects.iter().filter(|p| p.name.contains(name)).map(|p| p.id());
+    if let Some(p) = self.file.projects.iter().find(|p| p.aliases.iter().any(|a| a == name)) {
+      return Ok(p.id());
+    }
+
+    let mut iter = self
+      .file
+      .projects
+      .iter()
+      .filter(|p| p.name.contains(name) || p.aliases.iter().any(|a| a.contains(name)))
+      .map(|p| p.id());
     let id = iter.next().ok_or_else(|| bad!("No project named {}", name))?;
     if iter.next().is_some() {
       bail!("Multiple projects with name {}", name);
@@ -160,6 +196,27 @@ impl<S: StateRead> Config<S> {
     Ok(id)
   }
 
+  /// Find all projects whose name or alias matches a glob such as `lib-*`. A name without any glob characters
+  /// finds a single project, as `find_unique` does.
+  pub fn find_matching(&self, name: &str) -> Result<Vec<&ProjectId>> {
+    if !name.contains(&['*', '?', '['][..]) {
+      return Ok(vec![self.find_unique(name)?]);
+    }
+
+    let pattern = Pattern::new(name).chain_err(|| format!("Bad name pattern {}", name))?;
+    let ids: Vec<_> = self
+      .file
+      .projects
+      .iter()
+      .filter(|p| pattern.matches(&p.name) || p.aliases.iter().any(|a| pattern.matches(a)))
+      .map(|p| p.id())
+      .collect();
+    if ids.is_empty() {
+      bail!("No project matches {}", name);
+    }
+    Ok(ids)
+  }
+
   pub fn annotate(&self) -> Result<Vec<AnnotatedMark>> {
     self.file.projects.iter().map(|p| p.annotate(&self.state)).collect()
   }
@@ -176,28 +233,50 @@ impl<S: StateRead> Config<S> {
 
 pub struct FsConfig<F: FilesRead> {
   files: F,
-  file: ConfigFile
+  file: Arc<ConfigFile>
 }
 
+/// Configs that were already read from a config file, by its blob ID.
+pub type SeenConfigs = HashMap<String, Arc<ConfigFile>>;
+
 impl<'r> FsConfig<PrevFiles<'r>> {
-  pub fn slice_to(&self, spec: FromTagBuf) -> Result<FsConfig<PrevFiles<'r>>> {
-    FsConfig::from_read(self.files.slice_to(spec)?)
+  /// Slice to another commit. The config is re-read only if the config file there is a blob not in `seen`, or if
+  /// the config also depends on other files.
+  pub fn slice_to(self, spec: FromTagBuf, seen: &mut SeenConfigs) -> Result<FsConfig<PrevFiles<'r>>> {
+    FsConfig::read_seen(self.files.slice_to(spec)?, seen)
   }
 
-  pub fn from_slice(slice: Slice<'r>) -> Result<FsConfig<PrevFiles<'r>>> {
-    FsConfig::from_read(PrevFiles::from_slice(slice)?)
+  pub fn from_slice(slice: Slice<'r>, seen: &mut SeenConfigs) -> Result<FsConfig<PrevFiles<'r>>> {
+    FsConfig::read_seen(PrevFiles::from_slice(slice)?, seen)
+  }
+
+  /// Read the config, or reuse the one already read from the same config blob. Only configs read entirely from
+  /// the config file are kept in `seen`, along with their compiled globs.
+  fn read_seen(files: PrevFiles<'r>, seen: &mut SeenConfigs) -> Result<FsConfig<PrevFiles<'r>>> {
+    let blob = files.blob_id(CONFIG_FILENAME)?;
+    if let Some(file) = blob.as_ref().and_then(|blob| seen.get(blob)) {
+      trace!("Config blob already read, not re-reading.");
+      return Ok(FsConfig { files, file: file.clone() });
+    }
+
+    let fsc = FsConfig::from_read(files)?;
+    if let Some(blob) = blob.filter(|_| fsc.file.is_self_contained()) {
+      seen.insert(blob, fsc.shared_file());
+    }
+    Ok(fsc)
   }
 }
 
 impl<F: FilesRead> FsConfig<F> {
-  pub fn new(files: F, file: ConfigFile) -> FsConfig<F> { FsConfig { files, file } }
+  pub fn new(files: F, file: ConfigFile) -> FsConfig<F> { FsConfig { files, file: Arc::new(file) } }
 
   pub fn from_read(files: F) -> Result<FsConfig<F>> {
     let file = ConfigFile::from_read(&files)?;
     Ok(FsConfig::new(files, file))
   }
 
-  pub fn file(&self) -> &ConfigFile { &self.file }
+  /// The config, shared so that it can be read from other threads.
+  pub fn shared_file(&self) -> Arc<ConfigFile> { self.file.clone() }
 }
 
 #[derive(Deserialize, Debug)]
@@ -206,8 +285,18 @@ pub struct ConfigFile {
   options: Options,
   #[serde(default)]
   projects: Vec<Project>,
+  #[serde(default, deserialize_with = "deser_labels")]
+  include: Vec<String>,
+  #[serde(default, deserialize_with = "deser_one_or_many")]
+  auto: Vec<AutoProjects>,
+  #[serde(default)]
+  defaults: Defaults,
   #[serde(deserialize_with = "deser_sizes", default)]
-  sizes: HashMap<String, Size>
+  sizes: HashMap<String, Size>,
+  #[serde(skip)]
+  has_subs: bool,
+  #[serde(skip)]
+  digest: String
 }
 
 impl Default for ConfigFile {
@@ -216,7 +305,16 @@ impl Default for ConfigFile {
     insert_angular(&mut sizes);
     sizes.insert("*".into(), Size::Fail);
 
-    ConfigFile { options: Default::default(), projects: Default::default(), sizes }
+    ConfigFile {
+      options: Default::default(),
+      projects: Default::default(),
+      include: Default::default(),
+      auto: Default::default(),
+      defaults: Default::default(),
+      sizes,
+      has_subs: false,
+      digest: String::new()
+    }
   }
 }
 
@@ -225,7 +323,18 @@ impl ConfigFile {
     if !read.has_file(CONFIG_FILENAME.as_ref())? {
       return Ok(Default::default());
     }
-    ConfigFile::read(&read.read_file(CONFIG_FILENAME.as_ref())?)?.expand(read)
+    let read = DigestRead::new(read)?;
+    let bad_config = || Error::config(CONFIG_FILENAME);
+    let data = read.read_file(CONFIG_FILENAME.as_ref())?;
+    let mut file: ConfigFile = parse_yaml(&data, CONFIG_FILENAME).chain_err(bad_config)?;
+    file.include_fragments(&read).chain_err(bad_config)?;
+    file.apply_types();
+    file.apply_defaults();
+    file.interpolate().chain_err(bad_config)?;
+    file.validate().chain_err(bad_config)?;
+    let mut file = file.expand(&read).chain_err(bad_config)?;
+    file.digest = read.finish();
+    Ok(file)
   }
 
   pub fn from_dir<P: AsRef<Path>>(p: P) -> Result<ConfigFile> {
@@ -233,36 +342,359 @@ impl ConfigFile {
     ConfigFile::from_read(&files)
   }
 
+  /// Find the custom scanner of each version location that uses one.
+  pub fn bind_scanners(&mut self, scanners: &Scanners) -> Result<()> {
+    for proj in &mut self.projects {
+      if let Some(Location::File(FileLocation { picker: Picker::Custom(picker), .. })) = &mut proj.version {
+        picker.bind(scanners).chain_err(|| format!("Bad version location for project {}.", proj.id))?;
+      }
+    }
+    Ok(())
+  }
+
+  #[cfg(test)]
   fn read(data: &str) -> Result<ConfigFile> {
-    let file: ConfigFile = serde_yaml::from_str(data)?;
+    let mut file: ConfigFile = parse_yaml(data, CONFIG_FILENAME)?;
+    file.apply_types();
     file.validate()?;
     Ok(file)
   }
 
+  fn apply_types(&mut self) {
+    for proj in &mut self.projects {
+      proj.apply_type();
+    }
+  }
+
+  fn apply_defaults(&mut self) {
+    for proj in &mut self.projects {
+      self.defaults.apply(proj);
+    }
+  }
+
+  /// Substitute environment variables into those config values that allow it.
+  fn interpolate(&mut self) -> Result<()> {
+    self.options.prev_tag = interpolate(&self.options.prev_tag)?;
+    self.options.branch = self.options.branch.iter().map(|b| interpolate(b)).collect::<Result<_>>()?;
+    for proj in &mut self.projects {
+      proj.interpolate()?;
+    }
+    Ok(())
+  }
+
+  /// Add the projects from all included fragment files. Project roots in a fragment are relative to the
+  /// fragment's directory.
+  fn include_fragments<R: FilesRead>(&mut self, read: &R) -> Result<()> {
+    for pattern in &self.include {
+      for (dir, path) in find_files(read, pattern)? {
+        let data = read.read_file(&PathBuf::from_slash(&path))?;
+        let fragment: Fragment = parse_yaml(&data, &path)?;
+        for mut proj in fragment.projects {
+          if !dir.is_empty() {
+            proj.root = Some(match proj.root.as_deref() {
+              None | Some(".") => dir.clone(),
+              Some(root) => join_slash(&dir, root)
+            });
+          }
+          self.projects.push(proj);
+        }
+      }
+    }
+    Ok(())
+  }
+
   fn expand<R: FilesRead>(self, read: &R) -> Result<ConfigFile> {
+    let has_subs = self.projects.iter().any(|p| p.subs.is_some());
     let iters: Vec<_> = self.projects.into_iter().map(move |p| p.expand(read)).collect::<Result<_>>()?;
-    let projects = iters.into_iter().flatten().collect();
+    let mut projects: Vec<Project> = iters.into_iter().flatten().collect();
+
+    for auto in &self.auto {
+      for mut proj in auto.discover(read)? {
+        self.defaults.apply(&mut proj);
+        proj.interpolate()?;
+        if projects.iter().any(|p| p.id == proj.id) {
+          bail!("id {} of discovered project {} is duplicated", proj.id, proj.name);
+        }
+        if projects.iter().any(|p| p.name == proj.name) {
+          bail!("name {} of discovered project is duplicated", proj.name);
+        }
+        projects.push(proj);
+      }
+    }
+    check_tag_collisions(&projects)?;
+    for proj in &mut projects {
+      proj.compile_globs()?;
+    }
 
-    Ok(ConfigFile { projects, ..self })
+    Ok(ConfigFile { projects, has_subs, ..self })
   }
 
   pub fn prev_tag(&self) -> &str { self.options.prev_tag() }
   pub fn projects(&self) -> &[Project] { &self.projects }
+
+  /// A hash of everything that this config was resolved from: the config file, the files that it includes or
+  /// discovers projects from, and the environment variables that those name. It's empty for the default config.
+  pub fn digest(&self) -> &str { &self.digest }
+
+  /// True if this config is read entirely from the config file, without includes, auto-discovery, or subs.
+  fn is_self_contained(&self) -> bool { self.include.is_empty() && self.auto.is_empty() && !self.has_subs }
   pub fn get_project(&self, id: &ProjectId) -> Option<&Project> { self.projects.iter().find(|p| p.id() == id) }
   pub fn sizes(&self) -> &HashMap<String, Size> { &self.sizes }
-  pub fn branch(&self) -> &Option<String> { self.options.branch() }
+  pub fn branch(&self) -> &[String] { self.options.branch() }
+  pub fn off_branch(&self) -> OffBranch { self.options.off_branch() }
+  pub fn max_history(&self) -> Option<usize> { self.options.max_history() }
+  pub fn missing_prev(&self, limited: bool) -> MissingPrev { self.options.missing_prev(limited) }
+  pub fn notify(&self) -> &[Notify] { self.options.notify() }
+  pub fn build_info(&self) -> bool { self.options.build_info() }
+  pub fn commits(&self) -> CommitsMode { self.options.commits() }
+  pub fn retire_removed(&self) -> bool { self.options.retire_removed() }
+  pub fn vcs_levels(&self) -> &HashMap<String, CommandVcs> { self.options.vcs_levels() }
+  pub fn fetch(&self) -> FetchOpts { self.options.fetch() }
+  pub fn release_branch(&self) -> Option<&str> { self.options.release_branch() }
+  pub fn maintenance_branch(&self) -> &[String] { self.options.maintenance_branch() }
+  pub fn github_deploy(&self) -> Option<&GithubDeploy> { self.options.github_deploy() }
+  pub fn provider(&self) -> &ProviderConfig { self.options.provider() }
+  pub fn commit_mode(&self) -> CommitMode { self.options.commit_mode() }
+  pub fn skip_ci(&self) -> SkipCi { self.options.skip_ci() }
+
+  /// The message of a release commit that sets the given project versions, filled in from the `commit_message`
+  /// option: `{projects}` lists each project with its version, and `{names}` lists just the names.
+  pub fn commit_message(&self, versions: &[(&str, &str)]) -> Option<String> {
+    let projects = versions.iter().map(|(name, vers)| format!("{} {}", name, vers)).collect::<Vec<_>>().join(", ");
+    let names = versions.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ");
+    self.options.commit_message().map(|msg| msg.replace("{projects}", &projects).replace("{names}", &names))
+  }
+
+  /// True if commits by this author email are ignored: they neither size projects nor appear in changelogs.
+  pub fn is_bot_author(&self, email: &str) -> bool {
+    self.options.bot_authors().iter().any(|p| branch_matches(p, email).unwrap_or(false))
+  }
+
+  /// True if a commit type has a configured size, either globally or for some project, or is a security type. A
+  /// `*` catch-all doesn't count.
+  pub fn knows_kind(&self, kind: &str) -> bool {
+    let kind = kind.trim();
+    kind != "*"
+      && (self.sizes.contains_key(kind)
+        || self.projects.iter().any(|p| p.sizes.contains_key(kind))
+        || self.is_security_kind(kind))
+  }
+
+  /// True if a commit type marks a security fix, which is always at least a patch.
+  pub fn is_security_kind(&self, kind: &str) -> bool { self.options.security_kinds().iter().any(|k| k == kind.trim()) }
+
+  /// True if any of a PR's labels marks it as a security fix.
+  pub fn has_security_label(&self, labels: &[String]) -> bool {
+    labels.iter().any(|l| self.options.security_labels().contains(l))
+  }
 
   pub fn hooks(&self) -> HashMap<ProjectId, (Option<&String>, &HookSet)> {
     self.projects.iter().map(|p| (p.id().clone(), (p.root(), p.hooks()))).collect()
   }
 
+  /// Find the projects that a file is attributed to: of all the projects that cover it, only those with the
+  /// highest priority.
+  pub fn covering(&self, path: &str) -> Result<Vec<&Project>> {
+    let mut covering = Vec::new();
+    for proj in &self.projects {
+      if proj.does_cover(path)? {
+        covering.push(proj);
+      }
+    }
+    let top = covering.iter().map(|p| p.priority()).max().unwrap_or(0);
+    covering.retain(|p| p.priority() == top);
+    Ok(covering)
+  }
+
+  /// Group files by the projects that they're attributed to, in the order of the projects, as in `covering`. A
+  /// file can be in more than one group; files that no project covers, and projects with no files, are left out.
+  pub fn files_by_project<I: IntoIterator<Item = String>>(&self, files: I) -> Result<Vec<(ProjectId, Vec<String>)>> {
+    let mut by_project: Vec<_> = self.projects.iter().map(|p| (p.id().clone(), Vec::new())).collect();
+    for file in files {
+      for proj in self.covering(&file)? {
+        if let Some((_, proj_files)) = by_project.iter_mut().find(|(id, _)| id == proj.id()) {
+          proj_files.push(file.clone());
+        }
+      }
+    }
+
+    by_project.retain(|(_, files)| !files.is_empty());
+    for (_, files) in &mut by_project {
+      files.sort();
+      files.dedup();
+    }
+    Ok(by_project)
+  }
+
+  /// The projects that any of the files are attributed to, in config order.
+  pub fn affected<I: IntoIterator<Item = String>>(&self, files: I) -> Result<Vec<ProjectId>> {
+    Ok(self.files_by_project(files)?.into_iter().map(|(id, _)| id).collect())
+  }
+
+  /// The lines of a CODEOWNERS file for the projects that have `owners`: each of a project's includes, rooted at the
+  /// top of the repo, followed by its owners. The last matching line of a CODEOWNERS file wins, so projects are
+  /// written from lowest to highest priority. CODEOWNERS has no way to exclude a path, so excludes and "!" includes
+  /// are left out.
+  pub fn codeowners(&self) -> Vec<String> {
+    let mut projects: Vec<_> = self.projects.iter().filter(|p| !p.umbrella() && !p.owners().is_empty()).collect();
+    projects.sort_by_key(|p| p.priority());
+
+    let mut lines = Vec::new();
+    for proj in projects {
+      lines.push(format!("# {}", proj.name()));
+      let owners = proj.owners().join(" ");
+      for include in proj.rooted_coverage().0.into_iter().filter(|i| !i.starts_with('!')) {
+        lines.push(format!("/{} {}", include.trim_start_matches('/').replace(' ', "\\ "), owners));
+      }
+    }
+    lines
+  }
+
+  /// Find the files that are attributed to more than one project. Each result has the overlapping projects, an
+  /// example file, and the count of files that those projects overlap on.
+  pub fn overlaps<I: IntoIterator<Item = String>>(&self, files: I) -> Result<Vec<(Vec<ProjectId>, String, usize)>> {
+    let mut overlaps: Vec<(Vec<ProjectId>, String, usize)> = Vec::new();
+    for file in files {
+      let ids: Vec<_> = self.covering(&file)?.iter().map(|p| p.id().clone()).collect();
+      if ids.len() < 2 {
+        continue;
+      }
+      match overlaps.iter_mut().find(|(o, ..)| o == &ids) {
+        Some((_, _, count)) => *count += 1,
+        None => overlaps.push((ids, file, 1))
+      }
+    }
+    Ok(overlaps)
+  }
+
+  /// Check that the project dependencies don't form a cycle, and report the cycle's path if they do.
+  pub fn check_cycles(&self) -> Result<()> {
+    let mut done = HashSet::new();
+    for proj in &self.projects {
+      if let Some(cycle) = self.find_cycle(proj.id(), &mut Vec::new(), &mut done) {
+        let path: Vec<_> = cycle
+          .iter()
+          .map(|id| match self.get_project(id) {
+            Some(p) => format!("{} ({})", p.name(), id),
+            None => id.to_string()
+          })
+          .collect();
+        bail!("Dependency cycle: {}", path.join(" -> "));
+      }
+    }
+    Ok(())
+  }
+
+  fn find_cycle(
+    &self, id: &ProjectId, path: &mut Vec<ProjectId>, done: &mut HashSet<ProjectId>
+  ) -> Option<Vec<ProjectId>> {
+    if let Some(start) = path.iter().position(|p| p == id) {
+      let mut cycle = path[start ..].to_vec();
+      cycle.push(id.clone());
+      return Some(cycle);
+    }
+    if done.contains(id) {
+      return None;
+    }
+
+    path.push(id.clone());
+    if let Some(proj) = self.get_project(id) {
+      let mut deps: Vec<_> = proj.depends().keys().collect();
+      deps.sort_by_key(|d| d.to_string());
+      for dep in deps {
+        if let Some(cycle) = self.find_cycle(dep, path, done) {
+          return Some(cycle);
+        }
+      }
+    }
+    path.pop();
+    done.insert(id.clone());
+    None
+  }
+
   /// Check that IDs are unique, etc.
   fn validate(&self) -> Result<()> {
     let mut ids = HashSet::new();
     let mut names = HashSet::new();
     let mut prefs = HashSet::new();
 
+    for branch in self.options.branch().iter().chain(self.options.maintenance_branch()) {
+      branch_matches(branch, "").chain_err(|| format!("illegal branch pattern \"{}\"", branch))?;
+    }
+
+    for author in self.options.bot_authors() {
+      branch_matches(author, "").chain_err(|| format!("illegal bot_authors pattern \"{}\"", author))?;
+    }
+
+    if self.options.missing_prev == Some(MissingPrev::Limit) && self.options.max_history().is_none() {
+      bail!("missing_prev \"limit\" requires a max_history");
+    }
+
+    for (command, levels) in &self.options.vcs_levels {
+      let illegal = || format!("illegal vcs_levels for command {}", command);
+      let preferred = levels.preferred().chain_err(illegal)?;
+      let required = levels.required().chain_err(illegal)?;
+      if preferred.iter().chain(required.iter()).any(|range| range.is_empty()) {
+        bail!("empty vcs_levels range for command {}", command);
+      }
+    }
+
+    if let Some(pattern) = &self.options.release_branch {
+      if !pattern.contains("{major}") {
+        bail!("release_branch \"{}\" has no {{major}}", pattern);
+      }
+    }
+
+    if let ProviderConfig::File { path } = &self.options.provider {
+      if path.is_empty() {
+        bail!("provider file has an empty path");
+      }
+    }
+
+    if let Some(deploy) = &self.options.github_deploy {
+      if deploy.environment.is_some() == deploy.event_type.is_some() {
+        bail!("github_deploy needs exactly one of environment or event_type");
+      }
+    }
+
     for p in &self.projects {
+      if p.version.is_none() {
+        bail!("project {} has no version or type", p.id);
+      }
+      for rule in &p.restrictions {
+        rule.validate().chain_err(|| format!("illegal restriction \"{}\" in project {}", rule.name, p.id))?;
+      }
+      for rule in &p.path_sizes {
+        rule.validate().chain_err(|| format!("illegal path size in project {}", p.id))?;
+      }
+      if let Some(min_size) = p.min_size {
+        if min_size.is_failure() || min_size < Size::Patch {
+          bail!("illegal min_size \"{}\" in project {}", min_size, p.id);
+        }
+      }
+      if let Some(max_size) = p.max_size {
+        if max_size.is_failure() || max_size < Size::Patch {
+          bail!("illegal max_size \"{}\" in project {}", max_size, p.id);
+        }
+        if p.min_size.map(|min_size| min_size > max_size).unwrap_or(false) {
+          bail!("min_size is larger than max_size in project {}", p.id);
+        }
+      }
+      if p.first_release() == FirstRelease::Explicit && p.tag_format().is_none() {
+        bail!("first_release \"explicit\" needs a tag_prefix or tag_format in project {}", p.id);
+      }
+      if !p.alias_tags.is_empty() && p.tag_format().is_none() {
+        bail!("alias_tags needs a tag_prefix or tag_format in project {}", p.id);
+      }
+      if p.floating_tags && p.tag_format().is_none() {
+        bail!("floating_tags needs a tag_prefix or tag_format in project {}", p.id);
+      }
+      if matches!(p.version, Some(Location::Command(_))) && p.tag_format().is_none() {
+        bail!("a version command needs a tag_prefix or tag_format in project {}", p.id);
+      }
+      if let Some(owner) = p.owners.iter().find(|o| !o.contains('@') || o.contains(char::is_whitespace)) {
+        bail!("bad owner \"{}\" in project {}: use @user, @org/team, or an email", owner, p.id);
+      }
       if ids.contains(&p.id) {
         bail!("id {} is duplicated", p.id);
       }
@@ -284,1277 +716,3679 @@ impl ConfigFile {
       }
     }
 
+    check_tag_collisions(&self.projects)?;
+
+    let mut aliases = HashSet::new();
+    for alias in self.projects.iter().flat_map(|p| p.aliases.iter()) {
+      if names.contains(alias) || aliases.contains(alias) {
+        bail!("alias {} is duplicated", alias);
+      }
+      aliases.insert(alias.clone());
+    }
+
     Ok(())
   }
 }
 
+/// Settings that every project inherits, unless the project sets them itself.
+#[derive(Deserialize, Debug, Default)]
+struct Defaults {
+  changelog: Option<ChangelogConfig>,
+  tag_format: Option<String>,
+  tag_message: Option<String>,
+  first_release: Option<FirstRelease>,
+  #[serde(default)]
+  hooks: HookSet,
+  #[serde(deserialize_with = "deser_sizes", default)]
+  sizes: HashMap<String, Size>
+}
+
+impl Defaults {
+  fn apply(&self, proj: &mut Project) {
+    if let Some(changelog) = &self.changelog {
+      match &mut proj.changelog {
+        Some(proj_changelog) => {
+          if proj_changelog.template.is_none() {
+            proj_changelog.template = changelog.template.clone();
+          }
+          if proj_changelog.date_format.is_none() {
+            proj_changelog.date_format = changelog.date_format.clone();
+          }
+          if proj_changelog.date_zone.is_none() {
+            proj_changelog.date_zone = changelog.date_zone.clone();
+          }
+        }
+        None => proj.changelog = Some(changelog.clone())
+      }
+    }
+    if proj.tag_format.is_none() {
+      proj.tag_format = self.tag_format.clone();
+    }
+    if proj.tag_message.is_none() {
+      proj.tag_message = self.tag_message.clone();
+    }
+    if proj.first_release.is_none() {
+      proj.first_release = self.first_release;
+    }
+    for (which, hook) in &self.hooks.hooks {
+      proj.hooks.hooks.entry(which.clone()).or_insert_with(|| hook.clone());
+    }
+    for (kind, size) in &self.sizes {
+      proj.sizes.entry(kind.clone()).or_insert(*size);
+    }
+  }
+}
+
 #[derive(Deserialize, Debug)]
 struct Options {
   #[serde(default = "default_prev_tag")]
   prev_tag: String,
-  #[serde(default = "default_branch")]
-  branch: Option<String>
+  #[serde(default, deserialize_with = "deser_labels")]
+  branch: Vec<String>,
+  #[serde(default)]
+  off_branch: OffBranch,
+  max_history: Option<usize>,
+  missing_prev: Option<MissingPrev>,
+  #[serde(default)]
+  notify: Vec<Notify>,
+  #[serde(default)]
+  build_info: bool,
+  #[serde(default)]
+  commits: CommitsMode,
+  #[serde(default)]
+  retire_removed: bool,
+  #[serde(default)]
+  vcs_levels: HashMap<String, CommandVcs>,
+  #[serde(default)]
+  fetch_tags: FetchTags,
+  #[serde(default)]
+  fetch_refspecs: Vec<String>,
+  #[serde(default = "default_security", deserialize_with = "deser_labels")]
+  security_kinds: Vec<String>,
+  #[serde(default = "default_security", deserialize_with = "deser_labels")]
+  security_labels: Vec<String>,
+  #[serde(default)]
+  release_branch: Option<String>,
+  #[serde(default, deserialize_with = "deser_labels")]
+  maintenance_branch: Vec<String>,
+  #[serde(default)]
+  github_deploy: Option<GithubDeploy>,
+  #[serde(default)]
+  provider: ProviderConfig,
+  #[serde(default, deserialize_with = "deser_labels")]
+  bot_authors: Vec<String>,
+  #[serde(default)]
+  commit_message: Option<String>,
+  #[serde(default)]
+  commit_mode: CommitMode,
+  #[serde(default)]
+  skip_ci: SkipCi
 }
 
 impl Default for Options {
-  fn default() -> Options { Options { prev_tag: default_prev_tag(), branch: default_branch() } }
+  fn default() -> Options {
+    Options {
+      prev_tag: default_prev_tag(),
+      branch: Vec::new(),
+      off_branch: Default::default(),
+      max_history: None,
+      missing_prev: None,
+      notify: Vec::new(),
+      build_info: false,
+      commits: Default::default(),
+      retire_removed: false,
+      vcs_levels: HashMap::new(),
+      fetch_tags: Default::default(),
+      fetch_refspecs: Vec::new(),
+      security_kinds: default_security(),
+      security_labels: default_security(),
+      release_branch: None,
+      maintenance_branch: Vec::new(),
+      github_deploy: None,
+      provider: Default::default(),
+      bot_authors: Vec::new(),
+      commit_message: None,
+      commit_mode: Default::default(),
+      skip_ci: Default::default()
+    }
+  }
 }
 
 impl Options {
   pub fn prev_tag(&self) -> &str { &self.prev_tag }
-  pub fn branch(&self) -> &Option<String> { &self.branch }
+  pub fn branch(&self) -> &[String] { &self.branch }
+  pub fn off_branch(&self) -> OffBranch { self.off_branch }
+  pub fn notify(&self) -> &[Notify] { &self.notify }
+  pub fn build_info(&self) -> bool { self.build_info }
+  pub fn commits(&self) -> CommitsMode { self.commits }
+  pub fn retire_removed(&self) -> bool { self.retire_removed }
+  pub fn vcs_levels(&self) -> &HashMap<String, CommandVcs> { &self.vcs_levels }
+  pub fn fetch(&self) -> FetchOpts { FetchOpts::new(self.fetch_tags, self.fetch_refspecs.clone()) }
+  pub fn security_kinds(&self) -> &[String] { &self.security_kinds }
+  pub fn security_labels(&self) -> &[String] { &self.security_labels }
+  pub fn release_branch(&self) -> Option<&str> { self.release_branch.as_deref() }
+  pub fn maintenance_branch(&self) -> &[String] { &self.maintenance_branch }
+  pub fn github_deploy(&self) -> Option<&GithubDeploy> { self.github_deploy.as_ref() }
+  pub fn provider(&self) -> &ProviderConfig { &self.provider }
+  pub fn bot_authors(&self) -> &[String] { &self.bot_authors }
+  pub fn commit_message(&self) -> Option<&str> { self.commit_message.as_deref() }
+  pub fn commit_mode(&self) -> CommitMode { self.commit_mode }
+  pub fn skip_ci(&self) -> SkipCi { self.skip_ci }
+
+  /// The maximum history to scan.
+  pub fn max_history(&self) -> Option<usize> { self.max_history }
+
+  /// What to do if the prev tag is missing: by default, limit the history if it's `limited` by a maximum, or else
+  /// scan all history.
+  pub fn missing_prev(&self, limited: bool) -> MissingPrev {
+    match self.missing_prev {
+      Some(missing) => missing,
+      None if limited => MissingPrev::Limit,
+      None => MissingPrev::Full
+    }
+  }
 }
 
-fn legal_tag(prefix: &str) -> bool {
-  prefix.is_empty()
-    || ((prefix.starts_with('_') || prefix.chars().next().unwrap().is_alphabetic())
-      && (prefix.chars().all(|c| c.is_ascii() && (c == '_' || c == '-' || c.is_alphanumeric()))))
+/// The VCS levels that a command prefers and requires, which replace the levels built into the command. Each is a
+/// single level ("local") or a range ("none-local").
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct CommandVcs {
+  preferred: Option<String>,
+  required: Option<String>
 }
 
-#[derive(Deserialize, Debug)]
-pub struct Project {
-  name: String,
-  id: ProjectId,
-  root: Option<String>,
-  #[serde(default = "default_includes")]
-  includes: Vec<String>,
-  #[serde(default)]
-  excludes: Vec<String>,
-  #[serde(default)]
-  depends: HashMap<ProjectId, Depends>,
-  changelog: Option<ChangelogConfig>,
-  version: Location,
-  #[serde(default)]
-  also: Vec<Location>,
-  #[serde(default, deserialize_with = "deser_labels")]
-  labels: Vec<String>,
-  tag_prefix: Option<String>,
-  #[serde(default)]
-  subs: Option<Subs>,
-  #[serde(default)]
-  hooks: HookSet
+impl CommandVcs {
+  pub fn preferred(&self) -> Result<Option<VcsRange>> { self.preferred.as_deref().map(str::parse).transpose() }
+  pub fn required(&self) -> Result<Option<VcsRange>> { self.required.as_deref().map(str::parse).transpose() }
 }
 
-impl Project {
-  pub fn id(&self) -> &ProjectId { &self.id }
-  pub fn name(&self) -> &str { &self.name }
-  pub fn depends(&self) -> &HashMap<ProjectId, Depends> { &self.depends }
-  pub fn root(&self) -> Option<&String> { self.root.as_ref().and_then(|r| if r == "." { None } else { Some(r) }) }
-  pub fn hooks(&self) -> &HookSet { &self.hooks }
-  pub fn labels(&self) -> &[String] { &self.labels }
+/// A webhook that is told about each release.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Notify {
+  url: String,
+  #[serde(default)]
+  format: NotifyFormat,
+  template: Option<String>
+}
 
-  fn annotate<S: StateRead>(&self, state: &S) -> Result<AnnotatedMark> {
-    Ok(AnnotatedMark::new(self.id.clone(), self.name.clone(), self.get_value(state)?))
-  }
+impl Notify {
+  /// The webhook URL, with any environment variables filled in: webhook URLs are usually secret.
+  pub fn url(&self) -> Result<String> { interpolate(&self.url) }
+  pub fn format(&self) -> NotifyFormat { self.format }
+  pub fn template(&self) -> Option<&str> { self.template.as_deref() }
+}
 
-  pub fn verify_restrictions(&self, vers: &str) -> Result<()> {
-    let major = Size::parts(vers)?[0];
-    if let Some(tag_majors) = self.tag_majors() {
-      if !tag_majors.contains(&major) {
-        bail!("Illegal version {} for restricted project \"{}\" with majors {:?}.", vers, self.id, tag_majors);
-      }
-    }
-    Ok(())
-  }
+/// How each released project is handed to GitHub, to start its deploy workflows: either a deployment in an
+/// `environment`, or a `repository_dispatch` event of an `event_type`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct GithubDeploy {
+  environment: Option<String>,
+  event_type: Option<String>
+}
 
-  pub fn changelog(&self) -> Option<(Cow<str>, &str)> {
-    self.changelog.as_ref().map(|changelog| {
-      if let Some(root) = self.root() {
-        (
-          Cow::Owned(PathBuf::from_slash(root).join(PathBuf::from_slash(changelog.file())).to_slash_lossy()),
-          changelog.template()
-        )
-      } else {
-        (Cow::Borrowed(changelog.file()), changelog.template())
-      }
-    })
-  }
+impl GithubDeploy {
+  pub fn environment(&self) -> Option<&str> { self.environment.as_deref() }
+  pub fn event_type(&self) -> Option<&str> { self.event_type.as_deref() }
+}
 
-  pub fn tag_prefix(&self) -> &Option<String> { &self.tag_prefix }
-  pub fn tag_majors(&self) -> Option<&[u32]> { self.version.tag_majors() }
+/// Where the PRs of each commit are found: the repository's GitHub remote (the default), or a JSON `file` of PRs,
+/// which lets PR-aware changelogs be tested without the network.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProviderConfig {
+  Github,
+  File { path: String }
+}
 
-  pub async fn write_changelog(
-    &self, write: &mut StateWrite, cl: &Changelog, new_vers: &str
-  ) -> Result<Option<PathBuf>> {
-    if cl.is_empty() {
-      return Ok(None);
-    }
+impl Default for ProviderConfig {
+  fn default() -> ProviderConfig { ProviderConfig::Github }
+}
 
-    if let Some((log_path, template)) = self.changelog().as_ref() {
-      let log_path = PathBuf::from_slash(log_path.as_ref());
-      let old_content = extract_old_content(&log_path)?;
-      let tmpl = read_template(template, self.root().map(PathBuf::from_slash).as_deref(), true).await?;
-      write.write_file(
-        log_path.clone(),
-        construct_changelog_html(cl, ProjLine::from_version(self, new_vers.to_string())?, new_vers, old_content, tmpl)?,
-        self.id(),
-        true
-      )?;
-      Ok(Some(log_path))
-    } else {
-      Ok(None)
-    }
-  }
+/// The shape of the JSON body that is posted to a webhook.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyFormat {
+  Generic,
+  Slack,
+  Teams
+}
 
-  pub fn size(&self, parent_sizes: &HashMap<String, Size>, kind: &str) -> Result<Size> {
-    let kind = kind.trim();
-    parent_sizes
-      .get(kind)
-      .copied()
-      .map(Ok)
-      .unwrap_or_else(|| parent_sizes.get("*").copied().map(Ok).unwrap_or_else(|| err!("Unknown kind \"{}\".", kind)))
-  }
+impl Default for NotifyFormat {
+  fn default() -> NotifyFormat { NotifyFormat::Generic }
+}
 
-  pub fn does_cover(&self, path: &str) -> Result<bool> {
-    let excludes = self.excludes.iter().try_fold::<_, _, Result<_>>(false, |val, cov| {
-      Ok(
-        val || {
-          let rooted = self.rooted_pattern(cov);
-          let result = Pattern::new(&rooted)?.matches_with(path, match_opts());
-          trace!("exclude {} match {} vs {}: {}", self.id(), rooted, path, result);
-          result
-        }
-      )
-    })?;
+/// How to scan history when the prev tag doesn't exist, e.g. on the first run in a repository.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingPrev {
+  Fail,
+  Limit,
+  Full
+}
 
-    if excludes {
-      return Ok(false);
-    }
+/// What `release` should do when run on a branch that doesn't match the `branch` option.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OffBranch {
+  Fail,
+  DryRun,
+  Prerelease
+}
 
-    self.includes.iter().try_fold(false, |val, cov| {
-      Ok(
-        val || {
-          let rooted = self.rooted_pattern(cov);
-          let result = Pattern::new(&rooted)?.matches_with(path, match_opts());
-          trace!("include {} match {} vs {}: {}", self.id(), rooted, path, result);
-          result
-        }
-      )
-    })
-  }
+impl Default for OffBranch {
+  fn default() -> OffBranch { OffBranch::Fail }
+}
 
-  pub fn check<S: StateRead>(&self, state: &S) -> Result<()> {
-    // Check that we can find the given mark.
-    self.get_value(state)?;
+/// How to treat commits whose type isn't configured in any `sizes`: `lenient` sizes them with the `*` catch-all (if
+/// any), while `strict` fails the plan.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommitsMode {
+  Lenient,
+  Strict
+}
 
-    self.check_excludes()?;
+impl Default for CommitsMode {
+  fn default() -> CommitsMode { CommitsMode::Lenient }
+}
 
-    self.check_prefix()?;
+/// How `release` commits its changes: `combined` makes a single release commit, while `per-project` gives each
+/// released project its own commit (and tags it there), so that a single project's release can be reverted alone.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommitMode {
+  Combined,
+  PerProject
+}
 
-    // Check that each pattern includes at least one file.
-    for cov in &self.includes {
-      let pattern = self.rooted_pattern(cov);
-      if !glob_with(&pattern, match_opts())?.any(|_| true) {
-        return err!("No files in proj. {} covered by \"{}\".", self.id, pattern);
-      }
-    }
+impl Default for CommitMode {
+  fn default() -> CommitMode { CommitMode::Combined }
+}
 
-    Ok(())
+/// The marker that tells CI not to run a pipeline for a release commit, so that a release doesn't trigger
+/// another release. `off` leaves the commit message alone.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[allow(clippy::enum_variant_names)]
+pub enum SkipCi {
+  Off,
+  SkipCi,
+  CiSkip
+}
+
+impl Default for SkipCi {
+  fn default() -> SkipCi { SkipCi::Off }
+}
+
+impl FromStr for SkipCi {
+  type Err = crate::errors::Error;
+
+  fn from_str(v: &str) -> Result<SkipCi> {
+    match v {
+      "off" => Ok(SkipCi::Off),
+      "skip-ci" => Ok(SkipCi::SkipCi),
+      "ci-skip" => Ok(SkipCi::CiSkip),
+      other => err!("Illegal skip_ci \"{}\".", other)
+    }
   }
+}
 
-  /// Ensure that we don't have excludes without includes.
-  fn check_excludes(&self) -> Result<()> {
-    if !self.excludes.is_empty() && self.includes.is_empty() {
-      bail!("Proj {} has excludes, but no includes.", self.id);
+impl SkipCi {
+  pub fn marker(self) -> Option<&'static str> {
+    match self {
+      SkipCi::Off => None,
+      SkipCi::SkipCi => Some("[skip ci]"),
+      SkipCi::CiSkip => Some("[ci skip]")
     }
-    Ok(())
   }
 
-  /// Ensure that we don't have a version tag without a tag_prefix.
-  fn check_prefix(&self) -> Result<()> {
-    if self.version.is_tag() && self.tag_prefix.is_none() {
-      bail!("Proj {} has version: tag without tag_prefix.", self.id);
+  /// Add the marker to the end of a commit message, unless it's already there.
+  pub fn mark(self, msg: String) -> String {
+    match self.marker() {
+      Some(marker) if !msg.contains(marker) => format!("{} {}", msg, marker),
+      _ => msg
     }
-    Ok(())
   }
+}
 
-  pub fn get_value<S: StateRead>(&self, read: &S) -> Result<String> {
-    self.version.read_value(read, self.root(), self.id())
+/// What `release` does with a project that has never been released: `adopt` the version already in the tree,
+/// start at `0.1.0` or `1.0.0`, or fail until a first version is given `explicit`ly with `versio set`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirstRelease {
+  #[serde(rename = "adopt")]
+  Adopt,
+  #[serde(rename = "0.1.0")]
+  ZeroOne,
+  #[serde(rename = "1.0.0")]
+  One,
+  #[serde(rename = "explicit")]
+  Explicit
+}
+
+impl Default for FirstRelease {
+  fn default() -> FirstRelease { FirstRelease::Adopt }
+}
+
+impl FirstRelease {
+  /// The version to start at, if this policy starts at a fixed version.
+  pub fn start(&self) -> Option<&'static str> {
+    match self {
+      FirstRelease::ZeroOne => Some("0.1.0"),
+      FirstRelease::One => Some("1.0.0"),
+      FirstRelease::Adopt | FirstRelease::Explicit => None
+    }
   }
+}
 
-  pub fn set_value(&self, write: &mut StateWrite, vers: &str) -> Result<()> {
-    self.version.write_value(write, self.root(), vers, &self.id)?;
-    self.set_also(write, vers)?;
-    self.forward_tag(write, vers)
+/// Check if a branch name matches a `branch` option pattern: a regex if surrounded by slashes, or else a glob.
+pub fn branch_matches(pattern: &str, branch: &str) -> Result<bool> {
+  if pattern.len() > 1 && pattern.starts_with('/') && pattern.ends_with('/') {
+    Ok(Regex::new(&format!("^(?:{})$", &pattern[1 .. pattern.len() - 1]))?.is_match(branch))
+  } else {
+    Ok(Pattern::new(pattern)?.matches(branch))
   }
+}
 
-  fn set_also(&self, write: &mut StateWrite, vers: &str) -> Result<()> {
-    for loc in &self.also {
-      loc.write_value(write, self.root(), vers, &self.id)?;
+/// A config file fragment, holding a single project or a list of them.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct Fragment {
+  #[serde(deserialize_with = "deser_one_or_many")]
+  projects: Vec<Project>
+}
+
+/// A set of projects that are discovered at load time by looking for manifest files.
+#[derive(Deserialize, Debug)]
+struct AutoProjects {
+  pattern: String,
+  #[serde(rename = "type")]
+  kind: ProjectType,
+  #[serde(default)]
+  tag_prefix: bool,
+  #[serde(default, deserialize_with = "deser_labels")]
+  labels: Vec<String>
+}
+
+impl AutoProjects {
+  fn discover<R: FilesRead>(&self, read: &R) -> Result<Vec<Project>> {
+    let mut projects = Vec::new();
+    for (dir, manifest) in find_files(read, &self.pattern)? {
+      let file = &manifest[manifest.rfind('/').map(|i| i + 1).unwrap_or(0) ..];
+      let data = read.read_file(&PathBuf::from_slash(&manifest))?;
+      let name = self.kind.find_name(&data).chain_err(|| format!("Unable to find name in {}.", manifest))?;
+      let name = name.value().to_string();
+      trace!("Discovered {} project {} at {}.", self.kind.label(), name, manifest);
+
+      projects.push(Project {
+        id: ProjectId::from_id(auto_id(&dir)),
+        root: if dir.is_empty() { None } else { Some(dir) },
+        includes: default_includes(),
+        excludes: self.kind.excludes().iter().map(|e| e.to_string()).collect(),
+        depends: HashMap::new(),
+        changelog: None,
+        kind: Some(self.kind),
+        version: Some(self.kind.location(file)),
+        also: Vec::new(),
+        labels: self.labels.clone(),
+        aliases: Vec::new(),
+        owners: Vec::new(),
+        tag_prefix: if self.tag_prefix { Some(tag_sanitize(&name)) } else { None },
+        tag_format: None,
+        tag_message: None,
+        alias_tags: Vec::new(),
+        floating_tags: false,
+        subs: None,
+        hooks: Default::default(),
+        sizes: HashMap::new(),
+        restrictions: Vec::new(),
+        umbrella: false,
+        path_sizes: Vec::new(),
+        priority: 0,
+        min_size: None,
+        max_size: None,
+        first_release: None,
+        publish: None,
+        workspace_sync: false,
+        maven_sync: false,
+        images: Vec::new(),
+        deploys: false,
+        formulas: Vec::new(),
+        globs: None,
+        name
+      });
     }
-    Ok(())
+
+    Ok(projects)
   }
+}
 
-  pub fn forward_tag(&self, write: &mut StateWrite, vers: &str) -> Result<()> {
-    if let Some(full_tag) = self.full_version(vers) {
-      write.tag_head_or_last(vers, full_tag, &self.id)?;
+/// A common kind of project, which knows where its manifest keeps the project's name and version.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ProjectType {
+  Npm,
+  Cargo,
+  Maven,
+  Python,
+  Helm,
+  Gradle
+}
+
+impl ProjectType {
+  fn label(&self) -> &'static str {
+    match self {
+      ProjectType::Npm => "npm",
+      ProjectType::Cargo => "cargo",
+      ProjectType::Maven => "maven",
+      ProjectType::Python => "python",
+      ProjectType::Helm => "helm",
+      ProjectType::Gradle => "gradle"
     }
-    Ok(())
   }
 
-  pub fn full_version(&self, vers: &str) -> Option<String> {
-    self.tag_prefix.as_ref().map(|tag_prefix| {
-      if tag_prefix.is_empty() {
-        format!("v{}", vers)
-      } else {
-        format!("{}-v{}", tag_prefix, vers)
-      }
-    })
+  /// The manifest file in the project's root.
+  fn manifest(&self) -> &'static str {
+    match self {
+      ProjectType::Npm => "package.json",
+      ProjectType::Cargo => "Cargo.toml",
+      ProjectType::Maven => "pom.xml",
+      ProjectType::Python => "pyproject.toml",
+      ProjectType::Helm => "Chart.yaml",
+      ProjectType::Gradle => "gradle.properties"
+    }
   }
 
-  fn rooted_pattern(&self, pat: &str) -> String {
-    if let Some(root) = self.root() {
-      if root == "." {
-        pat.to_string()
-      } else {
-        PathBuf::from_slash(root).join(PathBuf::from_slash(pat)).to_slash_lossy()
-      }
-    } else {
-      pat.to_string()
+  /// Build output and caches that shouldn't count as changes to the project.
+  fn excludes(&self) -> &'static [&'static str] {
+    match self {
+      ProjectType::Npm => &["node_modules/**/*"],
+      ProjectType::Cargo | ProjectType::Maven => &["target/**/*"],
+      ProjectType::Python => &["dist/**/*", "build/**/*", "**/__pycache__/**/*", "*.egg-info/**/*"],
+      ProjectType::Helm => &["charts/*.tgz"],
+      ProjectType::Gradle => &["build/**/*", ".gradle/**/*"]
     }
   }
 
-  fn expand<R: FilesRead>(self, read: &R) -> Result<impl Iterator<Item = Project>> {
-    if let Some(subs) = self.read_subs(read)? {
-      Ok(E2::A(subs.into_iter().map(move |sub| Project {
-        name: expand_name(&self.name, &sub),
-        id: self.id.expand(&sub),
-        root: expand_root(self.root(), &sub),
-        includes: self.includes.clone(),
-        excludes: expand_excludes(&self.excludes, &sub),
-        depends: expand_depends(&self.depends, &sub),
-        changelog: self.changelog.clone(),
-        version: expand_version(&self.version, &sub),
-        also: expand_also(&self.also),
-        labels: Default::default(),
-        tag_prefix: self.tag_prefix.clone(),
-        subs: None,
-        hooks: self.hooks.clone()
-      })))
-    } else {
-      Ok(E2::B(once(self)))
+  fn find_name(&self, data: &str) -> Result<Mark> {
+    match self {
+      ProjectType::Npm => JsonScanner::new("name").find(data),
+      ProjectType::Cargo => TomlScanner::new("package.name").find(data),
+      ProjectType::Maven => XmlScanner::new("project.artifactId").find(data),
+      ProjectType::Python => TomlScanner::new("project.name").find(data),
+      ProjectType::Helm => YamlScanner::new("name").find(data),
+      ProjectType::Gradle => bail!("gradle.properties doesn't name its project.")
     }
   }
 
-  fn read_subs<R: FilesRead>(&self, read: &R) -> Result<Option<Vec<SubExtent>>> {
-    if let Some(subs) = &self.subs {
-      let pattern = format!("^{}$", escape(subs.dirs()).replace("<>", "([0-9]+)"));
-      let dirs = read.subdirs(self.root(), &pattern)?;
-      let regex = Regex::new(&pattern)?;
-      let extents: Vec<_> = dirs
-        .iter()
-        .cloned()
-        .map(|dir| {
-          let caps = regex.captures(&dir).ok_or_else(|| bad!("Unable to capture major from {}", dir))?;
-          let major: u32 = caps[1].parse().chain_err(|| format!("Can't parse dir {} as major.", dir))?;
-          Ok((dir, major))
-        })
-        .collect::<Result<_>>()?;
-      let largest = extents.iter().map(|(_, m)| *m).max();
-      let excludes = dirs.iter().map(|d| format!("{}/**/*", d)).collect();
-      let majors = subs.tops().to_vec();
-
-      let list = once(SubExtent { dir: None, majors, largest: dirs.is_empty(), excludes })
-        .chain(extents.into_iter().map(|(dir, major)| SubExtent {
-          dir: Some(dir),
-          majors: vec![major],
-          largest: major == *largest.as_ref().unwrap(),
-          excludes: Vec::new()
-        }))
-        .collect::<Vec<_>>();
-
-      Ok(Some(list))
-    } else {
-      Ok(None)
-    }
+  fn location(&self, file: &str) -> Location {
+    let picker = match self {
+      ProjectType::Npm => Picker::Json(ScanningPicker::new("version".into_part_vec())),
+      ProjectType::Cargo => Picker::Toml(ScanningPicker::new("package.version".into_part_vec())),
+      ProjectType::Maven => Picker::Xml(ScanningPicker::new("project.version".into_part_vec())),
+      ProjectType::Python => Picker::Toml(ScanningPicker::new("project.version".into_part_vec())),
+      ProjectType::Helm => Picker::Yaml(ScanningPicker::new("version".into_part_vec())),
+      ProjectType::Gradle => Picker::Line(LinePicker::new(r"(?m)^\s*version\s*=\s*(\S+)".to_string()))
+    };
+    Location::File(FileLocation { file: file.to_string(), picker, format: None })
   }
 }
 
-#[derive(Clone, Debug)]
-pub struct ChangelogConfig {
-  file: String,
-  template: String
+/// Derive a stable project ID from the project's directory, so that discovered projects keep their ID as other
+/// projects come and go. The high bit is always set, to stay clear of hand-assigned IDs.
+fn auto_id(dir: &str) -> u32 {
+  // 32-bit FNV-1a: unlike the std hasher, this is guaranteed not to change between builds.
+  let hash = dir.bytes().fold(0x811c_9dc5u32, |hash, b| (hash ^ b as u32).wrapping_mul(0x0100_0193));
+  hash | 0x8000_0000
 }
 
-impl ChangelogConfig {
-  pub fn from_file(file: String) -> ChangelogConfig { ChangelogConfig { file, template: default_changelog_template() } }
+/// Find all files that match a forward-slash pattern, whose directory parts may contain `*` or `?` wildcards.
+/// Returns the directory and full path of each file found, sorted by path.
+fn find_files<R: FilesRead>(read: &R, pattern: &str) -> Result<Vec<(String, String)>> {
+  let (file, dirs) = match pattern.rsplit_once('/') {
+    Some((dirs, file)) => (file, dirs.split('/').collect()),
+    None => (pattern, Vec::new())
+  };
+
+  let mut found = vec![String::new()];
+  for seg in dirs {
+    let mut next = Vec::new();
+    for dir in &found {
+      if seg.contains(|c| c == '*' || c == '?') {
+        let root = if dir.is_empty() { None } else { Some(dir.clone()) };
+        let regex = format!("^{}$", escape(seg).replace("\\*", "[^/]*").replace("\\?", "[^/]"));
+        // Not every match is a directory, so failing to read a match just means nothing is found there.
+        let subs = read.subdirs(root.as_ref(), &regex).unwrap_or_default();
+        next.extend(subs.into_iter().map(|sub| join_slash(dir, &sub)));
+      } else {
+        next.push(join_slash(dir, seg));
+      }
+    }
+    found = next;
+  }
 
-  pub fn file(&self) -> &str { &self.file }
-  pub fn template(&self) -> &str { &self.template }
+  found.sort();
+  let mut files = Vec::new();
+  for dir in found {
+    let path = join_slash(&dir, file);
+    if read.has_file(&PathBuf::from_slash(&path))? {
+      files.push((dir, path));
+    }
+  }
+  Ok(files)
 }
 
-fn default_changelog_template() -> String { "builtin:html".to_string() }
-
-impl<'de> Deserialize<'de> for ChangelogConfig {
-  fn deserialize<D: Deserializer<'de>>(desr: D) -> std::result::Result<ChangelogConfig, D::Error> {
-    struct TheVisitor;
-    type T = ChangelogConfig;
-    type R<T, E> = std::result::Result<T, E>;
-
-    impl<'de> Visitor<'de> for TheVisitor {
-      type Value = T;
-
-      fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "a changelog config") }
-
-      fn visit_str<E: de::Error>(self, v: &str) -> R<T, E> { Ok(ChangelogConfig::from_file(v.to_string())) }
-      fn visit_string<E: de::Error>(self, v: String) -> R<T, E> { Ok(ChangelogConfig::from_file(v)) }
-
-      fn visit_map<M: MapAccess<'de>>(self, map: M) -> R<T, M::Error> {
-        #[derive(Deserialize)]
-        struct InnerConfig {
-          file: String,
-          #[serde(default = "default_changelog_template")]
-          template: String
-        }
+fn join_slash(dir: &str, name: &str) -> String {
+  if dir.is_empty() {
+    name.to_string()
+  } else {
+    format!("{}/{}", dir, name)
+  }
+}
 
-        impl InnerConfig {
-          pub fn into_changelog(self) -> ChangelogConfig {
-            ChangelogConfig { file: self.file, template: self.template }
-          }
-        }
+pub fn tag_sanitize(name: &str) -> String {
+  // match the logic of `legal_tag`
+  let mut prefix: String =
+    name.chars().filter(|c| c.is_ascii() && (*c == '_' || *c == '-' || c.is_alphanumeric())).collect();
 
-        let i: InnerConfig = Deserialize::deserialize(de::value::MapAccessDeserializer::new(map))?;
-        Ok(i.into_changelog())
-      }
-    }
+  if prefix.is_empty() {
+    return "_".into();
+  }
 
-    desr.deserialize_any(TheVisitor)
+  let char0 = prefix.chars().next().unwrap();
+  if char0 != '_' && !char0.is_alphabetic() {
+    prefix = format!("_{}", prefix);
   }
-}
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct Depends {
-  #[serde(default)]
-  files: Vec<Location>,
-  #[serde(default = "default_relative_size")]
-  size: RelativeSize
+  prefix
 }
 
-impl Depends {
-  pub fn write_values(
-    &self, write: &mut StateWrite, root: Option<&String>, val: &str, proj_id: &ProjectId
-  ) -> Result<()> {
-    for file in &self.files {
-      file.write_value(write, root, val, proj_id)?;
+/// Replace each `${VAR}` in the value with the environment variable `VAR`. A default can be given as
+/// `${VAR:-default}`, which is used if the variable is unset or empty.
+fn interpolate(val: &str) -> Result<String> {
+  static VARS: OnceLock<Regex> = OnceLock::new();
+  let regex = VARS.get_or_init(|| Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)(:-([^}]*))?\}").unwrap());
+  let mut result = String::new();
+  let mut last = 0;
+  for caps in regex.captures_iter(val) {
+    let whole = caps.get(0).unwrap();
+    result.push_str(&val[last .. whole.start()]);
+    match (std::env::var(&caps[1]).ok().filter(|v| !v.is_empty()), caps.get(3)) {
+      (Some(v), _) => result.push_str(&v),
+      (None, Some(default)) => result.push_str(default.as_str()),
+      (None, None) => bail!("Undefined variable {} in \"{}\".", &caps[1], val)
     }
-    Ok(())
+    last = whole.end();
   }
-
-  pub fn size(&self) -> &RelativeSize { &self.size }
+  result.push_str(&val[last ..]);
+  Ok(result)
 }
 
-fn default_relative_size() -> RelativeSize { RelativeSize::Match }
-
-#[derive(Debug, Clone)]
-pub enum RelativeSize {
-  Match,
-  Exact(Size)
+/// Reads files while a config is resolved, hashing each thing that is read along with its result. Any environment
+/// variables that a file names are hashed too, since they may be interpolated into the config.
+struct DigestRead<'r, R> {
+  read: &'r R,
+  vars: Regex,
+  hash: RefCell<Sha256>
 }
 
-impl<'de> Deserialize<'de> for RelativeSize {
-  fn deserialize<D: Deserializer<'de>>(desr: D) -> std::result::Result<RelativeSize, D::Error> {
-    struct StrVisitor;
+impl<'r, R: FilesRead> DigestRead<'r, R> {
+  fn new(read: &'r R) -> Result<DigestRead<'r, R>> {
+    let vars = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)")?;
+    Ok(DigestRead { read, vars, hash: RefCell::new(Sha256::new()) })
+  }
 
-    type DeResult<E> = std::result::Result<RelativeSize, E>;
+  fn record(&self, parts: &[&str]) {
+    let mut hash = self.hash.borrow_mut();
+    for part in parts {
+      hash.update(part.as_bytes());
+      hash.update(&[0]);
+    }
+  }
 
-    impl<'de> Visitor<'de> for StrVisitor {
-      type Value = RelativeSize;
+  fn finish(self) -> String { format!("{:x}", self.hash.into_inner().finalize()) }
+}
 
-      fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result { formatter.write_str("a relative size") }
+impl<'r, R: FilesRead> FilesRead for DigestRead<'r, R> {
+  fn has_file(&self, path: &Path) -> Result<bool> {
+    let has = self.read.has_file(path)?;
+    self.record(&["has", &path.to_slash_lossy(), if has { "yes" } else { "no" }]);
+    Ok(has)
+  }
 
-      fn visit_str<E: de::Error>(self, v: &str) -> DeResult<E> {
-        match v {
-          "match" => Ok(RelativeSize::Match),
-          "major" => Ok(RelativeSize::Exact(Size::Major)),
-          "minor" => Ok(RelativeSize::Exact(Size::Minor)),
-          "patch" => Ok(RelativeSize::Exact(Size::Patch)),
-          "none" => Ok(RelativeSize::Exact(Size::None)),
-          _ => Err(E::invalid_value(Unexpected::Str(v), &self))
-        }
-      }
+  fn read_file(&self, path: &Path) -> Result<String> {
+    let data = self.read.read_file(path)?;
+    self.record(&["read", &path.to_slash_lossy(), &data]);
+    for caps in self.vars.captures_iter(&data) {
+      let val = std::env::var(&caps[1]).ok();
+      self.record(&["var", &caps[1], val.as_deref().unwrap_or("\0")]);
     }
+    Ok(data)
+  }
 
-    desr.deserialize_str(StrVisitor)
+  fn subdirs(&self, root: Option<&String>, regex: &str) -> Result<Vec<String>> {
+    let subs = self.read.subdirs(root, regex)?;
+    self.record(&["subdirs", root.map(|r| r.as_str()).unwrap_or(""), regex, &subs.join("\0")]);
+    Ok(subs)
   }
+
+  fn working_root(&self) -> Option<&Path> { self.read.working_root() }
 }
 
-impl RelativeSize {
-  pub fn convert(&self, size: Size) -> Size {
-    if size == Size::Empty {
-      Size::Empty
-    } else {
-      match self {
-        Self::Match => size,
-        Self::Exact(s) => *s
+/// Ensure that no two projects would write the same tags, which would cause each project to read the other's
+/// versions. This includes the empty tag prefix, and custom tag formats.
+fn check_tag_collisions(projects: &[Project]) -> Result<()> {
+  let mut tags: HashMap<String, &ProjectId> = HashMap::new();
+  for proj in projects {
+    if let Some(tag) = proj.full_version("{version}") {
+      if let Some(other) = tags.get(&tag) {
+        bail!("tags \"{}\" of project {} collide with project {}", tag, proj.id(), other);
       }
+      tags.insert(tag, proj.id());
     }
   }
+  Ok(())
 }
 
-#[derive(Clone, Debug)]
-pub struct HookSet {
-  hooks: HashMap<String, Hook>
+fn legal_tag(prefix: &str) -> bool {
+  prefix.is_empty()
+    || ((prefix.starts_with('_') || prefix.chars().next().unwrap().is_alphabetic())
+      && (prefix.chars().all(|c| c.is_ascii() && (c == '_' || c == '-' || c.is_alphanumeric()))))
 }
 
-impl Default for HookSet {
-  fn default() -> HookSet { HookSet { hooks: Default::default() } }
+/// A rule that adjusts the size of changes to matching files: either replacing the size, or raising it to a
+/// minimum.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PathSize {
+  path: String,
+  size: Option<Size>,
+  min: Option<Size>
 }
 
-impl HookSet {
-  pub fn execute(&self, which: &str, root: &Option<&String>) -> Result<()> {
-    if let Some(hook) = self.hooks.get(which) {
-      hook.execute(root)?;
+impl PathSize {
+  fn validate(&self) -> Result<()> {
+    if self.size.is_some() == self.min.is_some() {
+      bail!("path size for \"{}\" needs exactly one of size or min", self.path);
     }
-
+    glob(&self.path)?;
     Ok(())
   }
+}
 
-  pub fn execute_post_write(&self, root: &Option<&String>) -> Result<()> { self.execute("post_write", root) }
+/// A named rule that restricts the versions a project can be released at.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Restriction {
+  name: String,
+  max_major: Option<u32>,
+  min: Option<String>,
+  below: Option<String>,
+  #[serde(default, deserialize_with = "deser_labels")]
+  no_prerelease_on: Vec<String>,
+  major_label: Option<String>
 }
 
-impl<'de> Deserialize<'de> for HookSet {
-  fn deserialize<D: Deserializer<'de>>(desr: D) -> std::result::Result<HookSet, D::Error> {
-    Ok(HookSet { hooks: Deserialize::deserialize(desr)? })
+impl Restriction {
+  fn validate(&self) -> Result<()> {
+    for vers in self.min.iter().chain(self.below.iter()) {
+      Size::parts(vers)?;
+    }
+    for branch in &self.no_prerelease_on {
+      branch_matches(branch, "")?;
+    }
+    Ok(())
+  }
+
+  fn verify(&self, vers: &str, prev: Option<&str>, branch: Option<&str>, changelog: &Changelog) -> Result<()> {
+    let major = Size::parts(vers)?[0];
+    if let Some(max_major) = self.max_major {
+      if major > max_major {
+        bail!("major version is greater than {}", max_major);
+      }
+    }
+    if let Some(min) = &self.min {
+      if Size::less_than(vers, min)? {
+        bail!("version is less than {}", min);
+      }
+    }
+    if let Some(below) = &self.below {
+      if !Size::less_than(vers, below)? {
+        bail!("version is not less than {}", below);
+      }
+    }
+    if let Some(branch) = branch {
+      let prerelease = vers.split('+').next().unwrap_or(vers).contains('-');
+      if prerelease && self.no_prerelease_on.iter().any(|p| branch_matches(p, branch).unwrap_or(false)) {
+        bail!("prerelease versions are not allowed on branch \"{}\"", branch);
+      }
+    }
+    if let (Some(label), Some(prev)) = (&self.major_label, prev) {
+      if major > Size::parts(prev)?[0] && !changelog.has_label(label) {
+        bail!("major version increase requires the \"{}\" label", label);
+      }
+    }
+    Ok(())
   }
 }
 
-impl Serialize for HookSet {
-  fn serialize<S: Serializer>(&self, srlr: S) -> std::result::Result<S::Ok, S::Error> { self.hooks.serialize(srlr) }
+#[derive(Deserialize, Debug)]
+pub struct Project {
+  name: String,
+  id: ProjectId,
+  root: Option<String>,
+  #[serde(default = "default_includes")]
+  includes: Vec<String>,
+  #[serde(default)]
+  excludes: Vec<String>,
+  #[serde(default, deserialize_with = "deser_depends")]
+  depends: HashMap<ProjectId, Depends>,
+  changelog: Option<ChangelogConfig>,
+  #[serde(default, rename = "type")]
+  kind: Option<ProjectType>,
+  #[serde(default)]
+  version: Option<Location>,
+  #[serde(default)]
+  also: Vec<Location>,
+  #[serde(default, deserialize_with = "deser_labels")]
+  labels: Vec<String>,
+  #[serde(default, deserialize_with = "deser_labels")]
+  aliases: Vec<String>,
+  #[serde(default, deserialize_with = "deser_labels")]
+  owners: Vec<String>,
+  tag_prefix: Option<String>,
+  tag_format: Option<String>,
+  tag_message: Option<String>,
+  #[serde(default, deserialize_with = "deser_labels")]
+  alias_tags: Vec<String>,
+  #[serde(default)]
+  floating_tags: bool,
+  #[serde(default)]
+  subs: Option<Subs>,
+  #[serde(default)]
+  hooks: HookSet,
+  #[serde(deserialize_with = "deser_sizes", default)]
+  sizes: HashMap<String, Size>,
+  #[serde(default)]
+  restrictions: Vec<Restriction>,
+  #[serde(default)]
+  umbrella: bool,
+  #[serde(default)]
+  path_sizes: Vec<PathSize>,
+  #[serde(default)]
+  priority: i32,
+  #[serde(default)]
+  min_size: Option<Size>,
+  #[serde(default)]
+  max_size: Option<Size>,
+  #[serde(default)]
+  first_release: Option<FirstRelease>,
+  #[serde(default)]
+  publish: Option<Publish>,
+  #[serde(default)]
+  workspace_sync: bool,
+  #[serde(default)]
+  maven_sync: bool,
+  #[serde(default, deserialize_with = "deser_labels")]
+  images: Vec<String>,
+  #[serde(default)]
+  deploys: bool,
+  #[serde(default)]
+  formulas: Vec<Formula>,
+  #[serde(skip)]
+  globs: Option<Globs>
 }
 
-#[derive(Clone, Debug)]
-pub struct Hook {
-  cmd: String
+/// A project's compiled coverage and path size patterns, so that they aren't re-compiled for every file. Each set
+/// matches all of its patterns at once, and reports their indexes in the project's lists.
+#[derive(Debug, Clone)]
+struct Globs {
+  includes: GlobSet,
+  negates: Vec<bool>,
+  excludes: GlobSet,
+  path_sizes: GlobSet
 }
 
-impl Hook {
-  pub fn execute(&self, root: &Option<&String>) -> Result<()> {
-    use std::process::Command;
+impl Project {
+  pub fn id(&self) -> &ProjectId { &self.id }
+  pub fn name(&self) -> &str { &self.name }
+  pub fn depends(&self) -> &HashMap<ProjectId, Depends> { &self.depends }
+  pub fn root(&self) -> Option<&String> { self.root.as_ref().and_then(|r| if r == "." { None } else { Some(r) }) }
+  pub fn hooks(&self) -> &HookSet { &self.hooks }
+  pub fn labels(&self) -> &[String] { &self.labels }
+  pub fn aliases(&self) -> &[String] { &self.aliases }
+  pub fn owners(&self) -> &[String] { &self.owners }
+  pub fn umbrella(&self) -> bool { self.umbrella }
+  pub fn priority(&self) -> i32 { self.priority }
+  pub fn min_size(&self) -> Option<Size> { self.min_size }
+  pub fn max_size(&self) -> Option<Size> { self.max_size }
+  pub fn first_release(&self) -> FirstRelease { self.first_release.unwrap_or_default() }
+  pub fn publish(&self) -> Option<&Publish> { self.publish.as_ref() }
+  pub fn workspace_sync(&self) -> bool { self.workspace_sync }
+  pub fn maven_sync(&self) -> bool { self.maven_sync }
+  pub fn images(&self) -> &[String] { &self.images }
+  pub fn deploys(&self) -> bool { self.deploys }
+  pub fn formulas(&self) -> &[Formula] { &self.formulas }
+
+  /// The environment variables given to commands that run for a change in this project's version.
+  pub fn version_vars(&self, old_vers: Option<&str>, new_vers: &str) -> Vec<(String, String)> {
+    vec![
+      ("VERSIO_PROJECT".into(), self.name.clone()),
+      ("VERSIO_OLD_VERSION".into(), old_vers.unwrap_or("").into()),
+      ("VERSIO_NEW_VERSION".into(), new_vers.into()),
+      ("VERSIO_ROOT".into(), self.root().cloned().unwrap_or_else(|| ".".into())),
+    ]
+  }
 
-    let mut command = Command::new("bash");
-    if let Some(root) = root {
-      command.current_dir(root);
+  fn interpolate(&mut self) -> Result<()> {
+    self.tag_prefix = self.tag_prefix.as_deref().map(interpolate).transpose()?;
+    self.tag_format = self.tag_format.as_deref().map(interpolate).transpose()?;
+    self.tag_message = self.tag_message.as_deref().map(interpolate).transpose()?;
+    self.alias_tags = self.alias_tags.iter().map(|t| interpolate(t)).collect::<Result<_>>()?;
+    if let Some(changelog) = &mut self.changelog {
+      changelog.file = interpolate(&changelog.file)?;
+      changelog.template = changelog.template.as_deref().map(interpolate).transpose()?;
     }
-    let status = command.args(&["-e", "-c", &self.cmd]).status()?;
-    if !status.success() {
-      bail!("Unable to run hook {}.", self.cmd);
-    } else {
-      Ok(())
+    Ok(())
+  }
+
+  fn annotate<S: StateRead>(&self, state: &S) -> Result<AnnotatedMark> {
+    Ok(AnnotatedMark::new(self.id.clone(), self.name.clone(), self.get_value(state)?))
+  }
+
+  pub fn verify_restrictions(
+    &self, vers: &str, prev: Option<&str>, branch: Option<&str>, changelog: &Changelog
+  ) -> Result<()> {
+    let major = Size::parts(vers)?[0];
+    if let Some(tag_majors) = self.tag_majors() {
+      if !tag_majors.contains(&major) {
+        bail!("Illegal version {} for restricted project \"{}\" with majors {:?}.", vers, self.id, tag_majors);
+      }
     }
+    for rule in &self.restrictions {
+      rule.verify(vers, prev, branch, changelog).chain_err(|| {
+        format!("Illegal version {} for project \"{}\": breaks rule \"{}\".", vers, self.id, rule.name)
+      })?;
+    }
+    Ok(())
   }
-}
 
-impl<'de> Deserialize<'de> for Hook {
-  fn deserialize<D: Deserializer<'de>>(desr: D) -> std::result::Result<Hook, D::Error> {
-    Ok(Hook { cmd: Deserialize::deserialize(desr)? })
+  pub fn changelog(&self) -> Option<(Cow<str>, &str)> {
+    self.changelog.as_ref().map(|changelog| {
+      if let Some(root) = self.root() {
+        (
+          Cow::Owned(PathBuf::from_slash(root).join(PathBuf::from_slash(changelog.file())).to_slash_lossy()),
+          changelog.template()
+        )
+      } else {
+        (Cow::Borrowed(changelog.file()), changelog.template())
+      }
+    })
   }
-}
 
-impl Serialize for Hook {
-  fn serialize<S: Serializer>(&self, srlr: S) -> std::result::Result<S::Ok, S::Error> { self.cmd.serialize(srlr) }
-}
+  /// How dates are shown in this project's changelog.
+  pub fn changelog_dates(&self) -> DateStyle {
+    self.changelog.as_ref().map(|changelog| changelog.date_style()).unwrap_or_default()
+  }
 
-fn expand_name(name: &str, sub: &SubExtent) -> String {
-  match sub.dir() {
-    Some(subdir) => format!("{}/{}", name, subdir),
-    None => name.to_string()
+  pub fn tag_prefix(&self) -> &Option<String> { &self.tag_prefix }
+  pub fn tag_majors(&self) -> Option<&[u32]> { self.version.as_ref().and_then(|v| v.tag_majors()) }
+
+  /// Where the project's version is kept: either given as `version`, or the manifest of its `type`.
+  fn location(&self) -> Result<&Location> {
+    self.version.as_ref().ok_or_else(|| bad!("No version or type for project {}.", self.id))
   }
-}
 
-fn expand_root(root: Option<&String>, sub: &SubExtent) -> Option<String> {
-  match root {
-    Some(root) => match sub.dir() {
-      Some(subdir) => Some(PathBuf::from_slash(root).join(subdir).to_slash_lossy()),
-      None => Some(root.clone())
-    },
-    None => sub.dir().clone()
+  fn is_tag_version(&self) -> bool { self.version.as_ref().map(|v| v.is_tag()).unwrap_or(false) }
+
+  /// Fill in the version location and the excludes of a project that gives a `type` instead.
+  fn apply_type(&mut self) {
+    if let Some(kind) = self.kind {
+      if self.version.is_none() {
+        self.version = Some(kind.location(kind.manifest()));
+      }
+      if self.excludes.is_empty() {
+        self.excludes = kind.excludes().iter().map(|e| e.to_string()).collect();
+      }
+    }
   }
-}
 
-fn expand_excludes(excludes: &[String], sub: &SubExtent) -> Vec<String> {
-  let mut result = excludes.to_vec();
-  result.extend_from_slice(sub.excludes());
-  result
-}
+  /// Write this project's changelog for a release to `new_vers`. If `unreleased`, the changes are instead written
+  /// as the changelog's unreleased section, which is replaced by the next write.
+  pub async fn write_changelog(
+    &self, write: &mut StateWrite, cl: &Changelog, new_vers: &str, unreleased: bool,
+    dep_versions: &HashMap<ProjectId, String>
+  ) -> Result<Option<PathBuf>> {
+    if cl.is_empty() {
+      return Ok(None);
+    }
 
-fn expand_depends(depends: &HashMap<ProjectId, Depends>, sub: &SubExtent) -> HashMap<ProjectId, Depends> {
-  if sub.is_largest() {
-    depends.clone()
-  } else {
-    HashMap::new()
+    let kind = if unreleased { ReleaseKind::Unreleased } else { ReleaseKind::Version(new_vers) };
+    self.render_changelog(write, cl, new_vers, kind, dep_versions).await
   }
-}
 
-fn expand_version(version: &Location, sub: &SubExtent) -> Location {
-  if version.is_tags() {
-    Location::Tag(TagLocation { tags: TagSpec::MajorTag(MajorTagSpec { majors: sub.majors().to_vec() }) })
-  } else {
-    version.clone()
+  /// Add a last entry to the changelog of a project that was removed from the config, saying that `vers` is its
+  /// final version. Nothing is written if the changelog itself was removed.
+  pub async fn write_final_changelog(&self, write: &mut StateWrite, vers: &str) -> Result<Option<PathBuf>> {
+    match self.changelog() {
+      Some((log_path, _)) if PathBuf::from_slash(log_path.as_ref()).exists() => {
+        self.render_changelog(write, &Changelog::empty(), vers, ReleaseKind::Final(vers), &HashMap::new()).await
+      }
+      _ => Ok(None)
+    }
   }
-}
 
-fn expand_also(also: &[Location]) -> Vec<Location> { also.iter().filter(|l| !l.is_tags()).cloned().collect() }
+  async fn render_changelog(
+    &self, write: &mut StateWrite, cl: &Changelog, vers: &str, kind: ReleaseKind<'_>,
+    dep_versions: &HashMap<ProjectId, String>
+  ) -> Result<Option<PathBuf>> {
+    if let Some((log_path, template)) = self.changelog().as_ref() {
+      let log_path = PathBuf::from_slash(log_path.as_ref());
 
-struct SubExtent {
-  // TODO: ensure `dir` will always be a single-level relative path (i.e. no slashes)
-  dir: Option<String>,
-  majors: Vec<u32>,
-  largest: bool,
-  excludes: Vec<String>
-}
+      // A changelog that already has this very section (from an earlier, failed run) is kept as it is.
+      if let Some(hash) = section_hash(cl, &kind, dep_versions) {
+        if let Ok(existing) = std::fs::read_to_string(&log_path) {
+          if existing.contains(&hash) {
+            info!("Changelog {} already has release {}: not writing it again.", log_path.display(), vers);
+            write.write_file(log_path.clone(), existing, self.id(), true)?;
+            return Ok(Some(log_path));
+          }
+        }
+      }
 
-impl SubExtent {
-  pub fn dir(&self) -> &Option<String> { &self.dir }
-  pub fn excludes(&self) -> &[String] { &self.excludes }
-  pub fn is_largest(&self) -> bool { self.largest }
-  pub fn majors(&self) -> &[u32] { &self.majors }
-}
+      let old_content = extract_old_content(&log_path)?;
+      let tmpl = read_template(template, self.root().map(PathBuf::from_slash).as_deref(), true).await?;
+      let proj = ProjLine::from_version(self, vers.to_string())?;
+      let dates = self.changelog_dates();
+      let html = construct_changelog_html(cl, proj, kind, old_content, tmpl, dep_versions, &dates)?;
+      write.write_file(log_path.clone(), html, self.id(), true)?;
+      Ok(Some(log_path))
+    } else {
+      Ok(None)
+    }
+  }
 
-#[derive(Clone, Debug)]
-// #[serde(untagged)]
-enum Location {
-  File(FileLocation),
-  Tag(TagLocation)
-}
+  /// Find the size of a commit kind for this project. The project's own sizes override the parent sizes, and
+  /// any specific kind overrides a "*" catch-all.
+  pub fn size(&self, parent_sizes: &HashMap<String, Size>, kind: &str) -> Result<Size> {
+    let kind = kind.trim();
+    self
+      .explicit_size(parent_sizes, kind)
+      .or_else(|| self.sizes.get("*").copied())
+      .or_else(|| parent_sizes.get("*").copied())
+      .ok_or_else(|| bad!("Unknown kind \"{}\".", kind))
+  }
 
-impl Location {
-  pub fn is_tags(&self) -> bool { matches!(self, Location::Tag(_)) }
+  /// Find the size of a commit kind for this project, without falling back to a "*" catch-all.
+  pub fn explicit_size(&self, parent_sizes: &HashMap<String, Size>, kind: &str) -> Option<Size> {
+    let kind = kind.trim();
+    self.sizes.get(kind).or_else(|| parent_sizes.get(kind)).copied()
+  }
 
-  pub fn tag_majors(&self) -> Option<&[u32]> {
-    match self {
-      Location::File(_) => None,
-      Location::Tag(tagl) => tagl.majors()
+  /// Adjust the size of a change to a file by this project's `path_sizes`: the last matching `size` rule replaces
+  /// the size, and then any matching `min` rules raise it.
+  pub fn path_size(&self, path: &str, size: Size) -> Result<Size> {
+    let matched = self.globs()?.path_sizes.matches(slashed(path).as_ref());
+    let rules = || self.path_sizes.iter().enumerate().filter(|(i, _)| matched.contains(i));
+
+    let mut result = size;
+    for (_, rule) in rules() {
+      if let Some(rule_size) = rule.size {
+        result = rule_size;
+      }
+    }
+    for (_, rule) in rules() {
+      if let Some(min) = rule.min {
+        result = std::cmp::max(result, min);
+      }
     }
+    Ok(result)
   }
 
-  pub fn write_value(&self, write: &mut StateWrite, root: Option<&String>, vers: &str, id: &ProjectId) -> Result<()> {
-    match self {
-      Location::File(l) => l.write_value(write, root, vers, id),
-      Location::Tag(_) => Ok(())
+  pub fn does_cover(&self, path: &str) -> Result<bool> {
+    if self.umbrella {
+      return Ok(false);
+    }
+
+    let globs = self.globs()?;
+    let path = slashed(path);
+    let path = path.as_ref();
+
+    if globs.excludes.is_match(path) {
+      trace!("exclude {} matches {}.", self.id(), path);
+      return Ok(false);
     }
+
+    // A "!" include negates an earlier include; the last matching pattern wins.
+    let last = globs.includes.matches(path).into_iter().max();
+    trace!("include {} vs {}: last match {:?}.", self.id(), path, last);
+    Ok(last.map(|i| !globs.negates[i]).unwrap_or(false))
   }
 
-  pub fn read_value<S: StateRead>(&self, read: &S, root: Option<&String>, proj: &ProjectId) -> Result<String> {
-    match self {
-      Location::File(l) => l.read_value(read, root),
-      Location::Tag(l) => Ok(l.read_value(read, proj))
+  /// Compile this project's patterns once, after the project is fully read.
+  fn compile_globs(&mut self) -> Result<()> {
+    self.globs = Some(self.build_globs()?);
+    Ok(())
+  }
+
+  fn globs(&self) -> Result<Cow<Globs>> {
+    match &self.globs {
+      Some(globs) => Ok(Cow::Borrowed(globs)),
+      None => Ok(Cow::Owned(self.build_globs()?))
     }
   }
 
-  pub fn is_tag(&self) -> bool { matches!(self, Location::Tag(..)) }
+  fn build_globs(&self) -> Result<Globs> {
+    let negates = self.includes.iter().map(|cov| cov.starts_with('!')).collect();
+    let includes = self.includes.iter().map(|cov| cov.strip_prefix('!').unwrap_or(cov));
+    let includes = self.glob_set(includes)?;
+    let excludes = self.glob_set(self.excludes.iter().map(|cov| cov.as_str()))?;
+    let path_sizes = self.glob_set(self.path_sizes.iter().map(|rule| rule.path.as_str()))?;
+    Ok(Globs { includes, negates, excludes, path_sizes })
+  }
 
-  #[cfg(test)]
-  pub fn picker(&self) -> &Picker {
-    match self {
-      Location::File(l) => &l.picker,
-      _ => panic!("Not a file location")
+  fn glob_set<'a, I: Iterator<Item = &'a str>>(&self, patterns: I) -> Result<GlobSet> {
+    let mut set = GlobSetBuilder::new();
+    for pat in patterns {
+      set.add(glob(&self.rooted_pattern(pat)).chain_err(|| format!("Bad pattern \"{}\".", pat))?);
     }
+    Ok(set.build()?)
   }
-}
 
-impl<'de> Deserialize<'de> for Location {
-  fn deserialize<D: Deserializer<'de>>(desr: D) -> std::result::Result<Location, D::Error> {
-    struct VecPartSeed;
+  /// Check this project against the state, and against the `files` of the current tree; return any warnings.
+  pub fn check<S: StateRead>(&self, state: &S, files: &[String]) -> Result<Vec<String>> {
+    // Check that we can find the given mark.
+    self.get_value(state)?;
 
-    impl<'de> DeserializeSeed<'de> for VecPartSeed {
-      type Value = Vec<Part>;
-      fn deserialize<D>(self, deslr: D) -> std::result::Result<Self::Value, D::Error>
-      where
-        D: Deserializer<'de>
-      {
-        deserialize_parts(deslr)
+    self.check_excludes()?;
+
+    self.check_prefix()?;
+
+    let mut warnings = Vec::new();
+    if !self.umbrella {
+      if self.includes.iter().all(|cov| cov.starts_with('!')) {
+        warnings.push(format!("Proj. {} doesn't cover any files.", self.id));
+      }
+
+      // Check that each pattern includes at least one file: one that matches nothing is almost always a typo,
+      // which would quietly leave the project out of its bumps.
+      let includes = &self.globs()?.includes;
+      let matched: HashSet<usize> = files.iter().flat_map(|file| includes.matches(file)).collect();
+      for (i, cov) in self.includes.iter().enumerate().filter(|(_, cov)| !cov.starts_with('!')) {
+        if !matched.contains(&i) {
+          warnings.push(format!("No files in proj. {} covered by \"{}\".", self.id, self.rooted_pattern(cov)));
+        }
       }
     }
 
-    struct LocatorVisitor;
+    if let Some(prefix) = &self.tag_prefix {
+      if !self.is_tag_version() && state.latest_tag(&self.id).is_none() {
+        warnings.push(format!("No tags found for tag_prefix \"{}\" of proj. {}.", prefix, self.id));
+      }
+    }
 
-    impl<'de> Visitor<'de> for LocatorVisitor {
-      type Value = Location;
+    Ok(warnings)
+  }
 
-      fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result { formatter.write_str("a version location") }
+  /// Ensure that we don't have excludes without includes.
+  fn check_excludes(&self) -> Result<()> {
+    if !self.excludes.is_empty() && self.includes.is_empty() {
+      bail!("Proj {} has excludes, but no includes.", self.id);
+    }
+    Ok(())
+  }
 
-      fn visit_map<V>(self, mut map: V) -> std::result::Result<Self::Value, V::Error>
-      where
-        V: MapAccess<'de>
-      {
-        let mut file: Option<String> = None;
-        let mut pattern: Option<String> = None;
-        let mut parts: Option<Vec<Part>> = None;
-        let mut tags: Option<TagSpec> = None;
-        let mut code: Option<String> = None;
-        let mut format: Option<String> = None;
+  /// Ensure that we don't have a version tag without a tag_prefix or tag_format, and that a tag_format has a
+  /// version.
+  fn check_prefix(&self) -> Result<()> {
+    if self.is_tag_version() && self.tag_format().is_none() {
+      bail!("Proj {} has version: tag without tag_prefix.", self.id);
+    }
+    if let Some(format) = &self.tag_format {
+      if !format.contains("{version}") {
+        bail!("Proj {} has tag_format \"{}\" without {{version}}.", self.id, format);
+      }
+    }
+    Ok(())
+  }
 
-        while let Some(key) = map.next_key::<String>()? {
-          match key.as_str() {
-            "file" => {
-              file = Some(map.next_value()?);
-            }
-            "tags" => {
-              tags = Some(map.next_value()?);
-            }
-            "json" | "yaml" | "toml" | "xml" => {
-              code = Some(key);
-              parts = Some(map.next_value_seed(VecPartSeed)?);
-            }
-            "pattern" => {
-              pattern = Some(map.next_value()?);
-            }
-            "format" => {
-              format = Some(map.next_value()?);
-            }
-            other => return Err(de::Error::invalid_value(Unexpected::Str(other), &"a location key"))
-          }
+  pub fn get_value<S: StateRead>(&self, read: &S) -> Result<String> {
+    let _timer = Timer::start("file scanning");
+    self.location()?.read_value(read, self.root(), self.id())
+  }
+
+  pub fn set_value(&self, write: &mut StateWrite, vers: &str) -> Result<()> {
+    self.location()?.write_value(write, self.root(), vers, &self.id)?;
+    self.set_also(write, vers)?;
+    self.forward_tag(write, vers)
+  }
+
+  fn set_also(&self, write: &mut StateWrite, vers: &str) -> Result<()> {
+    for loc in &self.also {
+      loc.write_value(write, self.root(), vers, &self.id)?;
+    }
+    Ok(())
+  }
+
+  pub fn forward_tag(&self, write: &mut StateWrite, vers: &str) -> Result<()> {
+    if let Some(full_tag) = self.full_version(vers) {
+      let msg = self.tag_message.as_ref().map(|m| self.fill_tag_format(m, vers));
+      write.tag_head_or_last(vers, full_tag, msg, &self.id)?;
+      for alias in self.alias_tags(vers)? {
+        write.alias_head_or_last(alias, &self.id);
+      }
+    }
+    Ok(())
+  }
+
+  /// The alias tags of a new version, filled in from `alias_tags`, followed by the floating major and minor tags
+  /// (e.g. `v1` and `v1.2` for `v1.2.3`) if `floating_tags` is set. A prerelease doesn't move any alias tags.
+  pub fn alias_tags(&self, vers: &str) -> Result<Vec<String>> {
+    if vers.contains('-') {
+      return Ok(Vec::new());
+    }
+    let floating = match self.tag_format().filter(|_| self.floating_tags) {
+      Some(format) => vec![format.replace("{version}", "{major}"), format.replace("{version}", "{major}.{minor}")],
+      None => Vec::new()
+    };
+    self.alias_tags.iter().chain(floating.iter()).map(|alias| self.fill_version_parts(alias, vers)).collect()
+  }
+
+  /// Schedule a release branch for a new version, named by filling in the `release_branch` pattern.
+  pub fn release_branch(&self, write: &mut StateWrite, pattern: &str, vers: &str) -> Result<()> {
+    let branch = self.fill_version_parts(pattern, vers)?;
+    write.branch_head_or_last(branch, &self.id);
+    Ok(())
+  }
+
+  /// Fill in a tag format that can also have the `{major}`, `{minor}`, and `{patch}` parts of the version.
+  fn fill_version_parts(&self, format: &str, vers: &str) -> Result<String> {
+    let parts = Size::parts(vers)?;
+    let filled = self
+      .fill_tag_format(format, vers)
+      .replace("{major}", &parts[0].to_string())
+      .replace("{minor}", &parts[1].to_string())
+      .replace("{patch}", &parts[2].to_string());
+    Ok(filled)
+  }
+
+  pub fn full_version(&self, vers: &str) -> Option<String> {
+    self.tag_format().map(|format| self.fill_tag_format(&format, vers))
+  }
+
+  /// Extract the version from one of this project's tags, if it matches the tag format.
+  pub fn tag_version(&self, tag: &str) -> Option<String> {
+    let format = self.tag_format()?;
+    let pattern: Vec<_> = format.split("{version}").map(|part| escape(&self.fill_tag_format(part, ""))).collect();
+    let regex = Regex::new(&format!("^{}$", pattern.join("(.+)"))).ok()?;
+    regex.captures(tag).and_then(|caps| caps.get(1)).map(|m| m.as_str().to_string())
+  }
+
+  /// The format of this project's tags: projects without a `tag_format` or `tag_prefix` aren't tagged.
+  fn tag_format(&self) -> Option<Cow<str>> {
+    match (&self.tag_format, &self.tag_prefix) {
+      (Some(format), _) => Some(Cow::Borrowed(format.as_str())),
+      (None, Some(prefix)) if prefix.is_empty() => Some(Cow::Borrowed("v{version}")),
+      (None, Some(_)) => Some(Cow::Borrowed("{prefix}-v{version}")),
+      (None, None) => None
+    }
+  }
+
+  fn fill_tag_format(&self, format: &str, vers: &str) -> String {
+    format
+      .replace("{prefix}", self.tag_prefix.as_deref().unwrap_or(""))
+      .replace("{project}", &self.name)
+      .replace("{version}", vers)
+  }
+
+  /// The project's includes and excludes, each rooted at the top of the repo rather than at the project's root.
+  pub fn rooted_coverage(&self) -> (Vec<String>, Vec<String>) {
+    let includes = self
+      .includes
+      .iter()
+      .map(|cov| match cov.strip_prefix('!') {
+        Some(cov) => format!("!{}", self.rooted_pattern(cov)),
+        None => self.rooted_pattern(cov)
+      })
+      .collect();
+    let excludes = self.excludes.iter().map(|cov| self.rooted_pattern(cov)).collect();
+    (includes, excludes)
+  }
+
+  /// The pattern relative to the repo root. This is joined as a string, not a path, so that the pattern keeps its
+  /// forward slashes on every platform.
+  fn rooted_pattern(&self, pat: &str) -> String {
+    match self.root() {
+      Some(root) => format!("{}/{}", root.trim_end_matches('/'), pat),
+      None => pat.to_string()
+    }
+  }
+
+  fn expand<R: FilesRead>(self, read: &R) -> Result<impl Iterator<Item = Project>> {
+    if let Some(subs) = self.read_subs(read)? {
+      Ok(E2::A(subs.into_iter().map(move |sub| Project {
+        name: expand_name(&self.name, &sub),
+        id: self.id.expand(&sub),
+        root: expand_root(self.root(), &sub),
+        includes: self.includes.clone(),
+        excludes: expand_excludes(&self.excludes, &sub),
+        depends: expand_depends(&self.depends, &sub),
+        changelog: self.changelog.clone(),
+        kind: self.kind,
+        version: self.version.as_ref().map(|v| expand_version(v, &sub)),
+        also: expand_also(&self.also),
+        labels: Default::default(),
+        aliases: Default::default(),
+        owners: self.owners.clone(),
+        tag_prefix: self.tag_prefix.clone(),
+        tag_format: self.tag_format.clone(),
+        tag_message: self.tag_message.clone(),
+        alias_tags: self.alias_tags.clone(),
+        floating_tags: self.floating_tags,
+        subs: None,
+        hooks: self.hooks.clone(),
+        sizes: self.sizes.clone(),
+        restrictions: self.restrictions.clone(),
+        umbrella: self.umbrella,
+        path_sizes: self.path_sizes.clone(),
+        priority: self.priority,
+        min_size: self.min_size,
+        max_size: self.max_size,
+        first_release: self.first_release,
+        publish: self.publish,
+        workspace_sync: self.workspace_sync,
+        maven_sync: self.maven_sync,
+        images: self.images.clone(),
+        deploys: self.deploys,
+        formulas: self.formulas.clone(),
+        globs: None
+      })))
+    } else {
+      Ok(E2::B(once(self)))
+    }
+  }
+
+  fn read_subs<R: FilesRead>(&self, read: &R) -> Result<Option<Vec<SubExtent>>> {
+    if let Some(subs) = &self.subs {
+      let pattern = format!("^{}$", escape(subs.dirs()).replace("<>", "([0-9]+)"));
+      let dirs = read.subdirs(self.root(), &pattern)?;
+      let regex = Regex::new(&pattern)?;
+      let extents: Vec<_> = dirs
+        .iter()
+        .cloned()
+        .map(|dir| {
+          let caps = regex.captures(&dir).ok_or_else(|| bad!("Unable to capture major from {}", dir))?;
+          let major: u32 = caps[1].parse().chain_err(|| format!("Can't parse dir {} as major.", dir))?;
+          Ok((dir, major))
+        })
+        .collect::<Result<_>>()?;
+      let largest = extents.iter().map(|(_, m)| *m).max();
+      let excludes = dirs.iter().map(|d| format!("{}/**/*", d)).collect();
+      let majors = subs.tops().to_vec();
+
+      let list = once(SubExtent { dir: None, majors, largest: dirs.is_empty(), excludes })
+        .chain(extents.into_iter().map(|(dir, major)| SubExtent {
+          dir: Some(dir),
+          majors: vec![major],
+          largest: major == *largest.as_ref().unwrap(),
+          excludes: Vec::new()
+        }))
+        .collect::<Vec<_>>();
+
+      Ok(Some(list))
+    } else {
+      Ok(None)
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct ChangelogConfig {
+  file: String,
+  template: Option<String>,
+  date_format: Option<String>,
+  date_zone: Option<String>
+}
+
+impl ChangelogConfig {
+  pub fn from_file(file: String) -> ChangelogConfig {
+    ChangelogConfig { file, template: None, date_format: None, date_zone: None }
+  }
+
+  pub fn file(&self) -> &str { &self.file }
+  pub fn template(&self) -> &str { self.template.as_deref().unwrap_or("builtin:html") }
+  pub fn date_style(&self) -> DateStyle { DateStyle::new(self.date_format.clone(), self.date_zone.clone()) }
+}
+
+impl<'de> Deserialize<'de> for ChangelogConfig {
+  fn deserialize<D: Deserializer<'de>>(desr: D) -> std::result::Result<ChangelogConfig, D::Error> {
+    struct TheVisitor;
+    type T = ChangelogConfig;
+    type R<T, E> = std::result::Result<T, E>;
+
+    impl<'de> Visitor<'de> for TheVisitor {
+      type Value = T;
+
+      fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "a changelog config") }
+
+      fn visit_str<E: de::Error>(self, v: &str) -> R<T, E> { Ok(ChangelogConfig::from_file(v.to_string())) }
+      fn visit_string<E: de::Error>(self, v: String) -> R<T, E> { Ok(ChangelogConfig::from_file(v)) }
+
+      fn visit_map<M: MapAccess<'de>>(self, map: M) -> R<T, M::Error> {
+        #[derive(Deserialize)]
+        struct InnerConfig {
+          file: String,
+          template: Option<String>,
+          date_format: Option<String>,
+          date_zone: Option<String>
         }
 
-        if let Some(file) = file {
-          if tags.is_some() {
-            Err(de::Error::custom("cant have both 'file' and 'tags' for location"))
-          } else if pattern.is_none() && parts.is_none() {
-            Ok(Location::File(FileLocation { file, format, picker: Picker::File(FilePicker {}) }))
-          } else if let Some(pattern) = pattern {
-            if parts.is_some() {
-              Err(de::Error::custom("can't have both 'pattern' and parts field"))
-            } else {
-              Ok(Location::File(FileLocation { file, format, picker: Picker::Line(LinePicker::new(pattern)) }))
-            }
-          } else {
-            let parts = parts.unwrap();
-            let loc = match code.unwrap().as_str() {
-              "json" => Location::File(FileLocation { file, format, picker: Picker::Json(ScanningPicker::new(parts)) }),
-              "yaml" => Location::File(FileLocation { file, format, picker: Picker::Yaml(ScanningPicker::new(parts)) }),
-              "toml" => Location::File(FileLocation { file, format, picker: Picker::Toml(ScanningPicker::new(parts)) }),
-              "xml" => Location::File(FileLocation { file, format, picker: Picker::Xml(ScanningPicker::new(parts)) }),
-              other => return Err(de::Error::custom(format!("unrecognized part {}", other)))
-            };
-            Ok(loc)
-          }
-        } else if let Some(tags) = tags {
-          if format.is_some() {
-            Err(de::Error::custom("cant have 'format' in 'tags' location"))
-          } else {
-            Ok(Location::Tag(TagLocation { tags }))
+        impl InnerConfig {
+          pub fn into_changelog(self) -> ChangelogConfig {
+            let InnerConfig { file, template, date_format, date_zone } = self;
+            ChangelogConfig { file, template, date_format, date_zone }
           }
-        } else {
-          Err(de::Error::custom("must have 'file' or 'tags' for location"))
         }
+
+        let i: InnerConfig = Deserialize::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        Ok(i.into_changelog())
       }
     }
 
-    desr.deserialize_map(LocatorVisitor)
+    desr.deserialize_any(TheVisitor)
   }
 }
 
-#[derive(Clone, Deserialize, Debug)]
-struct TagLocation {
-  tags: TagSpec
+#[derive(Deserialize, Debug, Clone)]
+pub struct Depends {
+  #[serde(default)]
+  files: Vec<Location>,
+  #[serde(default = "default_relative_size", alias = "bump")]
+  size: RelativeSize
 }
 
-impl TagLocation {
-  pub fn majors(&self) -> Option<&[u32]> { self.tags.majors() }
+/// A dependency listed by id, as an alternative to the map of dependencies.
+#[derive(Deserialize)]
+struct DependsEntry {
+  id: ProjectId,
+  #[serde(default)]
+  files: Vec<Location>,
+  #[serde(default = "default_relative_size", alias = "bump")]
+  size: RelativeSize
+}
 
-  fn read_value<S: StateRead>(&self, read: &S, proj: &ProjectId) -> String {
-    read.latest_tag(proj).cloned().unwrap_or_else(|| self.tags.default_value())
+impl Depends {
+  pub fn write_values(
+    &self, write: &mut StateWrite, root: Option<&String>, val: &str, proj_id: &ProjectId
+  ) -> Result<()> {
+    for file in &self.files {
+      file.write_value(write, root, val, proj_id)?;
+    }
+    Ok(())
   }
+
+  pub fn size(&self) -> &RelativeSize { &self.size }
 }
 
-#[derive(Clone, Deserialize, Debug)]
-#[serde(untagged)]
-enum TagSpec {
-  DefaultTag(DefaultTagSpec),
-  MajorTag(MajorTagSpec)
+fn default_relative_size() -> RelativeSize { RelativeSize::Match }
+
+#[derive(Debug, Clone)]
+pub enum RelativeSize {
+  Match,
+  Exact(Size)
 }
 
-impl TagSpec {
-  pub fn majors(&self) -> Option<&[u32]> {
-    match self {
-      TagSpec::DefaultTag(_) => None,
-      TagSpec::MajorTag(mtag) => Some(mtag.majors())
-    }
-  }
+impl<'de> Deserialize<'de> for RelativeSize {
+  fn deserialize<D: Deserializer<'de>>(desr: D) -> std::result::Result<RelativeSize, D::Error> {
+    struct StrVisitor;
+
+    type DeResult<E> = std::result::Result<RelativeSize, E>;
+
+    impl<'de> Visitor<'de> for StrVisitor {
+      type Value = RelativeSize;
+
+      fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result { formatter.write_str("a relative size") }
+
+      fn visit_str<E: de::Error>(self, v: &str) -> DeResult<E> {
+        match v {
+          "match" => Ok(RelativeSize::Match),
+          "major" => Ok(RelativeSize::Exact(Size::Major)),
+          "minor" => Ok(RelativeSize::Exact(Size::Minor)),
+          "patch" => Ok(RelativeSize::Exact(Size::Patch)),
+          "none" => Ok(RelativeSize::Exact(Size::None)),
+          _ => Err(E::invalid_value(Unexpected::Str(v), &self))
+        }
+      }
+    }
+
+    desr.deserialize_str(StrVisitor)
+  }
+}
+
+impl RelativeSize {
+  pub fn convert(&self, size: Size) -> Size {
+    if size == Size::Empty {
+      Size::Empty
+    } else {
+      match self {
+        Self::Match => size,
+        Self::Exact(s) => *s
+      }
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct HookSet {
+  hooks: HashMap<String, Hook>
+}
+
+impl Default for HookSet {
+  fn default() -> HookSet { HookSet { hooks: Default::default() } }
+}
+
+impl HookSet {
+  pub fn get(&self, which: &str) -> Option<&Hook> { self.hooks.get(which) }
+
+  pub fn execute(&self, which: &str, root: &Option<&String>) -> Result<()> { self.execute_with(which, root, &[]) }
+
+  pub fn execute_with(&self, which: &str, root: &Option<&String>, vars: &[(String, String)]) -> Result<()> {
+    if let Some(hook) = self.hooks.get(which) {
+      hook.execute(root, vars)?;
+    }
+
+    Ok(())
+  }
+
+  pub fn execute_post_write(&self, root: &Option<&String>) -> Result<()> { self.execute("post_write", root) }
+}
+
+impl<'de> Deserialize<'de> for HookSet {
+  fn deserialize<D: Deserializer<'de>>(desr: D) -> std::result::Result<HookSet, D::Error> {
+    Ok(HookSet { hooks: Deserialize::deserialize(desr)? })
+  }
+}
+
+impl Serialize for HookSet {
+  fn serialize<S: Serializer>(&self, srlr: S) -> std::result::Result<S::Ok, S::Error> { self.hooks.serialize(srlr) }
+}
+
+#[derive(Clone, Debug)]
+pub struct Hook {
+  cmd: String
+}
+
+impl Hook {
+  pub fn new(cmd: String) -> Hook { Hook { cmd } }
+
+  pub fn execute(&self, root: &Option<&String>, vars: &[(String, String)]) -> Result<()> {
+    use std::process::Command;
+
+    let mut command = Command::new("bash");
+    if let Some(root) = root {
+      command.current_dir(root);
+    }
+    command.envs(vars.iter().cloned());
+    let status = command.args(&["-e", "-c", &self.cmd]).status()?;
+    if !status.success() {
+      bail!("Unable to run hook {}.", self.cmd);
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for Hook {
+  fn deserialize<D: Deserializer<'de>>(desr: D) -> std::result::Result<Hook, D::Error> {
+    Ok(Hook { cmd: Deserialize::deserialize(desr)? })
+  }
+}
+
+impl Serialize for Hook {
+  fn serialize<S: Serializer>(&self, srlr: S) -> std::result::Result<S::Ok, S::Error> { self.cmd.serialize(srlr) }
+}
+
+fn expand_name(name: &str, sub: &SubExtent) -> String {
+  match sub.dir() {
+    Some(subdir) => format!("{}/{}", name, subdir),
+    None => name.to_string()
+  }
+}
+
+fn expand_root(root: Option<&String>, sub: &SubExtent) -> Option<String> {
+  match root {
+    Some(root) => match sub.dir() {
+      Some(subdir) => Some(PathBuf::from_slash(root).join(subdir).to_slash_lossy()),
+      None => Some(root.clone())
+    },
+    None => sub.dir().clone()
+  }
+}
+
+fn expand_excludes(excludes: &[String], sub: &SubExtent) -> Vec<String> {
+  let mut result = excludes.to_vec();
+  result.extend_from_slice(sub.excludes());
+  result
+}
+
+fn expand_depends(depends: &HashMap<ProjectId, Depends>, sub: &SubExtent) -> HashMap<ProjectId, Depends> {
+  if sub.is_largest() {
+    depends.clone()
+  } else {
+    HashMap::new()
+  }
+}
+
+fn expand_version(version: &Location, sub: &SubExtent) -> Location {
+  if version.is_tags() {
+    Location::Tag(TagLocation { tags: TagSpec::MajorTag(MajorTagSpec { majors: sub.majors().to_vec() }) })
+  } else {
+    version.clone()
+  }
+}
+
+fn expand_also(also: &[Location]) -> Vec<Location> { also.iter().filter(|l| !l.is_tags()).cloned().collect() }
+
+struct SubExtent {
+  // TODO: ensure `dir` will always be a single-level relative path (i.e. no slashes)
+  dir: Option<String>,
+  majors: Vec<u32>,
+  largest: bool,
+  excludes: Vec<String>
+}
+
+impl SubExtent {
+  pub fn dir(&self) -> &Option<String> { &self.dir }
+  pub fn excludes(&self) -> &[String] { &self.excludes }
+  pub fn is_largest(&self) -> bool { self.largest }
+  pub fn majors(&self) -> &[u32] { &self.majors }
+}
+
+#[derive(Clone, Debug)]
+// #[serde(untagged)]
+enum Location {
+  File(FileLocation),
+  Tag(TagLocation),
+  Command(CommandLocation)
+}
+
+impl Location {
+  pub fn is_tags(&self) -> bool { matches!(self, Location::Tag(_)) }
+
+  pub fn tag_majors(&self) -> Option<&[u32]> {
+    match self {
+      Location::File(_) | Location::Command(_) => None,
+      Location::Tag(tagl) => tagl.majors()
+    }
+  }
+
+  pub fn write_value(&self, write: &mut StateWrite, root: Option<&String>, vers: &str, id: &ProjectId) -> Result<()> {
+    match self {
+      Location::File(l) => l.write_value(write, root, vers, id),
+      Location::Tag(_) => Ok(()),
+      Location::Command(l) => l.write_value(write, root, vers, id)
+    }
+  }
+
+  pub fn read_value<S: StateRead>(&self, read: &S, root: Option<&String>, proj: &ProjectId) -> Result<String> {
+    match self {
+      Location::File(l) => l.read_value(read, root),
+      Location::Tag(l) => Ok(l.read_value(read, proj)),
+      Location::Command(l) => l.read_value(read, root, proj)
+    }
+  }
+
+  pub fn is_tag(&self) -> bool { matches!(self, Location::Tag(..)) }
+
+  #[cfg(test)]
+  pub fn picker(&self) -> &Picker {
+    match self {
+      Location::File(l) => &l.picker,
+      _ => panic!("Not a file location")
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for Location {
+  fn deserialize<D: Deserializer<'de>>(desr: D) -> std::result::Result<Location, D::Error> {
+    struct VecPartSeed;
+
+    impl<'de> DeserializeSeed<'de> for VecPartSeed {
+      type Value = Vec<Part>;
+      fn deserialize<D>(self, deslr: D) -> std::result::Result<Self::Value, D::Error>
+      where
+        D: Deserializer<'de>
+      {
+        deserialize_parts(deslr)
+      }
+    }
+
+    struct LocatorVisitor;
+
+    impl<'de> Visitor<'de> for LocatorVisitor {
+      type Value = Location;
+
+      fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result { formatter.write_str("a version location") }
+
+      fn visit_map<V>(self, mut map: V) -> std::result::Result<Self::Value, V::Error>
+      where
+        V: MapAccess<'de>
+      {
+        let mut file: Option<String> = None;
+        let mut pattern: Option<String> = None;
+        let mut parts: Option<Vec<Part>> = None;
+        let mut tags: Option<TagSpec> = None;
+        let mut code: Option<String> = None;
+        let mut format: Option<String> = None;
+        let mut command: Option<String> = None;
+        let mut write: Option<String> = None;
+
+        while let Some(key) = map.next_key::<String>()? {
+          match key.as_str() {
+            "file" => {
+              file = Some(map.next_value()?);
+            }
+            "tags" => {
+              tags = Some(map.next_value()?);
+            }
+            "json" | "yaml" | "toml" | "xml" => {
+              code = Some(key);
+              parts = Some(map.next_value_seed(VecPartSeed)?);
+            }
+            "pattern" => {
+              pattern = Some(map.next_value()?);
+            }
+            "format" => {
+              format = Some(map.next_value()?);
+            }
+            "command" => {
+              command = Some(map.next_value()?);
+            }
+            "write" => {
+              write = Some(map.next_value()?);
+            }
+            _ => {
+              // Any other key names a custom scanner, which is checked when the config is bound to its scanners.
+              code = Some(key);
+              parts = Some(map.next_value_seed(VecPartSeed)?);
+            }
+          }
+        }
+
+        if let Some(command) = command {
+          if file.is_some() || tags.is_some() || parts.is_some() || pattern.is_some() || format.is_some() {
+            Err(de::Error::custom("can't have 'command' with any other location key but 'write'"))
+          } else if let Some(write) = write {
+            Ok(Location::Command(CommandLocation { command, write }))
+          } else {
+            Err(de::Error::custom("must have 'write' for 'command' location"))
+          }
+        } else if write.is_some() {
+          Err(de::Error::custom("can't have 'write' without 'command' for location"))
+        } else if let Some(file) = file {
+          if tags.is_some() {
+            Err(de::Error::custom("cant have both 'file' and 'tags' for location"))
+          } else if pattern.is_none() && parts.is_none() {
+            Ok(Location::File(FileLocation { file, format, picker: Picker::File(FilePicker {}) }))
+          } else if let Some(pattern) = pattern {
+            if parts.is_some() {
+              Err(de::Error::custom("can't have both 'pattern' and parts field"))
+            } else {
+              Ok(Location::File(FileLocation { file, format, picker: Picker::Line(LinePicker::new(pattern)) }))
+            }
+          } else {
+            let parts = parts.unwrap();
+            let loc = match code.unwrap().as_str() {
+              "json" => Location::File(FileLocation { file, format, picker: Picker::Json(ScanningPicker::new(parts)) }),
+              "yaml" => Location::File(FileLocation { file, format, picker: Picker::Yaml(ScanningPicker::new(parts)) }),
+              "toml" => Location::File(FileLocation { file, format, picker: Picker::Toml(ScanningPicker::new(parts)) }),
+              "xml" => Location::File(FileLocation { file, format, picker: Picker::Xml(ScanningPicker::new(parts)) }),
+              other => Location::File(FileLocation {
+                file,
+                format,
+                picker: Picker::Custom(CustomPicker::new(other.to_string(), parts))
+              })
+            };
+            Ok(loc)
+          }
+        } else if let Some(tags) = tags {
+          if format.is_some() {
+            Err(de::Error::custom("cant have 'format' in 'tags' location"))
+          } else {
+            Ok(Location::Tag(TagLocation { tags }))
+          }
+        } else {
+          Err(de::Error::custom("must have 'file' or 'tags' for location"))
+        }
+      }
+    }
+
+    desr.deserialize_map(LocatorVisitor)
+  }
+}
+
+/// A version that's read and written by commands, for tools that own the version of their project (e.g. `poetry
+/// version -s` to read, and `poetry version {{ v }}` to write). Commands run in the project root; the last line
+/// that the read command prints is the version.
+#[derive(Clone, Deserialize, Debug)]
+struct CommandLocation {
+  command: String,
+  write: String
+}
+
+impl CommandLocation {
+  pub fn write_value(&self, write: &mut StateWrite, root: Option<&String>, vers: &str, id: &ProjectId) -> Result<()> {
+    let tmpl = ParserBuilder::with_stdlib().build()?.parse(&self.write)?;
+    let cmd = tmpl.render(&liquid::object!({ "v": shell_escape(vers) }))?;
+    write.run_command(Hook::new(cmd), root.cloned(), id)
+  }
+
+  /// Run the version command in the working tree. This runs for every command that reads the project's version,
+  /// including read-only ones like `show` and `plan`, so a config with a version command is as trusted as a script.
+  pub fn read_value<S: StateRead>(&self, read: &S, root: Option<&String>, proj: &ProjectId) -> Result<String> {
+    use std::process::Command;
+
+    // A command can only read the working tree: older versions are found from the project's tags instead.
+    let dir = match read.working_root() {
+      Some(dir) => dir,
+      None => return read.latest_tag(proj).cloned().ok_or_else(|| bad!("No older version of project {}.", proj))
+    };
+    let dir = match root {
+      Some(root) => dir.join(PathBuf::from_slash(root)),
+      None => dir.to_path_buf()
+    };
+
+    let output = Command::new("bash")
+      .current_dir(&dir)
+      .args(&["-e", "-c", &self.command])
+      .output()
+      .chain_err(|| format!("Unable to run version command {}.", self.command))?;
+    if !output.status.success() {
+      let err = String::from_utf8_lossy(&output.stderr);
+      bail!("Version command {} failed: {}", self.command, err.trim());
+    }
+    let out = String::from_utf8(output.stdout)?;
+    match out.lines().map(|l| l.trim()).filter(|l| !l.is_empty()).last() {
+      Some(vers) => Ok(vers.to_string()),
+      None => bail!("Version command {} didn't print a version.", self.command)
+    }
+  }
+}
+
+/// Quote a value for bash, unless it's made only of characters that bash leaves alone, as versions usually are.
+fn shell_escape(val: &str) -> String {
+  let plain = |c: char| c.is_ascii_alphanumeric() || "._+-".contains(c);
+  if !val.is_empty() && val.chars().all(plain) {
+    val.to_string()
+  } else {
+    format!("'{}'", val.replace('\'', "'\\''"))
+  }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+struct TagLocation {
+  tags: TagSpec
+}
+
+impl TagLocation {
+  pub fn majors(&self) -> Option<&[u32]> { self.tags.majors() }
+
+  fn read_value<S: StateRead>(&self, read: &S, proj: &ProjectId) -> String {
+    read.latest_tag(proj).cloned().unwrap_or_else(|| self.tags.default_value())
+  }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+#[serde(untagged)]
+enum TagSpec {
+  DefaultTag(DefaultTagSpec),
+  MajorTag(MajorTagSpec)
+}
+
+impl TagSpec {
+  pub fn majors(&self) -> Option<&[u32]> {
+    match self {
+      TagSpec::DefaultTag(_) => None,
+      TagSpec::MajorTag(mtag) => Some(mtag.majors())
+    }
+  }
+
+  pub fn default_value(&self) -> String {
+    match self {
+      TagSpec::DefaultTag(spec) => spec.default.clone(),
+      TagSpec::MajorTag(MajorTagSpec { majors }) => {
+        let small = majors.iter().min().copied().unwrap_or(0);
+        format!("{}.0.0", small)
+      }
+    }
+  }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+struct DefaultTagSpec {
+  default: String
+}
+
+#[derive(Clone, Deserialize, Debug)]
+struct MajorTagSpec {
+  majors: Vec<u32>
+}
+
+impl MajorTagSpec {
+  pub fn majors(&self) -> &[u32] { &self.majors }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+struct FileLocation {
+  file: String,
+  #[serde(flatten)]
+  picker: Picker,
+  format: Option<String>
+}
+
+impl FileLocation {
+  pub fn write_value(&self, write: &mut StateWrite, root: Option<&String>, vers: &str, id: &ProjectId) -> Result<()> {
+    let file = self.rooted(root);
+    let val = self.format_vers(vers)?;
+    write.update_mark(PickPath::new(file, self.picker.clone()), val, id)
+  }
+
+  fn format_vers(&self, vers: &str) -> Result<String> {
+    if let Some(format) = &self.format {
+      let tmpl = ParserBuilder::with_stdlib().build()?.parse(format)?;
+      let globals = liquid::object!({ "v": vers });
+      Ok(tmpl.render(&globals)?)
+    } else {
+      Ok(vers.to_string())
+    }
+  }
+
+  pub fn read_value<S: StateRead>(&self, read: &S, root: Option<&String>) -> Result<String> {
+    let file = self.rooted(root);
+    let data: String = read.read_file(&file)?;
+    self.picker.find(&data).map(|m| m.into_value())
+  }
+
+  pub fn rooted(&self, root: Option<&String>) -> PathBuf {
+    match root {
+      Some(root) => PathBuf::from_slash(root).join(PathBuf::from_slash(&self.file)),
+      None => PathBuf::from_slash(&self.file)
+    }
+  }
+}
+
+#[derive(Deserialize, Debug)]
+struct Subs {
+  #[serde(default)]
+  dirs: Option<String>,
+  #[serde(default)]
+  tops: Option<Vec<u32>>
+}
+
+impl Subs {
+  fn dirs(&self) -> &str { self.dirs.as_deref().unwrap_or("v<>") }
+  fn tops(&self) -> &[u32] { self.tops.as_deref().unwrap_or(&[0, 1]) }
+}
+
+fn default_includes() -> Vec<String> { vec!["**/*".into()] }
+fn default_prev_tag() -> String { "versio-prev".into() }
+
+fn default_security() -> Vec<String> { vec!["security".into()] }
+
+fn deser_labels<'de, D: Deserializer<'de>>(desr: D) -> std::result::Result<Vec<String>, D::Error> {
+  struct StringsVisitor;
+  type T = Vec<String>;
+
+  impl<'de> Visitor<'de> for StringsVisitor {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result { formatter.write_str("a string or list") }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<T, E> { Ok(vec![v.to_string()]) }
+    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> std::result::Result<T, E> { Ok(vec![v.to_string()]) }
+    fn visit_string<E: de::Error>(self, v: String) -> std::result::Result<T, E> { Ok(vec![v]) }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, seq: S) -> std::result::Result<T, S::Error> {
+      Deserialize::deserialize(de::value::SeqAccessDeserializer::new(seq))
+    }
+  }
+
+  desr.deserialize_any(StringsVisitor)
+}
+
+fn deser_one_or_many<'de, D, T>(desr: D) -> std::result::Result<Vec<T>, D::Error>
+where
+  D: Deserializer<'de>,
+  T: Deserialize<'de>
+{
+  struct OneOrManyVisitor<T> {
+    _t: PhantomData<T>
+  }
+
+  impl<'de, T: Deserialize<'de>> Visitor<'de> for OneOrManyVisitor<T> {
+    type Value = Vec<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result { formatter.write_str("a map or list") }
+
+    fn visit_map<M: MapAccess<'de>>(self, map: M) -> std::result::Result<Vec<T>, M::Error> {
+      Ok(vec![Deserialize::deserialize(de::value::MapAccessDeserializer::new(map))?])
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, seq: S) -> std::result::Result<Vec<T>, S::Error> {
+      Deserialize::deserialize(de::value::SeqAccessDeserializer::new(seq))
+    }
+  }
+
+  desr.deserialize_any(OneOrManyVisitor { _t: PhantomData })
+}
+
+fn deser_depends<'de, D>(desr: D) -> std::result::Result<HashMap<ProjectId, Depends>, D::Error>
+where
+  D: Deserializer<'de>
+{
+  struct DependsVisitor;
+
+  impl<'de> Visitor<'de> for DependsVisitor {
+    type Value = HashMap<ProjectId, Depends>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result { formatter.write_str("a map or list") }
+
+    fn visit_map<M: MapAccess<'de>>(self, map: M) -> std::result::Result<Self::Value, M::Error> {
+      Deserialize::deserialize(de::value::MapAccessDeserializer::new(map))
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, seq: S) -> std::result::Result<Self::Value, S::Error> {
+      let entries: Vec<DependsEntry> = Deserialize::deserialize(de::value::SeqAccessDeserializer::new(seq))?;
+      let mut result = HashMap::new();
+      for DependsEntry { id, files, size } in entries {
+        if result.contains_key(&id) {
+          return Err(de::Error::custom(format!("dependency {} is duplicated", id)));
+        }
+        result.insert(id, Depends { files, size });
+      }
+      Ok(result)
+    }
+  }
+
+  desr.deserialize_any(DependsVisitor)
+}
+
+fn deser_sizes<'de, D: Deserializer<'de>>(desr: D) -> std::result::Result<HashMap<String, Size>, D::Error> {
+  struct MapVisitor;
+
+  impl<'de> Visitor<'de> for MapVisitor {
+    type Value = HashMap<String, Size>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result { formatter.write_str("a list of sizes") }
+
+    fn visit_map<M>(self, mut map: M) -> std::result::Result<Self::Value, M::Error>
+    where
+      M: MapAccess<'de>
+    {
+      let mut result = HashMap::new();
+      let mut using_angular = false;
+
+      while let Some(val) = map.next_key::<String>()? {
+        match val.as_str() {
+          val if Size::is_size(val) => {
+            let size = Size::from_str(val).unwrap();
+            let keys: Vec<String> = map.next_value()?;
+            for key in keys {
+              let key = key.to_lowercase();
+              if result.contains_key(&key) {
+                return Err(de::Error::custom(format!("Duplicated kind \"{}\".", key)));
+              }
+              result.insert(key, size);
+            }
+          }
+          "use_angular" => {
+            using_angular = map.next_value()?;
+          }
+          _ => return Err(de::Error::custom(format!("Unrecognized sizes key \"{}\".", val)))
+        }
+      }
+
+      // Based on [angular.js](https://github.com/angular/angular.js/blob/master/DEVELOPERS.md#type) and
+      // [angular](https://github.com/angular/angular/blob/master/CONTRIBUTING.md#type)
+      if using_angular {
+        insert_angular(&mut result);
+      }
+
+      Ok(result)
+    }
+  }
+
+  desr.deserialize_map(MapVisitor)
+}
+
+/// A repo-relative path with forward slashes, as config patterns use. Paths from the OS on Windows may have
+/// backslashes instead.
+fn slashed(path: &str) -> Cow<str> {
+  if cfg!(windows) && path.contains('\\') {
+    Cow::Owned(path.replace('\\', "/"))
+  } else {
+    Cow::Borrowed(path)
+  }
+}
+
+/// Compile a pattern like a shell glob: a `*` doesn't match across a `/`, but a `**` path component does.
+fn glob(pattern: &str) -> Result<globset::Glob> { Ok(GlobBuilder::new(pattern).literal_separator(true).build()?) }
+
+#[cfg(test)]
+mod test {
+  use super::{auto_id, interpolate, shell_escape, CommitMode, CommitsMode, ConfigBuilder, ConfigFile, FileLocation,
+              FirstRelease, Fragment, HashMap, Location, NotifyFormat, Picker, Project, ProjectBuilder, ProjectId,
+              ProviderConfig, ScanningPicker, Size, SkipCi};
+  use crate::errors::Error;
+  use crate::git::FetchTags;
+  use crate::publish::Publish;
+  use crate::scan::parts::Part;
+  use crate::scan::{Scanner, Scanners, TomlScanner};
+  use crate::state::{MemoryState, StateRead, StateWrite};
+  use crate::vcs::VcsLevel;
+
+  #[test]
+  fn test_both_file_and_tags() {
+    let data = r#"
+projects:
+  - name: everything
+    id: 1
+    version:
+      tags:
+        default: "1.0.0"
+      file: "toplevel.json""#;
+
+    assert!(ConfigFile::read(data).is_err())
+  }
+
+  #[test]
+  fn test_scan() {
+    let data = r#"
+projects:
+  - name: everything
+    id: 1
+    version:
+      file: "toplevel.json"
+      json: "version"
+
+  - name: project1
+    id: 2
+    root: "project1"
+    version:
+      file: "Cargo.toml"
+      toml: "version"
+
+  - name: "combined a and b"
+    id: 3
+    root: "nested"
+    includes: ["project_a/**/*", "project_b/**/*"]
+    version:
+      file: "version.txt"
+      pattern: "v([0-9]+\\.[0-9]+\\.[0-9]+) .*"
+
+  - name: "build image"
+    id: 4
+    depends: { 2: { size: match }, 3: { size: match } }
+    version:
+      file: "build/VERSION""#;
+
+    let config = ConfigFile::read(data).unwrap();
+
+    assert_eq!(config.projects[0].id, ProjectId::from_id(1));
+    assert_eq!("line", config.projects[2].location().unwrap().picker().picker_type());
+  }
+
+  #[test]
+  fn test_validate() {
+    let config = r#"
+projects:
+  - name: p1
+    id: 1
+    version: { file: f1 }
+
+  - name: project1
+    id: 1
+    version: { file: f2 }
+    "#;
+
+    assert!(ConfigFile::read(config).is_err());
+  }
+
+  #[test]
+  fn test_validate_names() {
+    let config = r#"
+projects:
+  - name: p1
+    id: 1
+    version: { file: f1 }
+
+  - name: p1
+    id: 2
+    version: { file: f2 }
+    "#;
+
+    assert!(ConfigFile::read(config).is_err());
+  }
+
+  #[test]
+  fn test_validate_illegal_prefix() {
+    let config = r#"
+projects:
+  - name: p1
+    id: 1
+    tag_prefix: "ixth*&o"
+    version: { file: f1 }
+    "#;
+
+    assert!(ConfigFile::read(config).is_err());
+  }
+
+  #[test]
+  fn test_validate_unascii_prefix() {
+    let config = r#"
+projects:
+  - name: p1
+    id: 1
+    tag_prefix: "ixthïo"
+    version: { file: f1 }
+    "#;
+
+    assert!(ConfigFile::read(config).is_err());
+  }
+
+  #[test]
+  fn test_validate_prefix() {
+    let config = r#"
+projects:
+  - name: p1
+    id: 1
+    tag_prefix: proj
+    version: { file: f1 }
+
+  - name: p2
+    id: 2
+    tag_prefix: proj
+    version: { file: f2 }
+    "#;
+
+    assert!(ConfigFile::read(config).is_err());
+  }
+
+  #[test]
+  fn test_validate_ok() {
+    let config = r#"
+projects:
+  - name: p1
+    id: 1
+    tag_prefix: "_proj1-abc"
+    version: { file: f1 }
+
+  - name: p2
+    id: 2
+    tag_prefix: proj2
+    version: { file: f2 }
+    "#;
+
+    assert!(ConfigFile::read(config).is_ok());
+  }
+
+  #[test]
+  fn test_sizes() {
+    let config = r#"
+projects: []
+sizes:
+  major: [ break, "!" ]
+  minor: [ feat ]
+  patch: [ fix, "-" ]
+  none: [ none ]
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    assert_eq!(&Size::Major, config.sizes.get("break").unwrap());
+    assert_eq!(&Size::Major, config.sizes.get("!").unwrap());
+    assert_eq!(&Size::Minor, config.sizes.get("feat").unwrap());
+    assert_eq!(&Size::Patch, config.sizes.get("fix").unwrap());
+    assert_eq!(&Size::Patch, config.sizes.get("-").unwrap());
+    assert_eq!(&Size::None, config.sizes.get("none").unwrap());
+  }
+
+  #[test]
+  fn test_sizes_dup() {
+    let config = r#"
+projects: []
+sizes:
+  major: [ break, feat ]
+  minor: [ feat ]
+  patch: [ fix, "-" ]
+  none: [ none ]
+"#;
+
+    assert!(ConfigFile::read(config).is_err());
+  }
+
+  #[test]
+  fn test_include_w_root() {
+    let proj = Project {
+      name: "test".into(),
+      id: ProjectId::from_id(1),
+      root: Some("base".into()),
+      includes: vec!["**/*".into()],
+      excludes: Vec::new(),
+      depends: HashMap::new(),
+      changelog: None,
+      kind: None,
+      version: Some(Location::File(FileLocation {
+        file: "package.json".into(),
+        picker: Picker::Json(ScanningPicker::new(vec![Part::Map("version".into())])),
+        format: None
+      })),
+      also: Vec::new(),
+      tag_prefix: None,
+      tag_format: None,
+      tag_message: None,
+      alias_tags: Vec::new(),
+      floating_tags: false,
+      labels: Default::default(),
+      aliases: Default::default(),
+      owners: Vec::new(),
+      hooks: Default::default(),
+      subs: None,
+      sizes: HashMap::new(),
+      restrictions: Vec::new(),
+      umbrella: false,
+      path_sizes: Vec::new(),
+      priority: 0,
+      min_size: None,
+      max_size: None,
+      first_release: None,
+      publish: None,
+      workspace_sync: false,
+      maven_sync: false,
+      images: Vec::new(),
+      deploys: false,
+      formulas: Vec::new(),
+      globs: None
+    };
+
+    assert!(proj.does_cover("base/somefile.txt").unwrap());
+    assert!(!proj.does_cover("outerfile.txt").unwrap());
+  }
+
+  #[test]
+  fn test_negative_include() {
+    let proj = Project {
+      name: "test".into(),
+      id: ProjectId::from_id(1),
+      root: Some("services/api".into()),
+      includes: vec!["**/*".into(), "!docs/**/*".into(), "docs/openapi.yaml".into()],
+      excludes: Vec::new(),
+      depends: HashMap::new(),
+      changelog: None,
+      kind: None,
+      version: Some(Location::File(FileLocation {
+        file: "package.json".into(),
+        picker: Picker::Json(ScanningPicker::new(vec![Part::Map("version".into())])),
+        format: None
+      })),
+      also: Vec::new(),
+      tag_prefix: None,
+      tag_format: None,
+      tag_message: None,
+      alias_tags: Vec::new(),
+      floating_tags: false,
+      labels: Default::default(),
+      aliases: Default::default(),
+      owners: Vec::new(),
+      hooks: Default::default(),
+      subs: None,
+      sizes: HashMap::new(),
+      restrictions: Vec::new(),
+      umbrella: false,
+      path_sizes: Vec::new(),
+      priority: 0,
+      min_size: None,
+      max_size: None,
+      first_release: None,
+      publish: None,
+      workspace_sync: false,
+      maven_sync: false,
+      images: Vec::new(),
+      deploys: false,
+      formulas: Vec::new(),
+      globs: None
+    };
+
+    assert!(proj.does_cover("services/api/src/main.rs").unwrap());
+    assert!(!proj.does_cover("services/api/docs/guide.md").unwrap());
+    assert!(proj.does_cover("services/api/docs/openapi.yaml").unwrap());
+  }
+
+  #[test]
+  fn test_star_stays_in_dir() {
+    let data = r#"
+projects:
+  - name: proj
+    id: 1
+    includes: ["src/*", "docs/**"]
+    version:
+      file: "package.json"
+      json: "version""#;
+
+    let file = ConfigFile::read(data).unwrap();
+    let proj = &file.projects()[0];
+    assert!(proj.does_cover("src/main.rs").unwrap());
+    assert!(!proj.does_cover("src/nested/main.rs").unwrap());
+    assert!(proj.does_cover("docs/nested/guide.md").unwrap());
+    assert!(!proj.does_cover("other/main.rs").unwrap());
+  }
+
+  #[test]
+  fn test_exclude_w_root() {
+    let proj = Project {
+      name: "test".into(),
+      id: ProjectId::from_id(1),
+      root: Some("base".into()),
+      includes: vec!["**/*".into()],
+      excludes: vec!["internal/**/*".into()],
+      depends: HashMap::new(),
+      changelog: None,
+      kind: None,
+      version: Some(Location::File(FileLocation {
+        file: "package.json".into(),
+        picker: Picker::Json(ScanningPicker::new(vec![Part::Map("version".into())])),
+        format: None
+      })),
+      also: Vec::new(),
+      tag_prefix: None,
+      tag_format: None,
+      tag_message: None,
+      alias_tags: Vec::new(),
+      floating_tags: false,
+      labels: Default::default(),
+      aliases: Default::default(),
+      owners: Vec::new(),
+      hooks: Default::default(),
+      subs: None,
+      sizes: HashMap::new(),
+      restrictions: Vec::new(),
+      umbrella: false,
+      path_sizes: Vec::new(),
+      priority: 0,
+      min_size: None,
+      max_size: None,
+      first_release: None,
+      publish: None,
+      workspace_sync: false,
+      maven_sync: false,
+      images: Vec::new(),
+      deploys: false,
+      formulas: Vec::new(),
+      globs: None
+    };
+
+    assert!(!proj.does_cover("base/internal/infile.txt").unwrap());
+  }
+
+  #[test]
+  fn test_excludes_check() {
+    let proj = Project {
+      name: "test".into(),
+      id: ProjectId::from_id(1),
+      root: Some("base".into()),
+      includes: vec![],
+      excludes: vec!["internal/**/*".into()],
+      depends: HashMap::new(),
+      changelog: None,
+      kind: None,
+      version: Some(Location::File(FileLocation {
+        file: "package.json".into(),
+        picker: Picker::Json(ScanningPicker::new(vec![Part::Map("version".into())])),
+        format: None
+      })),
+      also: Vec::new(),
+      tag_prefix: None,
+      tag_format: None,
+      tag_message: None,
+      alias_tags: Vec::new(),
+      floating_tags: false,
+      labels: Default::default(),
+      aliases: Default::default(),
+      owners: Vec::new(),
+      hooks: Default::default(),
+      subs: None,
+      sizes: HashMap::new(),
+      restrictions: Vec::new(),
+      umbrella: false,
+      path_sizes: Vec::new(),
+      priority: 0,
+      min_size: None,
+      max_size: None,
+      first_release: None,
+      publish: None,
+      workspace_sync: false,
+      maven_sync: false,
+      images: Vec::new(),
+      deploys: false,
+      formulas: Vec::new(),
+      globs: None
+    };
+
+    assert!(proj.check_excludes().is_err());
+  }
+
+  #[test]
+  fn test_angular_size() {
+    let config = r#"
+projects: []
+sizes:
+  use_angular: true
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+
+    assert_eq!(&Size::Major, config.sizes.get("!").unwrap());
+    assert_eq!(&Size::None, config.sizes.get("build").unwrap());
+    assert_eq!(&Size::None, config.sizes.get("chore").unwrap());
+    assert_eq!(&Size::None, config.sizes.get("ci").unwrap());
+    assert_eq!(&Size::None, config.sizes.get("docs").unwrap());
+    assert_eq!(&Size::Minor, config.sizes.get("feat").unwrap());
+    assert_eq!(&Size::Patch, config.sizes.get("fix").unwrap());
+    assert_eq!(&Size::None, config.sizes.get("perf").unwrap());
+    assert_eq!(&Size::None, config.sizes.get("refactor").unwrap());
+    assert_eq!(&Size::None, config.sizes.get("style").unwrap());
+    assert_eq!(&Size::None, config.sizes.get("test").unwrap());
+  }
+
+  #[test]
+  fn test_auto_single() {
+    let config = r#"
+auto:
+  pattern: "packages/*/package.json"
+  type: npm
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    assert_eq!(config.auto.len(), 1);
+    assert_eq!(config.auto[0].pattern, "packages/*/package.json");
+  }
+
+  #[test]
+  fn test_auto_list() {
+    let config = r#"
+auto:
+  - pattern: "crates/*/Cargo.toml"
+    type: cargo
+  - pattern: "services/*/pom.xml"
+    type: maven
+    tag_prefix: true
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    assert_eq!(config.auto.len(), 2);
+    assert!(config.auto[1].tag_prefix);
+  }
+
+  #[test]
+  fn test_check_includes() {
+    use crate::state::MemoryState;
+
+    let config = r#"
+projects:
+  - name: proj
+    id: 1
+    root: base
+    includes: [ "src/**/*", "scr/**/*", "!src/gen/**/*" ]
+    version:
+      file: "VERSION"
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    let state = MemoryState::new().with_file("base/VERSION", "1.0.0");
+    let files = vec!["base/VERSION".to_string(), "base/src/main.rs".to_string()];
+
+    let warnings = config.projects[0].check(&state, &files).unwrap();
+    assert_eq!(vec!["No files in proj. 1 covered by \"base/scr/**/*\".".to_string()], warnings);
+  }
+
+  #[test]
+  fn test_project_type() {
+    use crate::state::MemoryState;
+
+    let config = r#"
+projects:
+  - name: chart
+    id: 1
+    root: chart
+    type: helm
+  - name: app
+    id: 2
+    root: app
+    type: gradle
+    excludes: [ "out/**/*" ]
+  - name: lib
+    id: 3
+    root: lib
+    type: npm
+    version: { file: "VERSION" }
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    let state = MemoryState::new()
+      .with_file("chart/Chart.yaml", "apiVersion: v2\nname: chart\nversion: 1.2.3\n")
+      .with_file("app/gradle.properties", "group=com.example\nversion=2.0.1\n")
+      .with_file("lib/VERSION", "3.4.5\n");
+
+    assert_eq!("1.2.3", config.projects[0].get_value(&state).unwrap());
+    assert_eq!(&["charts/*.tgz".to_string()], config.projects[0].excludes.as_slice());
+    assert_eq!("2.0.1", config.projects[1].get_value(&state).unwrap());
+    assert_eq!(&["out/**/*".to_string()], config.projects[1].excludes.as_slice());
+    assert_eq!("3.4.5", config.projects[2].get_value(&state).unwrap());
+
+    let err = ConfigFile::read("projects:\n  - name: x\n    id: 1\n").unwrap_err();
+    assert_eq!("project 1 has no version or type", err.to_string());
+  }
+
+  #[test]
+  fn test_command_version() {
+    use crate::state::{CurrentState, OldTags};
+    use std::path::PathBuf;
+
+    let config = r#"
+projects:
+  - name: app
+    id: 1
+    tag_prefix: app
+    version:
+      command: "echo 'Reading version'; echo 1.2.3"
+      write: "echo {{ v }} > VERSION"
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    let proj = &config.projects[0];
+    let current = CurrentState::new(PathBuf::from("."), OldTags::new(HashMap::new(), HashMap::new()));
+    assert_eq!("1.2.3", proj.get_value(&current).unwrap());
+
+    // Without a working tree, the version comes from the latest tag.
+    let state = MemoryState::new().with_tag(ProjectId::from_id(1), "1.2.0");
+    assert_eq!("1.2.0", proj.get_value(&state).unwrap());
+    assert!(proj.get_value(&MemoryState::new()).is_err());
+
+    let mut write = StateWrite::new();
+    proj.set_value(&mut write, "1.3.0").unwrap();
+    assert!(write.commit_to_memory(&mut MemoryState::new()).is_err());
+
+    assert!(ConfigFile::read("projects:\n  - name: app\n    id: 1\n    version: { command: \"echo 1\" }\n").is_err());
+  }
+
+  #[test]
+  fn test_command_version_needs_tags() {
+    let config = r#"
+projects:
+  - name: app
+    id: 1
+    version:
+      command: "echo 1.2.3"
+      write: "echo {{ v }} > VERSION"
+"#;
+
+    let err = ConfigFile::read(config).unwrap_err();
+    assert_eq!("a version command needs a tag_prefix or tag_format in project 1", err.to_string());
+  }
+
+  #[test]
+  fn test_auto_id() {
+    assert_eq!(auto_id("packages/a"), 4047716636);
+    assert!(auto_id("") >= 0x8000_0000);
+  }
+
+  #[test]
+  fn test_fragment_single() {
+    let fragment = r#"
+name: foo
+id: 12
+version: { file: "package.json", json: "version" }
+"#;
+
+    let fragment: Fragment = serde_yaml::from_str(fragment).unwrap();
+    assert_eq!(fragment.projects.len(), 1);
+    assert_eq!(fragment.projects[0].id(), &ProjectId::from_id(12));
+  }
+
+  #[test]
+  fn test_fragment_list() {
+    let fragment = r#"
+- name: foo
+  id: 12
+  version: { file: "package.json", json: "version" }
+- name: bar
+  id: 13
+  root: "bar"
+  version: { tags: { default: "0.0.0" } }
+"#;
+
+    let fragment: Fragment = serde_yaml::from_str(fragment).unwrap();
+    assert_eq!(fragment.projects.len(), 2);
+    assert_eq!(fragment.projects[1].name(), "bar");
+  }
+
+  #[test]
+  fn test_interpolate() {
+    std::env::set_var("VERSIO_TEST_INTERPOLATE", "abc");
+    assert_eq!(interpolate("x-${VERSIO_TEST_INTERPOLATE}-y").unwrap(), "x-abc-y");
+    assert_eq!(interpolate("${VERSIO_TEST_INTERPOLATE:-def}").unwrap(), "abc");
+    assert_eq!(interpolate("no vars").unwrap(), "no vars");
+  }
+
+  #[test]
+  fn test_interpolate_default() {
+    std::env::remove_var("VERSIO_TEST_INTERPOLATE_UNSET");
+    assert_eq!(interpolate("${VERSIO_TEST_INTERPOLATE_UNSET:-def}").unwrap(), "def");
+    assert_eq!(interpolate("${VERSIO_TEST_INTERPOLATE_UNSET:-}").unwrap(), "");
+    assert!(interpolate("${VERSIO_TEST_INTERPOLATE_UNSET}").is_err());
+  }
+
+  #[test]
+  fn test_hooks_not_interpolated() {
+    let config = r#"
+projects:
+  - name: proj
+    id: 1
+    tag_prefix: "proj"
+    version: { file: "VERSION", pattern: "(.*)" }
+    hooks:
+      post_bump: "echo ${VERSIO_NEW_VERSION} > released.txt"
+"#;
+
+    std::env::remove_var("VERSIO_NEW_VERSION");
+    let config = ConfigFile::read(config).unwrap();
+    let hook = config.get_project(&ProjectId::from_id(1)).unwrap().hooks().get("post_bump").unwrap();
+    assert_eq!(hook.cmd, "echo ${VERSIO_NEW_VERSION} > released.txt");
+  }
+
+  #[test]
+  fn test_project_sizes() {
+    let config = r#"
+projects:
+  - name: website
+    id: 1
+    version: { file: "package.json", json: "version" }
+    sizes:
+      patch: [ docs ]
+  - name: service
+    id: 2
+    version: { file: "pom.xml", xml: "project.version" }
+sizes:
+  use_angular: true
+  fail: [ "*" ]
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    let website = config.get_project(&ProjectId::from_id(1)).unwrap();
+    let service = config.get_project(&ProjectId::from_id(2)).unwrap();
+
+    assert_eq!(Size::Patch, website.size(config.sizes(), "docs").unwrap());
+    assert_eq!(Size::None, service.size(config.sizes(), "docs").unwrap());
+    assert_eq!(Size::Minor, website.size(config.sizes(), "feat").unwrap());
+    assert_eq!(Size::Fail, website.size(config.sizes(), "other").unwrap());
+  }
+
+  #[test]
+  fn test_branch_matches() {
+    use super::branch_matches;
+
+    assert!(branch_matches("main", "main").unwrap());
+    assert!(!branch_matches("main", "maintenance").unwrap());
+    assert!(branch_matches("release/*", "release/1.2").unwrap());
+    assert!(branch_matches("/release-[0-9]+/", "release-12").unwrap());
+    assert!(!branch_matches("/release-[0-9]+/", "release-12b").unwrap());
+  }
+
+  #[test]
+  fn test_restrictions() {
+    use crate::mono::Changelog;
+
+    let config = r#"
+projects:
+  - name: website
+    id: 1
+    version: { file: "package.json", json: "version" }
+    restrictions:
+      - name: stay-on-1
+        max_major: 1
+      - name: no-pre-on-main
+        no_prerelease_on: main
+      - name: major-label
+        major_label: allow-major
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    let website = config.get_project(&ProjectId::from_id(1)).unwrap();
+    let cl = Changelog::empty();
+
+    assert!(website.verify_restrictions("1.2.3", Some("1.2.0"), Some("main"), &cl).is_ok());
+    assert!(website.verify_restrictions("1.3.0-feat", Some("1.2.0"), Some("feat"), &cl).is_ok());
+    assert!(website.verify_restrictions("1.3.0-feat", Some("1.2.0"), Some("main"), &cl).is_err());
+    assert!(website.verify_restrictions("1.0.0", Some("0.9.0"), Some("main"), &cl).is_err());
+    assert!(website.verify_restrictions("2.0.0", Some("1.2.0"), Some("main"), &cl).is_err());
+  }
+
+  #[test]
+  fn test_tag_format() {
+    let config = r#"
+projects:
+  - name: website
+    id: 1
+    version: { file: "package.json", json: "version" }
+    tag_prefix: web
+  - name: service
+    id: 2
+    version: { file: "pom.xml", xml: "project.version" }
+    tag_format: "{project}/{version}"
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    let website = config.get_project(&ProjectId::from_id(1)).unwrap();
+    let service = config.get_project(&ProjectId::from_id(2)).unwrap();
+
+    assert_eq!(Some("web-v1.2.3".to_string()), website.full_version("1.2.3"));
+    assert_eq!(Some("1.2.3".to_string()), website.tag_version("web-v1.2.3"));
+    assert_eq!(Some("service/1.2.3".to_string()), service.full_version("1.2.3"));
+    assert_eq!(Some("1.2.3".to_string()), service.tag_version("service/1.2.3"));
+    assert_eq!(None, service.tag_version("website/1.2.3"));
+  }
+
+  #[test]
+  fn test_codeowners() {
+    let config = r#"
+projects:
+  - name: service
+    id: 1
+    root: service
+    priority: 1
+    owners: [ "@acme/service", "ops@acme.com" ]
+    version: { file: "Cargo.toml", toml: "package.version" }
+  - name: docs
+    id: 2
+    includes: [ "**/*.md", "!CHANGELOG.md" ]
+    owners: "@acme/docs"
+    version: { file: "docs/VERSION" }
+  - name: tools
+    id: 3
+    root: tools
+    version: { file: "VERSION" }
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    assert_eq!(
+      vec!["# docs", "/**/*.md @acme/docs", "# service", "/service/**/* @acme/service ops@acme.com"],
+      config.codeowners()
+    );
+    let docs = config.get_project(&ProjectId::from_id(2)).unwrap();
+    assert_eq!((vec!["**/*.md".to_string(), "!CHANGELOG.md".to_string()], Vec::new()), docs.rooted_coverage());
+
+    let config = r#"
+projects:
+  - name: service
+    id: 1
+    owners: "service team"
+    version: { file: "VERSION" }
+"#;
+    let err = ConfigFile::read(config).unwrap_err();
+    assert_eq!("bad owner \"service team\" in project 1: use @user, @org/team, or an email", err.to_string());
+  }
+
+  #[test]
+  fn test_alias_tags() {
+    let config = r#"
+projects:
+  - name: foo
+    id: 1
+    version: { file: "Cargo.toml", toml: "package.version" }
+    tag_prefix: foo
+    alias_tags: [ "v{version}", "{prefix}-v{major}", "{project}-v{major}.{minor}" ]
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    let foo = config.get_project(&ProjectId::from_id(1)).unwrap();
+    assert_eq!(vec!["v1.2.3", "foo-v1", "foo-v1.2"], foo.alias_tags("1.2.3").unwrap());
+    assert!(foo.alias_tags("1.3.0-rc.1").unwrap().is_empty());
+    assert_eq!(None, foo.tag_version("foo-v1").filter(|v| Size::parts(v).is_ok()));
+
+    let config = r#"
+projects:
+  - name: x
+    id: 1
+    version: { file: "VERSION" }
+    alias_tags: "v{major}"
+"#;
 
-  pub fn default_value(&self) -> String {
-    match self {
-      TagSpec::DefaultTag(spec) => spec.default.clone(),
-      TagSpec::MajorTag(MajorTagSpec { majors }) => {
-        let small = majors.iter().min().copied().unwrap_or(0);
-        format!("{}.0.0", small)
-      }
-    }
+    let err = ConfigFile::read(config).unwrap_err();
+    assert_eq!("alias_tags needs a tag_prefix or tag_format in project 1", err.to_string());
   }
-}
 
-#[derive(Clone, Deserialize, Debug)]
-struct DefaultTagSpec {
-  default: String
-}
+  #[test]
+  fn test_floating_tags() {
+    let config = r#"
+projects:
+  - name: action
+    id: 1
+    version: { file: "package.json", json: "version" }
+    tag_prefix: ""
+    floating_tags: true
+  - name: other
+    id: 2
+    version: { file: "other/package.json", json: "version" }
+    tag_format: "{project}@{version}"
+    floating_tags: true
+    alias_tags: "{project}-latest"
+"#;
 
-#[derive(Clone, Deserialize, Debug)]
-struct MajorTagSpec {
-  majors: Vec<u32>
-}
+    let config = ConfigFile::read(config).unwrap();
+    let action = config.get_project(&ProjectId::from_id(1)).unwrap();
+    let other = config.get_project(&ProjectId::from_id(2)).unwrap();
+    assert_eq!(vec!["v1", "v1.2"], action.alias_tags("1.2.3").unwrap());
+    assert_eq!(vec!["other-latest", "other@2", "other@2.0"], other.alias_tags("2.0.1").unwrap());
+    assert!(action.alias_tags("2.0.0-beta.1").unwrap().is_empty());
+  }
 
-impl MajorTagSpec {
-  pub fn majors(&self) -> &[u32] { &self.majors }
-}
+  #[test]
+  fn test_aliases() {
+    use super::Config;
+    use crate::state::{CurrentState, OldTags};
 
-#[derive(Clone, Deserialize, Debug)]
-struct FileLocation {
-  file: String,
-  #[serde(flatten)]
-  picker: Picker,
-  format: Option<String>
-}
+    let config = r#"
+projects:
+  - name: backend-api-service
+    id: 1
+    aliases: [ api, backend-api ]
+    version: { file: "package.json", json: "version" }
+  - name: backend-api-worker
+    id: 2
+    version: { file: "pom.xml", xml: "project.version" }
+"#;
 
-impl FileLocation {
-  pub fn write_value(&self, write: &mut StateWrite, root: Option<&String>, vers: &str, id: &ProjectId) -> Result<()> {
-    let file = self.rooted(root);
-    let val = self.format_vers(vers)?;
-    write.update_mark(PickPath::new(file, self.picker.clone()), val, id)
+    let state = CurrentState::new(".".into(), OldTags::new(HashMap::new(), HashMap::new()));
+    let config = Config::new(state, ConfigFile::read(config).unwrap());
+    assert_eq!(&ProjectId::from_id(1), config.find_unique("api").unwrap());
+    assert_eq!(&ProjectId::from_id(1), config.find_unique("backend-api").unwrap());
+    assert_eq!(&ProjectId::from_id(2), config.find_unique("worker").unwrap());
+    assert!(config.find_unique("backend").is_err());
+
+    let (id1, id2) = (ProjectId::from_id(1), ProjectId::from_id(2));
+    assert_eq!(vec![&id1, &id2], config.find_matching("backend-api-*").unwrap());
+    assert_eq!(vec![&id1], config.find_matching("api*").unwrap());
+    assert_eq!(vec![&id2], config.find_matching("worker").unwrap());
+    assert!(config.find_matching("frontend-*").is_err());
   }
 
-  fn format_vers(&self, vers: &str) -> Result<String> {
-    if let Some(format) = &self.format {
-      let tmpl = ParserBuilder::with_stdlib().build()?.parse(format)?;
-      let globals = liquid::object!({ "v": vers });
-      Ok(tmpl.render(&globals)?)
-    } else {
-      Ok(vers.to_string())
-    }
-  }
+  #[test]
+  fn test_depends_list() {
+    use super::RelativeSize;
 
-  pub fn read_value<S: StateRead>(&self, read: &S, root: Option<&String>) -> Result<String> {
-    let file = self.rooted(root);
-    let data: String = read.read_file(&file)?;
-    self.picker.find(&data).map(|m| m.into_value())
+    let config = r#"
+projects:
+  - name: website
+    id: 1
+    version: { file: "package.json", json: "version" }
+  - name: service
+    id: 2
+    version: { file: "pom.xml", xml: "project.version" }
+    depends:
+      - id: 1
+        bump: patch
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    let service = config.get_project(&ProjectId::from_id(2)).unwrap();
+    let dep = service.depends().get(&ProjectId::from_id(1)).unwrap();
+    assert!(matches!(dep.size(), RelativeSize::Exact(Size::Patch)));
   }
 
-  pub fn rooted(&self, root: Option<&String>) -> PathBuf {
-    match root {
-      Some(root) => PathBuf::from_slash(root).join(PathBuf::from_slash(&self.file)),
-      None => PathBuf::from_slash(&self.file)
-    }
+  #[test]
+  fn test_dependency_cycle() {
+    let config = r#"
+projects:
+  - name: website
+    id: 1
+    version: { file: "package.json", json: "version" }
+    depends: [ { id: 2 } ]
+  - name: service
+    id: 2
+    version: { file: "pom.xml", xml: "project.version" }
+    depends: [ { id: 3 } ]
+  - name: library
+    id: 3
+    version: { file: "Cargo.toml", toml: "package.version" }
+    depends: [ { id: 1 } ]
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    let err = config.check_cycles().unwrap_err();
+    assert_eq!("Dependency cycle: website (1) -> service (2) -> library (3) -> website (1)", err.to_string());
   }
-}
 
-#[derive(Deserialize, Debug)]
-struct Subs {
-  #[serde(default)]
-  dirs: Option<String>,
-  #[serde(default)]
-  tops: Option<Vec<u32>>
-}
+  #[test]
+  fn test_umbrella_covers_nothing() {
+    let config = r#"
+projects:
+  - name: everything
+    id: 1
+    umbrella: true
+    version: { file: "VERSION" }
+"#;
 
-impl Subs {
-  fn dirs(&self) -> &str { self.dirs.as_deref().unwrap_or("v<>") }
-  fn tops(&self) -> &[u32] { self.tops.as_deref().unwrap_or(&[0, 1]) }
-}
+    let config = ConfigFile::read(config).unwrap();
+    let everything = config.get_project(&ProjectId::from_id(1)).unwrap();
+    assert!(everything.umbrella());
+    assert!(!everything.does_cover("src/main.rs").unwrap());
+  }
 
-/// The "size" of the commit is a measure of "how much" to increment a project's version number based on the
-/// significance of its changes. There are currently six sizes from smallest to largest:
-///
-/// - **Empty**: The project was untouched, so the version will not change.
-/// - **None**: Non-altering / cosmetic changes were made. The new version of the project is operationally
-/// identical to the old version, or close enough to make no difference. The version number will not change.
-/// - **Patch**: Bugs were fixed and/or slightly-more-than-cosmetic changes were made; the new version of the
-/// project is fully backwards-compatible with the old, and probably operationally similar. The "patch" part of
-/// the version number will increment.
-/// - **Minor**: New features were added and/or other significant changes were made; the new version of the
-/// project is backwards-compatible with the old, but possibly expanded or operationally dissimilar. The "minor"
-/// part of the version number will be incremented, and the "patch" part will be reset.
-/// - **Major**: Breaking changes were made: anything from pruning APIs to a full restructuring of the code; the
-/// new version of the project is incompatible with the the old version, and can't be expected to act as a
-/// drop-in replacement. The "major" part of the version number will be incremented, and other parts reset.
-/// - **Fail**: A change occured to the project that could not be understood. No changes will be made to any
-/// version numbers; in fact, the entire process is prematurely halted.
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum Size {
-  Fail,
-  Major,
-  Minor,
-  Patch,
-  None,
-  Empty
-}
+  #[test]
+  #[cfg(windows)]
+  fn test_cover_backslash_path() {
+    let config = r#"
+projects:
+  - name: api
+    id: 1
+    root: services/api
+    version: { file: "Cargo.toml", toml: "package.version" }
+    includes: ["src/**/*"]
+"#;
 
-impl Size {
-  pub fn is_size(v: &str) -> bool { Size::from_str(v).is_ok() }
-  pub fn is_failure(&self) -> bool { matches!(self, Size::Fail) }
+    let config = ConfigFile::read(config).unwrap();
+    let api = config.get_project(&ProjectId::from_id(1)).unwrap();
+    assert!(api.does_cover("services\\api\\src\\main.rs").unwrap());
+    assert!(api.does_cover("services/api/src/main.rs").unwrap());
+  }
 
-  fn from_str(v: &str) -> Result<Size> {
-    match v {
-      "major" => Ok(Size::Major),
-      "minor" => Ok(Size::Minor),
-      "patch" => Ok(Size::Patch),
-      "none" => Ok(Size::None),
-      "empty" => Ok(Size::Empty),
-      "fail" => Ok(Size::Fail),
-      other => err!("Unknown size: {}", other)
-    }
+  #[test]
+  fn test_path_sizes() {
+    let config = r#"
+projects:
+  - name: service
+    id: 1
+    root: service
+    version: { file: "Cargo.toml", toml: "package.version" }
+    path_sizes:
+      - path: "src/**/*"
+        min: patch
+      - path: "benches/**/*"
+        size: none
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    let service = config.get_project(&ProjectId::from_id(1)).unwrap();
+    assert_eq!(Size::Patch, service.path_size("service/src/main.rs", Size::None).unwrap());
+    assert_eq!(Size::Minor, service.path_size("service/src/main.rs", Size::Minor).unwrap());
+    assert_eq!(Size::None, service.path_size("service/benches/bench.rs", Size::Major).unwrap());
+    assert_eq!(Size::None, service.path_size("service/README.md", Size::None).unwrap());
   }
 
-  pub fn parts(v: &str) -> Result<[u32; 3]> {
-    let parts: Vec<_> = v
-      .split('.')
-      .map(|p| p.parse())
-      .collect::<std::result::Result<_, _>>()
-      .chain_err(|| format!("Couldn't split {} into parts", v))?;
-    if parts.len() != 3 {
-      return err!("Not a 3-part version: {}", v);
-    }
-    Ok([parts[0], parts[1], parts[2]])
+  #[test]
+  fn test_overlaps_priority() {
+    let config = r#"
+projects:
+  - name: service
+    id: 1
+    root: service
+    version: { file: "Cargo.toml", toml: "package.version" }
+  - name: docs
+    id: 2
+    includes: [ "**/*.md" ]
+    version: { file: "docs/VERSION" }
+  - name: api
+    id: 3
+    root: service/api
+    priority: 1
+    version: { file: "VERSION" }
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    let files = vec!["service/README.md".to_string(), "service/a.md".to_string(), "service/api/lib.rs".to_string()];
+    let overlaps = config.overlaps(files).unwrap();
+    assert_eq!(1, overlaps.len());
+    assert_eq!(vec![ProjectId::from_id(1), ProjectId::from_id(2)], overlaps[0].0);
+    assert_eq!("service/README.md", overlaps[0].1);
+    assert_eq!(2, overlaps[0].2);
+
+    let covering = config.covering("service/api/lib.rs").unwrap();
+    assert_eq!(vec![&ProjectId::from_id(3)], covering.iter().map(|p| p.id()).collect::<Vec<_>>());
   }
 
-  pub fn less_than(v1: &str, v2: &str) -> Result<bool> {
-    let p1 = Size::parts(v1)?;
-    let p2 = Size::parts(v2)?;
+  #[test]
+  fn test_files_by_project() {
+    let config = r#"
+projects:
+  - name: service
+    id: 1
+    root: service
+    excludes: [ "target/**/*" ]
+    version: { file: "Cargo.toml", toml: "package.version" }
+  - name: docs
+    id: 2
+    includes: [ "**/*.md", "!drafts/**/*" ]
+    version: { file: "docs/VERSION" }
+  - name: api
+    id: 3
+    root: service/api
+    priority: 1
+    version: { file: "VERSION" }
+  - name: unused
+    id: 4
+    root: unused
+    version: { file: "VERSION" }
+"#;
 
-    Ok(p1[0] < p2[0] || (p1[0] == p2[0] && (p1[1] < p2[1] || (p1[1] == p2[1] && p1[2] < p2[2]))))
+    let config = ConfigFile::read(config).unwrap();
+    let files = vec![
+      "service/main.rs",
+      "service/api/lib.rs",
+      "service/a.md",
+      "service/a.md",
+      "service/target/debug/out",
+      "drafts/plan.md",
+      "other.txt",
+    ];
+    let by_project = config.files_by_project(files.into_iter().map(|f| f.to_string())).unwrap();
+    assert_eq!(
+      vec![
+        (ProjectId::from_id(1), vec!["service/a.md".to_string(), "service/main.rs".to_string()]),
+        (ProjectId::from_id(2), vec!["service/a.md".to_string()]),
+        (ProjectId::from_id(3), vec!["service/api/lib.rs".to_string()])
+      ],
+      by_project
+    );
+
+    assert!(config.files_by_project(vec!["other.txt".to_string()]).unwrap().is_empty());
   }
 
-  pub fn apply(self, v: &str) -> Result<String> {
-    let parts = Size::parts(v)?;
+  #[test]
+  fn test_affected() {
+    let config = r#"
+projects:
+  - name: web
+    id: 1
+    root: web
+    version: { file: "package.json", json: "version" }
+  - name: api
+    id: 2
+    root: api
+    version: { file: "VERSION" }
+  - name: shared
+    id: 3
+    includes: [ "proto/**/*", "api/proto/**/*" ]
+    version: { file: "proto/VERSION" }
+"#;
+
+    let config = ConfigFile::read(config).unwrap();
+    let affected = |files: &[&str]| config.affected(files.iter().map(|f| f.to_string())).unwrap();
+    assert_eq!(vec![ProjectId::from_id(2), ProjectId::from_id(3)], affected(&["api/proto/a.proto", "api/main.go"]));
+    assert_eq!(vec![ProjectId::from_id(1), ProjectId::from_id(3)], affected(&["proto/b.proto", "web/index.js"]));
+    assert!(affected(&["README.md"]).is_empty());
+    assert!(affected(&[]).is_empty());
+  }
 
-    let newv = match self {
-      Size::Major => format!("{}.{}.{}", parts[0] + 1, 0, 0),
-      Size::Minor => format!("{}.{}.{}", parts[0], parts[1] + 1, 0),
-      Size::Patch => format!("{}.{}.{}", parts[0], parts[1], parts[2] + 1),
-      Size::None => format!("{}.{}.{}", parts[0], parts[1], parts[2]),
-      Size::Empty => format!("{}.{}.{}", parts[0], parts[1], parts[2]),
-      Size::Fail => bail!("Unparseable conventional commit encountered.")
-    };
+  #[test]
+  fn test_defaults() {
+    let config = r#"
+defaults:
+  changelog:
+    file: CHANGELOG.html
+    template: "file:.versio/changelog.liquid"
+  tag_message: "Release {project} {version}"
+  sizes:
+    patch: [ docs ]
+projects:
+  - name: website
+    id: 1
+    version: { file: "package.json", json: "version" }
+  - name: service
+    id: 2
+    version: { file: "pom.xml", xml: "project.version" }
+    changelog: NEWS.html
+    tag_message: "Service {version}"
+    sizes:
+      none: [ docs ]
+"#;
 
-    Ok(newv)
+    let mut config = ConfigFile::read(config).unwrap();
+    config.apply_defaults();
+    let website = config.get_project(&ProjectId::from_id(1)).unwrap();
+    let service = config.get_project(&ProjectId::from_id(2)).unwrap();
+
+    let (file, template) = website.changelog().unwrap();
+    assert_eq!("CHANGELOG.html", file);
+    assert_eq!("file:.versio/changelog.liquid", template);
+    let (file, template) = service.changelog().unwrap();
+    assert_eq!("NEWS.html", file);
+    assert_eq!("file:.versio/changelog.liquid", template);
+    assert_eq!(Some(&"Release {project} {version}".to_string()), website.tag_message.as_ref());
+    assert_eq!(Some(&"Service {version}".to_string()), service.tag_message.as_ref());
+    assert_eq!(Size::Patch, website.size(config.sizes(), "docs").unwrap());
+    assert_eq!(Size::None, service.size(config.sizes(), "docs").unwrap());
   }
-}
 
-impl fmt::Display for Size {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    match self {
-      Size::Major => write!(f, "major"),
-      Size::Minor => write!(f, "minor"),
-      Size::Patch => write!(f, "patch"),
-      Size::None => write!(f, "none"),
-      Size::Fail => write!(f, "fail"),
-      Size::Empty => write!(f, "empty")
-    }
+  #[test]
+  fn test_tag_collisions() {
+    let config = r#"
+projects:
+  - name: website
+    id: 1
+    version: { file: "package.json", json: "version" }
+    tag_prefix: ""
+  - name: service
+    id: 2
+    version: { file: "pom.xml", xml: "project.version" }
+    tag_format: "v{version}"
+"#;
+
+    let err = ConfigFile::read(config).unwrap_err();
+    assert_eq!("tags \"v{version}\" of project 2 collide with project 1", err.to_string());
   }
-}
 
-impl PartialOrd for Size {
-  fn partial_cmp(&self, other: &Size) -> Option<Ordering> { Some(self.cmp(other)) }
-}
+  #[test]
+  fn test_missing_prev_limit() {
+    let config = r#"
+options:
+  missing_prev: limit
+"#;
 
-impl Ord for Size {
-  fn cmp(&self, other: &Size) -> Ordering {
-    match self {
-      Size::Fail => match other {
-        Size::Fail => Ordering::Equal,
-        _ => Ordering::Greater
-      },
-      Size::Major => match other {
-        Size::Fail => Ordering::Less,
-        Size::Major => Ordering::Equal,
-        _ => Ordering::Greater
-      },
-      Size::Minor => match other {
-        Size::Major | Size::Fail => Ordering::Less,
-        Size::Minor => Ordering::Equal,
-        _ => Ordering::Greater
-      },
-      Size::Patch => match other {
-        Size::None | Size::Empty => Ordering::Greater,
-        Size::Patch => Ordering::Equal,
-        _ => Ordering::Less
-      },
-      Size::None => match other {
-        Size::Empty => Ordering::Greater,
-        Size::None => Ordering::Equal,
-        _ => Ordering::Less
-      },
-      Size::Empty => match other {
-        Size::Empty => Ordering::Equal,
-        _ => Ordering::Less
-      }
-    }
+    let err = ConfigFile::read(config).unwrap_err();
+    assert_eq!("missing_prev \"limit\" requires a max_history", err.to_string());
+
+    let config = r#"
+options:
+  max_history: 500
+"#;
+
+    let file = ConfigFile::read(config).unwrap();
+    assert_eq!(Some(500), file.options.max_history);
+    assert!(file.options.missing_prev.is_none());
   }
-}
 
-fn default_includes() -> Vec<String> { vec!["**/*".into()] }
-fn default_prev_tag() -> String { "versio-prev".into() }
-fn default_branch() -> Option<String> { None }
+  #[test]
+  fn test_vcs_levels() {
+    let config = r#"
+options:
+  vcs_levels:
+    plan:
+      preferred: none-local
+    release:
+      required: smart
+"#;
 
-fn deser_labels<'de, D: Deserializer<'de>>(desr: D) -> std::result::Result<Vec<String>, D::Error> {
-  struct StringsVisitor;
-  type T = Vec<String>;
+    let file = ConfigFile::read(config).unwrap();
+    let plan = file.vcs_levels()["plan"].preferred().unwrap().unwrap();
+    assert_eq!((VcsLevel::None, VcsLevel::Local), (plan.min(), plan.max()));
+    let release = &file.vcs_levels()["release"];
+    assert!(release.preferred().unwrap().is_none());
+    assert_eq!(VcsLevel::Smart, release.required().unwrap().unwrap().max());
 
-  impl<'de> Visitor<'de> for StringsVisitor {
-    type Value = T;
+    let config = r#"
+options:
+  vcs_levels:
+    plan:
+      preferred: smart-local
+"#;
 
-    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result { formatter.write_str("a string or list") }
+    let err = ConfigFile::read(config).unwrap_err();
+    assert_eq!("empty vcs_levels range for command plan", err.to_string());
+  }
 
-    fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<T, E> { Ok(vec![v.to_string()]) }
-    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> std::result::Result<T, E> { Ok(vec![v.to_string()]) }
-    fn visit_string<E: de::Error>(self, v: String) -> std::result::Result<T, E> { Ok(vec![v]) }
+  #[test]
+  fn test_fetch_opts() {
+    let file = ConfigFile::read("options: {}\n").unwrap();
+    assert_eq!(FetchTags::All, file.options.fetch_tags);
+    assert!(file.options.fetch_refspecs.is_empty());
 
-    fn visit_seq<S: SeqAccess<'de>>(self, seq: S) -> std::result::Result<T, S::Error> {
-      Deserialize::deserialize(de::value::SeqAccessDeserializer::new(seq))
-    }
+    let config = r#"
+options:
+  fetch_tags: none
+  fetch_refspecs: [ "refs/tags/v*:refs/tags/v*" ]
+"#;
+
+    let file = ConfigFile::read(config).unwrap();
+    assert_eq!(FetchTags::None, file.options.fetch_tags);
+    assert_eq!(vec!["refs/tags/v*:refs/tags/v*".to_string()], file.options.fetch_refspecs);
   }
 
-  desr.deserialize_any(StringsVisitor)
-}
+  #[test]
+  fn test_commits_strict() {
+    let config = r#"
+options:
+  commits: strict
 
-fn deser_sizes<'de, D: Deserializer<'de>>(desr: D) -> std::result::Result<HashMap<String, Size>, D::Error> {
-  struct MapVisitor;
+projects:
+  - name: proj
+    id: 1
+    version:
+      file: "file.txt"
+    sizes:
+      patch: [ deploy ]
 
-  impl<'de> Visitor<'de> for MapVisitor {
-    type Value = HashMap<String, Size>;
+sizes:
+  minor: [ feat ]
+  fail: [ "*" ]
+"#;
 
-    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result { formatter.write_str("a list of sizes") }
+    let file = ConfigFile::read(config).unwrap();
+    assert_eq!(CommitsMode::Strict, file.commits());
+    assert!(file.knows_kind("feat"));
+    assert!(file.knows_kind("deploy"));
+    assert!(!file.knows_kind("wip"));
+    assert!(!file.knows_kind("*"));
+  }
 
-    fn visit_map<M>(self, mut map: M) -> std::result::Result<Self::Value, M::Error>
-    where
-      M: MapAccess<'de>
-    {
-      let mut result = HashMap::new();
-      let mut using_angular = false;
+  #[test]
+  fn test_security() {
+    let config = r#"
+projects:
+  - name: proj
+    id: 1
+    version:
+      file: "file.txt"
 
-      while let Some(val) = map.next_key::<String>()? {
-        match val.as_str() {
-          val if Size::is_size(val) => {
-            let size = Size::from_str(val).unwrap();
-            let keys: Vec<String> = map.next_value()?;
-            for key in keys {
-              let key = key.to_lowercase();
-              if result.contains_key(&key) {
-                return Err(de::Error::custom(format!("Duplicated kind \"{}\".", key)));
-              }
-              result.insert(key, size);
-            }
-          }
-          "use_angular" => {
-            using_angular = map.next_value()?;
-          }
-          _ => return Err(de::Error::custom(format!("Unrecognized sizes key \"{}\".", val)))
-        }
-      }
+sizes:
+  minor: [ feat ]
+  fail: [ "*" ]
+"#;
+
+    let file = ConfigFile::read(config).unwrap();
+    assert!(file.knows_kind("security"));
+    assert!(file.is_security_kind("security"));
+    assert!(!file.is_security_kind("feat"));
+    assert!(file.has_security_label(&["bug".to_string(), "security".to_string()]));
+    assert_eq!(None, file.projects()[0].explicit_size(file.sizes(), "security"));
+    assert_eq!(Size::Fail, file.projects()[0].size(file.sizes(), "security").unwrap());
+
+    let config = r#"
+options:
+  security_kinds: [ sec, vuln ]
+  security_labels: cve
+"#;
 
-      // Based on [angular.js](https://github.com/angular/angular.js/blob/master/DEVELOPERS.md#type) and
-      // [angular](https://github.com/angular/angular/blob/master/CONTRIBUTING.md#type)
-      if using_angular {
-        insert_angular(&mut result);
-      }
+    let file = ConfigFile::read(config).unwrap();
+    assert!(file.is_security_kind("vuln"));
+    assert!(!file.is_security_kind("security"));
+    assert!(file.has_security_label(&["cve".to_string()]));
+  }
 
-      Ok(result)
-    }
+  #[test]
+  fn test_release_branch() {
+    let file = ConfigFile::read("options: {}\n").unwrap();
+    assert_eq!(None, file.release_branch());
+
+    let file = ConfigFile::read("options:\n  release_branch: \"release/{major}.{minor}\"\n").unwrap();
+    assert_eq!(Some("release/{major}.{minor}"), file.release_branch());
+
+    let err = ConfigFile::read("options:\n  release_branch: \"release/{version}\"\n").unwrap_err();
+    assert_eq!("release_branch \"release/{version}\" has no {major}", err.to_string());
   }
 
-  desr.deserialize_map(MapVisitor)
-}
+  #[test]
+  fn test_provider() {
+    let file = ConfigFile::read("projects: []\n").unwrap();
+    assert_eq!(&ProviderConfig::Github, file.provider());
 
-fn insert_angular(result: &mut HashMap<String, Size>) {
-  insert_if_missing(result, "!", Size::Major);
-  insert_if_missing(result, "feat", Size::Minor);
-  insert_if_missing(result, "fix", Size::Patch);
-  insert_if_missing(result, "docs", Size::None);
-  insert_if_missing(result, "style", Size::None);
-  insert_if_missing(result, "refactor", Size::None);
-  insert_if_missing(result, "perf", Size::None);
-  insert_if_missing(result, "test", Size::None);
-  insert_if_missing(result, "chore", Size::None);
-  insert_if_missing(result, "build", Size::None);
-  insert_if_missing(result, "ci", Size::None);
-}
+    let file = ConfigFile::read("options:\n  provider:\n    type: file\n    path: \"test/prs.json\"\n").unwrap();
+    assert_eq!(&ProviderConfig::File { path: "test/prs.json".into() }, file.provider());
 
-fn insert_if_missing(result: &mut HashMap<String, Size>, key: &str, val: Size) {
-  if !result.contains_key(key) {
-    result.insert(key.to_string(), val);
+    let err = ConfigFile::read("options:\n  provider:\n    type: file\n    path: \"\"\n").unwrap_err();
+    assert_eq!("provider file has an empty path", err.to_string());
   }
-}
 
-fn match_opts() -> MatchOptions { MatchOptions { require_literal_separator: true, ..Default::default() } }
+  #[test]
+  fn test_github_deploy() {
+    let file = ConfigFile::read("options:\n  github_deploy:\n    environment: production\n").unwrap();
+    let deploy = file.github_deploy().unwrap();
+    assert_eq!(Some("production"), deploy.environment());
+    assert_eq!(None, deploy.event_type());
 
-#[cfg(test)]
-mod test {
-  use super::{ConfigFile, FileLocation, HashMap, Location, Picker, Project, ProjectId, ScanningPicker, Size};
-  use crate::scan::parts::Part;
+    let file = ConfigFile::read("options:\n  github_deploy:\n    event_type: versio-release\n").unwrap();
+    assert_eq!(Some("versio-release"), file.github_deploy().unwrap().event_type());
+
+    let err = ConfigFile::read("options:\n  github_deploy: {}\n").unwrap_err();
+    assert_eq!("github_deploy needs exactly one of environment or event_type", err.to_string());
+  }
 
   #[test]
-  fn test_both_file_and_tags() {
-    let data = r#"
-projects:
-  - name: everything
-    id: 1
-    version:
-      tags:
-        default: "1.0.0"
-      file: "toplevel.json""#;
+  fn test_maintenance_branch() {
+    let file = ConfigFile::read("options: {}\n").unwrap();
+    assert!(file.maintenance_branch().is_empty());
 
-    assert!(ConfigFile::read(data).is_err())
+    let file = ConfigFile::read("options:\n  maintenance_branch: \"release/*\"\n").unwrap();
+    assert_eq!(&["release/*".to_string()], file.maintenance_branch());
+
+    let err = ConfigFile::read("options:\n  maintenance_branch: \"/release/(/\"\n").unwrap_err();
+    assert_eq!("illegal branch pattern \"/release/(/\"", err.to_string());
   }
 
   #[test]
-  fn test_scan() {
-    let data = r#"
-projects:
-  - name: everything
-    id: 1
-    version:
-      file: "toplevel.json"
-      json: "version"
+  fn test_commit_message() {
+    let file = ConfigFile::read("projects: []\n").unwrap();
+    assert_eq!(None, file.commit_message(&[("web", "1.2.0")]));
 
-  - name: project1
-    id: 2
-    root: "project1"
-    version:
-      file: "Cargo.toml"
-      toml: "version"
+    let file = ConfigFile::read("options:\n  commit_message: \"chore(release): {projects} [skip ci]\"\n").unwrap();
+    let msg = file.commit_message(&[("web", "1.2.0"), ("api", "0.3.1")]);
+    assert_eq!(Some("chore(release): web 1.2.0, api 0.3.1 [skip ci]".to_string()), msg);
 
-  - name: "combined a and b"
-    id: 3
-    root: "nested"
-    includes: ["project_a/**/*", "project_b/**/*"]
-    version:
-      file: "version.txt"
-      pattern: "v([0-9]+\\.[0-9]+\\.[0-9]+) .*"
+    let file = ConfigFile::read("options:\n  commit_message: \"release {names}\"\n").unwrap();
+    assert_eq!(Some("release web, api".to_string()), file.commit_message(&[("web", "1.2.0"), ("api", "0.3.1")]));
+  }
 
-  - name: "build image"
-    id: 4
-    depends: { 2: { size: match }, 3: { size: match } }
-    version:
-      file: "build/VERSION""#;
+  #[test]
+  fn test_commit_mode() {
+    let file = ConfigFile::read("projects: []\n").unwrap();
+    assert_eq!(CommitMode::Combined, file.commit_mode());
 
-    let config = ConfigFile::read(data).unwrap();
+    let file = ConfigFile::read("options:\n  commit_mode: per-project\n").unwrap();
+    assert_eq!(CommitMode::PerProject, file.commit_mode());
 
-    assert_eq!(config.projects[0].id, ProjectId::from_id(1));
-    assert_eq!("line", config.projects[2].version.picker().picker_type());
+    assert!(ConfigFile::read("options:\n  commit_mode: per_project\n").is_err());
   }
 
   #[test]
-  fn test_validate() {
+  fn test_skip_ci() {
+    let file = ConfigFile::read("projects: []\n").unwrap();
+    assert_eq!(SkipCi::Off, file.skip_ci());
+    assert_eq!("release", file.skip_ci().mark("release".into()));
+
+    let file = ConfigFile::read("options:\n  skip_ci: ci-skip\n").unwrap();
+    assert_eq!(SkipCi::CiSkip, file.skip_ci());
+    assert_eq!("release [ci skip]", file.skip_ci().mark("release".into()));
+    assert_eq!("release [ci skip] now", file.skip_ci().mark("release [ci skip] now".into()));
+
+    assert_eq!(SkipCi::SkipCi, "skip-ci".parse().unwrap());
+    assert!("skip".parse::<SkipCi>().is_err());
+  }
+
+  #[test]
+  fn test_bot_authors() {
     let config = r#"
-projects:
-  - name: p1
-    id: 1
-    version: { file: f1 }
+options:
+  bot_authors: [ "*@users.noreply.github.com", '/ci-.*@example\.com/' ]
+"#;
 
-  - name: project1
+    let file = ConfigFile::read(config).unwrap();
+    assert!(file.is_bot_author("renovate[bot]@users.noreply.github.com"));
+    assert!(file.is_bot_author("ci-release@example.com"));
+    assert!(!file.is_bot_author("dev@example.com"));
+
+    let err = ConfigFile::read("options:\n  bot_authors: \"/ci-(/\"\n").unwrap_err();
+    assert_eq!("illegal bot_authors pattern \"/ci-(/\"", err.to_string());
+  }
+
+  #[test]
+  fn test_first_release() {
+    let config = r#"
+projects:
+  - name: proj
     id: 1
-    version: { file: f2 }
-    "#;
+    first_release: 1.0.0
+    version:
+      file: "file.txt"
+"#;
 
-    assert!(ConfigFile::read(config).is_err());
+    let file = ConfigFile::read(config).unwrap();
+    assert_eq!(FirstRelease::One, file.projects()[0].first_release());
+    assert_eq!(Some("1.0.0"), file.projects()[0].first_release().start());
+    assert!(ConfigFile::read(&config.replace("1.0.0", "explicit")).is_err());
   }
 
   #[test]
-  fn test_validate_names() {
+  fn test_min_size() {
     let config = r#"
 projects:
-  - name: p1
+  - name: proj
     id: 1
-    version: { file: f1 }
+    min_size: minor
+    version:
+      file: "file.txt"
+"#;
 
-  - name: p1
-    id: 2
-    version: { file: f2 }
-    "#;
+    let file = ConfigFile::read(config).unwrap();
+    assert_eq!(Some(Size::Minor), file.projects()[0].min_size());
+    assert!(ConfigFile::read(&config.replace("minor", "fail")).is_err());
+    assert!(ConfigFile::read(&config.replace("min_size: minor", "min_size: minor\n    max_size: patch")).is_err());
+  }
 
-    assert!(ConfigFile::read(config).is_err());
+  #[test]
+  fn test_config_error_location() {
+    let config = r#"
+options:
+  commits: strcit
+"#;
+
+    let err = ConfigFile::read(config).unwrap_err().to_string();
+    assert!(err.starts_with(".versio.yaml line 3, column "), "{}", err);
+    assert!(err.contains("(at `options.commits`)"), "{}", err);
+    assert!(err.ends_with("Hint: Did you mean `strict`?"), "{}", err);
   }
 
   #[test]
-  fn test_validate_illegal_prefix() {
+  fn test_changelog_dates() {
+    use chrono::{TimeZone, Utc};
+
     let config = r#"
 projects:
-  - name: p1
+  - name: proj
     id: 1
-    tag_prefix: "ixth*&o"
-    version: { file: f1 }
-    "#;
+    changelog:
+      file: "CHANGELOG.html"
+      date_format: "%d %b %Y"
+      date_zone: "+02:00"
+    version:
+      file: "file.txt"
+"#;
 
-    assert!(ConfigFile::read(config).is_err());
+    let file = ConfigFile::read(config).unwrap();
+    let dates = file.projects()[0].changelog_dates();
+    let time = Utc.ymd(2020, 1, 1).and_hms(23, 30, 0);
+    assert_eq!("02 Jan 2020", dates.render(&time).unwrap());
   }
 
   #[test]
-  fn test_validate_unascii_prefix() {
+  fn test_custom_scanner() {
+    fn build_cfg(parts: Vec<Part>) -> Box<dyn Scanner + Send + Sync> { Box::new(TomlScanner::build(parts)) }
+    let mut scanners = Scanners::new();
+    scanners.register("cfg", build_cfg).unwrap();
+    assert!(scanners.register("cfg", build_cfg).is_err());
+    assert!(scanners.register("json", build_cfg).is_err());
+
     let config = r#"
 projects:
   - name: p1
     id: 1
-    tag_prefix: "ixthïo"
-    version: { file: f1 }
-    "#;
+    version: { file: "setup.cfg", cfg: "metadata.version" }
+  - name: p2
+    id: 2
+    version: { file: "other.cfg", unknown: "metadata.version" }
+"#;
+    assert!(ConfigFile::read(config).unwrap().bind_scanners(&scanners).is_err());
 
-    assert!(ConfigFile::read(config).is_err());
+    let config = r#"
+projects:
+  - name: p1
+    id: 1
+    version: { file: "setup.cfg", cfg: "metadata.version" }
+"#;
+    assert!(ConfigFile::read(config).unwrap().bind_scanners(&Scanners::new()).is_err());
+    let mut config = ConfigFile::read(config).unwrap();
+    config.bind_scanners(&scanners).unwrap();
+    assert_eq!("custom", config.projects[0].location().unwrap().picker().picker_type());
+    let mark = config.projects[0].location().unwrap().picker().find("[metadata]\nversion = \"1.2.3\"\n").unwrap();
+    assert_eq!("1.2.3", mark.value());
   }
 
   #[test]
-  fn test_validate_prefix() {
+  fn test_memory_state() {
     let config = r#"
 projects:
   - name: p1
     id: 1
-    tag_prefix: proj
-    version: { file: f1 }
-
-  - name: p2
-    id: 2
-    tag_prefix: proj
-    version: { file: f2 }
-    "#;
+    tag_prefix: ""
+    version: { file: "pkg/package.json", json: "version" }
+"#;
 
-    assert!(ConfigFile::read(config).is_err());
+    let mut state = MemoryState::new()
+      .with_file(".versio.yaml", config)
+      .with_file("pkg/package.json", "{ \"version\": \"1.2.3\" }\n");
+    let file = ConfigFile::from_read(&state).unwrap();
+    let proj = &file.projects()[0];
+    assert_eq!("1.2.3", proj.get_value(&state).unwrap());
+
+    let mut write = StateWrite::new();
+    proj.set_value(&mut write, "1.3.0").unwrap();
+    write.commit_to_memory(&mut state).unwrap();
+    assert_eq!("1.3.0", proj.get_value(&state).unwrap());
+    assert_eq!(Some(&"1.3.0".to_string()), state.latest_tag(proj.id()));
+
+    // Everything is used up, including the repository changes that memory can't hold.
+    let mut other = MemoryState::new();
+    write.commit_to_memory(&mut other).unwrap();
+    assert!(other.latest_tag(proj.id()).is_none());
   }
 
   #[test]
-  fn test_validate_ok() {
+  fn test_publish() {
     let config = r#"
 projects:
   - name: p1
     id: 1
-    tag_prefix: "_proj1-abc"
-    version: { file: f1 }
+    version: { file: "Cargo.toml", toml: "package.version" }
+    publish: cargo
 
   - name: p2
     id: 2
-    tag_prefix: proj2
-    version: { file: f2 }
-    "#;
+    root: web
+    version: { file: "package.json", json: "version" }
+    publish: npm
+    workspace_sync: true
+"#;
 
-    assert!(ConfigFile::read(config).is_ok());
+    let file = ConfigFile::read(config).unwrap();
+    assert_eq!(Some(&Publish::Cargo), file.projects()[0].publish());
+    assert!(!file.projects()[0].workspace_sync());
+    assert_eq!(Some(&Publish::Npm), file.projects()[1].publish());
+    assert!(file.projects()[1].workspace_sync());
   }
 
   #[test]
-  fn test_sizes() {
+  fn test_images() {
     let config = r#"
-projects: []
-sizes:
-  major: [ break, "!" ]
-  minor: [ feat ]
-  patch: [ fix, "-" ]
-  none: [ none ]
+projects:
+  - name: api
+    id: 1
+    root: api
+    version: { tags: { default: "0.0.0" } }
+    images: ghcr.io/example/api
+
+  - name: deploy
+    id: 2
+    root: k8s
+    version: { tags: { default: "0.0.0" } }
+    deploys: true
 "#;
 
-    let config = ConfigFile::read(config).unwrap();
-    assert_eq!(&Size::Major, config.sizes.get("break").unwrap());
-    assert_eq!(&Size::Major, config.sizes.get("!").unwrap());
-    assert_eq!(&Size::Minor, config.sizes.get("feat").unwrap());
-    assert_eq!(&Size::Patch, config.sizes.get("fix").unwrap());
-    assert_eq!(&Size::Patch, config.sizes.get("-").unwrap());
-    assert_eq!(&Size::None, config.sizes.get("none").unwrap());
+    let file = ConfigFile::read(config).unwrap();
+    assert_eq!(&["ghcr.io/example/api".to_string()], file.projects()[0].images());
+    assert!(!file.projects()[0].deploys());
+    assert!(file.projects()[1].images().is_empty());
+    assert!(file.projects()[1].deploys());
   }
 
   #[test]
-  fn test_sizes_dup() {
+  fn test_notify() {
     let config = r#"
+options:
+  notify:
+    - url: "${VERSIO_TEST_NOTIFY_URL:-https://example.com/hook}"
+      format: slack
+
 projects: []
-sizes:
-  major: [ break, feat ]
-  minor: [ feat ]
-  patch: [ fix, "-" ]
-  none: [ none ]
 "#;
 
-    assert!(ConfigFile::read(config).is_err());
+    let file = ConfigFile::read(config).unwrap();
+    assert_eq!(1, file.notify().len());
+    assert_eq!(NotifyFormat::Slack, file.notify()[0].format());
+    assert_eq!("https://example.com/hook", file.notify()[0].url().unwrap());
   }
 
   #[test]
-  fn test_include_w_root() {
-    let proj = Project {
-      name: "test".into(),
-      id: ProjectId::from_id(1),
-      root: Some("base".into()),
-      includes: vec!["**/*".into()],
-      excludes: Vec::new(),
-      depends: HashMap::new(),
-      changelog: None,
-      version: Location::File(FileLocation {
-        file: "package.json".into(),
-        picker: Picker::Json(ScanningPicker::new(vec![Part::Map("version".into())])),
-        format: None
-      }),
-      also: Vec::new(),
-      tag_prefix: None,
-      labels: Default::default(),
-      hooks: Default::default(),
-      subs: None
-    };
+  fn test_config_error_kind() {
+    let config = r#"
+projects:
+  - name: p1
+    id: 1
+    version: { file: f1 }
 
-    assert!(proj.does_cover("base/somefile.txt").unwrap());
-    assert!(!proj.does_cover("outerfile.txt").unwrap());
+  - name: p2
+    id: 1
+    version: { file: f2 }
+"#;
+
+    let state = MemoryState::new().with_file(".versio.yaml", config);
+    let err = ConfigFile::from_read(&state).err().unwrap();
+    assert!(matches!(&err, Error::Config { file, .. } if file == ".versio.yaml"));
+    assert_eq!(2, err.exit_code());
   }
 
   #[test]
-  fn test_exclude_w_root() {
-    let proj = Project {
-      name: "test".into(),
-      id: ProjectId::from_id(1),
-      root: Some("base".into()),
-      includes: vec!["**/*".into()],
-      excludes: vec!["internal/**/*".into()],
-      depends: HashMap::new(),
-      changelog: None,
-      version: Location::File(FileLocation {
-        file: "package.json".into(),
-        picker: Picker::Json(ScanningPicker::new(vec![Part::Map("version".into())])),
-        format: None
-      }),
-      also: Vec::new(),
-      tag_prefix: None,
-      labels: Default::default(),
-      hooks: Default::default(),
-      subs: None
-    };
+  fn test_builder() {
+    let builder = ConfigBuilder::new()
+      .project(
+        ProjectBuilder::new("web")
+          .id(1)
+          .root("./web")
+          .tag_prefix("web")
+          .labels(&["npm"])
+          .version_file("package.json", Some(("json", "version")))
+          .hook("post_write", "npm install")
+      )
+      .project(ProjectBuilder::new("infra").id(2).root("infra").exclude("**/*.md").version_tags("0.1.0"));
+
+    let built = builder.build().unwrap();
+    let read = ConfigFile::read(&builder.to_yaml().unwrap()).unwrap();
+    for file in &[built, read] {
+      let web = file.get_project(&ProjectId::from_id(1)).unwrap();
+      assert_eq!(Some(&"web".to_string()), web.root.as_ref());
+      assert_eq!(Some("web-v1.2.3".to_string()), web.full_version("1.2.3"));
+      assert_eq!("json", web.location().unwrap().picker().picker_type());
+      assert!(web.hooks.get("post_write").is_some());
+
+      let infra = file.get_project(&ProjectId::from_id(2)).unwrap();
+      assert!(infra.does_cover("infra/main.tf").unwrap());
+      assert!(!infra.does_cover("infra/README.md").unwrap());
+      assert!(infra.location().unwrap().is_tag());
+    }
 
-    assert!(!proj.does_cover("base/internal/infile.txt").unwrap());
+    let dup = ConfigBuilder::new().project(ProjectBuilder::new("a")).project(ProjectBuilder::new("b"));
+    assert!(dup.build().is_err());
   }
 
   #[test]
-  fn test_excludes_check() {
-    let proj = Project {
-      name: "test".into(),
-      id: ProjectId::from_id(1),
-      root: Some("base".into()),
-      includes: vec![],
-      excludes: vec!["internal/**/*".into()],
-      depends: HashMap::new(),
-      changelog: None,
-      version: Location::File(FileLocation {
-        file: "package.json".into(),
-        picker: Picker::Json(ScanningPicker::new(vec![Part::Map("version".into())])),
-        format: None
-      }),
-      also: Vec::new(),
-      tag_prefix: None,
-      labels: Default::default(),
-      hooks: Default::default(),
-      subs: None
-    };
-
-    assert!(proj.check_excludes().is_err());
+  fn test_shell_escape() {
+    assert_eq!(shell_escape("1.2.3-rc.1+build_7"), "1.2.3-rc.1+build_7");
+    assert_eq!(shell_escape("1.2.3; rm -rf ."), "'1.2.3; rm -rf .'");
+    assert_eq!(shell_escape("it's"), "'it'\\''s'");
+    assert_eq!(shell_escape(""), "''");
   }
 
   #[test]
-  fn test_angular_size() {
-    let config = r#"
-projects: []
-sizes:
-  use_angular: true
-"#;
-
-    let config = ConfigFile::read(config).unwrap();
-
-    assert_eq!(&Size::Major, config.sizes.get("!").unwrap());
-    assert_eq!(&Size::None, config.sizes.get("build").unwrap());
-    assert_eq!(&Size::None, config.sizes.get("chore").unwrap());
-    assert_eq!(&Size::None, config.sizes.get("ci").unwrap());
-    assert_eq!(&Size::None, config.sizes.get("docs").unwrap());
-    assert_eq!(&Size::Minor, config.sizes.get("feat").unwrap());
-    assert_eq!(&Size::Patch, config.sizes.get("fix").unwrap());
-    assert_eq!(&Size::None, config.sizes.get("perf").unwrap());
-    assert_eq!(&Size::None, config.sizes.get("refactor").unwrap());
-    assert_eq!(&Size::None, config.sizes.get("style").unwrap());
-    assert_eq!(&Size::None, config.sizes.get("test").unwrap());
+  fn test_builder_yaml_quoting() {
+    let pattern = r#"version = ['"](\d+\.\d+\.\d+)['"]"#;
+    let hook = r#"echo "it's: done" # really"#;
+    let builder = ConfigBuilder::new().prev_tag("prev: 'tag'").project(
+      ProjectBuilder::new("a \"quoted\": name")
+        .labels(&["- dash", "yes"])
+        .version_file("version.py", Some(("pattern", pattern)))
+        .hook("post_write", hook)
+    );
+
+    let read = ConfigFile::read(&builder.to_yaml().unwrap()).unwrap();
+    assert_eq!("prev: 'tag'", read.prev_tag());
+    let proj = &read.projects()[0];
+    assert_eq!("a \"quoted\": name", proj.name());
+    assert_eq!(&["- dash".to_string(), "yes".to_string()], proj.labels());
+    assert_eq!(hook, proj.hooks.get("post_write").unwrap().cmd);
+    let mark = proj.location().unwrap().picker().find("version = '1.2.3'\n").unwrap();
+    assert_eq!("1.2.3", mark.value());
   }
 }
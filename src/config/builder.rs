@@ -0,0 +1,270 @@
+//! Construct configs in code, and write them out as YAML.
+
+use super::{default_includes, ConfigFile, DefaultTagSpec, FileLocation, Hook, HookSet, Location, Options, Project,
+            ProjectId, Subs, TagLocation, TagSpec};
+use crate::errors::Result;
+use crate::mark::{CustomPicker, FilePicker, LinePicker, Picker, ScanningPicker};
+use crate::scan::parts::IntoPartVec;
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+
+/// Builds a `ConfigFile`, or the contents of a `.versio.yaml` file that would read as that config.
+pub struct ConfigBuilder {
+  prev_tag: String,
+  projects: Vec<ProjectBuilder>
+}
+
+impl Default for ConfigBuilder {
+  fn default() -> ConfigBuilder { ConfigBuilder::new() }
+}
+
+impl ConfigBuilder {
+  pub fn new() -> ConfigBuilder { ConfigBuilder { prev_tag: "versio-prev".into(), projects: Vec::new() } }
+
+  pub fn prev_tag(mut self, prev_tag: impl ToString) -> ConfigBuilder {
+    self.prev_tag = prev_tag.to_string();
+    self
+  }
+
+  pub fn project(mut self, project: ProjectBuilder) -> ConfigBuilder {
+    self.projects.push(project);
+    self
+  }
+
+  pub fn projects(&self) -> &[ProjectBuilder] { &self.projects }
+
+  /// Construct the config, which is validated just like a config file. Unlike a config that is read from a repo,
+  /// projects with `subs` aren't expanded.
+  pub fn build(&self) -> Result<ConfigFile> {
+    let options = Options { prev_tag: self.prev_tag.clone(), ..Default::default() };
+    let projects = self.projects.iter().map(|p| p.build()).collect::<Result<_>>()?;
+    let has_subs = self.projects.iter().any(|p| p.subs);
+    let file = ConfigFile { options, projects, has_subs, ..Default::default() };
+    file.validate()?;
+    Ok(file)
+  }
+
+  /// The contents of a `.versio.yaml` file that reads as this config.
+  pub fn to_yaml(&self) -> Result<String> {
+    let mut yaml = Mapping::new();
+    yaml.insert("options".into(), single("prev_tag", self.prev_tag.as_str()));
+    if !self.projects.is_empty() {
+      yaml.insert("projects".into(), Value::Sequence(self.projects.iter().map(|p| p.to_yaml()).collect()));
+    }
+    let mut sizes = Mapping::new();
+    sizes.insert("use_angular".into(), true.into());
+    sizes.insert("fail".into(), vec!["*"].into());
+    yaml.insert("sizes".into(), Value::Mapping(sizes));
+    Ok(serde_yaml::to_string(&yaml)?)
+  }
+}
+
+/// Builds a single project of a config.
+pub struct ProjectBuilder {
+  name: String,
+  id: u32,
+  root: String,
+  tag_prefix: Option<String>,
+  labels: Vec<String>,
+  includes: Vec<String>,
+  excludes: Vec<String>,
+  version: VersionSpec,
+  hooks: BTreeMap<String, String>,
+  subs: bool
+}
+
+enum VersionSpec {
+  File { file: String, kind: Option<(String, String)> },
+  Tags { default: String }
+}
+
+impl ProjectBuilder {
+  /// A new project, whose version is kept in tags until another location is given.
+  pub fn new(name: impl ToString) -> ProjectBuilder {
+    ProjectBuilder {
+      name: name.to_string(),
+      id: 1,
+      root: ".".into(),
+      tag_prefix: None,
+      labels: Vec::new(),
+      includes: Vec::new(),
+      excludes: Vec::new(),
+      version: VersionSpec::Tags { default: "0.0.0".into() },
+      hooks: BTreeMap::new(),
+      subs: false
+    }
+  }
+
+  pub fn name(&self) -> &str { &self.name }
+
+  pub fn id(mut self, id: u32) -> ProjectBuilder {
+    self.id = id;
+    self
+  }
+
+  pub fn root(mut self, root: impl ToString) -> ProjectBuilder {
+    self.root = root.to_string();
+    self
+  }
+
+  pub fn tag_prefix(mut self, tag_prefix: impl ToString) -> ProjectBuilder {
+    self.tag_prefix = Some(tag_prefix.to_string());
+    self
+  }
+
+  pub fn labels(mut self, labels: &[impl ToString]) -> ProjectBuilder {
+    self.labels.extend(labels.iter().map(|l| l.to_string()));
+    self
+  }
+
+  pub fn include(mut self, include: impl ToString) -> ProjectBuilder {
+    self.includes.push(include.to_string());
+    self
+  }
+
+  pub fn exclude(mut self, exclude: impl ToString) -> ProjectBuilder {
+    self.excludes.push(exclude.to_string());
+    self
+  }
+
+  /// Keep the version in a file. `kind` is `json`, `yaml`, `toml`, `xml`, `pattern`, or a registered custom
+  /// scanner, and `parts` is its value; if no kind is given, the file holds only the version.
+  pub fn version_file(mut self, file: impl ToString, kind: Option<(&str, &str)>) -> ProjectBuilder {
+    let kind = kind.map(|(k, p)| (k.to_string(), p.to_string()));
+    self.version = VersionSpec::File { file: file.to_string(), kind };
+    self
+  }
+
+  pub fn version_tags(mut self, default: impl ToString) -> ProjectBuilder {
+    self.version = VersionSpec::Tags { default: default.to_string() };
+    self
+  }
+
+  pub fn hook(mut self, key: impl ToString, cmd: impl ToString) -> ProjectBuilder {
+    self.hooks.insert(key.to_string(), cmd.to_string());
+    self
+  }
+
+  pub fn subs(mut self, subs: bool) -> ProjectBuilder {
+    self.subs = subs;
+    self
+  }
+
+  fn build(&self) -> Result<Project> {
+    let hooks = self.hooks.iter().map(|(k, v)| (k.clone(), Hook { cmd: v.clone() })).collect();
+    Ok(Project {
+      name: self.name.clone(),
+      id: ProjectId::from_id(self.id),
+      root: self.rel_root().map(|r| r.to_string()),
+      includes: if self.includes.is_empty() { default_includes() } else { self.includes.clone() },
+      excludes: self.excludes.clone(),
+      depends: Default::default(),
+      changelog: None,
+      kind: None,
+      version: Some(self.location()?),
+      also: Vec::new(),
+      labels: self.labels.clone(),
+      aliases: Vec::new(),
+      owners: Vec::new(),
+      tag_prefix: self.tag_prefix.clone(),
+      tag_format: None,
+      tag_message: None,
+      alias_tags: Vec::new(),
+      floating_tags: false,
+      subs: if self.subs { Some(Subs { dirs: None, tops: None }) } else { None },
+      hooks: HookSet { hooks },
+      sizes: Default::default(),
+      restrictions: Vec::new(),
+      umbrella: false,
+      path_sizes: Vec::new(),
+      priority: 0,
+      min_size: None,
+      max_size: None,
+      first_release: None,
+      publish: None,
+      workspace_sync: false,
+      maven_sync: false,
+      images: Vec::new(),
+      deploys: false,
+      formulas: Vec::new(),
+      globs: None
+    })
+  }
+
+  fn location(&self) -> Result<Location> {
+    match &self.version {
+      VersionSpec::Tags { default } => {
+        Ok(Location::Tag(TagLocation { tags: TagSpec::DefaultTag(DefaultTagSpec { default: default.clone() }) }))
+      }
+      VersionSpec::File { file, kind } => {
+        let picker = match kind {
+          None => Picker::File(FilePicker {}),
+          Some((kind, parts)) => match kind.as_str() {
+            "pattern" => Picker::Line(LinePicker::new(parts.clone())),
+            "json" => Picker::Json(ScanningPicker::new(parts.as_str().into_part_vec())),
+            "yaml" => Picker::Yaml(ScanningPicker::new(parts.as_str().into_part_vec())),
+            "toml" => Picker::Toml(ScanningPicker::new(parts.as_str().into_part_vec())),
+            "xml" => Picker::Xml(ScanningPicker::new(parts.as_str().into_part_vec())),
+            other => Picker::Custom(CustomPicker::new(other.to_string(), parts.as_str().into_part_vec()))
+          }
+        };
+        Ok(Location::File(FileLocation { file: file.clone(), format: None, picker }))
+      }
+    }
+  }
+
+  fn rel_root(&self) -> Option<&str> {
+    if self.root == "." {
+      None
+    } else if let Some(root) = self.root.strip_prefix("./") {
+      Some(root)
+    } else {
+      Some(&self.root)
+    }
+  }
+
+  fn to_yaml(&self) -> Value {
+    let mut proj = Mapping::new();
+    proj.insert("name".into(), self.name.as_str().into());
+    if let Some(root) = self.rel_root() {
+      proj.insert("root".into(), root.into());
+    }
+    proj.insert("id".into(), self.id.into());
+    if let Some(tag_prefix) = &self.tag_prefix {
+      proj.insert("tag_prefix".into(), tag_prefix.as_str().into());
+    }
+    for (key, vals) in &[("labels", &self.labels), ("includes", &self.includes), ("excludes", &self.excludes)] {
+      if !vals.is_empty() {
+        proj.insert((*key).into(), vals.to_vec().into());
+      }
+    }
+
+    let version = match &self.version {
+      VersionSpec::File { file, kind } => {
+        let mut version = Mapping::new();
+        version.insert("file".into(), file.as_str().into());
+        if let Some((kind, parts)) = kind {
+          version.insert(kind.as_str().into(), parts.as_str().into());
+        }
+        Value::Mapping(version)
+      }
+      VersionSpec::Tags { default } => single("tags", single("default", default.as_str()))
+    };
+    proj.insert("version".into(), version);
+
+    if !self.hooks.is_empty() {
+      let hooks = self.hooks.iter().map(|(k, v)| (k.as_str().into(), v.as_str().into())).collect();
+      proj.insert("hooks".into(), Value::Mapping(hooks));
+    }
+    if self.subs {
+      proj.insert("subs".into(), Value::Mapping(Mapping::new()));
+    }
+    Value::Mapping(proj)
+  }
+}
+
+fn single(key: &str, val: impl Into<Value>) -> Value {
+  let mut map = Mapping::new();
+  map.insert(key.into(), val.into());
+  Value::Mapping(map)
+}
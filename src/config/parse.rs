@@ -0,0 +1,179 @@
+//! Read config YAML, with errors that point at the failing key.
+
+use crate::errors::Result;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+/// Deserialize the YAML `data` of the config `file`. A failure names the file, the line and column, and the key
+/// path where it happened, with a hint if it looks like a common mistake.
+pub fn parse_yaml<T: DeserializeOwned>(data: &str, file: &str) -> Result<T> {
+  serde_yaml::from_str(data).map_err(|e| describe(data, file, &e).into())
+}
+
+fn describe(data: &str, file: &str, err: &serde_yaml::Error) -> String {
+  let location = err.location();
+  let path = location.as_ref().and_then(|loc| key_path(data, loc.index()));
+
+  let mut msg = err.to_string();
+  if let Some(at) = Regex::new(r" at line \d+ column \d+$").unwrap().find(&msg) {
+    msg.truncate(at.start());
+  }
+  if let Some(path) = &path {
+    if let Some(rest) = msg.strip_prefix(&format!("{}: ", path)) {
+      msg = rest.to_string();
+    }
+  }
+
+  let mut desc = file.to_string();
+  if let Some(loc) = &location {
+    desc.push_str(&format!(" line {}, column {}", loc.line(), loc.column()));
+  }
+  if let Some(path) = &path {
+    desc.push_str(&format!(" (at `{}`)", path));
+  }
+  desc.push_str(": ");
+  desc.push_str(&msg);
+  if let Some(hint) = hint(data, location.as_ref().map(|loc| loc.line()), &msg) {
+    if !desc.ends_with('.') {
+      desc.push('.');
+    }
+    desc.push_str(&format!(" Hint: {}", hint));
+  }
+  desc
+}
+
+/// Suggest a fix for some common config mistakes.
+fn hint(data: &str, line: Option<usize>, msg: &str) -> Option<String> {
+  let err_line = line.and_then(|line| data.lines().nth(line.saturating_sub(1))).unwrap_or("");
+
+  if err_line.trim_start_matches(' ').starts_with('\t') || msg.contains("cannot start any token") {
+    return Some("YAML must be indented with spaces, not tabs.".into());
+  }
+  if msg.contains("mapping values are not allowed") {
+    return Some("A value that contains \": \" must be quoted.".into());
+  }
+
+  let unknown = Regex::new(r"^unknown (?:field|variant) `([^`]*)`, expected (.*)$").unwrap();
+  if let Some(caps) = unknown.captures(msg) {
+    let quoted = Regex::new(r"`([^`]*)`").unwrap();
+    let closest = quoted
+      .captures_iter(&caps[2])
+      .map(|c| c[1].to_string())
+      .map(|e| (distance(&caps[1], &e), e))
+      .filter(|(d, _)| *d <= 2)
+      .min_by_key(|(d, _)| *d)
+      .map(|(_, e)| e);
+    return closest.map(|e| format!("Did you mean `{}`?", e));
+  }
+
+  if let Some(caps) = Regex::new(r"^missing field `([^`]*)`").unwrap().captures(msg) {
+    return Some(format!("Add the required `{}` property.", &caps[1]));
+  }
+  if msg.starts_with("invalid type") && msg.ends_with("expected a sequence") {
+    return Some("This property takes a list, like `[ a, b ]`.".into());
+  }
+  None
+}
+
+/// The edit distance between two short strings.
+fn distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut row: Vec<usize> = (0 ..= b.len()).collect();
+  for (i, ca) in a.chars().enumerate() {
+    let mut prev = row[0];
+    row[0] = i + 1;
+    for (j, cb) in b.iter().enumerate() {
+      let cur = row[j + 1];
+      row[j + 1] = if ca == *cb { prev } else { 1 + prev.min(cur).min(row[j]) };
+      prev = cur;
+    }
+  }
+  row[b.len()]
+}
+
+/// Find the key path (like `projects[0].version`) of the last YAML node that starts at or before `index`.
+fn key_path(data: &str, index: usize) -> Option<String> {
+  let mut rcvr = PathReceiver { target: index, stack: Vec::new(), found: None };
+  // A syntax error ends the parse, but the path up to the error is still useful.
+  let _ = Parser::new(data.chars()).load(&mut rcvr, false);
+  rcvr.found.filter(|p| !p.is_empty())
+}
+
+enum Frame {
+  Map(Option<String>),
+  Seq(usize)
+}
+
+struct PathReceiver {
+  target: usize,
+  stack: Vec<Frame>,
+  found: Option<String>
+}
+
+impl PathReceiver {
+  fn path(&self) -> String {
+    let mut path = String::new();
+    for frame in &self.stack {
+      match frame {
+        Frame::Map(Some(key)) => {
+          if !path.is_empty() {
+            path.push('.');
+          }
+          path.push_str(key);
+        }
+        Frame::Map(None) => (),
+        Frame::Seq(i) => path.push_str(&format!("[{}]", i))
+      }
+    }
+    path
+  }
+
+  fn visit(&mut self, mark: Marker) {
+    if mark.index() <= self.target {
+      self.found = Some(self.path());
+    }
+  }
+
+  /// True if the next node is a mapping key.
+  fn at_key(&self) -> bool { matches!(self.stack.last(), Some(Frame::Map(None))) }
+
+  /// Move past a completed value.
+  fn advance(&mut self) {
+    match self.stack.last_mut() {
+      Some(Frame::Map(key)) => *key = None,
+      Some(Frame::Seq(i)) => *i += 1,
+      None => ()
+    }
+  }
+}
+
+impl MarkedEventReceiver for PathReceiver {
+  fn on_event(&mut self, ev: Event, mark: Marker) {
+    match ev {
+      Event::Scalar(val, ..) if self.at_key() => {
+        self.stack.pop();
+        self.stack.push(Frame::Map(Some(val)));
+        self.visit(mark);
+      }
+      Event::Scalar(..) | Event::Alias(_) => {
+        self.visit(mark);
+        self.advance();
+      }
+      Event::MappingStart(_) => {
+        self.visit(mark);
+        self.stack.push(Frame::Map(None));
+      }
+      Event::SequenceStart(_) => {
+        self.visit(mark);
+        self.stack.push(Frame::Seq(0));
+      }
+      Event::MappingEnd | Event::SequenceEnd => {
+        self.stack.pop();
+        self.advance();
+      }
+      _ => ()
+    }
+  }
+}
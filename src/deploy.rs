@@ -0,0 +1,84 @@
+//! Starting the deploy workflows of a release on GitHub, with a deployment or a `repository_dispatch` event for each
+//! released project.
+
+use crate::cancel::{Cancel, Phase};
+use crate::config::GithubDeploy;
+use crate::errors::{Error, Result, ResultExt as _};
+use crate::git::GithubInfo;
+use crate::notify::Released;
+use crate::vcs::is_offline;
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use log::warn;
+use serde_json::json;
+
+/// Create a deployment or dispatch event for each released project at `head`. As with webhooks, a failure only
+/// causes a warning, since the release itself is already complete.
+pub async fn deploy_all(
+  deploy: &GithubDeploy, github: Result<GithubInfo>, head: &str, released: &[Released], cancel: &Cancel
+) {
+  if released.is_empty() {
+    return;
+  }
+  if is_offline() {
+    warn!("Offline: not triggering {} GitHub deploy(s).", released.len());
+    return;
+  }
+  let github = match github {
+    Ok(github) if github.token().is_some() => github,
+    Ok(_) => {
+      warn!("No GitHub token: not triggering GitHub deploys.");
+      return;
+    }
+    Err(e) => {
+      warn!("Unable to trigger GitHub deploys: {}", e);
+      return;
+    }
+  };
+
+  for proj in released {
+    if let Err(e) = deploy_one(deploy, &github, head, proj, cancel).await {
+      warn!("Unable to trigger GitHub deploy of {}: {}", proj.name(), e);
+    }
+  }
+}
+
+async fn deploy_one(
+  deploy: &GithubDeploy, github: &GithubInfo, head: &str, proj: &Released, cancel: &Cancel
+) -> Result<()> {
+  let payload = json!({ "project": proj.name(), "id": proj.id(), "version": proj.new_version(), "prev": proj.old() });
+  let (route, body) = match (deploy.environment(), deploy.event_type()) {
+    (Some(environment), _) => {
+      let body = json!({
+        "ref": head,
+        "environment": environment,
+        "description": format!("Release {} {}", proj.name(), proj.new_version()),
+        "auto_merge": false,
+        "required_contexts": [],
+        "payload": payload
+      });
+      ("deployments", body)
+    }
+    (None, Some(event_type)) => ("dispatches", json!({ "event_type": event_type, "client_payload": payload })),
+    (None, None) => bail!("No environment or event_type for GitHub deploys.")
+  };
+
+  let url = format!("https://api.github.com/repos/{}/{}/{}", github.owner_name(), github.repo_name(), route);
+  let token = github.token().as_deref().unwrap_or_default();
+  let request = Request::post(&url)
+    .header("accept", "application/vnd.github.v3+json")
+    .header("authorization", format!("token {}", token))
+    .header("content-type", "application/json")
+    .header("user-agent", "versio")
+    .body(Body::from(body.to_string()))
+    .map_err(|e| bad!("Bad GitHub deploy request: {}", e))?;
+
+  let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+  let resp = cancel
+    .guard(Phase::Api, async { client.request(request).await.chain_err(|| Error::network("the GitHub API")) })
+    .await?;
+  if !resp.status().is_success() {
+    bail!("Unsuccessful GitHub request: {}", resp.status().as_u16());
+  }
+  Ok(())
+}
@@ -1,53 +1,196 @@
-//! Error handling for Versio is all based on `error-chain`.
+//! Error handling for Versio.
+//!
+//! `Error` is a plain enum, so that callers can match on the kinds of errors that they may want to tell apart:
+//! `Config`, `Vcs`, `Network`, `Plan`, and `Cancelled`. These are usually found wrapped around a more specific cause,
+//! which is their `source`; `Error::exit_code` finds the outermost of them in the chain. Errors from other crates
+//! have their own variants, which only exist with the features that use those crates, and `Msg` holds everything
+//! else.
 
-use error_chain::error_chain;
+use std::error::Error as StdError;
+use std::iter::successors;
+use thiserror::Error;
 
-error_chain! {
-  links {
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The cause of an error.
+pub type Source = Box<dyn StdError + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("Invalid configuration in \"{file}\".")]
+  Config {
+    file: String,
+    #[source]
+    source: Option<Source>
+  },
+  #[error("Unable to {action}.")]
+  Vcs {
+    action: String,
+    #[source]
+    source: Option<Source>
+  },
+  #[error("Unable to reach {service}.")]
+  Network {
+    service: String,
+    #[source]
+    source: Option<Source>
+  },
+  #[error("Unable to plan release: {reason}.")]
+  Plan {
+    reason: String,
+    #[source]
+    source: Option<Source>
+  },
+  #[error("Stopped: {reason}.")]
+  Cancelled { reason: String },
+  #[error("{msg}")]
+  Msg {
+    msg: String,
+    #[source]
+    source: Option<Source>
+  },
+  #[error(transparent)]
+  Num(#[from] std::num::ParseIntError),
+  #[error(transparent)]
+  Io(#[from] std::io::Error),
+  #[cfg(feature = "git")]
+  #[error(transparent)]
+  Git2(#[from] git2::Error),
+  #[error(transparent)]
+  Yaml(#[from] yaml_rust::scanner::ScanError),
+  #[error(transparent)]
+  SerdeYaml(#[from] serde_yaml::Error),
+  #[error(transparent)]
+  SerdeJson(#[from] serde_json::Error),
+  #[error(transparent)]
+  Toml(#[from] toml::de::Error),
+  #[error(transparent)]
+  Regex(#[from] regex::Error),
+  #[error(transparent)]
+  Utf(#[from] std::str::Utf8Error),
+  #[error(transparent)]
+  FromUtf(#[from] std::string::FromUtf8Error),
+  #[error(transparent)]
+  Glob(#[from] glob::PatternError),
+  #[error(transparent)]
+  GlobSet(#[from] globset::Error),
+  #[error(transparent)]
+  Xml(#[from] xmlparser::Error),
+  #[cfg(feature = "cli")]
+  #[error(transparent)]
+  Log(#[from] log::SetLoggerError),
+  #[cfg(feature = "network")]
+  #[error(transparent)]
+  Octo(#[from] octocrab::Error),
+  #[cfg(feature = "cli")]
+  #[error(transparent)]
+  Liquid(#[from] liquid::Error),
+  #[cfg(feature = "cli")]
+  #[error(transparent)]
+  Ignore(#[from] ignore::Error),
+  #[cfg(feature = "network")]
+  #[error(transparent)]
+  Hyper(#[from] hyper::Error),
+  #[cfg(feature = "network")]
+  #[error(transparent)]
+  HyperInvalid(#[from] hyper::http::uri::InvalidUri)
+}
+
+impl Error {
+  pub fn msg<S: Into<String>>(msg: S) -> Error { Error::Msg { msg: msg.into(), source: None } }
+  pub fn config<S: Into<String>>(file: S) -> Error { Error::Config { file: file.into(), source: None } }
+  pub fn vcs<S: Into<String>>(action: S) -> Error { Error::Vcs { action: action.into(), source: None } }
+  pub fn network<S: Into<String>>(service: S) -> Error { Error::Network { service: service.into(), source: None } }
+  pub fn plan<S: Into<String>>(reason: S) -> Error { Error::Plan { reason: reason.into(), source: None } }
+  pub fn cancelled<S: Into<String>>(reason: S) -> Error { Error::Cancelled { reason: reason.into() } }
+
+  /// Wrap `cause` in this error. An error that can't hold a cause becomes a message that does.
+  pub fn with_source<E: Into<Source>>(self, cause: E) -> Error {
+    let cause = Some(cause.into());
+    match self {
+      Error::Config { file, .. } => Error::Config { file, source: cause },
+      Error::Vcs { action, .. } => Error::Vcs { action, source: cause },
+      Error::Network { service, .. } => Error::Network { service, source: cause },
+      Error::Plan { reason, .. } => Error::Plan { reason, source: cause },
+      Error::Msg { msg, .. } => Error::Msg { msg, source: cause },
+      other => Error::Msg { msg: other.to_string(), source: cause }
+    }
   }
 
-  foreign_links {
-    Num(std::num::ParseIntError);
-    Io(std::io::Error);
-    Git2(git2::Error);
-    Yaml(yaml_rust::scanner::ScanError);
-    SerdeYaml(serde_yaml::Error);
-    SerdeJson(serde_json::Error);
-    Toml(toml::de::Error);
-    Regex(regex::Error);
-    Utf(std::str::Utf8Error);
-    FromUtf(std::string::FromUtf8Error);
-    Glob(glob::PatternError);
-    Xml(xmlparser::Error);
-    Log(log::SetLoggerError);
-    Octo(octocrab::Error);
-    Liquid(liquid::Error);
-    Ignore(ignore::Error);
-    Hyper(hyper::Error);
-    HyperInvalid(hyper::http::uri::InvalidUri);
+  /// This error, followed by each of its causes in turn.
+  pub fn iter(&self) -> impl Iterator<Item = &(dyn StdError + 'static)> {
+    successors(Some(self as &(dyn StdError + 'static)), |&e| e.source())
   }
+
+  /// The exit code of the `versio` command for this error: 2 for a bad config, 3 for a VCS failure, 4 for a network
+  /// failure, 5 if the release can't be planned, 6 if the run was cancelled or timed out, and 1 for anything else.
+  pub fn exit_code(&self) -> i32 {
+    match self {
+      Error::Config { .. } => 2,
+      Error::Vcs { .. } => 3,
+      #[cfg(feature = "git")]
+      Error::Git2(_) => 3,
+      Error::Network { .. } => 4,
+      #[cfg(feature = "network")]
+      Error::Octo(_) | Error::Hyper(_) | Error::HyperInvalid(_) => 4,
+      Error::Plan { .. } => 5,
+      Error::Cancelled { .. } => 6,
+      _ => self.source().and_then(|e| e.downcast_ref::<Error>()).map(|e| e.exit_code()).unwrap_or(1)
+    }
+  }
+}
+
+impl From<String> for Error {
+  fn from(msg: String) -> Error { Error::msg(msg) }
+}
+
+impl From<&str> for Error {
+  fn from(msg: &str) -> Error { Error::msg(msg) }
 }
 
 impl<'a, T: ?Sized> From<std::sync::PoisonError<std::sync::MutexGuard<'a, T>>> for Error {
   fn from(err: std::sync::PoisonError<std::sync::MutexGuard<'a, T>>) -> Error {
-    format!("serde yaml error {:?}", err).into()
+    Error::msg(format!("serde yaml error {:?}", err))
   }
 }
 
+#[cfg(feature = "gpgme")]
 impl From<gpgme::Error> for Error {
-  fn from(err: gpgme::Error) -> Error { format!("gpgme error {:?}", err).into() }
+  fn from(err: gpgme::Error) -> Error { Error::msg(format!("gpgme error {:?}", err)) }
+}
+
+/// Adds context to the error of a result.
+pub trait ResultExt<T> {
+  /// Wrap the error, if any, in the error made by `wrap`.
+  fn chain_err<F: FnOnce() -> K, K: Into<Error>>(self, wrap: F) -> Result<T>;
+}
+
+impl<T, E: StdError + Send + Sync + 'static> ResultExt<T> for std::result::Result<T, E> {
+  fn chain_err<F: FnOnce() -> K, K: Into<Error>>(self, wrap: F) -> Result<T> {
+    self.map_err(|e| wrap().into().with_source(e))
+  }
+}
+
+#[macro_export]
+macro_rules! bail {
+  ($e:expr) => {
+    return std::result::Result::Err($e.into())
+  };
+  ($fmt:expr, $($arg:tt)+) => {
+    return std::result::Result::Err($crate::errors::Error::msg(format!($fmt, $($arg)+)))
+  };
 }
 
 #[macro_export]
 macro_rules! err {
   ($($arg:tt)*) => (
-    std::result::Result::Err($crate::errors::Error::from_kind($crate::errors::ErrorKind::Msg(format!($($arg)*))))
+    std::result::Result::Err($crate::errors::Error::msg(format!($($arg)*)))
   )
 }
 
 #[macro_export]
 macro_rules! bad {
-  ($($arg:tt)*) => ($crate::errors::Error::from_kind($crate::errors::ErrorKind::Msg(format!($($arg)*))))
+  ($($arg:tt)*) => ($crate::errors::Error::msg(format!($($arg)*)))
 }
 
 #[macro_export]
@@ -79,3 +222,26 @@ macro_rules! assert_ok {
     }
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::{Error, Result, ResultExt};
+
+  fn fail_plan() -> Result<()> { bail!(Error::plan("no good")) }
+
+  #[test]
+  fn test_chain_keeps_cause() {
+    let err = fail_plan().chain_err(|| "Unable to release.").unwrap_err();
+    assert!(matches!(&err, Error::Msg { msg, .. } if msg == "Unable to release."));
+    let chain: Vec<_> = err.iter().map(|e| e.to_string()).collect();
+    assert_eq!(chain, vec!["Unable to release.", "Unable to plan release: no good."]);
+    assert_eq!(5, err.exit_code());
+  }
+
+  #[test]
+  fn test_outer_kind_wins() {
+    let err = fail_plan().chain_err(|| Error::vcs("push")).unwrap_err();
+    assert!(matches!(&err, Error::Vcs { action, source: Some(_) } if action == "push"));
+    assert_eq!(3, err.exit_code());
+  }
+}
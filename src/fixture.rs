@@ -0,0 +1,96 @@
+//! Git repositories for tests, built in temporary directories.
+
+use crate::config::{ConfigFile, ProjectId};
+use crate::mono::Mono;
+use crate::state::{MemoryState, PrevTagMessage};
+use crate::vcs::{VcsLevel, VcsState};
+use git2::build::CheckoutBuilder;
+use git2::{IndexAddOption, ObjectType, Oid, Repository, Signature};
+use path_slash::PathExt as _;
+use std::collections::HashMap;
+use std::env::set_current_dir;
+use std::fs::{create_dir_all, write};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use tempfile::{tempdir, TempDir};
+
+/// Versio reads and writes project files relative to the working directory, which is shared by all tests.
+static CWD: Mutex<()> = Mutex::new(());
+
+/// A repo in a temporary directory, which is the working directory until the fixture is dropped.
+pub struct Fixture {
+  repo: Repository,
+  dir: TempDir,
+  _cwd: MutexGuard<'static, ()>
+}
+
+impl Fixture {
+  /// Create a repo with the given files in its first commit.
+  pub fn new(files: &[(&str, &str)]) -> Fixture {
+    let cwd = CWD.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = tempdir().unwrap();
+    set_current_dir(dir.path()).unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let fixture = Fixture { repo, dir, _cwd: cwd };
+
+    for (path, content) in files {
+      fixture.write(path, content);
+    }
+    fixture.commit("feat: start");
+    fixture
+  }
+
+  /// Create a repo that stands in for a memory state: the state's files are in the first commit, and its tagged
+  /// versions are in the prev tag, so that the repo's `Mono` reads the same versions as the state does.
+  pub fn from_state(state: &MemoryState) -> Fixture {
+    let files: Vec<_> = state.files().iter().map(|(path, content)| (path.to_slash_lossy(), content.as_str())).collect();
+    let files: Vec<_> = files.iter().map(|(path, content)| (path.as_str(), *content)).collect();
+    let fixture = Fixture::new(&files);
+
+    let prev_tag = ConfigFile::from_read(state).unwrap().prev_tag().to_string();
+    fixture.tag_prev(&prev_tag, state.tags().clone());
+    fixture
+  }
+
+  pub fn path(&self) -> &Path { self.dir.path() }
+  pub fn repo(&self) -> &Repository { &self.repo }
+
+  pub fn write(&self, path: &str, content: &str) {
+    let path = self.dir.path().join(path);
+    create_dir_all(path.parent().unwrap()).unwrap();
+    write(path, content).unwrap();
+  }
+
+  /// Commit everything in the working directory on top of HEAD.
+  pub fn commit(&self, msg: &str) -> Oid {
+    let mut index = self.repo.index().unwrap();
+    index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None).unwrap();
+    index.write().unwrap();
+    let tree = self.repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let parent = self.repo.head().ok().map(|h| h.peel_to_commit().unwrap());
+    let sig = Signature::now("Tester", "tester@example.com").unwrap();
+    self.repo.commit(Some("HEAD"), &sig, &sig, msg, &tree, &parent.iter().collect::<Vec<_>>()).unwrap()
+  }
+
+  /// Create a branch at the given commit, and check it out.
+  pub fn branch(&self, name: &str, at: Oid) {
+    self.repo.branch(name, &self.repo.find_commit(at).unwrap(), true).unwrap();
+    self.repo.set_head(&format!("refs/heads/{}", name)).unwrap();
+    self.repo.checkout_head(Some(CheckoutBuilder::new().force())).unwrap();
+  }
+
+  /// Put the annotated prev tag on HEAD, as a release of the given versions would.
+  pub fn tag_prev(&self, prev_tag: &str, versions: HashMap<ProjectId, String>) {
+    let msg = serde_json::to_string(&PrevTagMessage::new(versions)).unwrap();
+    let sig = Signature::now("Tester", "tester@example.com").unwrap();
+    let head = self.repo.head().unwrap().peel(ObjectType::Commit).unwrap();
+    self.repo.tag(prev_tag, &head, &sig, &msg, true).unwrap();
+  }
+
+  /// Put a lightweight tag on the given commit.
+  pub fn tag(&self, name: &str, at: Oid) {
+    self.repo.tag_lightweight(name, &self.repo.find_object(at, None).unwrap(), true).unwrap();
+  }
+
+  pub fn mono(&self) -> Mono { Mono::open(".", VcsState::new(VcsLevel::Local, false)).unwrap() }
+}
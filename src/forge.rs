@@ -0,0 +1,433 @@
+//! Pull requests from a code forge, such as GitHub, which group the commits of a release.
+//!
+//! The grouping itself doesn't depend on the forge: a `PrProvider` only has to say which PRs each commit belongs
+//! to, and `changes` does the rest with the local repository.
+
+use crate::clock;
+use crate::config::ProviderConfig;
+use crate::errors::{Error, Result, ResultExt as _};
+use crate::git::{Auth, CommitInfoBuf, FromTag, FromTagBuf, FullPr, Repo, Span};
+use crate::github::GithubProvider;
+use crate::progress::Progress;
+use crate::timing::Timer;
+use chrono::{DateTime, FixedOffset, TimeZone};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+pub type ProviderFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// The most API calls that are made at once.
+const MAX_CALLS: usize = 4;
+
+/// A forge that knows which PRs each commit belongs to.
+pub trait PrProvider: Send + Sync {
+  /// The name of the forge's API, as used in errors.
+  fn api_name(&self) -> &'static str;
+
+  /// The commits of a span, from its end back to its `since` time, each with the merged or open PRs that the forge
+  /// associates with it.
+  fn span_commits<'a>(&'a self, span: &'a Span) -> ProviderFuture<'a, Vec<ForgeCommit>>;
+
+  /// The web page of a PR, if the forge has one.
+  fn pr_url(&self, _number: u32) -> Option<String> { None }
+
+  /// The web page of a commit, if the forge has one.
+  fn commit_url(&self, _oid: &str) -> Option<String> { None }
+}
+
+/// The provider that the config asks for. For the default GitHub provider, this is `None` if the repository
+/// doesn't have a GitHub remote that we can reach.
+pub fn find_provider(auth: &Auth, repo: &Repo, config: &ProviderConfig) -> Result<Option<Arc<dyn PrProvider>>> {
+  match config {
+    ProviderConfig::Github => Ok(
+      repo
+        .github_info(auth)
+        .ok()
+        .map(|info| Arc::new(GithubProvider::new(info, repo.cancel().clone())) as Arc<dyn PrProvider>)
+    ),
+    ProviderConfig::File { path } => {
+      let root = repo.working_dir()?;
+      Ok(Some(Arc::new(FileProvider::read(root, &root.join(path))?)))
+    }
+  }
+}
+
+/// A commit that a forge found in a span, with the PRs that it belongs to.
+pub struct ForgeCommit {
+  oid: String,
+  prs: Vec<ForgePr>
+}
+
+impl ForgeCommit {
+  pub fn new(oid: String, prs: Vec<ForgePr>) -> ForgeCommit { ForgeCommit { oid, prs } }
+  pub fn oid(&self) -> &str { &self.oid }
+  fn extract(self) -> (String, Vec<ForgePr>) { (self.oid, self.prs) }
+}
+
+/// The metadata of a PR, as told by a forge.
+#[derive(Clone)]
+pub struct ForgePr {
+  number: u32,
+  title: String,
+  head_ref: String,
+  base_oid: String,
+  closed_at: Option<DateTime<FixedOffset>>,
+  labels: Vec<String>
+}
+
+impl ForgePr {
+  pub fn new(
+    number: u32, title: String, head_ref: String, base_oid: String, closed_at: Option<DateTime<FixedOffset>>,
+    labels: Vec<String>
+  ) -> ForgePr {
+    ForgePr { number, title, head_ref, base_oid, closed_at, labels }
+  }
+
+  pub fn number(&self) -> u32 { self.number }
+
+  fn lookup(self, repo: &Repo, discover_order: usize) -> Result<FullPr> {
+    FullPr::lookup(
+      repo,
+      FromTagBuf::new(self.base_oid, false),
+      self.head_ref,
+      self.number,
+      self.title,
+      self.closed_at,
+      self.labels,
+      discover_order
+    )
+  }
+}
+
+/// Find all changes in a repo more cleverly than `git rev-parse begin..end`, grouping the commits into pull
+/// requests (PRs) as told by `provider`.
+///
+/// This method starts with "PR zero" (which is an artificial group that contains all commits in the given range)
+/// and for each commit, asks the provider for "associated pull requests". For each such associated PR, it performs
+/// a rev-parse on the base/head of that PR to search for more commits, and continues recursively. Each commit
+/// found may placed into more than one PR.
+///
+/// When a commit is found where it itself does not belong to one of its own associated PRs' "base..head"
+/// rev-parse, we assume that this is the result of a "squash merge" from that PR (or some other type of PR
+/// rebase). The squash commit is excluded from all PRs: instead the PR's own commits are examined normally. In
+/// this way, the original type and size information from the PR is preserved.
+#[allow(clippy::map_entry)]
+pub async fn changes(
+  provider: Option<Arc<dyn PrProvider>>, repo: &Repo, baseref: FromTagBuf, headref: String
+) -> Result<Changes> {
+  let mut all_commits = HashSet::new();
+  let mut all_prs = HashMap::new();
+
+  // Nothing has changed in a repository without any commits.
+  if repo.is_unborn() {
+    return Ok(Changes { groups: all_prs, commits: all_commits });
+  }
+
+  let mut discover_order = 0;
+  let mut queue = Calls(VecDeque::new());
+  let offset = FixedOffset::west(0);
+  let pr_zero = FullPr::lookup(
+    repo,
+    baseref,
+    headref.clone(),
+    0,
+    "".into(),
+    Some(offset.timestamp(clock::now().timestamp(), 0)),
+    Vec::new(),
+    discover_order
+  )?;
+  discover_order += 1;
+  let zero_span = pr_zero.span().ok_or_else(|| bad!("Unable to get oid for seed ref \"{}\".", headref))?;
+  all_prs.insert(pr_zero.number(), pr_zero);
+
+  let provider = match provider {
+    Some(provider) => provider,
+    None => return Ok(Changes { groups: all_prs, commits: all_commits })
+  };
+  let api_name = provider.api_name();
+
+  // Each span's API call is started as soon as the span is found, so that the calls overlap with each other and
+  // with the local lookups of each PR's commits, though no more than `MAX_CALLS` are made at once. The results are
+  // still handled in the order that the spans were found, so the changes are the same as if each call were made in
+  // turn.
+  let permits = Arc::new(Semaphore::new(MAX_CALLS));
+  let start_call = |span: Span| {
+    let provider = provider.clone();
+    let permits = permits.clone();
+    let number = span.number();
+    let call = tokio::spawn(async move {
+      let _permit = permits.acquire_owned().await.chain_err(|| "API calls were closed.")?;
+      provider.span_commits(&span).await
+    });
+    (number, call)
+  };
+  queue.0.push_back(start_call(zero_span));
+
+  let progress = Progress::start("Finding PRs");
+  while let Some((span_number, call)) = queue.0.pop_front() {
+    progress.inc();
+
+    // Timings are kept per thread, so only the time spent waiting here on the call is counted.
+    let timer = Timer::start("API calls");
+    let commit_list = call.await.chain_err(|| Error::network(api_name))??;
+    drop(timer);
+    let commit_list: Vec<_> = commit_list
+      .into_iter()
+      .filter_map(|commit| {
+        if all_commits.contains(commit.oid()) {
+          return None;
+        }
+
+        let mut retain = true;
+        let (oid, prs) = commit.extract();
+        for pr in prs {
+          let number = pr.number();
+          if !all_prs.contains_key(&number) {
+            let full_pr = match pr.lookup(repo, discover_order) {
+              Ok(pr) => pr,
+              Err(e) => return Some(Err(e))
+            };
+            discover_order += 1;
+            if let Some(span) = full_pr.span() {
+              queue.0.push_back(start_call(span));
+            }
+            all_prs.insert(number, full_pr);
+          }
+          let full_pr = all_prs.get_mut(&number).unwrap();
+
+          if full_pr.best_guess() {
+            full_pr.add_commit(&oid);
+          } else if !full_pr.contains(&oid) {
+            retain = false;
+          }
+        }
+
+        if retain {
+          Some(Ok(oid))
+        } else {
+          all_prs.get_mut(&span_number).unwrap().add_exclude(&oid);
+          None
+        }
+      })
+      .collect::<Result<_>>()?;
+
+    all_commits.extend(commit_list.into_iter());
+  }
+
+  Ok(Changes { commits: all_commits, groups: all_prs })
+}
+
+/// The API calls that are still to be handled, in order. Any that remain when the walk stops early are aborted.
+struct Calls(VecDeque<(u32, JoinHandle<Result<Vec<ForgeCommit>>>)>);
+
+impl Drop for Calls {
+  fn drop(&mut self) {
+    for (_, call) in &self.0 {
+      call.abort();
+    }
+  }
+}
+
+pub fn line_commits_head(repo: &Repo, base: FromTag) -> Result<Vec<CommitInfoBuf>> {
+  repo.commits_to_head(base, false)?.map(|i| i?.buffer()).collect::<Result<_>>()
+}
+
+/// The PRs found from the base to the head, along with the commits of each. These are all gathered before any of
+/// them are planned, since a later span can still add commits to a best-guess PR, or exclude them from an earlier
+/// one. So only the IDs of the commits are kept here: the planning and file walks that follow read each PR's
+/// commits in full as they reach it, and drop them when they move on.
+pub struct Changes {
+  commits: HashSet<String>,
+  groups: HashMap<u32, FullPr>
+}
+
+impl Changes {
+  pub fn commits(&self) -> &HashSet<String> { &self.commits }
+  pub fn groups(&self) -> &HashMap<u32, FullPr> { &self.groups }
+  pub fn into_groups(self) -> HashMap<u32, FullPr> { self.groups }
+}
+
+/// PRs read from a JSON file, in place of a forge. Each PR in the file lists the commits that belong to it, by full or
+/// abbreviated ID:
+///
+/// ```json
+/// [ { "number": 12, "title": "Add frobs", "base": "main", "head": "4a1f3c2", "commits": ["4a1f3c2", "9e0d1b7"],
+///     "closed_at": "2021-06-01T12:00:00Z", "labels": ["feature"] } ]
+/// ```
+struct FileProvider {
+  root: PathBuf,
+  prs: Vec<(ForgePr, Vec<String>)>
+}
+
+#[derive(Deserialize)]
+struct FilePr {
+  number: u32,
+  #[serde(default)]
+  title: String,
+  base: String,
+  head: String,
+  commits: Vec<String>,
+  closed_at: Option<String>,
+  #[serde(default)]
+  labels: Vec<String>
+}
+
+impl FileProvider {
+  fn read(root: &Path, file: &Path) -> Result<FileProvider> {
+    let data = std::fs::read_to_string(file).chain_err(|| format!("Unable to read PR file {}.", file.display()))?;
+    let prs: Vec<FilePr> = serde_json::from_str(&data).chain_err(|| format!("Bad PR file {}.", file.display()))?;
+    let prs = prs
+      .into_iter()
+      .map(|pr| {
+        if pr.number == 0 || pr.commits.iter().any(|c| c.is_empty()) {
+          bail!("Bad PR {} in {}: PRs are numbered from 1, and commit IDs can't be empty.", pr.number, file.display());
+        }
+        let number = pr.number;
+        let closed_at = pr
+          .closed_at
+          .map(|d| DateTime::parse_from_rfc3339(&d).chain_err(|| format!("Bad closed_at \"{}\" of PR {}.", d, number)))
+          .transpose()?;
+        Ok((ForgePr::new(pr.number, pr.title, pr.head, pr.base, closed_at, pr.labels), pr.commits))
+      })
+      .collect::<Result<_>>()?;
+    Ok(FileProvider { root: root.to_path_buf(), prs })
+  }
+}
+
+impl PrProvider for FileProvider {
+  fn api_name(&self) -> &'static str { "the PR file" }
+
+  fn span_commits<'a>(&'a self, span: &'a Span) -> ProviderFuture<'a, Vec<ForgeCommit>> {
+    Box::pin(async move {
+      let commits = span.commits_at(&self.root)?.into_iter().map(|oid| {
+        let prs = self.prs.iter().filter(|(_, commits)| commits.iter().any(|c| oid.starts_with(c.as_str())));
+        let prs = prs.map(|(pr, _)| pr.clone()).collect();
+        ForgeCommit::new(oid, prs)
+      });
+      Ok(commits.collect())
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{changes, ForgeCommit, ForgePr, PrProvider, ProviderFuture, MAX_CALLS};
+  use crate::fixture::Fixture;
+  use crate::git::{FromTagBuf, Repo, Span};
+  use crate::vcs::{VcsLevel, VcsState};
+  use git2::Oid;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::sync::Arc;
+  use std::time::Duration;
+  use tokio::runtime::Runtime;
+
+  /// A slow forge where every commit after the first is its own PR. The span of PR `fail`, if any, can't be read.
+  struct SlowProvider {
+    commits: Vec<(Oid, Oid)>,
+    fail: Option<u32>,
+    running: AtomicUsize,
+    most_running: AtomicUsize,
+    finished: AtomicUsize
+  }
+
+  impl SlowProvider {
+    fn new(commits: Vec<(Oid, Oid)>, fail: Option<u32>) -> SlowProvider {
+      SlowProvider {
+        commits,
+        fail,
+        running: AtomicUsize::new(0),
+        most_running: AtomicUsize::new(0),
+        finished: AtomicUsize::new(0)
+      }
+    }
+
+    fn commit(&self, number: usize) -> ForgeCommit {
+      let (oid, parent) = self.commits[number - 1];
+      let pr = ForgePr::new(number as u32, String::new(), oid.to_string(), parent.to_string(), None, Vec::new());
+      ForgeCommit::new(oid.to_string(), vec![pr])
+    }
+  }
+
+  impl PrProvider for SlowProvider {
+    fn api_name(&self) -> &'static str { "the slow forge" }
+
+    fn span_commits<'a>(&'a self, span: &'a Span) -> ProviderFuture<'a, Vec<ForgeCommit>> {
+      Box::pin(async move {
+        if Some(span.number()) == self.fail {
+          bail!("No span {}.", span.number());
+        }
+        let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.most_running.fetch_max(running, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        self.running.fetch_sub(1, Ordering::SeqCst);
+        self.finished.fetch_add(1, Ordering::SeqCst);
+
+        match span.number() {
+          0 => Ok((1 ..= self.commits.len()).map(|n| self.commit(n)).collect()),
+          n => Ok(vec![self.commit(n as usize)])
+        }
+      })
+    }
+  }
+
+  fn slow_fixture(fail: Option<u32>) -> (Fixture, Arc<SlowProvider>) {
+    let fixture = Fixture::new(&[("README", "start\n")]);
+    let mut parent = fixture.repo().head().unwrap().target().unwrap();
+    let mut commits = Vec::new();
+    for n in 1 ..= 3 * MAX_CALLS {
+      fixture.write("README", &format!("change {}\n", n));
+      let oid = fixture.commit(&format!("fix: change {}", n));
+      commits.push((oid, parent));
+      parent = oid;
+    }
+    (fixture, Arc::new(SlowProvider::new(commits, fail)))
+  }
+
+  #[test]
+  fn test_calls_are_bounded() {
+    let (fixture, provider) = slow_fixture(None);
+    let repo = Repo::open(fixture.path(), VcsState::new(VcsLevel::Local, false)).unwrap();
+    let base = FromTagBuf::new("versio-prev".into(), true);
+
+    let found = Runtime::new().unwrap().block_on(changes(Some(provider.clone()), &repo, base, "HEAD".into())).unwrap();
+    assert_eq!(found.groups().len(), 3 * MAX_CALLS + 1);
+    assert_eq!(provider.finished.load(Ordering::SeqCst), 3 * MAX_CALLS + 1);
+    assert!(provider.most_running.load(Ordering::SeqCst) <= MAX_CALLS);
+  }
+
+  #[test]
+  fn test_commits_read_when_planned() {
+    let (fixture, provider) = slow_fixture(None);
+    let repo = Repo::open(fixture.path(), VcsState::new(VcsLevel::Local, false)).unwrap();
+    let base = FromTagBuf::new("versio-prev".into(), true);
+
+    let found = Runtime::new().unwrap().block_on(changes(Some(provider.clone()), &repo, base, "HEAD".into())).unwrap();
+    let pr = &found.groups()[&2];
+    let oid = provider.commits[1].0.to_string();
+    assert_eq!(pr.commit_ids(), std::slice::from_ref(&oid));
+
+    let commits = pr.included_commits(&repo).collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(commits.len(), 1);
+    assert_eq!(commits[0].id(), oid);
+    assert_eq!(commits[0].summary(), "fix: change 2");
+    assert_eq!(commits[0].files(), &["README".to_string()]);
+  }
+
+  #[test]
+  fn test_failed_call_aborts_the_rest() {
+    let (fixture, provider) = slow_fixture(Some(1));
+    let repo = Repo::open(fixture.path(), VcsState::new(VcsLevel::Local, false)).unwrap();
+    let base = FromTagBuf::new("versio-prev".into(), true);
+
+    let runtime = Runtime::new().unwrap();
+    assert!(runtime.block_on(changes(Some(provider.clone()), &repo, base, "HEAD".into())).is_err());
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(provider.finished.load(Ordering::SeqCst), 1);
+  }
+}
@@ -0,0 +1,170 @@
+//! Bumping package manager manifests, such as Homebrew formulas and Scoop manifests, after a release.
+
+use crate::errors::{Result, ResultExt as _};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// The kind of package manager manifest to update.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FormulaKind {
+  Homebrew,
+  Scoop
+}
+
+/// A package manager manifest that is updated when its project is released. The manifest usually lives in a
+/// separate tap or bucket repository, so `file` may be outside of this repo.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Formula {
+  #[serde(rename = "type")]
+  kind: FormulaKind,
+  file: String,
+  #[serde(default)]
+  artifact: Option<String>
+}
+
+impl Formula {
+  pub fn kind(&self) -> FormulaKind { self.kind }
+  pub fn file(&self) -> &str { &self.file }
+
+  /// The manifest with the new version written to it, the old version replaced in its download URLs, and its hash
+  /// set to the sha256 of the local `artifact` file, if there is one. Any `{version}` in `artifact` is the new
+  /// version. Returns `None` if the manifest doesn't change.
+  pub fn rewritten(&self, old_vers: Option<&str>, new_vers: &str) -> Result<Option<String>> {
+    let sha = match &self.artifact {
+      Some(artifact) => Some(sha256_file(Path::new(&artifact.replace("{version}", new_vers)))?),
+      None => None
+    };
+
+    let path = Path::new(&self.file);
+    let data = std::fs::read_to_string(path).chain_err(|| format!("Unable to read manifest {}.", self.file))?;
+    let new_data = rewrite(self.kind, &data, old_vers, new_vers, sha.as_deref())?;
+    Ok(Some(new_data).filter(|new_data| new_data != &data))
+  }
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+  let data = std::fs::read(path).chain_err(|| format!("Unable to read artifact {}.", path.display()))?;
+  Ok(format!("{:x}", Sha256::digest(&data)))
+}
+
+fn rewrite(kind: FormulaKind, data: &str, old_vers: Option<&str>, new_vers: &str, sha: Option<&str>) -> Result<String> {
+  let (version, url, hash) = match kind {
+    FormulaKind::Homebrew => {
+      (r#"(?m)^(\s*version\s+")[^"]*(")"#, r#"(?m)^\s*url\s+"[^"]*""#, r#"(?m)^(\s*sha256\s+")[0-9a-fA-F]*(")"#)
+    }
+    FormulaKind::Scoop => (r#"("version"\s*:\s*")[^"]*(")"#, r#""url"\s*:\s*"[^"]*""#, r#"("hash"\s*:\s*")[^"]*(")"#)
+  };
+
+  let mut data = Regex::new(version)?.replace_all(data, format!("${{1}}{}${{2}}", new_vers).as_str()).into_owned();
+  if let Some(old_vers) = old_vers {
+    data = Regex::new(url)?.replace_all(&data, |caps: &Captures| caps[0].replace(old_vers, new_vers)).into_owned();
+  }
+  if let Some(sha) = sha {
+    // A single artifact has a single hash, so it can't be used for a manifest with a hash per architecture.
+    let hash = Regex::new(hash)?;
+    let found = hash.find_iter(&data).count();
+    if found > 1 {
+      bail!("Manifest has {} hashes, but a single artifact can only set one: remove its `artifact`.", found);
+    }
+    data = hash.replace(&data, format!("${{1}}{}${{2}}", sha).as_str()).into_owned();
+  }
+  Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+  use super::{rewrite, FormulaKind};
+
+  #[test]
+  fn test_rewrite_homebrew() {
+    let data = r#"class Foo < Formula
+  url "https://example.com/foo-1.2.3.tar.gz"
+  version "1.2.3"
+  sha256 "abc123"
+
+  bottle do
+    sha256 cellar: :any, big_sur: "def456"
+  end
+end
+"#;
+
+    let rewritten = rewrite(FormulaKind::Homebrew, data, Some("1.2.3"), "1.3.0", Some("fff")).unwrap();
+    assert_eq!(
+      rewritten,
+      r#"class Foo < Formula
+  url "https://example.com/foo-1.3.0.tar.gz"
+  version "1.3.0"
+  sha256 "fff"
+
+  bottle do
+    sha256 cellar: :any, big_sur: "def456"
+  end
+end
+"#
+    );
+  }
+
+  #[test]
+  fn test_rewrite_scoop_hash() {
+    let data = r#"{
+  "version": "1.2.3",
+  "url": "https://example.com/foo-1.2.3.zip",
+  "hash": "abc123"
+}"#;
+
+    let rewritten = rewrite(FormulaKind::Scoop, data, Some("1.2.3"), "1.3.0", Some("fff")).unwrap();
+    assert_eq!(
+      rewritten,
+      r#"{
+  "version": "1.3.0",
+  "url": "https://example.com/foo-1.3.0.zip",
+  "hash": "fff"
+}"#
+    );
+  }
+
+  #[test]
+  fn test_rewrite_scoop_arch_hashes() {
+    let data = r#"{
+  "version": "1.2.3",
+  "architecture": {
+    "64bit": { "url": "https://example.com/foo-1.2.3-x64.zip", "hash": "abc123" },
+    "32bit": { "url": "https://example.com/foo-1.2.3-x86.zip", "hash": "def456" }
+  }
+}"#;
+
+    assert!(rewrite(FormulaKind::Scoop, data, Some("1.2.3"), "1.3.0", Some("fff")).is_err());
+    let rewritten = rewrite(FormulaKind::Scoop, data, Some("1.2.3"), "1.3.0", None).unwrap();
+    assert!(rewritten.contains(r#""hash": "abc123""#) && rewritten.contains(r#""hash": "def456""#));
+  }
+
+  #[test]
+  fn test_rewrite_scoop() {
+    let data = r#"{
+  "version": "1.2.3",
+  "architecture": {
+    "64bit": {
+      "url": "https://example.com/foo-1.2.3-x64.zip",
+      "hash": "abc123"
+    }
+  }
+}"#;
+
+    let rewritten = rewrite(FormulaKind::Scoop, data, Some("1.2.3"), "1.3.0", None).unwrap();
+    assert_eq!(
+      rewritten,
+      r#"{
+  "version": "1.3.0",
+  "architecture": {
+    "64bit": {
+      "url": "https://example.com/foo-1.3.0-x64.zip",
+      "hash": "abc123"
+    }
+  }
+}"#
+    );
+  }
+}
@@ -1,42 +1,103 @@
 //! Interactions with git.
 
+use crate::cancel::{Cancel, Phase};
+use crate::clock;
 use crate::config::CONFIG_FILENAME;
 use crate::either::IterEither2 as E2;
-use crate::errors::{Result, ResultExt};
+use crate::errors::{Error, Result, ResultExt};
+use crate::sizes::extract_kind;
 use crate::vcs::{VcsLevel, VcsState};
-use chrono::offset::Utc;
 use chrono::{DateTime, FixedOffset, TimeZone};
-use error_chain::bail;
 use git2::build::CheckoutBuilder;
 use git2::string_array::StringArray;
-use git2::{AnnotatedCommit, AutotagOption, Blob, Commit, Cred, CredentialType, Diff, DiffOptions, FetchOptions, Index,
-           Object, ObjectType, Oid, PushOptions, Reference, ReferenceType, Remote, RemoteCallbacks, Repository,
-           RepositoryOpenFlags, RepositoryState, ResetType, Revwalk, Signature, Sort, Status, StatusOptions, Time};
+use git2::{AnnotatedCommit, AutotagOption, Blob, BranchType, Commit, Cred, CredentialType, Diff, DiffFormat,
+           DiffOptions, Direction, FetchOptions, Index, Object, ObjectType, Oid, PushOptions, Reference,
+           ReferenceType, Remote, RemoteCallbacks, Repository, RepositoryOpenFlags, RepositoryState, ResetType,
+           Revwalk, Signature, Sort, Status, StatusOptions, Time};
 use gpgme::{Context, Protocol};
 use log::{error, info, trace, warn};
 use path_slash::PathBufExt as _;
 use regex::Regex;
-use serde::Deserialize;
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+use sha2::{Digest, Sha256};
 use std::cell::RefCell;
 use std::cmp::{min, Ord, Ordering, PartialOrd};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env::var;
 use std::ffi::OsStr;
 use std::fmt;
 use std::io::{stdout, Write};
-use std::iter::empty;
+use std::iter::{empty, once};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::thread::{spawn, JoinHandle};
+
+const VERSIO_NAME: &str = "Versio";
+const VERSIO_EMAIL: &str = "github.com/chaaz/versio";
+pub const RELEASE_MSG: &str = "build(deploy): Versio update versions";
+const NOTES_REF: &str = "refs/notes/versio";
 
 pub struct Repo {
   vcs: GitVcsLevel,
-  ignore_current: bool
+  ignore_current: bool,
+  fetch: FetchOpts,
+  cancel: Cancel
+}
+
+/// Which tags are downloaded along with a fetched ref.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FetchTags {
+  /// Every tag on the remote.
+  All,
+  /// Only tags that point into the fetched history.
+  Auto,
+  /// No tags, except those named by a refspec.
+  None
+}
+
+impl Default for FetchTags {
+  fn default() -> FetchTags { FetchTags::All }
+}
+
+impl FetchTags {
+  fn autotag(self) -> AutotagOption {
+    match self {
+      FetchTags::All => AutotagOption::All,
+      FetchTags::Auto => AutotagOption::Auto,
+      FetchTags::None => AutotagOption::None
+    }
+  }
+}
+
+/// A download of the current branch that runs on another thread, started by `Repo::start_fetch`.
+pub struct BranchFetch {
+  handle: JoinHandle<Result<()>>
+}
+
+impl BranchFetch {
+  /// Wait for the download to finish.
+  pub fn finish(self) -> Result<()> { self.handle.join().map_err(|_| bad!("Branch fetch panicked."))? }
+}
+
+/// What is fetched along with each ref that versio fetches from the remote.
+#[derive(Default, Debug, Clone)]
+pub struct FetchOpts {
+  tags: FetchTags,
+  refspecs: Vec<String>
+}
+
+impl FetchOpts {
+  pub fn new(tags: FetchTags, refspecs: Vec<String>) -> FetchOpts { FetchOpts { tags, refspecs } }
 }
 
 impl Repo {
   // All member methods of `Repo` should do the "best thing" for the current VCS level. For example,
   // `commits_to_head` will: if None, return an empty list; if Local, return all commits found from the given
   // spec to HEAD; if Remote or Smart, first fetch the spec from the remote (merging into the current directory
-  // if on the current branch), then return everything from the fetched commit to HEAD.
+  // if on the current branch), then return everything from the fetched commit to HEAD. In a repository that has
+  // no commits yet, it returns an empty list at every level.
   //
   // If a method deals with git OIDs in either in their argument or return, then that method will return an
   // `Err` if the VCS level is None: OIDs are an opaque artifact of git, and you really need some sort of
@@ -98,11 +159,27 @@ impl Repo {
     Ok(repo.workdir().ok_or_else(|| bad!("Repo has no working dir"))?.to_path_buf())
   }
 
+  /// Find the directory where git looks for hooks: the `core.hooksPath` config if it's set (relative paths are
+  /// relative to the working directory), or else the repo's own `hooks` directory.
+  pub fn find_hooks_dir<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+    let repo = Repository::open_ext(path, RepositoryOpenFlags::empty(), empty::<&OsStr>())?;
+    match repo.config()?.get_path("core.hooksPath") {
+      Ok(hooks) if hooks.is_absolute() => Ok(hooks),
+      Ok(hooks) => Ok(repo.workdir().ok_or_else(|| bad!("Repo has no working dir."))?.join(hooks)),
+      Err(_) => Ok(repo.path().join("hooks"))
+    }
+  }
+
   pub fn open<P: AsRef<Path>>(path: P, vcs: VcsState) -> Result<Repo> {
     let ignore_current = vcs.ignore_current();
     if vcs.level().is_none() {
       let root = find_root_blind(path)?;
-      return Ok(Repo { ignore_current, vcs: GitVcsLevel::None { root } });
+      return Ok(Repo {
+        ignore_current,
+        vcs: GitVcsLevel::None { root },
+        fetch: Default::default(),
+        cancel: Default::default()
+      });
     }
 
     let flags = RepositoryOpenFlags::empty();
@@ -110,16 +187,61 @@ impl Repo {
     let branch_name = find_branch_name(&repo)?;
 
     if vcs.level().is_local() {
-      return Ok(Repo { ignore_current, vcs: GitVcsLevel::Local { repo, branch_name } });
+      return Ok(Repo {
+        ignore_current,
+        vcs: GitVcsLevel::Local { repo, branch_name },
+        fetch: Default::default(),
+        cancel: Default::default()
+      });
     }
 
     let remote_name = find_remote_name(&repo, &branch_name)?;
     let fetches = RefCell::new(HashMap::new());
     let root = repo.workdir().ok_or_else(|| bad!("Repo has no working dir."))?.to_path_buf();
 
-    Ok(Repo { ignore_current, vcs: GitVcsLevel::from(vcs.level(), root, repo, branch_name, remote_name, fetches) })
+    let vcs = GitVcsLevel::from(vcs.level(), root, repo, branch_name, remote_name, fetches);
+    Ok(Repo { ignore_current, vcs, fetch: Default::default(), cancel: Default::default() })
+  }
+
+  /// Start downloading the current branch from the remote on another thread, so that its commits arrive while local
+  /// work goes on. Only the branch is fetched, without any tags: the usual fetches still happen when the refs are
+  /// first needed, with the configured options, but they find most of what they need already here. Nothing is
+  /// started unless we're on a branch at a remote level.
+  pub fn start_fetch(&self) -> Option<BranchFetch> {
+    let (repo, branch_name, remote_name) = match &self.vcs {
+      GitVcsLevel::Remote { repo, branch_name: Some(branch_name), remote_name, .. }
+      | GitVcsLevel::Smart { repo, branch_name: Some(branch_name), remote_name, .. } => {
+        (repo, branch_name, remote_name)
+      }
+      _ => return None
+    };
+
+    let (path, branch_name, remote_name) = (repo.path().to_path_buf(), branch_name.clone(), remote_name.clone());
+    let cancel = self.cancel.clone();
+    let handle = spawn(move || {
+      let repo = Repository::open(path)?;
+      safe_fetch(&repo, &remote_name, &[&branch_name], Some(FetchTags::None), &cancel)
+    });
+    Some(BranchFetch { handle })
+  }
+
+  /// Fetch with the given options, instead of fetching all tags with each ref.
+  pub fn with_fetch(mut self, fetch: FetchOpts) -> Repo {
+    self.fetch = fetch;
+    self
+  }
+
+  /// Abandon fetches when `cancel` is cancelled or its fetch timeout passes.
+  pub fn with_cancel(mut self, cancel: Cancel) -> Repo {
+    self.cancel = cancel;
+    self
   }
 
+  pub fn cancel(&self) -> &Cancel { &self.cancel }
+
+  /// True if commits and tags are pushed to a remote.
+  pub fn is_remote(&self) -> bool { matches!(&self.vcs, GitVcsLevel::Remote { .. } | GitVcsLevel::Smart { .. }) }
+
   pub fn working_dir(&self) -> Result<&Path> {
     match &self.vcs {
       GitVcsLevel::None { root } => Ok(root),
@@ -146,7 +268,8 @@ impl Repo {
       GitVcsLevel::Remote { repo, remote_name, .. } | GitVcsLevel::Smart { repo, remote_name, .. } => {
         let fetch_pat = if let Some(pat) = pattern { pat } else { "*" };
         let specs: &[&str] = &[&format!("refs/tags/{pat}:refs/tags/{pat}", pat = fetch_pat)];
-        safe_fetch(repo, remote_name, specs, false).chain_err(|| format!("Can't fetch tags \"{}\"", fetch_pat))?;
+        safe_fetch(repo, remote_name, specs, None, &self.cancel)
+          .chain_err(|| format!("Can't fetch tags \"{}\"", fetch_pat))?;
         Ok(IterString::Git(repo.tag_names(pattern)?))
       }
     }
@@ -159,27 +282,35 @@ impl Repo {
   /// Return all commits as in `git rev-list from..to_sha`, along with the earliest time in that range.
   ///
   /// `from` may be any legal target of `rev-parse`.
-  pub fn commits_between_buf(&self, from: FromTag, to_oid: Oid) -> Result<Option<(Vec<CommitInfoBuf>, Time)>> {
+  /// The IDs and times of the commits in `from..to_oid`, along with the earliest of those times. Only the IDs are
+  /// kept, so that a long history doesn't have to be held in memory: each commit is read in full later, with
+  /// `commit_buf`.
+  pub fn commit_ids_between(&self, from: FromTag, to_oid: Oid) -> Result<Option<(CommitIds, Time)>> {
     let repo = self.repo()?;
     let mut revwalk = repo.revwalk()?;
     revwalk.set_sorting(Sort::TOPOLOGICAL)?;
     hide_from(repo, &mut revwalk, from)?;
     revwalk.push(to_oid)?;
 
-    revwalk.try_fold::<_, _, Result<Option<(Vec<CommitInfoBuf>, Time)>>>(None, |v, oid| {
+    revwalk.try_fold::<_, _, Result<Option<(CommitIds, Time)>>>(None, |v, oid| {
       let oid = oid?;
-      let commit = repo.find_commit(oid)?;
-      let ctime = commit.time();
+      let ctime = repo.find_commit(oid)?.time();
+      let data = (oid.to_string(), time_to_datetime(&ctime));
       if let Some((mut datas, time)) = v {
-        datas.push(CommitInfoBuf::extract(repo, &commit)?);
+        datas.push(data);
         Ok(Some((datas, min(time, ctime))))
       } else {
-        let datas = vec![CommitInfoBuf::extract(repo, &commit)?];
-        Ok(Some((datas, ctime)))
+        Ok(Some((vec![data], ctime)))
       }
     })
   }
 
+  /// Read a commit in full.
+  pub fn commit_buf(&self, oid: &str) -> Result<CommitInfoBuf> {
+    let repo = self.repo()?;
+    CommitInfoBuf::extract(repo, &repo.find_commit(Oid::from_str(oid)?)?)
+  }
+
   /// Return all commits as in `git rev-list from..to_sha`.
   ///
   /// `from` may be any legal target of `rev-parse`.
@@ -207,6 +338,7 @@ impl Repo {
   ) -> Result<impl Iterator<Item = Result<CommitInfo<'r>>> + 'r> {
     let head_oid = match &self.vcs {
       GitVcsLevel::None { .. } => return Ok(E2::A(empty())),
+      _ if self.is_unborn() => return Ok(E2::A(empty())),
       _ => self.get_oid_head()?.id()
     };
 
@@ -239,7 +371,7 @@ impl Repo {
           get_oid_local(repo, spec)
         } else {
           // get_oid_remote() will verify current
-          get_oid_remote(repo, branch_name, spec, remote_name, fetches)
+          get_oid_remote(repo, branch_name, spec, remote_name, fetches, &self.fetch, &self.cancel)
         }
       }
     }
@@ -258,14 +390,15 @@ impl Repo {
       .and_then(|tag| tag.message().map(|m| m.to_string()))
   }
 
-  pub fn commit(&self) -> Result<bool> {
+  /// Commit all modified files with the given message, or the default release message.
+  pub fn commit(&self, msg: Option<&str>) -> Result<bool> {
     if let GitVcsLevel::None { .. } = self.vcs {
       return Ok(false);
     }
 
     if let Some(mut index) = self.add_all_modified()? {
       let tree_oid = index.write_tree()?;
-      self.commit_tree(tree_oid)?;
+      self.commit_tree(tree_oid, msg.unwrap_or(RELEASE_MSG))?;
       self.push_head(&[])?;
       Ok(true)
     } else {
@@ -273,7 +406,268 @@ impl Repo {
     }
   }
 
-  fn add_all_modified(&self) -> Result<Option<Index>> {
+  /// Commit only the given files, if any of them changed, without pushing. Returns `true` if a commit was made.
+  pub fn commit_paths(&self, paths: &[PathBuf], msg: &str) -> Result<bool> {
+    if let GitVcsLevel::None { .. } = self.vcs {
+      return Ok(false);
+    }
+
+    let root = self.working_dir()?;
+    let paths: Vec<_> =
+      paths.iter().map(|p| p.strip_prefix(root).or_else(|_| p.strip_prefix(".")).unwrap_or(p).to_path_buf()).collect();
+    if let Some(mut index) = self.add_modified(|path| paths.iter().any(|p| p == path))? {
+      let tree_oid = index.write_tree()?;
+      self.commit_tree(tree_oid, msg)?;
+      Ok(true)
+    } else {
+      Ok(false)
+    }
+  }
+
+  /// Create a new commit that reverts the given commit, and push it if we're at a remote level.
+  pub fn revert(&self, oid: &str, msg: &str) -> Result<()> {
+    if let GitVcsLevel::None { .. } = self.vcs {
+      return Ok(());
+    }
+
+    let repo = self.repo()?;
+    let commit = repo.find_commit(Oid::from_str(oid)?)?;
+    repo.revert(&commit, None).chain_err(|| format!("Unable to revert {}.", oid))?;
+    let tree_oid = repo.index()?.write_tree().chain_err(|| format!("Conflicts reverting {}.", oid))?;
+    repo.cleanup_state()?;
+    self.commit_tree(tree_oid, msg)?;
+    self.push_head(&[])
+  }
+
+  /// Check if the given commit was created by a previous versio release.
+  pub fn is_release_commit(&self, oid: &str) -> Result<bool> {
+    let repo = self.repo()?;
+    let commit = repo.find_commit(Oid::from_str(oid)?)?;
+    let by_versio = commit.author().name() == Some(VERSIO_NAME);
+    Ok(by_versio)
+  }
+
+  /// Attach a note to the HEAD commit under `refs/notes/versio`, and push the notes if we're at a remote level.
+  pub fn add_note(&self, note: &str) -> Result<()> {
+    if let GitVcsLevel::None { .. } = self.vcs {
+      return Ok(());
+    }
+
+    let repo = self.repo()?;
+    let head = self.find_last_commit()?.id();
+    let sig = versio_signature()?;
+    repo.note(&sig, &sig, Some(NOTES_REF), head, note, true)?;
+
+    let (repo, remote_name) = match &self.vcs {
+      GitVcsLevel::None { .. } | GitVcsLevel::Local { .. } => return Ok(()),
+      GitVcsLevel::Remote { repo, remote_name, .. } | GitVcsLevel::Smart { repo, remote_name, .. } => {
+        (repo, remote_name)
+      }
+    };
+
+    do_push(repo, remote_name, &[NOTES_REF.to_string()])
+  }
+
+  /// All notes under `refs/notes/versio`, as pairs of the annotated commit and the note's text.
+  pub fn notes(&self) -> Result<Vec<(String, String)>> {
+    let repo = self.repo()?;
+    let notes = match repo.notes(Some(NOTES_REF)) {
+      Ok(notes) => notes,
+      Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(Vec::new()),
+      Err(e) => return Err(e.into())
+    };
+
+    notes
+      .map(|ids| {
+        let (_, commit_id) = ids?;
+        let note = repo.find_note(Some(NOTES_REF), commit_id)?;
+        Ok((commit_id.to_string(), note.message().unwrap_or("").to_string()))
+      })
+      .collect()
+  }
+
+  /// The name of the person running versio: the configured git `user.name`, if any.
+  pub fn user_name(&self) -> Option<String> { self.repo().ok()?.config().ok()?.get_string("user.name").ok() }
+
+  /// Find the commit that a tag points to, without verifying that the repo is current.
+  pub fn tag_oid(&self, tag: &str) -> Option<String> {
+    let repo = match &self.vcs {
+      GitVcsLevel::None { .. } => return None,
+      GitVcsLevel::Local { repo, .. } | GitVcsLevel::Remote { repo, .. } | GitVcsLevel::Smart { repo, .. } => repo
+    };
+
+    repo.revparse_single(&format!("{}^{{}}", tag)).ok().map(|obj| obj.id().to_string())
+  }
+
+  /// Paths in the working tree or index that differ from `HEAD`, including untracked files. Always empty if
+  /// there is no repository.
+  pub fn dirty_paths(&self) -> Result<Vec<String>> {
+    let repo = match &self.vcs {
+      GitVcsLevel::None { .. } => return Ok(Vec::new()),
+      GitVcsLevel::Local { repo, .. } | GitVcsLevel::Remote { repo, .. } | GitVcsLevel::Smart { repo, .. } => repo
+    };
+
+    let mut status_opts = StatusOptions::new();
+    status_opts.include_ignored(false);
+    status_opts.include_untracked(true);
+    status_opts.exclude_submodules(true);
+
+    let statuses = repo.statuses(Some(&mut status_opts))?;
+    let dirty = statuses.iter().filter(|s| s.status() != Status::CURRENT);
+    dirty.map(|s| s.path().map(|p| p.to_string()).ok_or_else(|| bad!("Bad path"))).collect()
+  }
+
+  /// True if `HEAD` doesn't point to a commit yet, as in a freshly-initialized repository.
+  pub fn is_unborn(&self) -> bool {
+    match self.repo().map(|repo| repo.head()) {
+      Ok(Err(e)) => e.code() == git2::ErrorCode::UnbornBranch,
+      _ => false
+    }
+  }
+
+  /// True if the commit `oid` is `HEAD` or one of its ancestors. Always true if there is no repository or no
+  /// `HEAD` yet.
+  pub fn in_history(&self, oid: &str) -> Result<bool> {
+    let repo = match &self.vcs {
+      GitVcsLevel::None { .. } => return Ok(true),
+      GitVcsLevel::Local { repo, .. } | GitVcsLevel::Remote { repo, .. } | GitVcsLevel::Smart { repo, .. } => repo
+    };
+    if self.is_unborn() {
+      return Ok(true);
+    }
+
+    let head = repo.head()?.peel_to_commit()?.id();
+    let oid = Oid::from_str(oid)?;
+    Ok(head == oid || repo.graph_descendant_of(head, oid)?)
+  }
+
+  /// The commit that `HEAD` points to, if there is one.
+  pub fn head_commit_oid(&self) -> Option<String> { self.tag_oid("HEAD") }
+
+  /// The object that a tag ref points to directly: the tag object for an annotated tag, or the commit for a
+  /// lightweight one.
+  pub fn tag_ref(&self, tag: &str) -> Option<String> {
+    let reference = self.repo().ok()?.find_reference(&format!("refs/tags/{}", tag)).ok()?;
+    reference.target().map(|oid| oid.to_string())
+  }
+
+  /// Point a tag back at an object previously returned from `tag_ref`, or delete it if it didn't exist.
+  pub fn restore_tag(&self, tag: &str, target: Option<&str>) -> Result<()> {
+    match target {
+      Some(target) => {
+        let repo = self.repo()?;
+        repo.reference(&format!("refs/tags/{}", tag), Oid::from_str(target)?, true, "versio restore tag")?;
+        self.push_tag(tag)
+      }
+      None if self.tag_ref(tag).is_some() => self.delete_tag(tag),
+      None => Ok(())
+    }
+  }
+
+  /// Move `HEAD` back to the given commit, and restore just the given files to what they were at that commit,
+  /// both in the index and the working tree. A file that wasn't at that commit is removed; one outside of the
+  /// working tree is left alone. Other files, even if changed, are kept as they are.
+  pub fn reset_files(&self, oid: &str, paths: &[PathBuf]) -> Result<()> {
+    let repo = self.repo()?;
+    let root = self.working_dir()?;
+    let commit = repo.find_commit(Oid::from_str(oid)?)?;
+    if repo.head()?.peel_to_commit()?.id() != commit.id() {
+      repo.reset(commit.as_object(), ResetType::Soft, None)?;
+    }
+
+    let tree = commit.tree()?;
+    let mut index = repo.index()?;
+    for path in paths {
+      let rel = path.strip_prefix(root).or_else(|_| path.strip_prefix(".")).unwrap_or(path);
+      if rel.is_absolute() || rel.starts_with("..") {
+        continue;
+      }
+      let full = root.join(rel);
+      match tree.get_path(rel) {
+        Ok(entry) => {
+          let blob = repo.find_blob(entry.id())?;
+          std::fs::write(&full, blob.content()).chain_err(|| format!("Unable to restore {}.", full.display()))?;
+          index.add_path(rel)?;
+        }
+        Err(e) if e.code() == git2::ErrorCode::NotFound => {
+          if full.exists() {
+            std::fs::remove_file(&full).chain_err(|| format!("Unable to remove {}.", full.display()))?;
+          }
+          index.remove_path(rel)?;
+        }
+        Err(e) => return Err(e.into())
+      }
+    }
+    index.write()?;
+    Ok(())
+  }
+
+  /// Push the current branch, if we're at a remote level.
+  pub fn push_commit(&self) -> Result<()> { self.push_head(&[]) }
+
+  /// True if the branch that we push to already has the given commit on the remote, as far as we know.
+  pub fn is_pushed(&self, oid: &str) -> bool {
+    let (repo, branch_name, remote_name) = match &self.vcs {
+      GitVcsLevel::None { .. } | GitVcsLevel::Local { .. } => return false,
+      GitVcsLevel::Remote { repo, branch_name, remote_name, .. }
+      | GitVcsLevel::Smart { repo, branch_name, remote_name, .. } => (repo, branch_name, remote_name)
+    };
+
+    let branch_name = match branch_name {
+      Some(branch_name) => branch_name,
+      None => return false
+    };
+    let remote_ref = format!("refs/remotes/{}/{}", remote_name, branch_name);
+    repo.refname_to_id(&remote_ref).map(|id| id.to_string() == oid).unwrap_or(false)
+  }
+
+  /// The tags on the remote, and the commits that they point to. Empty if we're not at a remote level.
+  pub fn remote_tags(&self) -> Result<HashMap<String, String>> {
+    let (repo, remote_name) = match &self.vcs {
+      GitVcsLevel::None { .. } | GitVcsLevel::Local { .. } => return Ok(HashMap::new()),
+      GitVcsLevel::Remote { repo, remote_name, .. } | GitVcsLevel::Smart { repo, remote_name, .. } => {
+        (repo, remote_name)
+      }
+    };
+
+    let mut remote = repo.find_remote(remote_name)?;
+    let mut cb = RemoteCallbacks::new();
+    cb.credentials(find_creds);
+    let conn = remote
+      .connect_auth(Direction::Fetch, Some(cb), None)
+      .chain_err(|| Error::vcs(format!("list tags on {}", remote_name)))?;
+
+    let mut tags = HashMap::new();
+    for head in conn.list()? {
+      if let Some(tag) = head.name().strip_prefix("refs/tags/") {
+        // Annotated tags are listed twice: once for the tag object, and then for its commit with a `^{}` suffix.
+        match tag.strip_suffix("^{}") {
+          Some(tag) => {
+            tags.insert(tag.to_string(), head.oid().to_string());
+          }
+          None => {
+            tags.entry(tag.to_string()).or_insert_with(|| head.oid().to_string());
+          }
+        }
+      }
+    }
+    Ok(tags)
+  }
+
+  /// Where an in-progress release is journaled, so that it can be resumed or aborted if it is interrupted. There is
+  /// no journal if there is no repository.
+  pub fn journal_file(&self) -> Option<PathBuf> {
+    self.repo().ok().map(|repo| repo.path().join("versio").join("journal.json"))
+  }
+
+  /// Where the most recently built plan is cached, so that later commands on the same commits can reuse it.
+  pub fn plan_cache_file(&self) -> Option<PathBuf> {
+    self.repo().ok().map(|repo| repo.path().join("versio").join("plan-cache.json"))
+  }
+
+  fn add_all_modified(&self) -> Result<Option<Index>> { self.add_modified(|_| true) }
+
+  fn add_modified<F: Fn(&Path) -> bool>(&self, filter: F) -> Result<Option<Index>> {
     let repo = self.repo()?;
     let mut status_opts = StatusOptions::new();
     status_opts.include_ignored(false);
@@ -286,8 +680,11 @@ impl Repo {
       let s = s.status();
       s.is_wt_modified() || s.is_wt_deleted() || s.is_wt_renamed() || s.is_wt_typechange() || s.is_wt_new()
     }) {
-      found = true;
       let path = s.path().ok_or_else(|| bad!("Bad path"))?;
+      if !filter(Path::new(path)) {
+        continue;
+      }
+      found = true;
       index.add_path(path.as_ref())?;
     }
 
@@ -298,13 +695,12 @@ impl Repo {
     }
   }
 
-  fn commit_tree(&self, tree_oid: Oid) -> Result<()> {
+  fn commit_tree(&self, tree_oid: Oid, msg: &str) -> Result<()> {
     let repo = self.repo()?;
     let tree = repo.find_tree(tree_oid)?;
     let parent_commit = self.find_last_commit()?;
-    let sig = Signature::now("Versio", "github.com/chaaz/versio")?;
+    let sig = versio_signature()?;
     let head = Some("HEAD");
-    let msg = "build(deploy): Versio update versions";
 
     let commit_oid = if repo.config()?.get_bool("commit.gpgSign").unwrap_or(false) {
       let mut ctx = Context::from_protocol(Protocol::OpenPgp)?;
@@ -366,7 +762,7 @@ impl Repo {
 
     let repo = self.repo()?;
     let obj = repo.revparse_single(spec)?;
-    let tagger = Signature::now("Versio", "github.com/chaaz/versio")?;
+    let tagger = versio_signature()?;
 
     let config = repo.config()?;
     let fsa = config.get_bool("tag.forceSignAnnotated").unwrap_or(false);
@@ -415,6 +811,88 @@ impl Repo {
     Ok(())
   }
 
+  /// Delete a tag, and also delete it from the remote if we're at a remote level.
+  pub fn delete_tag(&self, tag: &str) -> Result<()> {
+    if let GitVcsLevel::None { .. } = self.vcs {
+      return Ok(());
+    }
+
+    // The tag may already be gone locally but still be on the remote, so only delete what's there.
+    let local = self.repo()?;
+    if local.find_reference(&format!("refs/tags/{}", tag)).is_ok() {
+      local.tag_delete(tag)?;
+    }
+
+    let (repo, remote_name) = match &self.vcs {
+      GitVcsLevel::None { .. } | GitVcsLevel::Local { .. } => return Ok(()),
+      GitVcsLevel::Remote { repo, remote_name, .. } | GitVcsLevel::Smart { repo, remote_name, .. } => {
+        (repo, remote_name)
+      }
+    };
+
+    do_push(repo, remote_name, &[format!(":refs/tags/{}", tag)])
+  }
+
+  /// Find the changes that were released somewhere other than `head`, so that backports of them can be recognized.
+  pub fn backports(&self, head: &str) -> Result<Backports> {
+    let repo = self.repo()?;
+    let mut revwalk = repo.revwalk()?;
+    for tag in repo.tag_names(None)?.iter().flatten() {
+      if let Ok(commit) = repo.revparse_single(&format!("refs/tags/{}", tag)).and_then(|o| o.peel_to_commit()) {
+        revwalk.push(commit.id())?;
+      }
+    }
+    revwalk.hide(repo.revparse_single(head)?.peel_to_commit()?.id())?;
+    let released = revwalk.collect::<std::result::Result<_, _>>()?;
+    Ok(Backports { repo: self, released, patch_ids: None })
+  }
+
+  /// True if a local branch of the given name exists.
+  pub fn has_branch(&self, branch: &str) -> bool {
+    self.repo().ok().map(|repo| repo.find_branch(branch, BranchType::Local).is_ok()).unwrap_or(false)
+  }
+
+  /// Create a branch at the given commit, and push it if we're at a remote level. Unlike tags, the branch is never
+  /// forced over an existing one: release branches may hold backports.
+  pub fn create_branch(&self, branch: &str, spec: &str) -> Result<()> {
+    if let GitVcsLevel::None { .. } = self.vcs {
+      return Ok(());
+    }
+
+    let repo = self.repo()?;
+    let commit = repo.revparse_single(spec)?.peel_to_commit()?;
+    repo.branch(branch, &commit, false).chain_err(|| format!("Unable to create branch {}.", branch))?;
+
+    let (repo, remote_name) = match &self.vcs {
+      GitVcsLevel::None { .. } | GitVcsLevel::Local { .. } => return Ok(()),
+      GitVcsLevel::Remote { repo, remote_name, .. } | GitVcsLevel::Smart { repo, remote_name, .. } => {
+        (repo, remote_name)
+      }
+    };
+
+    do_push(repo, remote_name, &[format!("refs/heads/{0}:refs/heads/{0}", branch)])
+  }
+
+  /// Delete a local branch if it exists, and also delete it from the remote if we're at a remote level.
+  pub fn delete_branch(&self, branch: &str) -> Result<()> {
+    if let GitVcsLevel::None { .. } = self.vcs {
+      return Ok(());
+    }
+
+    if let Ok(mut local) = self.repo()?.find_branch(branch, BranchType::Local) {
+      local.delete()?;
+    }
+
+    let (repo, remote_name) = match &self.vcs {
+      GitVcsLevel::None { .. } | GitVcsLevel::Local { .. } => return Ok(()),
+      GitVcsLevel::Remote { repo, remote_name, .. } | GitVcsLevel::Smart { repo, remote_name, .. } => {
+        (repo, remote_name)
+      }
+    };
+
+    do_push(repo, remote_name, &[format!(":refs/heads/{}", branch)])
+  }
+
   fn push_head(&self, tags: &[String]) -> Result<()> {
     let (repo, branch_name, remote_name) = match &self.vcs {
       GitVcsLevel::None { .. } | GitVcsLevel::Local { .. } => return Ok(()),
@@ -442,6 +920,17 @@ impl Repo {
     do_push(repo, remote_name, &[format!("+refs/tags/{}", tag)])
   }
 
+  /// The name of the remote branch that the current branch tracks, if it has one.
+  pub fn upstream_name(&self) -> Result<Option<String>> {
+    let branch_name = match self.branch_name()? {
+      Some(branch_name) => branch_name,
+      None => return Ok(None)
+    };
+    let config = self.repo()?.config()?.snapshot()?;
+    let merge = config.get_string(&format!("branch.{}.merge", branch_name)).ok();
+    Ok(merge.map(|merge| merge.trim_start_matches("refs/heads/").to_string()))
+  }
+
   pub fn branch_name(&self) -> Result<&Option<String>> {
     match &self.vcs {
       GitVcsLevel::None { .. } => err!("No branch name at `none` level."),
@@ -475,6 +964,10 @@ pub struct Slice<'r> {
 impl<'r> Slice<'r> {
   pub fn has_blob(&self, path: &str) -> Result<bool> { Ok(self.object(path).is_ok()) }
   pub fn slice(&self, refspec: FromTagBuf) -> Slice<'r> { Slice { repo: self.repo, refspec } }
+  pub fn repo(&self) -> &'r Repo { self.repo }
+
+  /// The ID of the blob at the path, or `None` if there is no such object.
+  pub fn blob_id(&self, path: &str) -> Result<Option<String>> { Ok(self.object(path).ok().map(|o| o.id().to_string())) }
 
   pub fn blob(&self, path: &str) -> Result<Blob> {
     let obj = self.object(path)?;
@@ -535,14 +1028,17 @@ impl GithubInfo {
   pub fn token(&self) -> &Option<String> { &self.token }
 }
 
-#[derive(Clone, Eq)]
+#[derive(Clone, Eq, Serialize, Deserialize)]
 pub struct CommitInfoBuf {
   id: String,
   summary: String,
   message: String,
   kind: String,
   files: Vec<String>,
-  time: DateTime<FixedOffset>
+  #[serde(serialize_with = "serialize_datetime", deserialize_with = "deserialize_datetime")]
+  time: DateTime<FixedOffset>,
+  #[serde(default)]
+  author: String
 }
 
 impl PartialEq for CommitInfoBuf {
@@ -567,12 +1063,18 @@ impl CommitInfoBuf {
   pub fn new(
     id: String, kind: String, summary: String, message: String, files: Vec<String>, time: DateTime<FixedOffset>
   ) -> CommitInfoBuf {
-    CommitInfoBuf { id, summary, message, kind, files, time }
+    CommitInfoBuf { id, summary, message, kind, files, time, author: String::new() }
+  }
+
+  /// Set the email of the commit's author.
+  pub fn with_author(mut self, author: String) -> CommitInfoBuf {
+    self.author = author;
+    self
   }
 
   pub fn guess(id: String) -> CommitInfoBuf {
     let offset = FixedOffset::west(0);
-    let now = offset.timestamp(Utc::now().timestamp(), 0);
+    let now = offset.timestamp(clock::now().timestamp(), 0);
     CommitInfoBuf::new(id, "-".into(), "-".into(), "".into(), Vec::new(), now)
   }
 
@@ -582,7 +1084,8 @@ impl CommitInfoBuf {
     let message = commit.message().unwrap_or("-").to_string();
     let kind = extract_kind(&message);
     let files = files_from_commit(repo, commit)?.collect();
-    Ok(CommitInfoBuf::new(id, kind, summary, message, files, time_to_datetime(&commit.time())))
+    let author = commit.author().email().unwrap_or_default().to_string();
+    Ok(CommitInfoBuf::new(id, kind, summary, message, files, time_to_datetime(&commit.time())).with_author(author))
   }
 
   pub fn id(&self) -> &str { &self.id }
@@ -590,6 +1093,8 @@ impl CommitInfoBuf {
   pub fn message(&self) -> &str { &self.message }
   pub fn kind(&self) -> &str { &self.kind }
   pub fn files(&self) -> &[String] { &self.files }
+  pub fn time(&self) -> &DateTime<FixedOffset> { &self.time }
+  pub fn author(&self) -> &str { &self.author }
 }
 
 pub struct CommitInfo<'a> {
@@ -606,16 +1111,18 @@ impl<'a> CommitInfo<'a> {
   pub fn kind(&self) -> String { extract_kind(self.message()) }
   pub fn files(&self) -> Result<impl Iterator<Item = String> + 'a> { files_from_commit(self.repo, &self.commit) }
   pub fn time(&self) -> DateTime<FixedOffset> { time_to_datetime(&self.commit.time()) }
+  pub fn author(&self) -> String { self.commit.author().email().unwrap_or_default().to_string() }
 
   pub fn buffer(self) -> Result<CommitInfoBuf> {
-    Ok(CommitInfoBuf::new(
+    let buf = CommitInfoBuf::new(
       self.id(),
       self.kind(),
       self.summary().to_string(),
       self.message().to_string(),
       self.files()?.collect(),
       self.time()
-    ))
+    );
+    Ok(buf.with_author(self.author()))
   }
 }
 
@@ -679,6 +1186,9 @@ impl<'repo> DeltaIter<'repo> {
   }
 }
 
+/// The IDs of some commits, with the time of each.
+type CommitIds = Vec<(String, DateTime<FixedOffset>)>;
+
 pub struct FullPr {
   number: u32,
   title: String,
@@ -686,17 +1196,28 @@ pub struct FullPr {
   head_oid: Option<Oid>,
   base_oid: FromTagBuf,
   base_time: Time,
-  commits: Vec<CommitInfoBuf>,
+  commits: Vec<String>,
   excludes: Vec<String>,
   closed_at: DateTime<FixedOffset>,
+  labels: Vec<String>,
   discovery_order: usize
 }
 
 impl FullPr {
+  /// Look up the commits of a PR. If `closed_at` isn't known, the time of the PR's latest commit is used instead;
+  /// either way, it's normalized to UTC.
+  #[allow(clippy::too_many_arguments)]
   pub fn lookup(
-    repo: &Repo, base: FromTagBuf, headref: String, number: u32, title: String, closed_at: DateTime<FixedOffset>,
-    discovery_order: usize
+    repo: &Repo, base: FromTagBuf, headref: String, number: u32, title: String,
+    closed_at: Option<DateTime<FixedOffset>>, labels: Vec<String>, discovery_order: usize
   ) -> Result<FullPr> {
+    let utc = FixedOffset::east(0);
+    let closed_time = |commits: &[(String, DateTime<FixedOffset>)]| {
+      let latest = || commits.iter().map(|(_, time)| *time).max();
+      let now = || utc.timestamp(clock::now().timestamp(), 0);
+      closed_at.or_else(latest).unwrap_or_else(now).with_timezone(&utc)
+    };
+
     let commit = repo.get_oid(&headref);
     match lookup_from_commit(repo, base.clone(), commit)? {
       Err(e) => {
@@ -710,7 +1231,8 @@ impl FullPr {
           base_time: Time::new(0, 0),
           commits: Vec::new(),
           excludes: Vec::new(),
-          closed_at,
+          closed_at: closed_time(&[]),
+          labels,
           discovery_order
         })
       }
@@ -721,9 +1243,10 @@ impl FullPr {
         head_oid: Some(commit.id()),
         base_oid: base,
         base_time,
-        commits,
+        closed_at: closed_time(&commits),
+        commits: commits.into_iter().map(|(id, _)| id).collect(),
         excludes: Vec::new(),
-        closed_at,
+        labels,
         discovery_order
       })
     }
@@ -734,24 +1257,33 @@ impl FullPr {
   pub fn head_ref(&self) -> &str { &self.head_ref }
   pub fn head_oid(&self) -> &Option<Oid> { &self.head_oid }
   pub fn base_oid(&self) -> FromTag { self.base_oid.as_from_tag() }
-  pub fn commits(&self) -> &[CommitInfoBuf] { &self.commits }
+  pub fn commit_ids(&self) -> &[String] { &self.commits }
   pub fn excludes(&self) -> &[String] { &self.excludes }
   pub fn best_guess(&self) -> bool { self.head_oid.is_none() }
   pub fn has_exclude(&self, oid: &str) -> bool { self.excludes.iter().any(|c| c == oid) }
   pub fn closed_at(&self) -> &DateTime<FixedOffset> { &self.closed_at }
+  pub fn labels(&self) -> &[String] { &self.labels }
   pub fn discovery_order(&self) -> usize { self.discovery_order }
 
-  pub fn included_commits(&self) -> impl Iterator<Item = &CommitInfoBuf> + '_ {
-    self.commits.iter().filter(move |c| !self.has_exclude(c.id()))
+  /// The commits of this PR that aren't excluded, each read from `repo` only when it's reached. The commits of a
+  /// best-guess PR can't be read, so they're guessed at instead.
+  pub fn included_commits<'a>(&'a self, repo: &'a Repo) -> impl Iterator<Item = Result<CommitInfoBuf>> + 'a {
+    self.commits.iter().filter(move |id| !self.has_exclude(id)).map(move |id| {
+      if self.best_guess() {
+        Ok(CommitInfoBuf::guess(id.clone()))
+      } else {
+        repo.commit_buf(id)
+      }
+    })
   }
 
   pub fn span(&self) -> Option<Span> {
     self.head_oid.map(|hoid| Span::new(self.number, hoid, self.base_time, self.base_oid.clone()))
   }
 
-  pub fn add_commit(&mut self, data: CommitInfoBuf) {
-    if !self.commits.iter().any(|c| c.id() == data.id()) {
-      self.commits.push(data)
+  pub fn add_commit(&mut self, commit_oid: &str) {
+    if !self.contains(commit_oid) {
+      self.commits.push(commit_oid.to_string())
     }
   }
 
@@ -761,7 +1293,7 @@ impl FullPr {
     }
   }
 
-  pub fn contains(&self, commit_oid: &str) -> bool { self.commits.iter().any(|c| c.id() == commit_oid) }
+  pub fn contains(&self, commit_oid: &str) -> bool { self.commits.iter().any(|c| c == commit_oid) }
 }
 
 pub struct Span {
@@ -776,6 +1308,15 @@ impl Span {
 
   pub fn number(&self) -> u32 { self.number }
   pub fn end(&self) -> Oid { self.end }
+
+  /// The IDs of this span's commits, newest first, read from the repository at `root`.
+  pub fn commits_at(&self, root: &Path) -> Result<Vec<String>> {
+    let repo = Repository::open(root)?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(self.end)?;
+    hide_from(&repo, &mut revwalk, self.begin())?;
+    revwalk.map(|oid| Ok(oid?.to_string())).collect()
+  }
   pub fn begin(&self) -> FromTag { self.begin.as_from_tag() }
   pub fn since(&self) -> &Time { &self.since }
 }
@@ -863,6 +1404,45 @@ impl fmt::Display for FromTagBuf {
   }
 }
 
+/// The commits that were released on other branches: those reachable from some tag, but not from the head being
+/// planned. A commit on a maintenance branch that is a cherry-pick of one of these is a backport.
+pub struct Backports<'r> {
+  repo: &'r Repo,
+  released: HashSet<Oid>,
+  patch_ids: Option<HashSet<String>>
+}
+
+impl<'r> Backports<'r> {
+  /// True if the commit is a backport of a released change: either its `cherry-pick -x` trailer names a released
+  /// commit, or it makes the same changes as one.
+  pub fn is_backport(&mut self, commit: &CommitInfoBuf) -> Result<bool> {
+    static TRAILER: OnceLock<Regex> = OnceLock::new();
+    let trailer = TRAILER.get_or_init(|| Regex::new(r"\(cherry picked from commit ([0-9a-f]{7,40})\)").unwrap());
+    let repo = self.repo.repo()?;
+    for caps in trailer.captures_iter(commit.message()) {
+      if let Ok(orig) = repo.revparse_single(&caps[1]).and_then(|o| o.peel_to_commit()) {
+        if self.released.contains(&orig.id()) {
+          trace!("Commit {} is a cherry-pick of released {}.", commit.id(), orig.id());
+          return Ok(true);
+        }
+      }
+    }
+
+    let commit_patch = match patch_id(repo, Oid::from_str(commit.id())?)? {
+      Some(commit_patch) => commit_patch,
+      None => return Ok(false)
+    };
+    if self.patch_ids.is_none() {
+      let mut patch_ids = HashSet::new();
+      for oid in &self.released {
+        patch_ids.extend(patch_id(repo, *oid)?);
+      }
+      self.patch_ids = Some(patch_ids);
+    }
+    Ok(self.patch_ids.as_ref().map(|ids| ids.contains(&commit_patch)).unwrap_or(false))
+  }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Auth {
   github_token: Option<String>
@@ -877,6 +1457,39 @@ impl Auth {
   pub fn set_github_token(&mut self, token: Option<String>) { self.github_token = token; }
 }
 
+/// A stable ID of the changes that a commit makes, like `git patch-id`: the changed lines of each file, ignoring
+/// whitespace, line numbers, and context. Merge and root commits have no patch ID.
+fn patch_id(repo: &Repository, oid: Oid) -> Result<Option<String>> {
+  let commit = repo.find_commit(oid)?;
+  if commit.parent_count() != 1 {
+    return Ok(None);
+  }
+  let diff = repo.diff_tree_to_tree(Some(&commit.parent(0)?.tree()?), Some(&commit.tree()?), None)?;
+
+  let mut hasher = Sha256::new();
+  diff.print(DiffFormat::Patch, |delta, _, line| {
+    let origin = line.origin();
+    if origin == '+' || origin == '-' {
+      let path = delta.new_file().path().or_else(|| delta.old_file().path()).unwrap_or_else(|| Path::new(""));
+      let content: Vec<u8> = line.content().iter().filter(|b| !b.is_ascii_whitespace()).cloned().collect();
+      hasher.update(path.to_string_lossy().as_bytes());
+      hasher.update(&[origin as u8]);
+      hasher.update(&content);
+    }
+    true
+  })?;
+  Ok(Some(format!("{:x}", hasher.finalize())))
+}
+
+/// The signature of Versio's own commits, tags, and notes, at the time of this run; see `clock::pin_time`.
+fn versio_signature() -> Result<Signature<'static>> {
+  if clock::is_pinned() {
+    Ok(Signature::new(VERSIO_NAME, VERSIO_EMAIL, &Time::new(clock::now().timestamp(), 0))?)
+  } else {
+    Ok(Signature::now(VERSIO_NAME, VERSIO_EMAIL)?)
+  }
+}
+
 fn find_root_blind<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
   let path = path.as_ref();
   if path.join(CONFIG_FILENAME).exists() {
@@ -1031,39 +1644,6 @@ fn fast_forward(repo: &Repository, rfrnc: &mut Reference, rc: &AnnotatedCommit)
   Ok(())
 }
 
-/// Finds a conventional commit "type" from a commit message.
-///
-/// The type can be one of the special characters "-" (no type found) or "!" ("BREAKING CHANGE:" or
-/// "BREAKING-CHANGE:" starting footer, or "!" after type/scope)
-fn extract_kind(message: &str) -> String {
-  let breaking_pattern =
-    Regex::new("^(?s).*?\\n\\n((BREAKING CHANGE|BREAKING-CHANGE):|.*\n(BREAKING CHANGE|BREAKING-CHANGE):)").unwrap();
-  if breaking_pattern.is_match(message) {
-    return "!".into();
-  }
-
-  match message.char_indices().find(|(_, c)| *c == ':' || *c == '\n') {
-    Some((i, c)) if c == ':' => {
-      let kind = &message[.. i].trim();
-      if kind.ends_with('!') {
-        return "!".into();
-      }
-      match kind.char_indices().find(|(_, c)| *c == '(').map(|(i, _)| i) {
-        Some(i) => {
-          let kind = &kind[0 .. i].trim();
-          if kind.ends_with('!') {
-            "!".into()
-          } else {
-            (*kind).to_lowercase()
-          }
-        }
-        None => (*kind).to_lowercase()
-      }
-    }
-    _ => "-".to_string()
-  }
-}
-
 fn files_from_commit<'a>(repo: &'a Repository, commit: &Commit<'a>) -> Result<impl Iterator<Item = String> + 'a> {
   if commit.parents().len() == 1 {
     let parent = commit.parent(0)?;
@@ -1079,14 +1659,14 @@ fn files_from_commit<'a>(repo: &'a Repository, commit: &Commit<'a>) -> Result<im
 
 fn lookup_from_commit<'a>(
   repo: &Repo, base: FromTagBuf, commit: Result<AnnotatedCommit<'a>>
-) -> Result<Result<(AnnotatedCommit<'a>, Vec<CommitInfoBuf>, Time)>> {
+) -> Result<Result<(AnnotatedCommit<'a>, CommitIds, Time)>> {
   let commit_id = commit.as_ref().map(|c| c.id().to_string()).unwrap_or_else(|_| "<err>".to_string());
   let result = match commit {
     Err(e) => Ok(Err(e)),
     Ok(commit) => {
       let base_time = repo.slice(base.clone()).date()?;
       let (commits, base_time) = repo
-        .commits_between_buf(base.as_from_tag(), commit.id())?
+        .commit_ids_between(base.as_from_tag(), commit.id())?
         .map(|(commits, early)| (commits, min_ok(base_time, early)))
         .unwrap_or_else(|| (Vec::new(), base_time.unwrap_or_else(|| Time::new(0, 0))));
       Ok(Ok((commit, commits, base_time)))
@@ -1096,7 +1676,7 @@ fn lookup_from_commit<'a>(
     "lookup from {} to {:?}: {:?}",
     base,
     commit_id,
-    result.as_ref().map(|r| r.as_ref().map(|(_, list, _)| list.iter().map(|c| c.id().to_string()).collect::<Vec<_>>()))
+    result.as_ref().map(|r| r.as_ref().map(|(_, list, _)| list.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>()))
   );
   result
 }
@@ -1116,9 +1696,9 @@ fn get_oid_local<'r>(repo: &'r Repository, spec: &str) -> Result<AnnotatedCommit
 
 fn get_oid_remote<'r>(
   repo: &'r Repository, branch_name: &Option<String>, spec: &str, remote_name: &str,
-  fetches: &RefCell<HashMap<String, Oid>>
+  fetches: &RefCell<HashMap<String, Oid>>, opts: &FetchOpts, cancel: &Cancel
 ) -> Result<AnnotatedCommit<'r>> {
-  let (commit, cached) = verified_fetch(repo, remote_name, fetches, spec)?;
+  let (commit, cached) = verified_fetch(repo, remote_name, fetches, spec, opts, cancel)?;
 
   if let Some(branch_name) = branch_name {
     if !cached && spec == branch_name {
@@ -1130,7 +1710,8 @@ fn get_oid_remote<'r>(
 }
 
 fn verified_fetch<'r>(
-  repo: &'r Repository, remote_name: &str, fetches: &RefCell<HashMap<String, Oid>>, spec: &str
+  repo: &'r Repository, remote_name: &str, fetches: &RefCell<HashMap<String, Oid>>, spec: &str, opts: &FetchOpts,
+  cancel: &Cancel
 ) -> Result<(AnnotatedCommit<'r>, bool)> {
   verify_current(repo).chain_err(|| "Can't start fetch.")?;
 
@@ -1140,7 +1721,8 @@ fn verified_fetch<'r>(
     return Ok((fetch_commit, true));
   }
 
-  safe_fetch(repo, remote_name, &[spec], true)?;
+  let specs: Vec<_> = once(spec).chain(opts.refspecs.iter().map(|s| s.as_str())).collect();
+  safe_fetch(repo, remote_name, &specs, Some(opts.tags), cancel)?;
 
   // Assume a standard git config `remote.<remote_name>.fetch` layout; if not we can force the tracking
   // branch (change the refspec to "{refspec}:refs/remotes/{remote_name}/{refspec}"), or parse the config
@@ -1187,7 +1769,9 @@ fn verify_current(repo: &Repository) -> Result<()> {
   Ok(())
 }
 
-fn safe_fetch(repo: &Repository, remote_name: &str, specs: &[&str], all_tags: bool) -> Result<()> {
+fn safe_fetch(
+  repo: &Repository, remote_name: &str, specs: &[&str], tags: Option<FetchTags>, cancel: &Cancel
+) -> Result<()> {
   let state = repo.state();
   if state != RepositoryState::Clean {
     // Don't bother if we're in the middle of a merge, rebase, etc.
@@ -1197,18 +1781,20 @@ fn safe_fetch(repo: &Repository, remote_name: &str, specs: &[&str], all_tags: bo
   let mut remote = repo.find_remote(remote_name)?;
 
   // As of git server 2.6, you can fetch `refs/tags/xyz*`
-  do_fetch(&mut remote, specs, all_tags)
+  do_fetch(&mut remote, specs, tags, cancel)
 }
 
-/// Fetch the given refspecs (and maybe all tags) from the remote.
-fn do_fetch(remote: &mut Remote, refs: &[&str], all_tags: bool) -> Result<()> {
+/// Fetch the given refspecs from the remote, along with the given tags; if no tags are given, the remote's
+/// `tagOpt` config decides.
+fn do_fetch(remote: &mut Remote, refs: &[&str], tags: Option<FetchTags>, cancel: &Cancel) -> Result<()> {
   // WARNING: Currently not supporting fetching via sha:
   //
   // git has supported `git fetch <remote> <sha>` for a while, but it has to work a bit differently (since sha's
   // are not technically refspecs).
 
-  info!("Fetching {:?}{}", refs, if all_tags { " and all tags." } else { "." });
+  info!("Fetching {:?}{}", refs, if tags == Some(FetchTags::All) { " and all tags." } else { "." });
 
+  let deadline = cancel.start(Phase::Fetch);
   let mut cb = RemoteCallbacks::new();
 
   cb.credentials(find_creds);
@@ -1225,11 +1811,11 @@ fn do_fetch(remote: &mut Remote, refs: &[&str], all_tags: bool) -> Result<()> {
       );
     }
     stdout().flush().unwrap();
-    true
+    !deadline.is_done()
   });
   cb.sideband_progress(|bytes| {
     info!("Fetch progress: {}", String::from_utf8_lossy(bytes));
-    true
+    !deadline.is_done()
   });
   cb.update_tips(|tip, _old, _new| {
     info!("Fetch update: {}", tip);
@@ -1239,10 +1825,14 @@ fn do_fetch(remote: &mut Remote, refs: &[&str], all_tags: bool) -> Result<()> {
   let mut fo = FetchOptions::new();
   fo.remote_callbacks(cb);
 
-  if all_tags {
-    fo.download_tags(AutotagOption::All);
+  if let Some(tags) = tags {
+    fo.download_tags(tags.autotag());
+  }
+  if let Err(e) = remote.fetch(refs, Some(&mut fo), None) {
+    // An abort from the progress callbacks looks like any other failure.
+    deadline.check()?;
+    return Err(e.into());
   }
-  remote.fetch(refs, Some(&mut fo), None)?;
 
   let stats = remote.stats();
   if stats.local_objects() > 0 {
@@ -1295,10 +1885,23 @@ pub fn do_push(repo: &Repository, remote_name: &str, specs: &[String]) -> Result
   push_opts.remote_callbacks(cb);
 
   let mut remote = repo.find_remote(remote_name)?;
-  remote.push(specs, Some(&mut push_opts))?;
+  remote.push(specs, Some(&mut push_opts)).chain_err(|| Error::vcs(format!("push to {}", remote_name)))?;
   Ok(())
 }
 
+pub fn serialize_datetime<S: Serializer>(
+  time: &DateTime<FixedOffset>, serializer: S
+) -> std::result::Result<S::Ok, S::Error> {
+  serializer.serialize_str(&time.to_rfc3339())
+}
+
+pub fn deserialize_datetime<'de, D: Deserializer<'de>>(
+  desr: D
+) -> std::result::Result<DateTime<FixedOffset>, D::Error> {
+  let v = String::deserialize(desr)?;
+  DateTime::parse_from_rfc3339(&v).map_err(|e| de::Error::custom(format!("Couldn't parse date {}: {:?}", v, e)))
+}
+
 pub fn time_to_datetime(time: &Time) -> DateTime<FixedOffset> {
   const MINUTES: i32 = 60;
   FixedOffset::east(time.offset_minutes() * MINUTES).timestamp(time.seconds(), 0)
@@ -1306,70 +1909,88 @@ pub fn time_to_datetime(time: &Time) -> DateTime<FixedOffset> {
 
 #[cfg(test)]
 mod test {
-  use super::extract_kind;
-
-  #[test]
-  fn test_kind_simple() {
-    assert_eq!(&extract_kind("thing: this is thing"), "thing");
-  }
-
-  #[test]
-  fn test_kind_bang() {
-    assert_eq!(&extract_kind("thing! : this is thing"), "!");
-  }
-
-  #[test]
-  fn test_kind_paren() {
-    assert_eq!(&extract_kind("thing(scope): this is thing"), "thing");
-  }
-
-  #[test]
-  fn test_kind_complex() {
-    assert_eq!(&extract_kind("thing(scope)!: this is thing"), "!");
-  }
-
-  #[test]
-  fn test_kind_backwards() {
-    assert_eq!(&extract_kind("thing!(scope): this is thing"), "!");
-  }
-
-  #[test]
-  fn test_kind_breaking() {
-    assert_eq!(&extract_kind("thing(scope): this is thing\n\nbody\n\nBREAKING CHANGE: yup"), "!");
-  }
-
-  #[test]
-  fn test_kind_breaking_no_body() {
-    assert_eq!(&extract_kind("thing(scope): this is thing\n\nBREAKING CHANGE: yup"), "!");
-  }
-
-  #[test]
-  fn test_kind_breaking_later() {
-    assert_eq!(&extract_kind("thing(scope): this is thing\n\nbody\n\nfoot: 1\nBREAKING CHANGE: yup"), "!");
-  }
-
-  #[test]
-  fn test_kind_breaking_both() {
-    assert_eq!(&extract_kind("thing(scope)!: this is thing\n\nbody\n\nBREAKING CHANGE: yup"), "!");
-  }
-
-  #[test]
-  fn test_kind_breaking_dash() {
-    assert_eq!(&extract_kind("thing(scope): this is thing\n\nbody\n\nBREAKING-CHANGE: yup"), "!");
-  }
+  use super::{do_push, Repo};
+  use crate::vcs::{VcsLevel, VcsState};
+  use git2::{Repository, Signature};
+  use tempfile::tempdir;
 
   #[test]
-  fn test_empty() {
-    assert_eq!(&extract_kind(""), "-");
+  fn test_delete_tag_only_on_remote() {
+    let remote_dir = tempdir().unwrap();
+    let remote = Repository::init_bare(remote_dir.path()).unwrap();
+    let dir = tempdir().unwrap();
+    let local = Repository::init(dir.path()).unwrap();
+    local.remote("origin", remote_dir.path().to_str().unwrap()).unwrap();
+
+    let sig = Signature::now("Tester", "tester@example.com").unwrap();
+    let tree = local.find_tree(local.index().unwrap().write_tree().unwrap()).unwrap();
+    let oid = local.commit(Some("HEAD"), &sig, &sig, "feat: start", &tree, &[]).unwrap();
+    local.tag_lightweight("v1.0.0", &local.find_object(oid, None).unwrap(), false).unwrap();
+    do_push(&local, "origin", &["refs/tags/v1.0.0".to_string()]).unwrap();
+    local.tag_delete("v1.0.0").unwrap();
+    assert!(remote.find_reference("refs/tags/v1.0.0").is_ok());
+
+    let repo = Repo::open(dir.path(), VcsState::new(VcsLevel::Remote, false)).unwrap();
+    repo.delete_tag("v1.0.0").unwrap();
+    assert!(remote.find_reference("refs/tags/v1.0.0").is_err());
   }
 
   #[test]
-  fn test_unconventional() {
-    assert_eq!(&extract_kind("-"), "-");
+  fn test_remote_tags_peel_annotated() {
+    let remote_dir = tempdir().unwrap();
+    Repository::init_bare(remote_dir.path()).unwrap();
+    let dir = tempdir().unwrap();
+    let local = Repository::init(dir.path()).unwrap();
+    local.remote("origin", remote_dir.path().to_str().unwrap()).unwrap();
+
+    let sig = Signature::now("Tester", "tester@example.com").unwrap();
+    let tree = local.find_tree(local.index().unwrap().write_tree().unwrap()).unwrap();
+    let oid = local.commit(Some("HEAD"), &sig, &sig, "feat: start", &tree, &[]).unwrap();
+    let commit = local.find_object(oid, None).unwrap();
+    local.tag_lightweight("v1.0.0", &commit, false).unwrap();
+    local.tag("v2.0.0", &commit, &sig, "annotated", false).unwrap();
+    do_push(&local, "origin", &["refs/tags/v1.0.0".to_string(), "refs/tags/v2.0.0".to_string()]).unwrap();
+
+    let repo = Repo::open(dir.path(), VcsState::new(VcsLevel::Remote, false)).unwrap();
+    let tags = repo.remote_tags().unwrap();
+    assert_eq!(tags.len(), 2);
+    assert_eq!(tags.get("v1.0.0"), Some(&oid.to_string()));
+    assert_eq!(tags.get("v2.0.0"), Some(&oid.to_string()));
+
+    let repo = Repo::open(dir.path(), VcsState::new(VcsLevel::Local, false)).unwrap();
+    assert!(repo.remote_tags().unwrap().is_empty());
   }
 
   #[test]
-  fn test_uncertain() {
-    assert_eq!(&extract_kind("ENG-123: I forgot to conventinal commit"), "eng-123");
+  fn test_start_fetch_skips_tags() {
+    let remote_dir = tempdir().unwrap();
+    Repository::init_bare(remote_dir.path()).unwrap();
+    let dir = tempdir().unwrap();
+    let local = Repository::init(dir.path()).unwrap();
+    local.remote("origin", remote_dir.path().to_str().unwrap()).unwrap();
+
+    let sig = Signature::now("Tester", "tester@example.com").unwrap();
+    let tree = local.find_tree(local.index().unwrap().write_tree().unwrap()).unwrap();
+    let oid = local.commit(Some("HEAD"), &sig, &sig, "feat: start", &tree, &[]).unwrap();
+    let branch = local.head().unwrap().name().unwrap().to_string();
+    do_push(&local, "origin", std::slice::from_ref(&branch)).unwrap();
+
+    let clone_dir = tempdir().unwrap();
+    let clone = Repository::clone(remote_dir.path().to_str().unwrap(), clone_dir.path()).unwrap();
+
+    let parent = local.find_commit(oid).unwrap();
+    let next = local.commit(Some("HEAD"), &sig, &sig, "fix: next", &tree, &[&parent]).unwrap();
+    local.tag_lightweight("v1.0.0", &local.find_object(next, None).unwrap(), false).unwrap();
+    do_push(&local, "origin", &[branch.clone(), "refs/tags/v1.0.0".to_string()]).unwrap();
+
+    let repo = Repo::open(clone_dir.path(), VcsState::new(VcsLevel::Remote, false)).unwrap();
+    repo.start_fetch().unwrap().finish().unwrap();
+    let tracking = branch.replace("refs/heads/", "refs/remotes/origin/");
+    assert_eq!(clone.refname_to_id(&tracking).unwrap(), next);
+    assert!(clone.find_reference("refs/tags/v1.0.0").is_err());
+    assert_eq!(clone.head().unwrap().target(), Some(oid));
+
+    let repo = Repo::open(clone_dir.path(), VcsState::new(VcsLevel::Local, false)).unwrap();
+    assert!(repo.start_fetch().is_none());
   }
 }
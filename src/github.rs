@@ -1,104 +1,46 @@
 //! Interactions with github API v4.
 
-use crate::errors::Result;
-use crate::git::{time_to_datetime, Auth, CommitInfoBuf, FromTag, FromTagBuf, FullPr, GithubInfo, Repo, Span};
-use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+use crate::cancel::{Cancel, Phase};
+use crate::errors::{Error, Result, ResultExt as _};
+use crate::forge::{ForgeCommit, ForgePr, PrProvider, ProviderFuture};
+use crate::git::{time_to_datetime, GithubInfo, Span};
+use chrono::{DateTime, FixedOffset};
 use octocrab::Octocrab;
 use serde::de::{self, Deserializer, Visitor};
 use serde::Deserialize;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 
-/// Find all changes in a repo more cleverly than `git rev-parse begin..end` using the GitHub v4 GraphQL API.
-///
-/// This method groups the commits into pull requests (PRs), starting with "PR zero" (which is an artificial
-/// group that contains all commits in the given range) and for each commit, ask the GitHub API for "associated
-/// pull requests". For each such associated PR, it performs a rev-parse on the base/head of that PR to search
-/// for more commits, and continues recursively. Each commit found may placed into more than one PR.
-///
-/// When a commit is found where it itself does not belong to one of its own associated PRs' "base..head"
-/// rev-parse, we assume that this is the result of a "squash merge" from that PR (or some other type of PR
-/// rebase). The squash commit is excluded from all PRs: instead the PR's own commits are examined normally. In
-/// this way, the original type and size information from the PR is preserved.
-#[allow(clippy::map_entry)]
-pub async fn changes(auth: &Auth, repo: &Repo, baseref: FromTagBuf, headref: String) -> Result<Changes> {
-  let mut all_commits = HashSet::new();
-  let mut all_prs = HashMap::new();
-
-  let mut discover_order = 0;
-  let mut queue = VecDeque::new();
-  let offset = FixedOffset::west(0);
-  let pr_zero = FullPr::lookup(
-    repo,
-    baseref,
-    headref.clone(),
-    0,
-    "".into(),
-    offset.timestamp(Utc::now().timestamp(), 0),
-    discover_order
-  )?;
-  discover_order += 1;
-  queue.push_back(pr_zero.span().ok_or_else(|| bad!("Unable to get oid for seed ref \"{}\".", headref))?);
-  all_prs.insert(pr_zero.number(), pr_zero);
-
-  let github_info = match repo.github_info(auth) {
-    Ok(github_info) => github_info,
-    Err(_) => return Ok(Changes { groups: all_prs, commits: all_commits })
-  };
-
-  while let Some(span) = queue.pop_front() {
-    let commit_list = commits_from_v4_api(&github_info, &span).await?;
-    let commit_list: Vec<_> = commit_list
-      .into_iter()
-      .filter_map(|commit| {
-        if all_commits.contains(commit.oid()) {
-          return None;
-        }
-
-        let mut retain = true;
-        let (oid, prs) = commit.extract();
-        for pr in prs.merged_only() {
-          let number = pr.number();
-          if !all_prs.contains_key(&number) {
-            let full_pr = match pr.lookup(repo, discover_order) {
-              Ok(pr) => pr,
-              Err(e) => return Some(Err(e))
-            };
-            discover_order += 1;
-            if let Some(span) = full_pr.span() {
-              queue.push_back(span);
-            }
-            all_prs.insert(number, full_pr);
-          }
-          let full_pr = all_prs.get_mut(&number).unwrap();
+/// The GitHub v4 GraphQL API, as a provider of the PRs of each commit.
+pub struct GithubProvider {
+  info: GithubInfo,
+  cancel: Cancel
+}
 
-          if full_pr.best_guess() {
-            full_pr.add_commit(CommitInfoBuf::guess(oid.clone()));
-          } else if !full_pr.contains(&oid) {
-            retain = false;
-          }
-        }
+impl GithubProvider {
+  pub fn new(info: GithubInfo, cancel: Cancel) -> GithubProvider { GithubProvider { info, cancel } }
+}
 
-        if retain {
-          Some(Ok(oid))
-        } else {
-          all_prs.get_mut(&span.number()).unwrap().add_exclude(&oid);
-          None
-        }
-      })
-      .collect::<Result<_>>()?;
+impl PrProvider for GithubProvider {
+  fn api_name(&self) -> &'static str { "the GitHub API" }
 
-    all_commits.extend(commit_list.into_iter());
+  fn span_commits<'a>(&'a self, span: &'a Span) -> ProviderFuture<'a, Vec<ForgeCommit>> {
+    Box::pin(async move {
+      let commits = commits_from_v4_api(&self.info, span, &self.cancel).await?;
+      Ok(commits.into_iter().map(ApiCommit::into_forge).collect())
+    })
   }
 
-  Ok(Changes { commits: all_commits, groups: all_prs })
-}
+  fn pr_url(&self, number: u32) -> Option<String> {
+    Some(format!("https://github.com/{}/{}/pull/{}", self.info.owner_name(), self.info.repo_name(), number))
+  }
 
-pub fn line_commits_head(repo: &Repo, base: FromTag) -> Result<Vec<CommitInfoBuf>> {
-  repo.commits_to_head(base, false)?.map(|i| i?.buffer()).collect::<Result<_>>()
+  fn commit_url(&self, oid: &str) -> Option<String> {
+    Some(format!("https://github.com/{}/{}/commit/{}", self.info.owner_name(), self.info.repo_name(), oid))
+  }
 }
 
-async fn commits_from_v4_api(github_info: &GithubInfo, span: &Span) -> Result<Vec<ApiCommit>> {
+async fn commits_from_v4_api(github_info: &GithubInfo, span: &Span, cancel: &Cancel) -> Result<Vec<ApiCommit>> {
   let query = r#"query associatedPRs($since:GitTimestamp!, $sha:String!, $repo:String!, $owner:String!){
   repository(name:$repo, owner:$owner){
     commit:object(expression: $sha){
@@ -127,6 +69,14 @@ fragment commitResult on Commit {
           headRefName
           baseRefOid
           closedAt
+          mergeCommit {
+            committedDate
+          }
+          labels(first:20) {
+            nodes {
+              name
+            }
+          }
         }
       }
     }
@@ -152,7 +102,11 @@ fragment commitResult on Commit {
   let octo = if let Some(token) = token { octo.personal_token(token) } else { octo };
   let octo = octo.build()?;
   let full_query = serde_json::json!({"query": &query, "variables": &variables});
-  let changes: ChangesResponse = octo.post("/graphql", Some(&full_query)).await?;
+  let changes: ChangesResponse = cancel
+    .guard(Phase::Api, async {
+      octo.post("/graphql", Some(&full_query)).await.chain_err(|| Error::network("the GitHub API"))
+    })
+    .await?;
 
   let changes = changes.data.repository.commit.history.nodes;
   let mut changes: HashMap<String, ApiCommit> = changes.into_iter().map(|c| (c.oid().to_string(), c)).collect();
@@ -171,17 +125,6 @@ fragment commitResult on Commit {
   Ok(changes.into_iter().map(|(_, v)| v).collect())
 }
 
-pub struct Changes {
-  commits: HashSet<String>,
-  groups: HashMap<u32, FullPr>
-}
-
-impl Changes {
-  pub fn commits(&self) -> &HashSet<String> { &self.commits }
-  pub fn groups(&self) -> &HashMap<u32, FullPr> { &self.groups }
-  pub fn into_groups(self) -> HashMap<u32, FullPr> { self.groups }
-}
-
 #[derive(Deserialize)]
 struct ChangesResponse {
   data: Data
@@ -216,8 +159,11 @@ struct ApiCommit {
 }
 
 impl ApiCommit {
-  fn extract(self) -> (String, PrList) { (self.oid, self.associated_pull_requests) }
   fn oid(&self) -> &str { &self.oid }
+
+  fn into_forge(self) -> ForgeCommit {
+    ForgeCommit::new(self.oid, self.associated_pull_requests.merged_only().map(PrEdgeNode::into_forge).collect())
+  }
 }
 
 #[derive(Deserialize)]
@@ -260,32 +206,54 @@ struct PrEdgeNode {
   head_ref_name: String,
   #[serde(rename = "baseRefOid")]
   base_ref_oid: String,
-  #[serde(rename = "closedAt", deserialize_with = "deserialize_datetime")]
-  closed_at: DateTime<FixedOffset>
+  #[serde(rename = "closedAt", default, deserialize_with = "deserialize_datetime")]
+  closed_at: Option<DateTime<FixedOffset>>,
+  #[serde(rename = "mergeCommit", default)]
+  merge_commit: Option<MergeCommit>,
+  #[serde(default)]
+  labels: LabelList
+}
+
+#[derive(Deserialize)]
+struct MergeCommit {
+  #[serde(rename = "committedDate", default, deserialize_with = "deserialize_datetime")]
+  committed_date: Option<DateTime<FixedOffset>>
+}
+
+#[derive(Deserialize, Default)]
+struct LabelList {
+  nodes: Vec<LabelNode>
+}
+
+#[derive(Deserialize)]
+struct LabelNode {
+  name: String
 }
 
 impl PrEdgeNode {
-  pub fn number(&self) -> u32 { self.number }
-  pub fn state(&self) -> &str { &self.state }
+  fn state(&self) -> &str { &self.state }
 
-  pub fn lookup(self, repo: &Repo, discover_order: usize) -> Result<FullPr> {
-    FullPr::lookup(
-      repo,
-      FromTagBuf::new(self.base_ref_oid, false),
-      self.head_ref_name,
+  fn into_forge(self) -> ForgePr {
+    let merge_commit = self.merge_commit;
+    let closed_at = self.closed_at.or_else(|| merge_commit.and_then(|m| m.committed_date));
+    ForgePr::new(
       self.number,
       self.title,
-      self.closed_at,
-      discover_order
+      self.head_ref_name,
+      self.base_ref_oid,
+      closed_at,
+      self.labels.nodes.into_iter().map(|l| l.name).collect()
     )
   }
 }
 
-fn deserialize_datetime<'de, D: Deserializer<'de>>(desr: D) -> std::result::Result<DateTime<FixedOffset>, D::Error> {
+fn deserialize_datetime<'de, D: Deserializer<'de>>(
+  desr: D
+) -> std::result::Result<Option<DateTime<FixedOffset>>, D::Error> {
   struct DateTimeVisitor;
 
   impl<'de> Visitor<'de> for DateTimeVisitor {
-    type Value = DateTime<FixedOffset>;
+    type Value = Option<DateTime<FixedOffset>>;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result { formatter.write_str("an RFC 3339 datetime") }
 
@@ -293,13 +261,11 @@ fn deserialize_datetime<'de, D: Deserializer<'de>>(desr: D) -> std::result::Resu
       if v.is_empty() || v.trim() == "null" {
         return self.visit_none();
       }
-      DateTime::parse_from_rfc3339(v).map_err(|e| de::Error::custom(format!("Couldn't parse date {}: {:?}", v, e)))
+      let date = DateTime::parse_from_rfc3339(v);
+      date.map(Some).map_err(|e| de::Error::custom(format!("Couldn't parse date {}: {:?}", v, e)))
     }
 
-    fn visit_none<E: de::Error>(self) -> std::result::Result<Self::Value, E> {
-      let offset = FixedOffset::west(0);
-      Ok(offset.timestamp(Utc::now().timestamp(), 0))
-    }
+    fn visit_none<E: de::Error>(self) -> std::result::Result<Self::Value, E> { Ok(None) }
 
     fn visit_unit<E: de::Error>(self) -> std::result::Result<Self::Value, E> { self.visit_none() }
   }
@@ -1,21 +1,63 @@
 //! Versio is a version management utility.
+//!
+//! Without the default `cli` feature, only the read-only core is built: the scanners that find versions in files,
+//! and the sizing of conventional commits. It doesn't need git, gpgme, or the network, and so builds to wasm32.
 
 #![recursion_limit = "1024"]
 
 #[macro_use]
 pub mod errors;
+pub mod api;
+#[cfg(feature = "cli")]
 pub mod commands;
+#[cfg(feature = "cli")]
 pub mod init;
+// Some scanning is only used by the config, which isn't built without `cli`.
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
 pub(crate) mod scan;
+#[cfg(feature = "cli")]
 pub mod vcs;
 
+#[cfg(feature = "cli")]
 mod analyze;
+#[cfg(feature = "cli")]
+mod cancel;
+#[cfg(feature = "cli")]
+mod clock;
+#[cfg(feature = "cli")]
 mod config;
+#[cfg(feature = "cli")]
+mod deploy;
+#[cfg(feature = "cli")]
 mod either;
+#[cfg(all(test, feature = "cli"))]
+mod fixture;
+#[cfg(feature = "cli")]
+mod forge;
+#[cfg(feature = "cli")]
+mod formula;
+#[cfg(feature = "cli")]
 mod git;
+#[cfg(feature = "cli")]
 mod github;
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
 mod mark;
+#[cfg(feature = "cli")]
 mod mono;
+#[cfg(feature = "cli")]
+mod notify;
+#[cfg(feature = "cli")]
+mod observe;
+#[cfg(feature = "cli")]
 mod output;
+#[cfg(feature = "cli")]
+mod progress;
+#[cfg(feature = "cli")]
+mod publish;
+mod sizes;
+#[cfg(feature = "cli")]
 mod state;
+#[cfg(feature = "cli")]
 mod template;
+#[cfg(feature = "cli")]
+mod timing;
@@ -19,12 +19,7 @@ fn main() {
       writeln!(stderr, "  Caused by: {}", e).expect(errmsg);
     }
 
-    // Try running with `RUST_BACKTRACE=1` for a backtrace
-    if let Some(backtrace) = e.backtrace() {
-      writeln!(stderr, "Backtrace:\n{:?}", backtrace).expect(errmsg);
-    }
-
-    std::process::exit(1);
+    std::process::exit(e.exit_code());
   }
 }
 
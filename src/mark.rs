@@ -1,9 +1,9 @@
 //! Management of reading and writing marks to files.
 
-use crate::errors::Result;
+use crate::errors::{Result, ResultExt as _};
 use crate::scan::parts::{deserialize_parts, Part};
-use crate::scan::{find_reg_data, scan_reg_data, JsonScanner, Scanner, TomlScanner, XmlScanner, YamlScanner};
-use error_chain::bail;
+use crate::scan::{find_reg_data, scan_reg_data, JsonScanner, Scanner, ScannerFactory, Scanners, TomlScanner,
+                  XmlScanner, YamlScanner};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::fmt;
@@ -13,6 +13,7 @@ use std::path::{Path, PathBuf};
 #[derive(Clone, Deserialize, Serialize, Debug)]
 #[serde(untagged)]
 pub enum Picker {
+  Custom(CustomPicker),
   Json(ScanningPicker<JsonScanner>),
   Yaml(ScanningPicker<YamlScanner>),
   Toml(ScanningPicker<TomlScanner>),
@@ -25,6 +26,7 @@ impl Picker {
   #[cfg(test)]
   pub fn picker_type(&self) -> &'static str {
     match self {
+      Picker::Custom(_) => "custom",
       Picker::Json(_) => "json",
       Picker::Yaml(_) => "yaml",
       Picker::Toml(_) => "toml",
@@ -36,6 +38,7 @@ impl Picker {
 
   pub fn scan(&self, data: NamedData) -> Result<MarkedData> {
     match self {
+      Picker::Custom(p) => p.scan(data),
       Picker::Json(p) => p.scan(data),
       Picker::Yaml(p) => p.scan(data),
       Picker::Toml(p) => p.scan(data),
@@ -47,6 +50,7 @@ impl Picker {
 
   pub fn find(&self, data: &str) -> Result<Mark> {
     match self {
+      Picker::Custom(p) => p.find_version(data),
       Picker::Json(p) => p.find_version(data),
       Picker::Yaml(p) => p.find_version(data),
       Picker::Toml(p) => p.find_version(data),
@@ -78,6 +82,34 @@ impl<T: Scanner> ScanningPicker<T> {
   pub fn scan(&self, data: NamedData) -> Result<MarkedData> { T::build(self.parts.clone()).scan(data) }
 }
 
+/// A picker that uses a registered custom scanner. The scanner is found by name when the picker's config is bound
+/// to the registered `Scanners`.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct CustomPicker {
+  scanner: String,
+  #[serde(deserialize_with = "deserialize_parts")]
+  parts: Vec<Part>,
+  #[serde(skip)]
+  factory: Option<ScannerFactory>
+}
+
+impl CustomPicker {
+  pub fn new(scanner: String, parts: Vec<Part>) -> CustomPicker { CustomPicker { scanner, parts, factory: None } }
+  pub fn find_version(&self, data: &str) -> Result<Mark> { self.build()?.find_version(data) }
+  pub fn scan(&self, data: NamedData) -> Result<MarkedData> { self.build()?.scan(data) }
+
+  pub fn bind(&mut self, scanners: &Scanners) -> Result<()> {
+    let factory = scanners.get(&self.scanner).ok_or_else(|| bad!("No scanner \"{}\" registered.", self.scanner))?;
+    self.factory = Some(factory);
+    Ok(())
+  }
+
+  fn build(&self) -> Result<Box<dyn Scanner + Send + Sync>> {
+    let factory = self.factory.ok_or_else(|| bad!("No scanner \"{}\" registered.", self.scanner))?;
+    Ok(factory(self.parts.clone()))
+  }
+}
+
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct LinePicker {
   pattern: String
@@ -113,7 +145,7 @@ impl FilePicker {
 
   pub fn scan(&self, data: NamedData) -> Result<MarkedData> {
     let mark = self.find(data.data())?;
-    Ok(data.mark(mark))
+    data.mark(mark)
   }
 }
 
@@ -130,7 +162,12 @@ impl NamedData {
   pub fn new(writeable_path: PathBuf, data: String) -> NamedData { NamedData { writeable_path, data } }
   pub fn writeable_path(&self) -> &Path { &self.writeable_path }
   pub fn data(&self) -> &str { &self.data }
-  pub fn mark(self, mark: Mark) -> MarkedData { MarkedData::new(self.writeable_path, self.data, mark) }
+
+  /// Attach a mark to this data, failing if the mark doesn't hold its value at a valid byte range of the data.
+  pub fn mark(self, mark: Mark) -> Result<MarkedData> {
+    mark.check(&self.data).chain_err(|| format!("Bad mark in {}.", self.writeable_path.display()))?;
+    Ok(MarkedData::new(self.writeable_path, self.data, mark))
+  }
 }
 
 pub struct MarkedData {
@@ -153,6 +190,12 @@ impl MarkedData {
     Ok(())
   }
 
+  /// Replace the marked value, and return the new data instead of writing it.
+  pub fn into_new_value(mut self, new_val: &str) -> String {
+    self.set_value(new_val);
+    self.data
+  }
+
   fn set_value(&mut self, new_val: &str) {
     let st = self.start();
     let ed = st + self.value().len();
@@ -181,6 +224,16 @@ impl Mark {
     Ok(())
   }
 
+  /// Verify that the mark's value is found at its byte offset in `data`, on character boundaries, so that
+  /// replacing it can't split a multi-byte character.
+  pub fn check(&self, data: &str) -> Result<()> {
+    match data.get(self.byte_start .. self.byte_start + self.value.len()) {
+      Some(found) if found == self.value => Ok(()),
+      Some(found) => bail!("Found \"{}\" at byte {} instead of \"{}\".", found, self.byte_start, self.value),
+      None => bail!("Byte {} is not a valid position for \"{}\".", self.byte_start, self.value)
+    }
+  }
+
   pub fn value(&self) -> &str { &self.value }
   pub fn set_value(&mut self, new_val: String) { self.value = new_val; }
   pub fn start(&self) -> usize { self.byte_start }
@@ -203,14 +256,19 @@ impl CharMark {
   pub fn char_start(&self) -> usize { self.char_start }
 
   pub fn into_byte_mark(self, data: &str) -> Result<Mark> {
-    let start = data.char_indices().nth(self.char_start).unwrap().0;
+    let start = data
+      .char_indices()
+      .map(|(i, _)| i)
+      .chain(std::iter::once(data.len()))
+      .nth(self.char_start)
+      .ok_or_else(|| bad!("Character {} is past the end of the data.", self.char_start))?;
     Ok(Mark::new(self.value, start))
   }
 }
 
 #[cfg(test)]
 mod test {
-  use super::find_reg_data;
+  use super::{find_reg_data, scan_reg_data, Mark, NamedData};
 
   #[test]
   fn test_find_reg() {
@@ -222,4 +280,20 @@ Current rev is "v1.2.3" because it is."#;
     assert_eq!("1.2.3", mark.value());
     assert_eq!(32, mark.start());
   }
+
+  #[test]
+  fn test_check_mark() {
+    let data = "naïve: 1.2.3";
+    assert!(Mark::new("1.2.3".into(), 8).check(data).is_ok());
+    assert!(Mark::new("1.2.3".into(), 7).check(data).is_err());
+    assert!(Mark::new("ve".into(), 3).check(data).is_err());
+    assert!(Mark::new("1.2.3".into(), 20).check(data).is_err());
+  }
+
+  #[test]
+  fn test_reg_rewrite_utf8() {
+    let data = NamedData::new("VERSION".into(), "Vérsiøn: v1.2.3\n".into());
+    let marked = scan_reg_data(data, "v(\\d+\\.\\d+\\.\\d+)").unwrap();
+    assert_eq!("Vérsiøn: v2.0.0\n", marked.into_new_value("2.0.0"));
+  }
 }
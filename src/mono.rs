@@ -1,80 +1,188 @@
 //! A monorepo can read and alter the current state of all projects.
 
 use crate::analyze::{analyze, Analysis};
-use crate::config::{Config, ConfigFile, Depends, FsConfig, Project, ProjectId, Size};
+use crate::cancel::{Cancel, Phase};
+use crate::clock;
+use crate::config::{branch_matches, CommitMode, CommitsMode, Config, ConfigFile, Depends, FsConfig, MissingPrev,
+                    Project, ProjectId, SeenConfigs, SkipCi};
 use crate::either::{IterEither2 as E2, IterEither3 as E3};
-use crate::errors::Result;
-use crate::git::{Auth, CommitInfoBuf, FromTag, FromTagBuf, FullPr, GithubInfo, Repo};
-use crate::github::{changes, line_commits_head, Changes};
-use crate::state::{CommitArgs, CurrentState, OldTags, PrevFiles, PrevTagMessage, StateRead, StateWrite};
+use crate::errors::{Error, Result, ResultExt};
+use crate::forge::{changes, find_provider, line_commits_head, Changes, PrProvider};
+use crate::git::{deserialize_datetime, serialize_datetime, Auth, Backports, CommitInfoBuf, FromTag, FromTagBuf,
+                 FullPr, GithubInfo, Repo, RELEASE_MSG};
+use crate::mark::{Picker, ScanningPicker};
+use crate::notify::Released;
+use crate::observe::Observer;
+use crate::progress::Progress;
+use crate::scan::parts::{IntoPartVec as _, Part};
+use crate::scan::{JsonScanner, Scanner, Scanners, XmlScanner};
+use crate::sizes::Size;
+use crate::state::{BumpHooks, CommitArgs, CurrentState, FilesRead, LastPrev, OldTags, PickPath, PrevFiles,
+                   PrevTagMessage, StateRead, StateWrite};
+use crate::timing::Timer;
 use crate::vcs::VcsState;
 use chrono::{DateTime, FixedOffset};
-use error_chain::bail;
-use log::trace;
-use serde::Deserialize;
+use ignore::WalkBuilder;
+use log::{info, trace, warn};
+use path_slash::{PathBufExt as _, PathExt as _};
+use rayon::prelude::*;
+use regex::{escape, Regex};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
 use std::cmp::{max, Ordering};
-use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::iter::{empty, once};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 const USER_PREFS_DIR: &str = ".versio";
 const USER_PREFS_FILE: &str = "prefs.toml";
+const BUILD_INFO_FILE: &str = "versio-release.json";
+
+/// Set this environment variable to build every plan from scratch, instead of reusing a cached plan.
+pub const NO_PLAN_CACHE_VAR: &str = "VERSIO_NO_PLAN_CACHE";
+
+/// A project, with the warnings of its check or the error that failed it.
+pub type ProjectCheck<'a> = (&'a Project, Result<Vec<String>>);
 
 pub struct Mono {
   current: Config<CurrentState>,
   next: StateWrite,
   last_commits: HashMap<ProjectId, String>,
   repo: Repo,
-  user_prefs: UserPrefs
+  user_prefs: UserPrefs,
+  base: String
+}
+
+/// Opens a `Mono` with more than the default options.
+pub struct MonoBuilder {
+  dir: PathBuf,
+  vcs: VcsState,
+  max_history: Option<usize>,
+  scanners: Scanners,
+  cancel: Cancel
+}
+
+impl MonoBuilder {
+  pub fn new<P: AsRef<Path>>(dir: P, vcs: VcsState) -> MonoBuilder {
+    MonoBuilder {
+      dir: dir.as_ref().to_path_buf(),
+      vcs,
+      max_history: None,
+      scanners: Scanners::new(),
+      cancel: Cancel::new()
+    }
+  }
+
+  /// If the prev tag is missing, limit history scans to `max` commits, in place of the config's `max_history`.
+  pub fn max_history(mut self, max: Option<usize>) -> MonoBuilder {
+    self.max_history = max;
+    self
+  }
+
+  /// Stop fetches, API calls, and plan building when `cancel` is cancelled or its timeouts pass.
+  pub fn cancel(mut self, cancel: Cancel) -> MonoBuilder {
+    self.cancel = cancel;
+    self
+  }
+
+  /// Use these custom scanners for version locations, in the current config and in the configs of older commits.
+  pub fn scanners(mut self, scanners: Scanners) -> MonoBuilder {
+    self.scanners = scanners;
+    self
+  }
+
+  pub fn open(self) -> Result<Mono> { Mono::open_with(self) }
 }
 
 impl Mono {
   pub fn here(vcs: VcsState) -> Result<Mono> { Mono::open(".", vcs) }
 
-  pub fn open<P: AsRef<Path>>(dir: P, vcs: VcsState) -> Result<Mono> {
-    let repo = Repo::open(dir.as_ref(), vcs)?;
-    let root = repo.working_dir()?;
+  /// Open the repo at `dir`. Use `Mono::builder` to open it with other options.
+  pub fn open<P: AsRef<Path>>(dir: P, vcs: VcsState) -> Result<Mono> { Mono::builder(dir, vcs).open() }
+
+  pub fn builder<P: AsRef<Path>>(dir: P, vcs: VcsState) -> MonoBuilder { MonoBuilder::new(dir, vcs) }
+
+  fn open_with(opts: MonoBuilder) -> Result<Mono> {
+    let MonoBuilder { dir, vcs, max_history, scanners, cancel } = opts;
+    let repo = Repo::open(&dir, vcs)?.with_cancel(cancel);
+
+    // The branch is downloaded while the config is read and the history base is found, which only need what's
+    // already here. It must be done before the tags are fetched, which would otherwise write to the same refs.
+    let fetch = repo.start_fetch();
 
     // A little dance to construct a state and config.
-    let file = ConfigFile::from_dir(root)?;
+    let file = ConfigFile::from_dir(repo.working_dir()?)?;
+    let repo = repo.with_fetch(file.fetch());
+    let root = repo.working_dir()?;
+    let base = history_base(&file, &repo, max_history)?;
+    let user_prefs = read_env_prefs()?;
+    if let Some(fetch) = fetch {
+      fetch.finish()?;
+    }
+
     let projects = file.projects().iter();
-    let old_tags = find_old_tags(projects, file.prev_tag(), &repo)?;
+    let old_tags = find_old_tags(projects, file.prev_tag(), &base, &repo)?;
     let state = CurrentState::new(root.to_path_buf(), old_tags);
-    let current = Config::new(state, file);
+    let current = Config::new(state, file).with_scanners(scanners)?;
 
-    let last_commits = find_last_commits(&current, &repo)?;
+    let last_commits = find_last_commits(&base, &current, &repo)?;
     let next = StateWrite::new();
 
-    let user_prefs = read_env_prefs()?;
+    Ok(Mono { current, next, last_commits, repo, user_prefs, base })
+  }
 
-    Ok(Mono { current, next, last_commits, repo, user_prefs })
+  /// The current branch name, if any.
+  pub fn branch_name(&self) -> Option<&str> { self.repo.branch_name().ok().and_then(|b| b.as_deref()) }
+
+  /// Check that a new version of a project obeys all of its restrictions.
+  pub fn verify_restrictions(
+    &self, id: &ProjectId, vers: &str, prev: Option<&str>, changelog: &Changelog
+  ) -> Result<()> {
+    self.get_project(id)?.verify_restrictions(vers, prev, self.branch_name(), changelog)
   }
 
   pub fn check_branch(&self) -> std::result::Result<(), (String, String)> {
-    if let Ok(branch_name) = self.repo.branch_name() {
-      if let Some(branch_name) = branch_name {
-        if let Some(cfg_name) = self.current.branch() {
-          if branch_name != cfg_name {
-            Err((cfg_name.clone(), branch_name.clone()))
-          } else {
-            Ok(())
-          }
-        } else {
-          Ok(())
-        }
-      } else if let Some(cfg_name) = self.current.branch() {
-        Err((cfg_name.clone(), "((No branch))".to_string()))
-      } else {
-        Ok(())
-      }
-    } else {
-      Ok(())
+    let patterns = self.current.branch();
+    if patterns.is_empty() {
+      return Ok(());
+    }
+
+    let branch_name = match self.repo.branch_name() {
+      Ok(Some(branch_name)) => branch_name.clone(),
+      Ok(None) => "((No branch))".to_string(),
+      Err(_) => return Ok(())
+    };
+
+    if !patterns.iter().any(|p| branch_matches(p, &branch_name).unwrap_or(false)) {
+      return Err((patterns.join(", "), branch_name));
     }
+    Ok(())
+  }
+
+  /// Check that a release would push to the right branch. A release pushes to the remote branch with the same name
+  /// as the current branch, so the branch's upstream (if any) must have that name, and must match the `branch`
+  /// option. Otherwise, a release from a branch that merely tracks a feature branch would push its commit there.
+  pub fn check_upstream(&self) -> Result<()> {
+    if !self.repo.is_remote() {
+      return Ok(());
+    }
+    let (branch_name, upstream) = match (self.repo.branch_name()?, self.repo.upstream_name()?) {
+      (Some(branch_name), Some(upstream)) => (branch_name, upstream),
+      _ => return Ok(())
+    };
+    verify_upstream(branch_name, &upstream, self.current.branch())
   }
 
   pub fn write_changelogs(&mut self) -> Result<()> { self.next.write_changelogs() }
 
-  pub fn commit(&mut self, advance_prev: bool, pause: bool) -> Result<()> {
+  pub fn commit(
+    &mut self, advance_prev: bool, pause: bool, force_tags: bool, observer: &mut dyn Observer
+  ) -> Result<()> {
+    let last_prev =
+      self.repo.tag_oid(self.current.prev_tag()).map(|oid| LastPrev::new(oid, self.current.old_tags().prev().clone()));
+
     self.next.commit(
       &self.repo,
       CommitArgs::new(
@@ -83,8 +191,11 @@ impl Mono {
         self.current.old_tags().current(),
         advance_prev,
         &self.current.hooks(),
-        pause
-      )
+        pause,
+        last_prev,
+        force_tags
+      ),
+      observer
     )
   }
 
@@ -92,6 +203,26 @@ impl Mono {
     self.current.get_project(id).ok_or_else(|| bad!("No such project {}", id))
   }
 
+  /// Run the `pre_bump` hook of a project whose version is about to change, and schedule its `post_bump` and
+  /// `post_tag` hooks and its publish.
+  pub fn pre_bump(&mut self, id: &ProjectId, old_vers: Option<&str>, new_vers: &str) -> Result<()> {
+    let proj = self.current.get_project(id).ok_or_else(|| bad!("No such project {}", id))?;
+    let vars = proj.version_vars(old_vers, new_vers);
+    proj.hooks().execute_with("pre_bump", &proj.root(), &vars)?;
+    let bump = BumpHooks::new(proj.root().cloned(), vars, proj.hooks());
+    self.next.add_bump(bump.with_publish(proj.publish().cloned()));
+    Ok(())
+  }
+
+  /// Check that a project would publish, without publishing it or writing anything.
+  pub fn check_publish(&self, id: &ProjectId) -> Result<()> {
+    let proj = self.current.get_project(id).ok_or_else(|| bad!("No such project {}", id))?;
+    match proj.publish() {
+      Some(publish) => publish.execute(&proj.root(), true),
+      None => Ok(())
+    }
+  }
+
   pub fn write_chains(&mut self, ids: &[(ProjectId, ProjectId)], vers: &HashMap<ProjectId, String>) -> Result<()> {
     for (id, dpid) in ids {
       let dproj =
@@ -103,6 +234,207 @@ impl Mono {
     Ok(())
   }
 
+  /// Point the dependency ranges in the other projects' `package.json` files at the new versions of the projects
+  /// that have `workspace_sync`, keeping each range's prefix (such as `^`).
+  pub fn sync_workspace(&mut self, vers: &HashMap<ProjectId, String>) -> Result<()> {
+    let read = self.current.state_read();
+    let projects = self.current.file().projects();
+    for proj in projects.iter().filter(|p| p.workspace_sync()) {
+      let new_vers = match vers.get(proj.id()) {
+        Some(new_vers) => new_vers,
+        None => continue
+      };
+      let pkg_file = npm_manifest(proj.root());
+      if !read.has_file(&pkg_file)? {
+        bail!("No package.json for workspace_sync in project {}.", proj.id());
+      }
+      let pkg_name = JsonScanner::build(vec![Part::Map("name".into())]).find(&read.read_file(&pkg_file)?)?.into_value();
+
+      for other in projects.iter().filter(|p| p.id() != proj.id()) {
+        let file = npm_manifest(other.root());
+        if !read.has_file(&file)? {
+          continue;
+        }
+        let data = read.read_file(&file)?;
+        for section in NPM_DEP_SECTIONS {
+          let parts = vec![Part::Map(section.to_string()), Part::Map(pkg_name.clone())];
+          let range = match JsonScanner::build(parts.clone()).find(&data) {
+            Ok(mark) => mark.into_value(),
+            Err(_) => continue
+          };
+          if let Some(synced) = sync_range(&range, new_vers) {
+            let pick = PickPath::new(file.clone(), Picker::Json(ScanningPicker::new(parts)));
+            self.next.update_mark(pick, synced, other.id())?;
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Point the `<parent>` and `<dependencyManagement>` versions in the other projects' `pom.xml` files at the new
+  /// versions of the projects that have `maven_sync`. Versions that use a property (such as `${revision}`) are left
+  /// alone.
+  pub fn sync_maven(&mut self, vers: &HashMap<ProjectId, String>) -> Result<()> {
+    let read = self.current.state_read();
+    let projects = self.current.file().projects();
+    for proj in projects.iter().filter(|p| p.maven_sync()) {
+      let new_vers = match vers.get(proj.id()) {
+        Some(new_vers) => new_vers,
+        None => continue
+      };
+      let pom_file = maven_pom(proj.root());
+      if !read.has_file(&pom_file)? {
+        bail!("No pom.xml for maven_sync in project {}.", proj.id());
+      }
+      let data = read.read_file(&pom_file)?;
+      let artifact = xml_value(&data, "project.artifactId");
+      let artifact = artifact.ok_or_else(|| bad!("No artifactId in {}.", pom_file.display()))?;
+      let group = xml_value(&data, "project.groupId").or_else(|| xml_value(&data, "project.parent.groupId"));
+
+      for other in projects.iter().filter(|p| p.id() != proj.id()) {
+        let file = maven_pom(other.root());
+        if !read.has_file(&file)? {
+          continue;
+        }
+        let data = read.read_file(&file)?;
+        let dep = "project.dependencyManagement.dependencies.dependency";
+        let deps = (0 ..)
+          .map(|i| format!("{}.{}", dep, i))
+          .take_while(|d| xml_value(&data, &format!("{}.artifactId", d)).is_some());
+
+        for target in once("project.parent".to_string()).chain(deps) {
+          if xml_value(&data, &format!("{}.artifactId", target)).as_ref() != Some(&artifact)
+            || xml_value(&data, &format!("{}.groupId", target)) != group
+          {
+            continue;
+          }
+          let version = format!("{}.version", target);
+          match xml_value(&data, &version) {
+            Some(old) if &old != new_vers && !old.starts_with("${") => {
+              let pick =
+                PickPath::new(file.clone(), Picker::Xml(ScanningPicker::new(version.as_str().into_part_vec())));
+              self.next.update_mark(pick, new_vers, other.id())?;
+            }
+            _ => ()
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// The new tag of each container image that a project in `vers` declares.
+  pub fn image_tags(&self, vers: &HashMap<ProjectId, String>) -> Vec<(String, String)> {
+    let projects = self.current.file().projects().iter();
+    let mut tags: Vec<_> = projects
+      .filter_map(|p| vers.get(p.id()).map(|v| (p, v)))
+      .flat_map(|(p, v)| p.images().iter().map(move |image| (image.clone(), v.clone())))
+      .collect();
+    tags.sort();
+    tags
+  }
+
+  /// Rewrite the tags of the given images in the YAML files covered by `deploys` projects, such as Kubernetes or
+  /// compose manifests, or Helm chart values.
+  pub fn write_image_tags(&mut self, tags: &[(String, String)]) -> Result<()> {
+    let deploys: Vec<_> = self.current.file().projects().iter().filter(|p| p.deploys()).collect();
+    if deploys.is_empty() || tags.is_empty() {
+      return Ok(());
+    }
+
+    let patterns = tags
+      .iter()
+      .map(|(image, tag)| Ok((Regex::new(&format!(r#"(image:\s*["']?{}):[\w.-]+"#, escape(image)))?, tag)))
+      .collect::<Result<Vec<_>>>()?;
+
+    for entry in WalkBuilder::new("./").build() {
+      let entry = entry?;
+      let path = entry.path().strip_prefix("./").unwrap_or_else(|_| entry.path());
+      let is_yaml = path.extension().map(|e| e == "yaml" || e == "yml").unwrap_or(false);
+      if !is_yaml || !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+        continue;
+      }
+
+      let slashed = path.to_slash_lossy();
+      for proj in &deploys {
+        if !proj.does_cover(&slashed)? {
+          continue;
+        }
+        let data = std::fs::read_to_string(path)?;
+        let mut new_data = data.clone();
+        for (pattern, tag) in &patterns {
+          new_data = pattern.replace_all(&new_data, format!("${{1}}:{}", tag).as_str()).into_owned();
+        }
+        for (image, tag) in tags {
+          new_data = rewrite_helm_tags(&new_data, image, tag)?;
+        }
+        if new_data != data {
+          self.next.write_file(path.to_path_buf(), new_data, proj.id(), false)?;
+        }
+        break;
+      }
+    }
+    Ok(())
+  }
+
+  /// Write a `versio-release.json` build-info file into the root of each released project, if the `build_info`
+  /// option is set. The file lists the new versions of all projects, the commit that the release was built from,
+  /// and the time of the release. Since the file is part of the release commit, it can't name that commit: it names
+  /// its parent instead.
+  pub fn write_build_info(&mut self, released: &[Released], vers: &HashMap<ProjectId, String>) -> Result<()> {
+    if !self.current.file().build_info() {
+      return Ok(());
+    }
+
+    let parent = self.repo.revparse_oid(FromTag::new("HEAD", false)).ok();
+    let timestamp = clock::now().to_rfc3339();
+    let projects = self
+      .current
+      .file()
+      .projects()
+      .iter()
+      .filter_map(|p| vers.get(p.id()).map(|v| (p.name().to_string(), v.clone())))
+      .collect::<BTreeMap<_, _>>();
+
+    for rel in released {
+      let proj = self.get_project(rel.id())?;
+      let path = match proj.root() {
+        Some(root) => PathBuf::from_slash(root).join(BUILD_INFO_FILE),
+        None => PathBuf::from(BUILD_INFO_FILE)
+      };
+      let info = json!({
+        "project": proj.name(),
+        "version": rel.new_version(),
+        "parent": parent,
+        "timestamp": timestamp,
+        "projects": projects
+      });
+      let id = proj.id().clone();
+      self.next.write_file(path, format!("{}\n", serde_json::to_string_pretty(&info)?), &id, false)?;
+    }
+    Ok(())
+  }
+
+  /// Update the Homebrew formulas and Scoop manifests of the released projects. They're written along with the
+  /// other release files, so a manifest in this repo is part of the release commit.
+  pub fn update_formulas(&mut self, released: &[Released]) -> Result<()> {
+    for rel in released {
+      for formula in self.get_project(rel.id())?.formulas().to_vec() {
+        let data = formula
+          .rewritten(rel.old(), rel.new_version())
+          .chain_err(|| format!("Unable to update {}.", formula.file()))?;
+        if let Some(data) = data {
+          self.next.write_file(PathBuf::from(formula.file()), data, rel.id(), false)?;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Remember the released projects in the release, so that a resumed release can announce them.
+  pub fn set_released(&mut self, released: Vec<Released>) { self.next.set_released(released); }
+
   pub fn diff(&self) -> Result<Analysis> {
     let prev_config = self.current.slice_to_prev(&self.repo)?;
 
@@ -112,35 +444,91 @@ impl Mono {
     Ok(analyze(prev_annotate, curt_annotate))
   }
 
+  /// Retire the projects that were removed from the config since the last release: tag the last version of each
+  /// with a `-final` suffix, at the commit of that release, and add a final entry to its changelog.
+  pub async fn retire_removed(&mut self) -> Result<Vec<Retired>> {
+    let removed = self.diff()?.older().iter().map(|m| (m.id().clone(), m.mark().to_string())).collect::<Vec<_>>();
+    if removed.is_empty() {
+      return Ok(Vec::new());
+    }
+
+    let prev_config = self.current.slice_to_prev(&self.repo)?;
+    let prev_oid = self.repo.tag_oid(self.current.prev_tag());
+    let mut retired = Vec::new();
+    for (id, vers) in removed {
+      let proj = prev_config.get_project(&id).ok_or_else(|| bad!("No such prev project {}", id))?;
+      let tag = proj.full_version(&vers).map(|tag| format!("{}-final", tag)).filter(|_| prev_oid.is_some());
+      if let (Some(tag), Some(oid)) = (&tag, &prev_oid) {
+        self.next.tag_commit(tag, oid);
+      }
+      let changelog = proj.write_final_changelog(&mut self.next, &vers).await?;
+      retired.push(Retired { name: proj.name().to_string(), version: vers, tag, changelog });
+    }
+    Ok(retired)
+  }
+
   pub fn config(&self) -> &Config<CurrentState> { &self.current }
   pub fn repo(&self) -> &Repo { &self.repo }
+  pub fn cancel(&self) -> &Cancel { self.repo.cancel() }
+  pub fn github_info(&self) -> Result<GithubInfo> { self.repo.github_info(self.user_prefs.auth()) }
 
   pub fn set_by_id(&mut self, id: &ProjectId, val: &str) -> Result<()> {
     self.do_project_write(id, move |p, n| p.set_value(n, val))
   }
 
-  pub fn set_by_name(&mut self, name: &str, val: &str) -> Result<()> {
-    let id = self.current.find_unique(name)?.clone();
-    self.set_by_id(&id, val)
+  /// Change a project's version to `targ` from `prev`, its version at the previous release (if it was released), and
+  /// tell `observer` about it.
+  pub fn bump_by_id(
+    &mut self, id: &ProjectId, prev: Option<&str>, targ: &str, observer: &mut dyn Observer
+  ) -> Result<()> {
+    let curt = self.current.get_value(id)?.ok_or_else(|| bad!("No such project {}.", id))?;
+    let name = self.get_project(id)?.name().to_string();
+    self.set_by_id(id, targ)?;
+    observer.on_project_bumped(id, &name, prev.unwrap_or(&curt), &curt, targ);
+    Ok(())
   }
 
-  pub fn set_by_only(&mut self, val: &str) -> Result<()> {
-    if self.current.projects().len() != 1 {
-      bail!("No solo project.");
+  /// Check that setting a project's version by hand is safe: the version doesn't move backwards or to a different
+  /// major version, and it obeys the project's restrictions. Only the `major.minor.patch` core of each version is
+  /// compared, so a prerelease or build suffix is ignored; and a 0.x version can move to any other 0.x version,
+  /// since they share a major of 0.
+  pub fn verify_set(&self, id: &ProjectId, val: &str) -> Result<()> {
+    let proj = self.get_project(id)?;
+    let curt = self.current.get_value(id)?;
+    if let Some(curt) = &curt {
+      if Size::less_than(val, curt)? {
+        bail!("Version {} of project {} is lower than its current {}: use --force to set it.", val, id, curt);
+      }
+      if Size::parts(val)?[0] != Size::parts(curt)?[0] {
+        bail!("Version {} of project {} changes the major of its current {}: use --force to set it.", val, id, curt);
+      }
     }
-    let id = self.current.projects().get(0).unwrap().id().clone();
-    self.set_by_id(&id, val)
+    proj.verify_restrictions(val, curt.as_deref(), self.branch_name(), &Changelog::empty())
   }
 
   pub fn forward_by_id(&mut self, id: &ProjectId, val: &str) -> Result<()> {
     self.do_project_write(id, move |p, n| p.forward_tag(n, val))
   }
 
+  /// Create the `release_branch` for a project's new version, if the config has one.
+  pub fn branch_by_id(&mut self, id: &ProjectId, val: &str) -> Result<()> {
+    let pattern = match self.current.file().release_branch() {
+      Some(pattern) => pattern.to_string(),
+      None => return Ok(())
+    };
+    self.do_project_write(id, move |p, n| p.release_branch(n, &pattern, val))
+  }
+
   pub async fn write_changelog(
-    &mut self, id: &ProjectId, changelog: &Changelog, new_vers: &str
+    &mut self, id: &ProjectId, changelog: &Changelog, new_vers: &str, unreleased: bool,
+    dep_versions: &HashMap<ProjectId, String>
   ) -> Result<Option<PathBuf>> {
     let proj = self.current.get_project(id).ok_or_else(|| bad!("No such project {}", id))?;
-    proj.write_changelog(&mut self.next, changelog, new_vers).await
+    proj.write_changelog(&mut self.next, changelog, new_vers, unreleased, dep_versions).await
+  }
+
+  pub fn hold(&mut self, id: &ProjectId, size: Size, changelog: &Changelog) {
+    self.next.hold(id, size, changelog.clone());
   }
 
   fn do_project_write<F, T>(&mut self, id: &ProjectId, f: F) -> Result<T>
@@ -151,58 +539,404 @@ impl Mono {
     f(proj, &mut self.next)
   }
 
-  pub fn check(&self) -> Result<()> {
-    for project in self.current.projects() {
-      project.check(self.current.state_read())?;
-    }
-    Ok(())
+  /// Check each project, along with the warnings found for it. A project that fails its check is returned with
+  /// its error, rather than stopping the check, so that every project can be reported.
+  pub fn check(&self) -> Result<Vec<ProjectCheck>> {
+    self.current.file().check_cycles()?;
+
+    let files = current_files()?;
+    let projects = self.current.projects();
+    let checked = projects.iter().map(|project| {
+      let result = project.check(self.current.state_read(), &files).map(|mut warnings| {
+        // A name that's part of another name can't be used with `--name` to find its project, unless it has an
+        // alias.
+        if project.aliases().is_empty() {
+          for other in projects.iter().filter(|o| o.id() != project.id() && o.name().contains(project.name())) {
+            let (name, id, other_id) = (project.name(), project.id(), other.id());
+            warnings.push(format!("Name \"{}\" of proj. {} is part of the name of proj. {}.", name, id, other_id));
+          }
+        }
+        warnings
+      });
+      (project, result)
+    });
+
+    Ok(checked.collect())
+  }
+
+  /// Find the current files that are attributed to more than one project.
+  pub fn overlaps(&self) -> Result<Vec<(Vec<ProjectId>, String, usize)>> {
+    self.current.file().overlaps(current_files()?)
   }
 
   pub async fn keyed_files(&self) -> Result<impl Iterator<Item = Result<(String, String)>> + '_> {
     let changes = self.changes().await?;
     let prs = changes.into_groups().into_iter().map(|(_, v)| v).filter(|pr| !pr.best_guess());
 
-    let mut vec = Vec::new();
-    for pr in prs {
-      vec.push(pr_keyed_files(&self.repo, pr));
+    // Lazily walk each PR's commits and files as they're consumed, rather than buffering them all.
+    Ok(prs.flat_map(move |pr| pr_keyed_files(&self.repo, pr)))
+  }
+
+  /// The files changed since the prev tag, grouped by the projects that they're attributed to.
+  pub async fn files_by_project(&self) -> Result<Vec<(ProjectId, Vec<String>)>> {
+    let files: Vec<_> = self.keyed_files().await?.map(|f| f.map(|(_, path)| path)).collect::<Result<_>>()?;
+    self.current.file().files_by_project(files)
+  }
+
+  /// The projects that the files changed after `since`, up to `HEAD`, are attributed to. Unlike the plan, this is
+  /// just the commits in `since..HEAD`: PRs aren't looked up, so it doesn't need a forge.
+  pub fn affected_since(&self, since: &str) -> Result<Vec<ProjectId>> {
+    let mut files = Vec::new();
+    for commit in self.repo.commits_to_head(FromTag::new(since, false), false)? {
+      files.extend(commit?.files()?);
     }
+    self.current.file().affected(files)
+  }
 
-    Ok(vec.into_iter().flatten())
+  /// Build the plan like `build_plan`, and tell `observer` that it's ready.
+  pub async fn build_plan_with(&self, observer: &mut dyn Observer) -> Result<Plan> {
+    let plan = self.build_plan().await?;
+    observer.on_plan_built(&plan);
+    Ok(plan)
   }
 
+  /// Build the plan for the changes from the prev tag up to `HEAD`. If the last plan was built for the same prev tag
+  /// and head commits, with the same config and PR provider, it's reused instead, which saves the work when a
+  /// pipeline runs several commands on the same commits.
   pub async fn build_plan(&self) -> Result<Plan> {
-    let mut plan = PlanBuilder::create(&self.repo, self.current.file(), self.user_prefs.auth());
-
-    // Consider the grouped, unsquashed commits to determine project sizing and changelogs.
-    for pr in self.changes().await?.groups().values() {
-      plan.start_pr(pr)?;
-      for commit in pr.included_commits() {
-        plan.start_commit(commit.clone())?;
-        for file in commit.files() {
-          plan.start_file(file)?;
+    let cache = self.repo.plan_cache_file().filter(|_| !self.repo.is_unborn());
+    let cache = cache.filter(|_| std::env::var(NO_PLAN_CACHE_VAR).map(|v| v.is_empty()).unwrap_or(true));
+    let cache = match cache {
+      Some(cache) => cache,
+      None => return self.build_plan_at("HEAD").await
+    };
+
+    let key = self.plan_key()?;
+    if let Some(plan) = read_cached_plan(&cache, &key) {
+      info!("Reusing the cached plan for {}.", key.head);
+      return Ok(plan);
+    }
+
+    let plan = self.build_plan_at("HEAD").await?;
+    if let Err(e) = write_cached_plan(&cache, &key, &plan) {
+      warn!("Unable to cache the plan: {}", e);
+    }
+    Ok(plan)
+  }
+
+  /// Everything that the plan at `HEAD` is built from, other than the commits between the prev tag and `HEAD`.
+  fn plan_key(&self) -> Result<PlanKey> {
+    Ok(PlanKey {
+      versio: env!("CARGO_PKG_VERSION").to_string(),
+      prev: self.repo.tag_oid(self.current.prev_tag()),
+      head: self.repo.revparse_oid(FromTag::new("HEAD", false))?,
+      config: self.current.file().digest().to_string(),
+      provider: self.provider()?.map(|p| p.api_name().to_string())
+    })
+  }
+
+  /// Build the plan for the changes from the prev tag up to `head`. The current config is used, whichever `head`
+  /// is planned.
+  pub async fn build_plan_at(&self, head: &str) -> Result<Plan> {
+    let provider = self.provider()?;
+    let changes = self.changes_at(provider.clone(), head).await?;
+    let _timer = Timer::start("plan building");
+    let deadline = self.repo.cancel().start(Phase::Plan);
+    let mut plan = PlanBuilder::create(&self.repo, self.current.file(), provider);
+    let progress = Progress::start("Planning PRs");
+    let mut backports = self.backports(head)?;
+
+    // Consider the grouped, unsquashed commits to determine project sizing and changelogs. Each PR is dropped as
+    // soon as it's planned, so only the logged (covering) commits are kept.
+    for (_, pr) in changes.into_groups() {
+      deadline.check()?;
+      progress.inc();
+      plan.start_pr(&pr)?;
+
+      // Slice the config to each commit in order, since the repo can't be shared between threads; then match all
+      // of the PR's files against their configs in parallel.
+      let mut commits = Vec::new();
+      for commit in pr.included_commits(&self.repo) {
+        let commit = commit?;
+        if self.current.file().is_bot_author(commit.author()) {
+          info!("Commit {} is by bot author {}: ignoring it.", commit.id(), commit.author());
+          continue;
+        }
+        let backport = match &mut backports {
+          Some(backports) => backports.is_backport(&commit)?,
+          None => false
+        };
+        let prev = plan.slice_to(&commit)?;
+        commits.push((commit, backport, prev));
+      }
+      let coverings = find_coverings(commits.iter().map(|(commit, _, prev)| (prev, commit.files())))?;
+
+      for ((commit, backport, prev), coverings) in commits.into_iter().zip(coverings) {
+        let files = commit.files().to_vec();
+        plan.start_commit(commit, backport, prev)?;
+        for (file, covering) in files.iter().zip(coverings) {
+          plan.start_file(file, &covering)?;
           plan.finish_file()?;
         }
         plan.finish_commit()?;
       }
       plan.finish_pr()?;
     }
+    drop(progress);
+    plan.check_strict()?;
+
+    // Clamp sizes to each project's `max_size` before its dependents see them.
+    plan.clamp_sizes();
 
     // Some projects might depend on other projects.
     plan.handle_deps()?;
 
+    // Umbrella projects gather the changes of all other projects.
+    plan.handle_umbrellas()?;
+
+    // Changes that were held back from earlier releases, because they were below a project's `min_size`.
+    plan.add_held(read_prev_message(&self.repo, self.current.prev_tag())?.into_held());
+
+    // Dependencies, umbrellas, and held changes may have raised some sizes again.
+    plan.clamp_sizes();
+
     // Sort projects by earliest closed date, mark duplicate commits.
     plan.sort_and_dedup()?;
 
     Ok(plan.build())
   }
 
-  pub async fn changes(&self) -> Result<Changes> {
-    let base = FromTagBuf::new(self.current.prev_tag().to_string(), true);
-    changes(&self.user_prefs.auth, &self.repo, base, "HEAD".into()).await
+  /// On a `maintenance_branch`, the changes already released elsewhere, whose backports aren't sized.
+  fn backports(&self, head: &str) -> Result<Option<Backports>> {
+    let patterns = self.current.file().maintenance_branch();
+    match self.branch_name() {
+      Some(branch) if patterns.iter().any(|p| branch_matches(p, branch).unwrap_or(false)) => {
+        Ok(Some(self.repo.backports(head)?))
+      }
+      _ => Ok(None)
+    }
+  }
+
+  pub fn save_plan(&self, plan: Plan, path: &Path) -> Result<Plan> {
+    if self.repo.is_unborn() {
+      bail!("Can't save a plan before the first commit.");
+    }
+    let head = self.repo.revparse_oid(FromTag::new("HEAD", false))?;
+    let prev = self.repo.tag_oid(self.current.prev_tag());
+    let saved = SavedPlan { head, prev, plan };
+    let file = std::fs::File::create(path).chain_err(|| format!("Unable to create plan file {}.", path.display()))?;
+    serde_json::to_writer_pretty(file, &saved)?;
+    Ok(saved.plan)
+  }
+
+  /// Read a plan from disk, failing if it wasn't built from the current head commit and prev tag, and tell `observer`
+  /// that it's ready.
+  pub fn load_plan(&self, path: &Path, observer: &mut dyn Observer) -> Result<Plan> {
+    let file = std::fs::File::open(path).chain_err(|| format!("Unable to open plan file {}.", path.display()))?;
+    let saved: SavedPlan = serde_json::from_reader(std::io::BufReader::new(file))?;
+    let head = self.repo.revparse_oid(FromTag::new("HEAD", false))?;
+    if saved.head != head {
+      bail!("Plan was built at {}, but HEAD is now {}.", saved.head, head);
+    }
+    let prev = self.repo.tag_oid(self.current.prev_tag());
+    if saved.prev != prev {
+      let show = |oid: &Option<String>| oid.clone().unwrap_or_else(|| "<none>".into());
+      bail!("Plan was built from prev tag at {}, but it's now at {}.", show(&saved.prev), show(&prev));
+    }
+    observer.on_plan_built(&saved.plan);
+    Ok(saved.plan)
+  }
+
+  /// Fill in the `commit_message` option, if any, with the new versions of the released projects. With the
+  /// `per-project` commit mode, each project also gets its own commit message. The skip-CI marker, from `skip_ci`
+  /// or else the option, is added to every message.
+  pub fn set_commit_message<'a, I: IntoIterator<Item = (&'a ProjectId, &'a str)>>(
+    &mut self, versions: I, skip_ci: Option<SkipCi>
+  ) -> Result<()> {
+    let file = self.current.file();
+    let versions = versions
+      .into_iter()
+      .map(|(id, vers)| Ok((id, file.get_project(id).ok_or_else(|| bad!("No such project {}", id))?.name(), vers)))
+      .collect::<Result<Vec<_>>>()?;
+
+    let skip_ci = skip_ci.unwrap_or_else(|| file.skip_ci());
+
+    let named: Vec<_> = versions.iter().map(|(_, name, vers)| (*name, *vers)).collect();
+    let msg = file.commit_message(&named).or_else(|| skip_ci.marker().map(|_| RELEASE_MSG.to_string()));
+    if let Some(msg) = msg {
+      self.next.set_commit_message(skip_ci.mark(msg));
+    }
+
+    if file.commit_mode() == CommitMode::PerProject {
+      for (id, name, vers) in &versions {
+        // The default names the project in place of the "versions" of the combined default.
+        let msg = file
+          .commit_message(&[(*name, *vers)])
+          .unwrap_or_else(|| format!("{} to {}", RELEASE_MSG.replace("versions", name), vers));
+        self.next.commit_project(id, skip_ci.mark(msg));
+      }
+    }
+    Ok(())
+  }
+
+  /// Record the hash of the plan being released, which is kept in the prev tag.
+  pub fn record_plan(&mut self, plan: &Plan) -> Result<()> {
+    // Values sort their map keys, so equal plans always have the same hash.
+    let json = serde_json::to_string(&serde_json::to_value(plan)?)?;
+    self.next.set_plan_hash(format!("{:x}", Sha256::digest(json.as_bytes())));
+    Ok(())
+  }
+
+  /// Record this release run as a note on the release commit, once the release is pushed.
+  pub fn write_note(&mut self, plan: &Plan) -> Result<()> {
+    let operator = self.repo.user_name().or_else(|| std::env::var("USER").ok()).unwrap_or_else(|| "unknown".into());
+    let note = ReleaseNote {
+      versio: env!("CARGO_PKG_VERSION").to_string(),
+      operator,
+      timestamp: clock::now().to_rfc3339(),
+      plan
+    };
+    self.next.set_note(serde_json::to_string_pretty(&note)?);
+    Ok(())
+  }
+
+  /// Read the notes of all recorded release runs, oldest first, along with the commit each is attached to.
+  pub fn history(&self) -> Result<Vec<(String, ReleaseNote<Plan>)>> {
+    let mut notes = self
+      .repo
+      .notes()?
+      .into_iter()
+      .map(|(oid, note)| {
+        let note = serde_json::from_str(&note).chain_err(|| format!("Unable to read release note on {}.", oid))?;
+        Ok((oid, note))
+      })
+      .collect::<Result<Vec<(String, ReleaseNote<Plan>)>>>()?;
+    notes.sort_by(|(_, a), (_, b)| a.timestamp.cmp(&b.timestamp));
+    Ok(notes)
+  }
+
+  pub async fn changes(&self) -> Result<Changes> { self.changes_at(self.provider()?, "HEAD").await }
+
+  async fn changes_at(&self, provider: Option<Arc<dyn PrProvider>>, head: &str) -> Result<Changes> {
+    let base = FromTagBuf::new(self.base.clone(), true);
+    changes(provider, &self.repo, base, head.into()).await
+  }
+
+  /// The provider of the PRs that group the commits, as set by the `provider` option.
+  fn provider(&self) -> Result<Option<Arc<dyn PrProvider>>> {
+    find_provider(self.user_prefs.auth(), &self.repo, self.current.file().provider())
+  }
+}
+
+const NPM_DEP_SECTIONS: &[&str] = &["dependencies", "devDependencies", "peerDependencies", "optionalDependencies"];
+
+fn read_cached_plan(cache: &Path, key: &PlanKey) -> Option<Plan> {
+  let file = std::fs::File::open(cache).ok()?;
+  let cached: CachedPlan = serde_json::from_reader(std::io::BufReader::new(file)).ok()?;
+  Some(cached).filter(|cached| &cached.key == key).map(|cached| cached.plan)
+}
+
+fn write_cached_plan(cache: &Path, key: &PlanKey, plan: &Plan) -> Result<()> {
+  if let Some(dir) = cache.parent() {
+    std::fs::create_dir_all(dir).chain_err(|| format!("Unable to create {}.", dir.display()))?;
+  }
+  let file = std::fs::File::create(cache).chain_err(|| format!("Unable to create {}.", cache.display()))?;
+  Ok(serde_json::to_writer(file, &CachedPlanRef { key, plan })?)
+}
+
+/// The files of the current working tree, as slashed paths, skipping those that are ignored.
+fn current_files() -> Result<Vec<String>> {
+  let mut files = Vec::new();
+  for entry in WalkBuilder::new("./").build() {
+    let entry = entry?;
+    if entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+      let path = entry.path().strip_prefix("./").unwrap_or_else(|_| entry.path());
+      files.push(path.to_slash_lossy());
+    }
+  }
+  Ok(files)
+}
+
+fn npm_manifest(root: Option<&String>) -> PathBuf {
+  match root {
+    Some(root) => PathBuf::from_slash(root).join("package.json"),
+    None => PathBuf::from("package.json")
+  }
+}
+
+/// Rewrite the tag of an image in Helm-style values, where the image's `repository` and `tag` are sibling keys of
+/// the same map, in either order.
+fn rewrite_helm_tags(data: &str, image: &str, tag: &str) -> Result<String> {
+  let repo_line = Regex::new(&format!(r#"^(\s*)repository:\s*["']?{}["']?\s*$"#, escape(image)))?;
+  let tag_line = Regex::new(r#"^(\s*)tag:(\s*["']?)[\w.-]*(["']?\s*)$"#)?;
+  let indent_of = |line: &str| line.len() - line.trim_start().len();
+
+  let mut lines: Vec<String> = data.split('\n').map(|l| l.to_string()).collect();
+  for i in 0 .. lines.len() {
+    let indent = match repo_line.captures(&lines[i]) {
+      Some(caps) => caps[1].len(),
+      None => continue
+    };
+
+    // The map's other keys are the nearby lines at the same indentation, up to a line that is indented less.
+    let in_map = |line: &String| line.trim().is_empty() || indent_of(line) >= indent;
+    let before = (0 .. i).rev().take_while(|j| in_map(&lines[*j]));
+    let after = (i + 1 .. lines.len()).take_while(|j| in_map(&lines[*j]));
+    let siblings: Vec<usize> = before.chain(after).filter(|j| indent_of(&lines[*j]) == indent).collect();
+    for j in siblings {
+      if let Some(caps) = tag_line.captures(&lines[j]) {
+        lines[j] = format!("{}tag:{}{}{}", &caps[1], &caps[2], tag, &caps[3]);
+      }
+    }
+  }
+  Ok(lines.join("\n"))
+}
+
+fn maven_pom(root: Option<&String>) -> PathBuf {
+  match root {
+    Some(root) => PathBuf::from_slash(root).join("pom.xml"),
+    None => PathBuf::from("pom.xml")
+  }
+}
+
+/// The trimmed text of the XML element at `target`, if there is one.
+fn xml_value(data: &str, target: &str) -> Option<String> {
+  XmlScanner::new(target).find(data).ok().map(|mark| mark.into_value().trim().to_string())
+}
+
+/// The range that points at `vers` with the same prefix as `range`, or `None` if the range already matches or isn't
+/// a simple range (such as `workspace:*` or `1.x || 2.x`).
+fn sync_range(range: &str, vers: &str) -> Option<String> {
+  let prefix_len = range.find(|c: char| !"^~>=v".contains(c)).unwrap_or(range.len());
+  let (prefix, rest) = range.split_at(prefix_len);
+  if !rest.starts_with(|c: char| c.is_ascii_digit()) || rest.contains(|c: char| c.is_whitespace() || c == '|') {
+    return None;
+  }
+  let synced = format!("{}{}", prefix, vers);
+  if synced == range {
+    None
+  } else {
+    Some(synced)
   }
 }
 
 /// Read the user preferences file, with some values override with environment variables.
+/// Check that `branch_name` tracks a remote branch of the same name, which matches one of the `branch` patterns.
+fn verify_upstream(branch_name: &str, upstream: &str, patterns: &[String]) -> Result<()> {
+  if upstream != branch_name {
+    bail!(
+      "Branch \"{}\" tracks \"{}\", but a release would push to \"{}\" instead.",
+      branch_name,
+      upstream,
+      branch_name
+    );
+  }
+  if !patterns.is_empty() && !patterns.iter().any(|p| branch_matches(p, upstream).unwrap_or(false)) {
+    bail!("Upstream branch \"{}\" doesn't match \"{}\": not pushing a release there.", upstream, patterns.join(", "));
+  }
+  Ok(())
+}
+
 fn read_env_prefs() -> Result<UserPrefs> {
   read_user_prefs().map(|mut prefs| {
     if let Ok(token) = std::env::var("GITHUB_TOKEN") {
@@ -241,17 +975,15 @@ impl UserPrefs {
 }
 
 /// Find the last covering commit ID, if any, for each current project.
-fn find_last_commits(current: &Config<CurrentState>, repo: &Repo) -> Result<HashMap<ProjectId, String>> {
-  let prev_spec = current.prev_tag();
+fn find_last_commits(base: &str, current: &Config<CurrentState>, repo: &Repo) -> Result<HashMap<ProjectId, String>> {
+  let _timer = Timer::start("history scan");
   let mut last_commits = LastCommitBuilder::create(repo, current);
+  let progress = Progress::start("Walking history");
 
   // Consider the in-line commits to determine the last commit (if any) for each project.
-  for commit in line_commits_head(repo, FromTag::new(prev_spec, true))? {
+  for commit in line_commits_head(repo, FromTag::new(base, true))? {
+    progress.inc();
     last_commits.start_line_commit(&commit)?;
-    for file in commit.files() {
-      last_commits.start_line_file(file)?;
-      last_commits.finish_line_file()?;
-    }
     last_commits.finish_line_commit()?;
   }
 
@@ -260,6 +992,21 @@ fn find_last_commits(current: &Config<CurrentState>, repo: &Repo) -> Result<Hash
   result
 }
 
+/// For each of some commits, given as its config and files, the projects that cover each file. The files are matched
+/// in parallel, but the results are in the same order as the commits and their files.
+fn find_coverings<'a, I>(commits: I) -> Result<Vec<Vec<Vec<ProjectId>>>>
+where
+  I: Iterator<Item = (&'a Arc<ConfigFile>, &'a [String])>
+{
+  let files: Vec<_> = commits.collect();
+  files
+    .par_iter()
+    .map(|(prev, paths)| {
+      paths.par_iter().map(|path| Ok(prev.covering(path)?.iter().map(|p| p.id().clone()).collect())).collect()
+    })
+    .collect()
+}
+
 fn pr_keyed_files(repo: &Repo, pr: FullPr) -> impl Iterator<Item = Result<(String, String)>> + '_ {
   let head_oid = match pr.head_oid() {
     Some(oid) => *oid,
@@ -295,8 +1042,11 @@ fn pr_keyed_files(repo: &Repo, pr: FullPr) -> impl Iterator<Item = Result<(Strin
 
 /// Additional information about a plan that doesn't contribute to plan's execution, but may still be of
 /// interested to the user.
+#[derive(Serialize, Deserialize)]
 pub struct PlanInfo {
-  failed_commits: BTreeSet<CommitInfoBuf>
+  failed_commits: BTreeSet<CommitInfoBuf>,
+  #[serde(default)]
+  clamped: HashMap<ProjectId, Size>
 }
 
 impl Default for PlanInfo {
@@ -304,11 +1054,20 @@ impl Default for PlanInfo {
 }
 
 impl PlanInfo {
-  pub fn new() -> PlanInfo { PlanInfo { failed_commits: BTreeSet::new() } }
+  pub fn new() -> PlanInfo { PlanInfo { failed_commits: BTreeSet::new(), clamped: HashMap::new() } }
   pub fn failed_commits(&self) -> &BTreeSet<CommitInfoBuf> { &self.failed_commits }
   pub fn add_failed_commit(&mut self, failure: CommitInfoBuf) { self.failed_commits.insert(failure); }
+
+  /// The projects whose size was lowered to their `max_size`, with the size they would otherwise have had.
+  pub fn clamped(&self) -> &HashMap<ProjectId, Size> { &self.clamped }
+
+  pub fn add_clamped(&mut self, id: ProjectId, size: Size) {
+    let orig = self.clamped.entry(id).or_insert(size);
+    *orig = max(*orig, size);
+  }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Plan {
   incrs: HashMap<ProjectId, (Size, Changelog)>, // proj ID, incr size, changelog
   ineffective: Vec<LoggedPr>,                   // PRs that didn't apply to any project
@@ -323,10 +1082,73 @@ impl Plan {
   pub fn info(&self) -> &PlanInfo { &self.info }
 }
 
+/// A plan as saved to disk, along with the head commit and prev tag commit that it was built from.
+#[derive(Serialize, Deserialize)]
+struct SavedPlan {
+  head: String,
+  prev: Option<String>,
+  plan: Plan
+}
+
+/// What a cached plan was built from: the plan is only reused if all of these are the same.
+#[derive(Serialize, Deserialize, PartialEq)]
+struct PlanKey {
+  versio: String,
+  prev: Option<String>,
+  head: String,
+  config: String,
+  provider: Option<String>
+}
+
+#[derive(Deserialize)]
+struct CachedPlan {
+  key: PlanKey,
+  plan: Plan
+}
+
+#[derive(Serialize)]
+struct CachedPlanRef<'a> {
+  key: &'a PlanKey,
+  plan: &'a Plan
+}
+
+/// A record of a release run, kept as a git note on its release commit.
+#[derive(Serialize, Deserialize)]
+pub struct ReleaseNote<P> {
+  versio: String,
+  operator: String,
+  timestamp: String,
+  plan: P
+}
+
+impl<P> ReleaseNote<P> {
+  pub fn versio(&self) -> &str { &self.versio }
+  pub fn operator(&self) -> &str { &self.operator }
+  pub fn timestamp(&self) -> &str { &self.timestamp }
+  pub fn plan(&self) -> &P { &self.plan }
+}
+
+/// A project that was retired by a release, after it was removed from the config.
+pub struct Retired {
+  name: String,
+  version: String,
+  tag: Option<String>,
+  changelog: Option<PathBuf>
+}
+
+impl Retired {
+  pub fn name(&self) -> &str { &self.name }
+  pub fn version(&self) -> &str { &self.version }
+  pub fn tag(&self) -> Option<&str> { self.tag.as_deref() }
+  pub fn changelog(&self) -> Option<&Path> { self.changelog.as_deref() }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Changelog {
   entries: Vec<ChangelogEntry>
 }
 
+#[derive(Serialize, Deserialize, Clone)]
 pub enum ChangelogEntry {
   Pr(LoggedPr, Size),
   Dep(ProjectId, String)
@@ -342,15 +1164,26 @@ impl Changelog {
   }
 
   pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+  pub fn has_label(&self, label: &str) -> bool {
+    self.entries.iter().any(|e| match e {
+      ChangelogEntry::Pr(pr, _) => pr.labels().iter().any(|l| l == label),
+      _ => false
+    })
+  }
 }
 
+#[derive(Serialize, Deserialize, Clone)]
 pub struct LoggedPr {
   number: u32,
   title: String,
-  _closed_at: DateTime<FixedOffset>,
+  #[serde(serialize_with = "serialize_datetime", deserialize_with = "deserialize_datetime")]
+  closed_at: DateTime<FixedOffset>,
   discovery_order: usize,
   commits: Vec<LoggedCommit>,
-  url: Option<String>
+  url: Option<String>,
+  #[serde(default)]
+  labels: Vec<String>
 }
 
 impl LoggedPr {
@@ -358,21 +1191,38 @@ impl LoggedPr {
     LoggedPr {
       number: pr.number(),
       title: pr.title().to_string(),
-      _closed_at: *pr.closed_at(),
+      closed_at: *pr.closed_at(),
       discovery_order: pr.discovery_order(),
       commits: Vec::new(),
-      url
+      url,
+      labels: pr.labels().to_vec()
     }
   }
 
   pub fn number(&self) -> u32 { self.number }
   pub fn title(&self) -> &str { &self.title }
-  pub fn _closed_at(&self) -> &DateTime<FixedOffset> { &self._closed_at }
+  pub fn closed_at(&self) -> &DateTime<FixedOffset> { &self.closed_at }
   pub fn discovery_order(&self) -> usize { self.discovery_order }
   pub fn commits(&self) -> &[LoggedCommit] { &self.commits }
   pub fn url(&self) -> &Option<String> { &self.url }
+  pub fn labels(&self) -> &[String] { &self.labels }
+
+  /// Merge the commits of another capture of the same PR into this one.
+  fn merge(&mut self, other: &LoggedPr) {
+    for commit in &other.commits {
+      match self.commits.iter_mut().find(|c| c.oid == commit.oid) {
+        Some(mine) => {
+          if commit.applies && (!mine.applies || commit.size > mine.size) {
+            *mine = commit.clone();
+          }
+        }
+        None => self.commits.push(commit.clone())
+      }
+    }
+  }
 }
 
+#[derive(Serialize, Deserialize, Clone)]
 pub struct LoggedCommit {
   oid: String,
   summary: String,
@@ -380,12 +1230,41 @@ pub struct LoggedCommit {
   size: Size,
   applies: bool,
   duplicate: bool,
-  url: Option<String>
+  url: Option<String>,
+  files: Vec<String>,
+  #[serde(default)]
+  security: bool,
+  #[serde(default)]
+  backport: bool
 }
 
 impl LoggedCommit {
   pub fn new(oid: String, summary: String, message: String, size: Size, url: Option<String>) -> LoggedCommit {
-    LoggedCommit { oid, summary, message, size, applies: false, duplicate: false, url }
+    let files = Vec::new();
+    LoggedCommit {
+      oid,
+      summary,
+      message,
+      size,
+      applies: false,
+      duplicate: false,
+      url,
+      files,
+      security: false,
+      backport: false
+    }
+  }
+
+  /// Mark the commit as a security fix, by its type or by a label of its PR.
+  pub fn with_security(mut self, security: bool) -> LoggedCommit {
+    self.security = security;
+    self
+  }
+
+  /// Mark the commit as a backport of a change that was already released, which doesn't count towards sizing.
+  pub fn with_backport(mut self, backport: bool) -> LoggedCommit {
+    self.backport = backport;
+    self
   }
 
   pub fn applies(&self) -> bool { self.applies }
@@ -396,49 +1275,60 @@ impl LoggedCommit {
   pub fn message(&self) -> &str { &self.message }
   pub fn size(&self) -> Size { self.size }
   pub fn url(&self) -> &Option<String> { &self.url }
+  pub fn files(&self) -> &[String] { &self.files }
+  pub fn security(&self) -> bool { self.security }
+  pub fn backport(&self) -> bool { self.backport }
+}
+
+/// The size of a commit of `kind` in a project. A security fix is always at least a patch; and a security commit
+/// type without a size of its own is a patch, rather than being caught by a `*` catch-all.
+fn commit_size(current: &ConfigFile, proj: &Project, kind: &str, security: bool) -> Result<Size> {
+  let size = match proj.explicit_size(current.sizes(), kind) {
+    Some(size) => size,
+    None if current.is_security_kind(kind) => Size::Patch,
+    None => proj.size(current.sizes(), kind)?
+  };
+  Ok(if security { max(size, Size::Patch) } else { size })
 }
 
 struct PlanBuilder<'s> {
   on_pr_sizes: HashMap<ProjectId, LoggedPr>,
   on_ineffective: Option<LoggedPr>,
   on_commit: Option<CommitInfoBuf>,
+  on_prev: Option<Arc<ConfigFile>>,
   prev: Slicer<'s>,
   current: &'s ConfigFile,
   incrs: HashMap<ProjectId, (Size, Changelog)>, // proj ID, incr size, changelog
   ineffective: Vec<LoggedPr>,                   // PRs that didn't apply to any project
-  github_info: Option<GithubInfo>,
+  provider: Option<Arc<dyn PrProvider>>,
   chain_writes: Vec<(ProjectId, ProjectId)>,
-  info: PlanInfo
+  info: PlanInfo,
+  unknown_commits: BTreeSet<CommitInfoBuf> // commits with unconfigured types, in strict mode
 }
 
 impl<'s> PlanBuilder<'s> {
-  fn create(repo: &'s Repo, current: &'s ConfigFile, auth: &Auth) -> PlanBuilder<'s> {
+  fn create(repo: &'s Repo, current: &'s ConfigFile, provider: Option<Arc<dyn PrProvider>>) -> PlanBuilder<'s> {
     let prev = Slicer::init(repo);
-    let github_info = repo.github_info(auth).ok();
     PlanBuilder {
       on_pr_sizes: HashMap::new(),
       on_ineffective: None,
       on_commit: None,
+      on_prev: None,
       prev,
       current,
       incrs: HashMap::new(),
       ineffective: Vec::new(),
-      github_info,
+      provider,
       chain_writes: Vec::new(),
-      info: PlanInfo::new()
+      info: PlanInfo::new(),
+      unknown_commits: BTreeSet::new()
     }
   }
 
   pub fn start_pr(&mut self, pr: &FullPr) -> Result<()> {
-    trace!(
-      "planning PR {} with {}.",
-      pr.number(),
-      self.github_info.as_ref().map(|gh| gh.repo_name()).unwrap_or("<no gh>")
-    );
-    let url = self
-      .github_info
-      .as_ref()
-      .map(|gh| format!("https://github.com/{}/{}/pull/{}", gh.owner_name(), gh.repo_name(), pr.number()));
+    let api_name = self.provider.as_ref().map(|p| p.api_name()).unwrap_or("<no forge>");
+    trace!("planning PR {} with {}.", pr.number(), api_name);
+    let url = self.provider.as_ref().and_then(|p| p.pr_url(pr.number()));
     self.on_pr_sizes =
       self.current.projects().iter().map(|p| (p.id().clone(), LoggedPr::capture(pr, url.clone()))).collect();
     self.on_ineffective = Some(LoggedPr::capture(pr, url));
@@ -459,36 +1349,57 @@ impl<'s> PlanBuilder<'s> {
     }
 
     let ineffective = self.on_ineffective.take().unwrap();
-    if !found {
+    if !found && !ineffective.commits.is_empty() {
       self.ineffective.push(ineffective);
     }
 
     Ok(())
   }
 
-  pub fn start_commit(&mut self, commit: CommitInfoBuf) -> Result<()> {
+  /// Slice the old config to a commit, returning the config there.
+  pub fn slice_to(&mut self, commit: &CommitInfoBuf) -> Result<Arc<ConfigFile>> {
+    self.prev.slice_to(FromTagBuf::new(commit.id().to_string(), false))?;
+    self.prev.file()
+  }
+
+  /// Start planning a commit, with `prev` as the config at that commit.
+  pub fn start_commit(&mut self, commit: CommitInfoBuf, backport: bool, prev: Arc<ConfigFile>) -> Result<()> {
     let id = commit.id().to_string();
     let kind = commit.kind().to_string();
     let summary = commit.summary().to_string();
     let msg = commit.message().to_string();
-    self.prev.slice_to(FromTagBuf::new(id.clone(), false))?;
+    self.on_prev = Some(prev);
 
-    let url = self
-      .github_info
-      .as_ref()
-      .map(|gh| format!("https://github.com/{}/{}/commit/{}", gh.owner_name(), gh.repo_name(), id));
+    let url = self.provider.as_ref().and_then(|p| p.commit_url(&id));
     trace!("  planning commit {} at {}.", id, url.as_deref().unwrap_or("<no url>"));
 
+    if self.current.commits() == CommitsMode::Strict && !self.current.knows_kind(&kind) {
+      self.unknown_commits.insert(commit.clone());
+    }
+
+    if backport {
+      info!("Commit {} is a backport of a released change: not sizing it.", id);
+    }
+
+    let security_kind = self.current.is_security_kind(&kind);
     for (proj_id, logged_pr) in &mut self.on_pr_sizes {
       if let Some(cur_project) = self.current.get_project(proj_id) {
-        let size = cur_project.size(self.current.sizes(), &kind)?;
+        let security = security_kind || self.current.has_security_label(logged_pr.labels());
+        let size = if backport { Size::None } else { commit_size(self.current, cur_project, &kind, security)? };
         if size.is_failure() {
           self.info.add_failed_commit(commit.clone());
         }
-        logged_pr.commits.push(LoggedCommit::new(id.clone(), summary.clone(), msg.clone(), size, url.clone()));
+        let logged = LoggedCommit::new(id.clone(), summary.clone(), msg.clone(), size, url.clone());
+        logged_pr.commits.push(logged.with_security(security).with_backport(backport));
       }
     }
 
+    // Keep the commit for the ineffective list too, in case the PR turns out not to apply to any project.
+    if let Some(ineffective) = &mut self.on_ineffective {
+      let size = self.current.sizes().get(&kind).copied().unwrap_or(Size::None);
+      ineffective.commits.push(LoggedCommit::new(id, summary, msg, size, url));
+    }
+
     self.on_commit = Some(commit);
     Ok(())
   }
@@ -498,17 +1409,38 @@ impl<'s> PlanBuilder<'s> {
     Ok(())
   }
 
-  pub fn start_file(&mut self, path: &str) -> Result<()> {
+  /// Plan a file of the commit, which is covered by the `covering` projects of the commit's config.
+  pub fn start_file(&mut self, path: &str, covering: &[ProjectId]) -> Result<()> {
     trace!("    planning file {}.", path);
     let commit = self.on_commit.as_ref().ok_or_else(|| bad!("Not on a commit"))?;
     let commit_id = commit.id();
 
-    for prev_project in self.prev.file()?.projects() {
+    let ineffective = self.on_ineffective.as_mut().and_then(|pr| pr.commits.iter_mut().find(|c| c.oid == commit_id));
+    if let Some(logged) = ineffective {
+      logged.files.push(path.to_string());
+    }
+
+    let prev_file = self.on_prev.clone().ok_or_else(|| bad!("Not on a commit"))?;
+    for prev_project in prev_file.projects() {
       if let Some(logged_pr) = self.on_pr_sizes.get_mut(prev_project.id()) {
         trace!("      vs current project {}.", prev_project.id());
-        if prev_project.does_cover(path)? {
-          let LoggedCommit { applies, .. } = logged_pr.commits.iter_mut().find(|c| c.oid == commit_id).unwrap();
+        if covering.contains(prev_project.id()) {
+          let proj = self.current.get_project(prev_project.id()).ok_or_else(|| bad!("No such project"))?;
+          let LoggedCommit { applies, files, size, security, backport, .. } =
+            logged_pr.commits.iter_mut().find(|c| c.oid == commit_id).unwrap();
+          let file_size = if *backport {
+            Size::None
+          } else {
+            let file_size = proj.path_size(path, commit_size(self.current, proj, commit.kind(), *security)?)?;
+            if *security {
+              max(file_size, Size::Patch)
+            } else {
+              file_size
+            }
+          };
+          *size = if *applies { max(*size, file_size) } else { file_size };
           *applies = true;
+          files.push(path.to_string());
           trace!("        covered.");
         } else {
           trace!("        not covered.");
@@ -523,6 +1455,9 @@ impl<'s> PlanBuilder<'s> {
   pub fn finish_file(&mut self) -> Result<()> { Ok(()) }
 
   pub fn handle_deps(&mut self) -> Result<()> {
+    // Kahn's algorithm would silently skip any projects in a cycle.
+    self.current.check_cycles()?;
+
     // Use a modified Kahn's algorithm to traverse deps in order.
     let mut queue: VecDeque<ProjectId> = VecDeque::new();
 
@@ -563,11 +1498,87 @@ impl<'s> PlanBuilder<'s> {
     Ok(())
   }
 
+  pub fn handle_umbrellas(&mut self) -> Result<()> {
+    let current = self.current;
+    let is_member = |id: &ProjectId| current.get_project(id).map(|p| !p.umbrella()).unwrap_or(false);
+
+    for umbrella in current.projects().iter().filter(|p| p.umbrella()) {
+      let mut size = Size::Empty;
+      let mut prs: Vec<LoggedPr> = Vec::new();
+      for (_, (member_size, changelog)) in self.incrs.iter().filter(|(id, _)| is_member(id)) {
+        size = max(size, *member_size);
+        for entry in changelog.entries() {
+          if let ChangelogEntry::Pr(pr, _) = entry {
+            match prs.iter_mut().find(|p| p.number() == pr.number()) {
+              Some(mine) => mine.merge(pr),
+              None => prs.push(pr.clone())
+            }
+          }
+        }
+      }
+
+      let (val, ch_log) = self.incrs.entry(umbrella.id().clone()).or_insert((Size::Empty, Changelog::empty()));
+      *val = max(*val, size);
+      for pr in prs {
+        let pr_size = pr.commits().iter().filter(|c| c.applies()).map(|c| c.size()).max().unwrap_or(Size::Empty);
+        ch_log.add_entry(pr, pr_size);
+      }
+    }
+
+    Ok(())
+  }
+
+  pub fn clamp_sizes(&mut self) {
+    for (proj_id, (size, _)) in &mut self.incrs {
+      let max_size = self.current.get_project(proj_id).and_then(|p| p.max_size());
+      if let Some(max_size) = max_size {
+        if *size > max_size && !size.is_failure() {
+          self.info.add_clamped(proj_id.clone(), *size);
+          *size = max_size;
+        }
+      }
+    }
+  }
+
+  pub fn add_held(&mut self, held: HashMap<ProjectId, (Size, Changelog)>) {
+    for (proj_id, (held_size, held_log)) in held {
+      if self.current.get_project(&proj_id).is_none() {
+        continue;
+      }
+
+      let (size, changelog) = self.incrs.entry(proj_id).or_insert((Size::Empty, Changelog::empty()));
+      *size = max(*size, held_size);
+      for entry in held_log.entries {
+        match &entry {
+          ChangelogEntry::Pr(held_pr, _) => {
+            let mine = changelog.entries.iter_mut().find_map(|e| match e {
+              ChangelogEntry::Pr(pr, _) if pr.number() == held_pr.number() => Some(pr),
+              _ => None
+            });
+            match mine {
+              Some(mine) => mine.merge(held_pr),
+              None => changelog.entries.push(entry)
+            }
+          }
+          ChangelogEntry::Dep(held_id, _) => {
+            if !changelog.entries.iter().any(|e| matches!(e, ChangelogEntry::Dep(id, _) if id == held_id)) {
+              changelog.entries.push(entry);
+            }
+          }
+        }
+      }
+    }
+  }
+
   pub fn sort_and_dedup(&mut self) -> Result<()> {
     for (.., changelog) in self.incrs.values_mut() {
+      // PRs are ordered by when they closed (compared as instants, so time zones don't matter), with the pseudo-PR
+      // of unattached commits closing "now", and so last.
       changelog.entries.sort_by(|entry1, entry2| match entry1 {
         ChangelogEntry::Pr(pr1, _) => match entry2 {
-          ChangelogEntry::Pr(pr2, _) => pr2.discovery_order().cmp(&pr1.discovery_order()),
+          ChangelogEntry::Pr(pr2, _) => {
+            pr1.closed_at().cmp(pr2.closed_at()).then_with(|| pr2.discovery_order().cmp(&pr1.discovery_order()))
+          }
           _ => Ordering::Greater
         },
         ChangelogEntry::Dep(pr_id1, _) => match entry2 {
@@ -592,14 +1603,24 @@ impl<'s> PlanBuilder<'s> {
     Ok(())
   }
 
+  /// In strict mode, fail if any planned commit has a type that isn't configured.
+  pub fn check_strict(&self) -> Result<()> {
+    if self.unknown_commits.is_empty() {
+      return Ok(());
+    }
+
+    let commits: Vec<_> =
+      self.unknown_commits.iter().map(|c| format!("{} \"{}\"", &c.id()[.. 7], c.summary())).collect();
+    bail!(Error::plan(format!("unrecognized commit type in {}", commits.join(", "))))
+  }
+
   pub fn build(self) -> Plan {
     Plan { incrs: self.incrs, ineffective: self.ineffective, chain_writes: self.chain_writes, info: self.info }
   }
 }
 
 struct LastCommitBuilder<'s, C: StateRead> {
-  on_line_commit: Option<String>,
-  last_commits: HashMap<ProjectId, String>,
+  line_commits: Vec<(CommitInfoBuf, Arc<ConfigFile>)>,
   prev: Slicer<'s>,
   current: &'s Config<C>
 }
@@ -607,85 +1628,104 @@ struct LastCommitBuilder<'s, C: StateRead> {
 impl<'s, C: StateRead> LastCommitBuilder<'s, C> {
   fn create(repo: &'s Repo, current: &'s Config<C>) -> LastCommitBuilder<'s, C> {
     let prev = Slicer::init(repo);
-    LastCommitBuilder { on_line_commit: None, last_commits: HashMap::new(), prev, current }
+    LastCommitBuilder { line_commits: Vec::new(), prev, current }
   }
 
+  /// Slice the old config to a line commit. Line commits must be started in order, newest first.
   pub fn start_line_commit(&mut self, commit: &CommitInfoBuf) -> Result<()> {
-    let id = commit.id().to_string();
-    self.on_line_commit = Some(id.clone());
-    self.prev.slice_to(FromTagBuf::new(id, false))?;
+    self.prev.slice_to(FromTagBuf::new(commit.id().to_string(), false))?;
+    self.line_commits.push((commit.clone(), self.prev.file()?));
     Ok(())
   }
 
   pub fn finish_line_commit(&mut self) -> Result<()> { Ok(()) }
 
-  pub fn start_line_file(&mut self, path: &str) -> Result<()> {
-    let commit_id = self.on_line_commit.as_ref().ok_or_else(|| bad!("Not on a line commit"))?;
+  /// Match the files of all line commits in parallel, and then take the newest commit that covers each project.
+  pub fn build(self) -> Result<HashMap<ProjectId, String>> {
+    let current: HashSet<_> = self.current.projects().iter().map(|p| p.id()).collect();
+    let covers = self
+      .line_commits
+      .par_iter()
+      .map(|(commit, prev)| {
+        let mut covers = Vec::new();
+        for prev_project in prev.projects().iter().filter(|p| current.contains(p.id())) {
+          for path in commit.files() {
+            if prev_project.does_cover(path)? {
+              covers.push(prev_project.id().clone());
+              break;
+            }
+          }
+        }
+        Ok(covers)
+      })
+      .collect::<Result<Vec<_>>>()?;
 
-    for prev_project in self.prev.file()?.projects() {
-      let proj_id = prev_project.id();
-      if self.current.get_project(proj_id).is_some()
-        && prev_project.does_cover(path)?
-        && !self.last_commits.contains_key(proj_id)
-      {
-        self.last_commits.insert(proj_id.clone(), commit_id.clone());
+    let mut last_commits = HashMap::new();
+    for ((commit, _), covers) in self.line_commits.iter().zip(covers) {
+      for proj_id in covers {
+        last_commits.entry(proj_id).or_insert_with(|| commit.id().to_string());
       }
     }
-    Ok(())
+    Ok(last_commits)
   }
-
-  pub fn finish_line_file(&mut self) -> Result<()> { Ok(()) }
-
-  pub fn build(self) -> Result<HashMap<ProjectId, String>> { Ok(self.last_commits) }
 }
 
-enum Slicer<'r> {
-  Orig(&'r Repo),
-  Slice(FsConfig<PrevFiles<'r>>)
+/// Slices the old config to commits in turn, keeping the configs it's read so that a config blob is only read once.
+struct Slicer<'r> {
+  repo: &'r Repo,
+  sliced: Option<FsConfig<PrevFiles<'r>>>,
+  seen: SeenConfigs
 }
 
 impl<'r> Slicer<'r> {
-  pub fn init(repo: &'r Repo) -> Slicer<'r> { Slicer::Orig(repo) }
+  pub fn init(repo: &'r Repo) -> Slicer<'r> { Slicer { repo, sliced: None, seen: HashMap::new() } }
 
-  pub fn file(&self) -> Result<&ConfigFile> {
-    match self {
-      Slicer::Slice(fsc) => Ok(fsc.file()),
-      _ => err!("Slicer not sliced")
-    }
+  pub fn file(&self) -> Result<Arc<ConfigFile>> {
+    self.sliced.as_ref().map(|fsc| fsc.shared_file()).ok_or_else(|| bad!("Slicer not sliced"))
   }
 
   pub fn slice_to(&mut self, id: FromTagBuf) -> Result<()> {
-    *self = Slicer::Slice(match self {
-      Slicer::Orig(repo) => FsConfig::from_slice(repo.slice(id))?,
-      Slicer::Slice(fsc) => fsc.slice_to(id)?
+    self.sliced = Some(match self.sliced.take() {
+      None => FsConfig::from_slice(self.repo.slice(id), &mut self.seen)?,
+      Some(fsc) => fsc.slice_to(id, &mut self.seen)?
     });
     Ok(())
   }
 }
 
-fn find_old_tags<'s, I: Iterator<Item = &'s Project>>(projects: I, prev_tag: &str, repo: &Repo) -> Result<OldTags> {
-  let mut by_proj_oid = HashMap::new(); // Map<proj_id, Map<oid, Vec<tag>>>
+fn find_old_tags<'s, I: Iterator<Item = &'s Project>>(
+  projects: I, prev_tag: &str, base: &str, repo: &Repo
+) -> Result<OldTags> {
+  let _timer = Timer::start("tag discovery");
+  let mut by_proj_oid = HashMap::new(); // Map<proj_id, Map<oid, Vec<version>>>
   let mut proj_ids = HashSet::new();
 
+  let progress = Progress::start("Scanning tags");
   for proj in projects {
     proj_ids.insert(proj.id().clone());
     for fnmatch in tag_fnmatches(proj) {
       trace!("Searching tags for proj {} matching \"{}\".", proj.id(), fnmatch);
       for tag in repo.tag_names(Some(fnmatch.as_str()))?.iter().flatten() {
+        progress.inc();
         let oid = repo.revparse_oid(FromTag::new(&format!("{}^{{}}", tag), false))?;
         trace!("Found proj {} tag {} at {}.", proj.id(), tag, oid);
-        let by_id = by_proj_oid.entry(proj.id().clone()).or_insert_with(HashMap::new);
-        by_id.entry(oid).or_insert_with(Vec::new).push(tag.to_string());
+        if let Some(vers) = proj.tag_version(tag).filter(|v| Size::parts(v).is_ok()) {
+          let by_id = by_proj_oid.entry(proj.id().clone()).or_insert_with(HashMap::new);
+          by_id.entry(oid).or_insert_with(Vec::new).push(vers);
+        }
       }
     }
   }
 
+  drop(progress);
+
   let mut current = HashMap::new();
-  for commit_oid in repo.commits_to_head(FromTag::new(prev_tag, true), false)?.map(|c| c.map(|c| c.id())) {
+  let progress = Progress::start("Walking history for tags");
+  for commit_oid in repo.commits_to_head(FromTag::new(base, true), false)?.map(|c| c.map(|c| c.id())) {
     let commit_oid = commit_oid?;
+    progress.inc();
     by_proj_oid.retain(|proj_id, by_id| {
-      if let Some(tags) = by_id.remove(&commit_oid) {
-        let mut versions = tags_to_versions(&tags);
+      if let Some(mut versions) = by_id.remove(&commit_oid) {
         versions.sort_unstable_by(version_sort);
         current.insert(proj_id.clone(), versions[0].clone());
         false
@@ -695,6 +1735,8 @@ fn find_old_tags<'s, I: Iterator<Item = &'s Project>>(projects: I, prev_tag: &st
     });
   }
 
+  drop(progress);
+
   let prev = pull_from_annotation(repo, prev_tag)?;
   fill_from_prev(&prev, &proj_ids, &mut current);
 
@@ -703,7 +1745,49 @@ fn find_old_tags<'s, I: Iterator<Item = &'s Project>>(projects: I, prev_tag: &st
   Ok(old_tags)
 }
 
+/// Find where history scans should stop: the prev tag if it exists, or else as directed by the `missing_prev`
+/// option. A given `max_history` overrides the config's.
+fn history_base(file: &ConfigFile, repo: &Repo, max_history: Option<usize>) -> Result<String> {
+  let prev_tag = file.prev_tag();
+  if let Some(prev_oid) = repo.tag_oid(prev_tag) {
+    if !repo.in_history(&prev_oid)? {
+      let versio = read_prev_message(repo, prev_tag).ok().and_then(|m| m.versio().map(|v| v.to_string()));
+      warn!(
+        "Prev tag \"{}\" at {} isn't in the history of HEAD: history may have been rewritten since {} wrote it.",
+        prev_tag,
+        &prev_oid[.. 7],
+        versio.map(|v| format!("versio {}", v)).unwrap_or_else(|| "versio".into())
+      );
+    }
+    return Ok(prev_tag.to_string());
+  }
+  if repo.tag_oid("HEAD").is_none() {
+    return Ok(prev_tag.to_string());
+  }
+
+  let max_history = max_history.or_else(|| file.max_history());
+  match file.missing_prev(max_history.is_some()) {
+    MissingPrev::Full => Ok(prev_tag.to_string()),
+    MissingPrev::Fail => err!("No prev tag \"{}\": refusing to scan all history.", prev_tag),
+    MissingPrev::Limit => {
+      let max = max_history.ok_or_else(|| bad!("No max_history to limit to."))?;
+      match repo.tag_oid(&format!("HEAD~{}", max)) {
+        Some(oid) => {
+          warn!("No prev tag \"{}\": only scanning the last {} commits.", prev_tag, max);
+          Ok(oid)
+        }
+        None => Ok(prev_tag.to_string())
+      }
+    }
+  }
+}
+
 fn pull_from_annotation(repo: &Repo, prev_tag: &str) -> Result<HashMap<ProjectId, String>> {
+  read_prev_message(repo, prev_tag).map(|m| m.into_versions())
+}
+
+/// Read the JSON message stored in the annotated prev tag, or an empty message if there is none.
+pub fn read_prev_message(repo: &Repo, prev_tag: &str) -> Result<PrevTagMessage> {
   repo
     .annotation_of(prev_tag)
     .map(|anno| {
@@ -713,7 +1797,7 @@ fn pull_from_annotation(repo: &Repo, prev_tag: &str) -> Result<HashMap<ProjectId
     })
     .transpose()
     .map_err(|e| e.into())
-    .map(|o| o.unwrap_or_default().into_versions())
+    .map(|o| o.unwrap_or_default())
 }
 
 fn fill_from_prev(
@@ -730,34 +1814,16 @@ fn fill_from_prev(
 
 /// Construct a fnmatch pattern for a project that can be used to retrieve the project's tags.
 ///
-/// This will return an empty iterator if the project doesn't have a tag_prefix. The resulting patterns are
-/// usable by both `Repository::tag_names` and as a git fetch refspec `refs/tags/{pattern}`.
+/// This will return an empty iterator if the project isn't tagged. The resulting patterns are usable by both
+/// `Repository::tag_names` and as a git fetch refspec `refs/tags/{pattern}`.
 fn tag_fnmatches(proj: &Project) -> impl Iterator<Item = String> + '_ {
-  let majors = proj.tag_majors();
-
-  let majors_v = if let Some(majors) = majors {
-    E2::A(majors.iter().map(|major| format!("v{}.*", major)))
+  let majors_v = if let Some(majors) = proj.tag_majors() {
+    E2::A(majors.iter().map(|major| format!("{}.*", major)))
   } else {
-    E2::B(once("v*".to_string()))
+    E2::B(once("*".to_string()))
   };
 
-  let tag_prefix = proj.tag_prefix().as_ref().map(|p| p.as_str());
-  match tag_prefix {
-    None => E3::A(empty()),
-    Some("") => E3::B(majors_v),
-    Some(pref) => E3::C(majors_v.map(move |major_v| format!("{}-{}", pref, major_v)))
-  }
-}
-
-fn tags_to_versions(tags: &[String]) -> Vec<String> {
-  tags
-    .iter()
-    .map(|tag| {
-      let v = tag.rfind('-').map(|d| d + 1).unwrap_or(0);
-      tag[v + 1 ..].to_string()
-    })
-    .filter(|v| Size::parts(v).is_ok())
-    .collect()
+  majors_v.filter_map(move |major_v| proj.full_version(&major_v))
 }
 
 #[allow(clippy::ptr_arg)]
@@ -791,3 +1857,510 @@ fn version_sort(a: &String, b: &String) -> Ordering {
     Ordering::Equal
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::{verify_upstream, ChangelogEntry, Mono, Plan, Slicer};
+  use crate::config::ProjectId;
+  use crate::fixture::Fixture;
+  use crate::git::{FromTagBuf, Repo};
+  use crate::observe::{NoObserver, Observer};
+  use crate::scan::parts::Part;
+  use crate::scan::{Scanner, Scanners, TomlScanner};
+  use crate::sizes::Size;
+  use crate::state::{CommitState, MemoryState};
+  use crate::vcs::{VcsLevel, VcsState};
+  use git2::Oid;
+  use std::collections::HashMap;
+  use std::sync::Arc;
+  use tempfile::tempdir;
+  use tokio::runtime::Runtime;
+
+  const CONFIG: &str = r#"
+projects:
+  - name: proj
+    id: 1
+    tag_prefix: "proj"
+    version:
+      file: "VERSION"
+      pattern: "([0-9]+\\.[0-9]+\\.[0-9]+)"
+
+sizes:
+  use_angular: true
+  fail: ["*"]
+"#;
+
+  const SPLIT_CONFIG: &str = r#"
+projects:
+  - name: proj_a
+    id: 1
+    root: "a"
+    version:
+      file: "VERSION"
+      pattern: "([0-9]+\\.[0-9]+\\.[0-9]+)"
+
+  - name: proj_b
+    id: 2
+    root: "b"
+    version:
+      file: "VERSION"
+      pattern: "([0-9]+\\.[0-9]+\\.[0-9]+)"
+
+sizes:
+  use_angular: true
+  fail: ["*"]
+"#;
+
+  fn fixture() -> Fixture { Fixture::new(&[(".versio.yaml", CONFIG), ("VERSION", "1.0.0\n")]) }
+
+  fn split_fixture() -> Fixture {
+    Fixture::new(&[(".versio.yaml", SPLIT_CONFIG), ("a/VERSION", "1.0.0\n"), ("b/VERSION", "1.0.0\n")])
+  }
+
+  #[test]
+  fn test_last_commits() {
+    let fixture = split_fixture();
+    fixture.write("a/one.txt", "one\n");
+    fixture.commit("fix: change a");
+    fixture.write("b/one.txt", "one\n");
+    let b_commit = fixture.commit("fix: change b");
+    fixture.write("a/two.txt", "two\n");
+    let a_commit = fixture.commit("fix: change a again");
+    fixture.write("other.txt", "other\n");
+    fixture.commit("fix: change neither");
+
+    let mono = fixture.mono();
+    assert_eq!(mono.last_commits.get(&ProjectId::from_id(1)), Some(&a_commit.to_string()));
+    assert_eq!(mono.last_commits.get(&ProjectId::from_id(2)), Some(&b_commit.to_string()));
+  }
+
+  #[test]
+  fn test_plan_by_project() {
+    let fixture = split_fixture();
+    fixture.write("a/one.txt", "one\n");
+    fixture.commit("feat: change a");
+    fixture.write("b/one.txt", "one\n");
+    fixture.write("a/two.txt", "two\n");
+    fixture.commit("fix: change both");
+
+    let plan = Runtime::new().unwrap().block_on(fixture.mono().build_plan()).unwrap();
+    assert_eq!(plan.incrs()[&ProjectId::from_id(1)].0, Size::Minor);
+    assert_eq!(plan.incrs()[&ProjectId::from_id(2)].0, Size::Patch);
+  }
+
+  #[test]
+  fn test_max_history_limits_base() {
+    let fixture = fixture();
+    let base = fixture.commit("fix: one");
+    fixture.commit("fix: two");
+
+    let vcs = VcsState::new(VcsLevel::Local, false);
+    let limited = Mono::builder(".", vcs).max_history(Some(1)).open().unwrap();
+    assert_eq!(limited.base, base.to_string());
+    let full = Mono::open(".", vcs).unwrap();
+    assert_eq!(full.base, full.config().prev_tag());
+  }
+
+  #[derive(Default)]
+  struct Events(Vec<String>);
+
+  impl Observer for Events {
+    fn on_plan_built(&mut self, plan: &Plan) { self.0.push(format!("plan {}", plan.incrs().len())); }
+
+    fn on_project_bumped(&mut self, id: &ProjectId, name: &str, prev: &str, curt: &str, targ: &str) {
+      self.0.push(format!("bump {} {} {} {} {}", id, name, prev, curt, targ));
+    }
+  }
+
+  #[test]
+  fn test_mono_observes() {
+    let fixture = fixture();
+    fixture.commit("feat: add");
+    let mut mono = fixture.mono();
+    let mut events = Events::default();
+    let plan = Runtime::new().unwrap().block_on(mono.build_plan_with(&mut events)).unwrap();
+    mono.bump_by_id(&ProjectId::from_id(1), Some("0.9.0"), "1.1.0", &mut events).unwrap();
+    mono.bump_by_id(&ProjectId::from_id(1), None, "1.2.0", &mut events).unwrap();
+    assert_eq!(plan.incrs().len(), 1);
+    assert_eq!(events.0, vec!["plan 1", "bump 1 proj 0.9.0 1.0.0 1.1.0", "bump 1 proj 1.0.0 1.0.0 1.2.0"]);
+  }
+
+  #[test]
+  fn test_backports_not_sized() {
+    let config = format!("{}\noptions:\n  maintenance_branch: \"release/*\"\n", CONFIG);
+    let fixture = Fixture::new(&[(".versio.yaml", &config), ("VERSION", "1.0.0\n")]);
+    let start = fixture.repo().head().unwrap().target().unwrap();
+    fixture.write("one.txt", "one\n");
+    let one = fixture.commit("fix: one");
+    fixture.write("two.txt", "two\n");
+    let two = fixture.commit("fix: two");
+    fixture.tag("proj1.1.0", two);
+
+    // `one` is picked with `-x`, but its conflict was resolved differently; `two` is picked without a trailer.
+    fixture.branch("release/1.0", start);
+    fixture.tag_prev("versio-prev", HashMap::new());
+    fixture.write("one.txt", "one, resolved\n");
+    let picked_one = fixture.commit(&format!("fix: one\n\n(cherry picked from commit {})", one));
+    fixture.write("two.txt", "two\n");
+    let picked_two = fixture.commit("fix: two");
+    fixture.write("three.txt", "three\n");
+    let three = fixture.commit("fix: three");
+
+    let plan = Runtime::new().unwrap().block_on(fixture.mono().build_plan()).unwrap();
+    let (size, changelog) = &plan.incrs()[&ProjectId::from_id(1)];
+    let backports: HashMap<_, _> = changelog
+      .entries()
+      .iter()
+      .filter_map(|e| match e {
+        ChangelogEntry::Pr(pr, _) => Some(pr.commits().iter().map(|c| (c.oid().to_string(), c.backport()))),
+        ChangelogEntry::Dep(..) => None
+      })
+      .flatten()
+      .collect();
+    assert_eq!(backports.get(&picked_one.to_string()), Some(&true));
+    assert_eq!(backports.get(&picked_two.to_string()), Some(&true));
+    assert_eq!(backports.get(&three.to_string()), Some(&false));
+    assert_eq!(size, &Size::Patch);
+  }
+
+  #[test]
+  fn test_verify_set() {
+    let config = r#"
+projects:
+  - name: one
+    id: 1
+    root: "one"
+    version: { file: "VERSION", pattern: "(.*)" }
+    restrictions:
+      - name: stay-below
+        below: "1.5.0"
+  - name: zero
+    id: 2
+    root: "zero"
+    version: { file: "VERSION", pattern: "(.*)" }
+  - name: pre
+    id: 3
+    root: "pre"
+    version: { file: "VERSION", pattern: "(.*)" }
+"#;
+    let fixture = Fixture::new(&[
+      (".versio.yaml", config),
+      ("one/VERSION", "1.2.0"),
+      ("zero/VERSION", "0.3.0"),
+      ("pre/VERSION", "2.1.0")
+    ]);
+    let mono = fixture.mono();
+    let verify = |id: u32, val: &str| mono.verify_set(&ProjectId::from_id(id), val);
+
+    assert!(verify(1, "1.2.0").is_ok());
+    assert!(verify(1, "1.3.0").is_ok());
+    assert!(verify(1, "1.1.9").unwrap_err().to_string().contains("lower than"));
+    assert!(verify(1, "2.0.0").unwrap_err().to_string().contains("changes the major"));
+    assert!(verify(1, "1.6.0").unwrap_err().to_string().contains("stay-below"));
+
+    assert!(verify(2, "0.4.0").is_ok());
+    assert!(verify(2, "0.2.0").is_err());
+    assert!(verify(2, "1.0.0").is_err());
+
+    assert!(verify(3, "2.1.0-rc.1").is_ok());
+    assert!(verify(3, "2.1.1-rc.1").is_ok());
+    assert!(verify(3, "2.0.9-rc.1").is_err());
+  }
+
+  fn released_per_project(fixture: &Fixture) {
+    let repo = fixture.repo();
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    let parent = head.parent(0).unwrap();
+    assert_eq!(head.summary(), Some("build(deploy): Versio update proj_b to 1.1.0"));
+    assert_eq!(parent.summary(), Some("build(deploy): Versio update proj_a to 1.1.0"));
+    assert_eq!(parent.parent(0).unwrap().summary(), Some("feat: start"));
+
+    let tagged = |tag: &str| repo.revparse_single(tag).unwrap().peel_to_commit().unwrap().id();
+    assert_eq!(tagged("proj_a-v1.1.0"), parent.id());
+    assert_eq!(tagged("proj_b-v1.1.0"), head.id());
+    assert_eq!(tagged("versio-prev"), head.id());
+    assert!(repo.statuses(None).unwrap().is_empty());
+  }
+
+  fn per_project_fixture() -> Fixture {
+    let config = SPLIT_CONFIG.replace("    root: \"a\"", "    root: \"a\"\n    tag_prefix: \"proj_a\"");
+    let config = config.replace("    root: \"b\"", "    root: \"b\"\n    tag_prefix: \"proj_b\"");
+    let config = format!("{}\noptions:\n  commit_mode: per-project\n", config);
+    Fixture::new(&[(".versio.yaml", &config), ("a/VERSION", "1.0.0\n"), ("b/VERSION", "1.0.0\n")])
+  }
+
+  fn bump_both(fixture: &Fixture, pause: bool) {
+    let mut mono = fixture.mono();
+    let (a, b) = (ProjectId::from_id(1), ProjectId::from_id(2));
+    mono.set_by_id(&a, "1.1.0").unwrap();
+    mono.set_by_id(&b, "1.1.0").unwrap();
+    mono.set_commit_message(vec![(&a, "1.1.0"), (&b, "1.1.0")], None).unwrap();
+    mono.commit(true, pause, false, &mut NoObserver).unwrap();
+  }
+
+  #[test]
+  fn test_per_project_commits() {
+    let fixture = per_project_fixture();
+    bump_both(&fixture, false);
+    released_per_project(&fixture);
+  }
+
+  #[test]
+  fn test_per_project_resumed() {
+    let fixture = per_project_fixture();
+    bump_both(&fixture, true);
+    let paused = fixture.path().join(".versio-paused");
+    let mut state: CommitState = serde_json::from_str(&std::fs::read_to_string(&paused).unwrap()).unwrap();
+    std::fs::remove_file(&paused).unwrap();
+
+    let repo = Repo::open(".", VcsState::new(VcsLevel::Local, false)).unwrap();
+    state.resume(&repo, &mut NoObserver, None).unwrap();
+    released_per_project(&fixture);
+  }
+
+  #[test]
+  fn test_fixture_from_state() {
+    let config = r#"
+projects:
+  - name: file
+    id: 1
+    includes: ["file/**/*"]
+    version: { file: "file/package.json", json: "version" }
+  - name: tags
+    id: 2
+    includes: ["tags/**/*"]
+    tag_prefix: "tags"
+    version: { tags: { default: "0.0.1" } }
+
+sizes:
+  use_angular: true
+  fail: ["*"]
+"#;
+    let state = MemoryState::new()
+      .with_file(".versio.yaml", config)
+      .with_file("file/package.json", "{ \"version\": \"1.2.3\" }\n")
+      .with_tag(ProjectId::from_id(2), "1.4.0");
+    let fixture = Fixture::from_state(&state);
+
+    let mono = fixture.mono();
+    for id in [1, 2].iter().copied().map(ProjectId::from_id) {
+      let proj = mono.get_project(&id).unwrap();
+      assert_eq!(proj.get_value(mono.config().state_read()).unwrap(), proj.get_value(&state).unwrap());
+    }
+
+    fixture.write("tags/one.txt", "one\n");
+    fixture.commit("feat: change tags");
+    let plan = Runtime::new().unwrap().block_on(fixture.mono().build_plan()).unwrap();
+    assert_eq!(plan.incrs()[&ProjectId::from_id(2)].0, Size::Minor);
+    assert_eq!(plan.incrs()[&ProjectId::from_id(1)].0, Size::Empty);
+  }
+
+  #[test]
+  fn test_scanners_are_per_mono() {
+    fn build_cfg(parts: Vec<Part>) -> Box<dyn Scanner + Send + Sync> { Box::new(TomlScanner::build(parts)) }
+    let config = r#"
+projects:
+  - name: proj
+    id: 1
+    includes: ["**/*"]
+    version: { file: "setup.cfg", cfg: "metadata.version" }
+"#;
+    let _fixture = Fixture::new(&[(".versio.yaml", config), ("setup.cfg", "[metadata]\nversion = \"1.2.3\"\n")]);
+    let vcs = VcsState::new(VcsLevel::Local, false);
+    assert!(Mono::open(".", vcs).is_err());
+
+    let mut scanners = Scanners::new();
+    scanners.register("cfg", build_cfg).unwrap();
+    let mono = Mono::builder(".", vcs).scanners(scanners).open().unwrap();
+    let proj = mono.get_project(&ProjectId::from_id(1)).unwrap();
+    assert_eq!(proj.get_value(mono.config().state_read()).unwrap(), "1.2.3");
+    assert!(Mono::open(".", vcs).is_err());
+  }
+
+  #[test]
+  fn test_slicer_reuses_config_blobs() {
+    let fixture = fixture();
+    let first = fixture.repo().head().unwrap().target().unwrap();
+    fixture.write(".versio.yaml", &CONFIG.replace("name: proj", "name: renamed"));
+    let second = fixture.commit("chore: rename");
+    fixture.write(".versio.yaml", CONFIG);
+    let third = fixture.commit("chore: rename back");
+
+    let mono = fixture.mono();
+    let mut slicer = Slicer::init(mono.repo());
+    let mut slice_to = |oid: Oid| {
+      slicer.slice_to(FromTagBuf::new(oid.to_string(), false)).unwrap();
+      slicer.file().unwrap()
+    };
+    let (first, second, third) = (slice_to(first), slice_to(second), slice_to(third));
+
+    assert_eq!(second.projects()[0].name(), "renamed");
+    assert!(!Arc::ptr_eq(&first, &second));
+    assert!(Arc::ptr_eq(&first, &third));
+  }
+
+  #[test]
+  fn test_plan_round_trip() {
+    let fixture = fixture();
+    fixture.write("new.txt", "new\n");
+    fixture.commit("feat: add new");
+
+    let mono = fixture.mono();
+    let plan = Runtime::new().unwrap().block_on(mono.build_plan()).unwrap();
+    let saves = tempdir().unwrap();
+    let path = saves.path().join("plan.json");
+    let plan = mono.save_plan(plan, &path).unwrap();
+    let loaded = mono.load_plan(&path, &mut NoObserver).unwrap();
+
+    let id = ProjectId::from_id(1);
+    assert_eq!(plan.incrs()[&id].0, Size::Minor);
+    assert_eq!(loaded.incrs()[&id].0, Size::Minor);
+    assert_eq!(loaded.incrs().len(), plan.incrs().len());
+    assert_eq!(loaded.ineffective().len(), plan.ineffective().len());
+  }
+
+  #[test]
+  fn test_plan_rejects_moved_head() {
+    let fixture = fixture();
+    let mono = fixture.mono();
+    let plan = Runtime::new().unwrap().block_on(mono.build_plan()).unwrap();
+    let saves = tempdir().unwrap();
+    let path = saves.path().join("plan.json");
+    mono.save_plan(plan, &path).unwrap();
+
+    fixture.write("new.txt", "new\n");
+    fixture.commit("feat: add new");
+    assert!(fixture.mono().load_plan(&path, &mut NoObserver).is_err());
+  }
+
+  #[test]
+  fn test_plan_rejects_moved_prev_tag() {
+    let fixture = fixture();
+    fixture.write("new.txt", "new\n");
+    fixture.commit("feat: add new");
+
+    let mono = fixture.mono();
+    let plan = Runtime::new().unwrap().block_on(mono.build_plan()).unwrap();
+    let saves = tempdir().unwrap();
+    let path = saves.path().join("plan.json");
+    mono.save_plan(plan, &path).unwrap();
+    assert!(fixture.mono().load_plan(&path, &mut NoObserver).is_ok());
+
+    let head = fixture.repo().head().unwrap().peel_to_commit().unwrap();
+    fixture.repo().tag_lightweight("versio-prev", head.as_object(), true).unwrap();
+    assert!(fixture.mono().load_plan(&path, &mut NoObserver).is_err());
+  }
+
+  const FRAGMENT: &str = r#"
+name: proj
+id: 1
+tag_prefix: "${VERSIO_TEST_PREFIX:-proj}"
+version:
+  file: "VERSION"
+  pattern: "([0-9]+\\.[0-9]+\\.[0-9]+)"
+"#;
+
+  const INCLUDE_CONFIG: &str = r#"
+include: "frag/.versio-frag.yaml"
+
+sizes:
+  use_angular: true
+  fail: ["*"]
+"#;
+
+  /// Build the plan, then empty the cached copy: a plan that comes back empty was read from the cache.
+  fn build_and_empty_cache(mono: &Mono) {
+    Runtime::new().unwrap().block_on(mono.build_plan()).unwrap();
+    let cache = mono.repo().plan_cache_file().unwrap();
+    let mut cached: serde_json::Value = serde_json::from_slice(&std::fs::read(&cache).unwrap()).unwrap();
+    cached["plan"]["incrs"] = serde_json::json!({});
+    std::fs::write(&cache, serde_json::to_vec(&cached).unwrap()).unwrap();
+  }
+
+  fn include_fixture() -> Fixture {
+    let files = [(".versio.yaml", INCLUDE_CONFIG), ("frag/.versio-frag.yaml", FRAGMENT), ("frag/VERSION", "1.0.0\n")];
+    let fixture = Fixture::new(&files);
+    fixture.write("frag/new.txt", "new\n");
+    fixture.commit("feat: add new");
+    fixture
+  }
+
+  #[test]
+  fn test_plan_cache_reused() {
+    let fixture = include_fixture();
+    build_and_empty_cache(&fixture.mono());
+
+    let plan = Runtime::new().unwrap().block_on(fixture.mono().build_plan()).unwrap();
+    assert!(plan.incrs().is_empty());
+  }
+
+  #[test]
+  fn test_plan_cache_follows_includes() {
+    let fixture = include_fixture();
+    build_and_empty_cache(&fixture.mono());
+
+    // The fragment changes, but the root config and the commits don't.
+    fixture.write("frag/.versio-frag.yaml", &FRAGMENT.replace("name: proj", "name: renamed"));
+    let mono = Mono::open(".", VcsState::new(VcsLevel::Local, true)).unwrap();
+    let plan = Runtime::new().unwrap().block_on(mono.build_plan()).unwrap();
+    assert_eq!(plan.incrs()[&ProjectId::from_id(1)].0, Size::Minor);
+  }
+
+  #[test]
+  fn test_plan_cache_follows_env() {
+    let fixture = include_fixture();
+    let key = fixture.mono().plan_key().unwrap();
+    build_and_empty_cache(&fixture.mono());
+
+    std::env::set_var("VERSIO_TEST_PREFIX", "other");
+    let mono = fixture.mono();
+    std::env::remove_var("VERSIO_TEST_PREFIX");
+    assert!(mono.plan_key().unwrap() != key);
+    let plan = Runtime::new().unwrap().block_on(mono.build_plan()).unwrap();
+    assert_eq!(plan.incrs()[&ProjectId::from_id(1)].0, Size::Minor);
+
+    assert!(fixture.mono().plan_key().unwrap() == key);
+  }
+
+  #[test]
+  fn test_affected_since() {
+    let fixture = split_fixture();
+    let since = fixture.repo().head().unwrap().target().unwrap().to_string();
+    assert!(fixture.mono().affected_since(&since).unwrap().is_empty());
+
+    fixture.write("b/one.txt", "one\n");
+    fixture.commit("fix: change b");
+    fixture.write("other.txt", "other\n");
+    fixture.commit("fix: change neither");
+    assert_eq!(vec![ProjectId::from_id(2)], fixture.mono().affected_since(&since).unwrap());
+
+    fixture.write("a/one.txt", "one\n");
+    let a_commit = fixture.commit("fix: change a").to_string();
+    assert_eq!(vec![ProjectId::from_id(1), ProjectId::from_id(2)], fixture.mono().affected_since(&since).unwrap());
+    assert!(fixture.mono().affected_since(&a_commit).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_upstream_same_name() {
+    let patterns = vec!["main".to_string(), "release/*".to_string()];
+    assert!(verify_upstream("main", "main", &patterns).is_ok());
+    assert!(verify_upstream("release/2", "release/2", &patterns).is_ok());
+    assert!(verify_upstream("feature", "feature", &[]).is_ok());
+  }
+
+  #[test]
+  fn test_upstream_different_name() {
+    let err = verify_upstream("main", "feature", &["main".to_string()]).unwrap_err();
+    assert_eq!("Branch \"main\" tracks \"feature\", but a release would push to \"main\" instead.", err.to_string());
+  }
+
+  #[test]
+  fn test_upstream_unmatched() {
+    let patterns = vec!["main".to_string(), "release/*".to_string()];
+    let err = verify_upstream("feature", "feature", &patterns).unwrap_err();
+    assert_eq!(
+      "Upstream branch \"feature\" doesn't match \"main, release/*\": not pushing a release there.",
+      err.to_string()
+    );
+  }
+}
@@ -0,0 +1,106 @@
+//! Announcing a release to webhooks, such as Slack or Teams incoming webhooks.
+
+use crate::cancel::{Cancel, Phase};
+use crate::config::{Notify, NotifyFormat, ProjectId};
+use crate::errors::{Error, Result, ResultExt as _};
+use crate::mono::{Changelog, ChangelogEntry};
+use crate::template::read_template;
+use crate::vcs::is_offline;
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use liquid::ParserBuilder;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// A project whose version was changed by a release.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Released {
+  id: ProjectId,
+  name: String,
+  old: Option<String>,
+  new: String,
+  changes: Vec<String>
+}
+
+impl Released {
+  pub fn new(id: ProjectId, name: String, old: Option<String>, new: String, changelog: &Changelog) -> Released {
+    let changes = changelog
+      .entries()
+      .iter()
+      .filter_map(|e| match e {
+        ChangelogEntry::Pr(pr, _) => Some(pr.title().to_string()),
+        ChangelogEntry::Dep(..) => None
+      })
+      .collect();
+    Released { id, name, old, new, changes }
+  }
+
+  pub fn id(&self) -> &ProjectId { &self.id }
+  pub fn name(&self) -> &str { &self.name }
+  pub fn old(&self) -> Option<&str> { self.old.as_deref() }
+  pub fn new_version(&self) -> &str { &self.new }
+}
+
+/// Post the released projects to each webhook. A failed webhook only causes a warning, since the release itself is
+/// already complete.
+pub async fn notify_all(notifies: &[Notify], released: &[Released], cancel: &Cancel) {
+  if released.is_empty() {
+    return;
+  }
+  if is_offline() && !notifies.is_empty() {
+    warn!("Offline: not notifying {} webhook(s).", notifies.len());
+    return;
+  }
+  for notify in notifies {
+    if let Err(e) = notify_one(notify, released, cancel).await {
+      warn!("Unable to notify webhook: {}", e);
+    }
+  }
+}
+
+async fn notify_one(notify: &Notify, released: &[Released], cancel: &Cancel) -> Result<()> {
+  let url = notify.url()?;
+  let body = match notify.template() {
+    Some(template) => {
+      let tmpl = ParserBuilder::with_stdlib().build()?.parse(&read_template(template, None, true).await?)?;
+      tmpl.render(&liquid::to_object(&json!({ "projects": released }))?)?
+    }
+    None => payload(notify.format(), released).to_string()
+  };
+
+  let request = Request::post(&url)
+    .header("content-type", "application/json")
+    .body(Body::from(body))
+    .map_err(|e| bad!("Bad webhook request: {}", e))?;
+
+  let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+  let resp = cancel
+    .guard(Phase::Api, async { client.request(request).await.chain_err(|| Error::network("the notify webhook")) })
+    .await?;
+  if !resp.status().is_success() {
+    bail!("Unsuccessful webhook request: {}", resp.status().as_u16());
+  }
+  Ok(())
+}
+
+fn payload(format: NotifyFormat, released: &[Released]) -> serde_json::Value {
+  match format {
+    NotifyFormat::Generic => json!({ "projects": released }),
+    NotifyFormat::Slack | NotifyFormat::Teams => json!({ "text": summary(released) })
+  }
+}
+
+fn summary(released: &[Released]) -> String {
+  let mut text = String::from("Released:");
+  for proj in released {
+    match &proj.old {
+      Some(old) => text.push_str(&format!("\n- {} {} -> {}", proj.name, old, proj.new)),
+      None => text.push_str(&format!("\n- {} {}", proj.name, proj.new))
+    }
+    for change in &proj.changes {
+      text.push_str(&format!("\n    - {}", change));
+    }
+  }
+  text
+}
@@ -0,0 +1,29 @@
+//! Callbacks for tools that embed Versio, so that they can follow the progress of a release.
+
+use crate::config::ProjectId;
+use crate::mono::Plan;
+
+/// Receives the events of a release as they happen, from the `Mono` methods that take an observer. Every method does
+/// nothing by default, so implementors only need to write the ones they care about.
+pub trait Observer {
+  /// The plan is ready, before any version is changed.
+  fn on_plan_built(&mut self, _plan: &Plan) {}
+
+  /// A project's version was changed from `prev` (its version at the previous release) to `targ`. `curt` is the
+  /// version that was in the working tree before the change, which is usually the same as `prev`.
+  fn on_project_bumped(&mut self, _id: &ProjectId, _name: &str, _prev: &str, _curt: &str, _targ: &str) {}
+
+  /// A tag was created or moved in the local repository.
+  fn on_tag_created(&mut self, _tag: &str) {}
+
+  /// A release branch was created in the local repository.
+  fn on_branch_created(&mut self, _branch: &str) {}
+
+  /// The release commit and tags were pushed to the remote.
+  fn on_push(&mut self) {}
+}
+
+/// An observer that ignores every event.
+pub struct NoObserver;
+
+impl Observer for NoObserver {}
@@ -2,15 +2,39 @@
 
 use crate::analyze::Analysis;
 use crate::commands::{failed_hashes, InfoShow};
-use crate::config::{Project, ProjectId, Size};
+use crate::config::{ConfigFile, Project, ProjectId};
 use crate::errors::{Result, ResultExt};
-use crate::github::Changes;
+use crate::forge::Changes;
 use crate::mono::ChangelogEntry;
-use crate::mono::{Mono, Plan};
-use crate::state::StateRead;
-use crate::template::{construct_changelog_html, read_template};
+use crate::mono::{Mono, Plan, ReleaseNote};
+use crate::observe::Observer;
+use crate::sizes::Size;
+use crate::state::{PrevTagMessage, StateRead};
+use crate::template::{construct_changelog_html, read_template, ReleaseKind};
+use log::LevelFilter;
+use serde::Serialize;
 use serde_json::json;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Print an informational line, unless output is quiet.
+macro_rules! chatter {
+  ($($arg:tt)*) => {
+    if !QUIET.load(Ordering::SeqCst) {
+      println!($($arg)*);
+    }
+  };
+}
+
+/// Leave out informational output and log messages, so that only a command's primary result is printed to stdout.
+/// Warnings and errors are still written to stderr.
+pub fn set_quiet() {
+  QUIET.store(true, Ordering::SeqCst);
+  log::set_max_level(LevelFilter::Error);
+}
 
 pub struct Output {}
 
@@ -20,31 +44,160 @@ impl Default for Output {
 
 impl Output {
   pub fn new() -> Output { Output {} }
-  pub fn check(&self) -> CheckOutput { CheckOutput::new() }
+  pub fn check(&self, format: Option<&str>) -> CheckOutput { CheckOutput::new(format) }
+  pub fn tests(&self, suite: &'static str, format: &str) -> TestOutput { TestOutput::new(suite, format) }
   pub fn projects(&self, wide: bool, vers_only: bool) -> ProjOutput { ProjOutput::new(wide, vers_only) }
   pub fn info(&self, show: InfoShow) -> ProjOutput { ProjOutput::info(show) }
   pub fn diff(&self) -> DiffOutput { DiffOutput::new() }
   pub fn files(&self) -> FilesOutput { FilesOutput::new() }
+  pub fn affected(&self) -> AffectedOutput { AffectedOutput::new() }
+  pub fn owners(&self, json: bool) -> OwnersOutput { OwnersOutput::new(json) }
   pub fn changes(&self) -> ChangesOutput { ChangesOutput::new() }
   pub fn plan(&self) -> PlanOutput { PlanOutput::new() }
+  pub fn compare(&self, ref_a: String, ref_b: String) -> CompareOutput { CompareOutput::new(ref_a, ref_b) }
   pub fn release(&self) -> ReleaseOutput { ReleaseOutput::new() }
   pub fn resume(&self) -> ResumeOutput { ResumeOutput::new() }
+  pub fn revert(&self) -> RevertOutput { RevertOutput::new() }
+  pub fn prev(&self, tag: String) -> PrevOutput { PrevOutput::new(tag) }
+  pub fn status(&self) -> StatusOutput { StatusOutput::new() }
+  pub fn history(&self) -> HistoryOutput { HistoryOutput::new() }
+  pub fn why(&self) -> WhyOutput { WhyOutput::new() }
 }
 
-pub struct CheckOutput {}
+pub struct CheckOutput {
+  warnings: Vec<String>,
+  tests: Option<TestOutput>
+}
 
 impl Default for CheckOutput {
-  fn default() -> CheckOutput { CheckOutput::new() }
+  fn default() -> CheckOutput { CheckOutput::new(None) }
 }
 
 impl CheckOutput {
-  pub fn new() -> CheckOutput { CheckOutput {} }
+  pub fn new(format: Option<&str>) -> CheckOutput {
+    CheckOutput { warnings: Vec::new(), tests: format.map(|f| TestOutput::new("check", f)) }
+  }
+
   pub fn write_done(&mut self) -> Result<()> { Ok(()) }
 
-  pub fn commit(&mut self) -> Result<()> {
-    println!("Check complete.");
+  /// Record the check of a single project. Without a test format, a failed project fails the whole check.
+  pub fn write_project(&mut self, name: &str, result: Result<Vec<String>>) -> Result<()> {
+    match &mut self.tests {
+      Some(tests) => tests.write_case(name, result),
+      None => self.warnings.extend(result?)
+    }
+    Ok(())
+  }
+
+  pub fn commit(&mut self, deny_warnings: bool) -> Result<()> {
+    if let Some(tests) = &self.tests {
+      return tests.commit(deny_warnings);
+    }
+
+    for warning in &self.warnings {
+      eprintln!("Warning: {}", warning);
+    }
+    if deny_warnings && !self.warnings.is_empty() {
+      return err!("Check failed: {} warning(s) denied.", self.warnings.len());
+    }
+    chatter!("Check complete.");
+    Ok(())
+  }
+}
+
+/// Results written as test cases for CI test reporters, in the `tap` or `junit` format. A case fails if it has an
+/// error, or if it has warnings that are denied.
+pub struct TestOutput {
+  suite: &'static str,
+  format: String,
+  cases: Vec<TestCase>
+}
+
+struct TestCase {
+  name: String,
+  error: Option<String>,
+  warnings: Vec<String>
+}
+
+impl TestCase {
+  fn failure(&self, deny_warnings: bool) -> Option<String> {
+    match &self.error {
+      Some(error) => Some(error.clone()),
+      None if deny_warnings && !self.warnings.is_empty() => Some(format!("{} warning(s) denied.", self.warnings.len())),
+      None => None
+    }
+  }
+}
+
+impl TestOutput {
+  pub fn new(suite: &'static str, format: &str) -> TestOutput {
+    TestOutput { suite, format: format.to_string(), cases: Vec::new() }
+  }
+
+  pub fn write_case(&mut self, name: &str, result: Result<Vec<String>>) {
+    let (error, warnings) = match result {
+      Ok(warnings) => (None, warnings),
+      Err(e) => (Some(e.to_string()), Vec::new())
+    };
+    self.cases.push(TestCase { name: name.to_string(), error, warnings });
+  }
+
+  pub fn commit(&self, deny_warnings: bool) -> Result<()> {
+    match self.format.as_str() {
+      "tap" => print!("{}", self.tap(deny_warnings)),
+      "junit" => print!("{}", self.junit(deny_warnings)),
+      other => return err!("Unknown test format \"{}\".", other)
+    }
+
+    let failed = self.cases.iter().filter(|c| c.failure(deny_warnings).is_some()).count();
+    if failed > 0 {
+      return err!("Failed {} of {} {} case(s).", failed, self.cases.len(), self.suite);
+    }
     Ok(())
   }
+
+  fn tap(&self, deny_warnings: bool) -> String {
+    let mut out = format!("TAP version 13\n1..{}\n", self.cases.len());
+    for (i, case) in self.cases.iter().enumerate() {
+      let failure = case.failure(deny_warnings);
+      let status = if failure.is_some() { "not ok" } else { "ok" };
+      out.push_str(&format!("{} {} - {}\n", status, i + 1, case.name.replace('#', "\\#")));
+      for line in failure.iter().chain(&case.warnings).flat_map(|m| m.lines()) {
+        out.push_str(&format!("# {}\n", line));
+      }
+    }
+    out
+  }
+
+  fn junit(&self, deny_warnings: bool) -> String {
+    let failed = self.cases.iter().filter(|c| c.failure(deny_warnings).is_some()).count();
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    out.push_str(&format!(
+      "  <testsuite name=\"versio {}\" tests=\"{}\" failures=\"{}\">\n",
+      self.suite,
+      self.cases.len(),
+      failed
+    ));
+    for case in &self.cases {
+      let name = xml_escape(&case.name);
+      out.push_str(&format!("    <testcase name=\"{}\" classname=\"versio.{}\">\n", name, self.suite));
+      if let Some(failure) = case.failure(deny_warnings) {
+        let failure = xml_escape(&failure);
+        out.push_str(&format!("      <failure message=\"{}\">{}</failure>\n", failure, failure));
+      }
+      if !case.warnings.is_empty() {
+        let warnings: Vec<_> = case.warnings.iter().map(|w| format!("Warning: {}", xml_escape(w))).collect();
+        out.push_str(&format!("      <system-out>{}</system-out>\n", warnings.join("\n")));
+      }
+      out.push_str("    </testcase>\n");
+    }
+    out.push_str("  </testsuite>\n</testsuites>\n");
+    out
+  }
+}
+
+fn xml_escape(v: &str) -> String {
+  v.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
 }
 
 pub struct ResumeOutput {}
@@ -53,16 +206,292 @@ impl Default for ResumeOutput {
   fn default() -> ResumeOutput { ResumeOutput::new() }
 }
 
+impl Observer for ResumeOutput {}
+
 impl ResumeOutput {
   pub fn new() -> ResumeOutput { ResumeOutput {} }
   pub fn write_done(&mut self) -> Result<()> { Ok(()) }
 
   pub fn commit(&mut self) -> Result<()> {
-    println!("Release complete.");
+    chatter!("Release complete.");
     Ok(())
   }
 }
 
+pub struct RevertOutput {
+  reverted: Option<String>,
+  deleted: Vec<String>,
+  restored: Option<(String, String)>
+}
+
+impl Default for RevertOutput {
+  fn default() -> RevertOutput { RevertOutput::new() }
+}
+
+impl RevertOutput {
+  pub fn new() -> RevertOutput { RevertOutput { reverted: None, deleted: Vec::new(), restored: None } }
+  pub fn write_reverted(&mut self, oid: String) { self.reverted = Some(oid); }
+  pub fn write_deleted(&mut self, tag: String) { self.deleted.push(tag); }
+  pub fn write_restored(&mut self, tag: String, oid: String) { self.restored = Some((tag, oid)); }
+
+  pub fn commit(&mut self) -> Result<()> {
+    match &self.reverted {
+      Some(oid) => println!("Reverted release commit {}.", &oid[.. 7]),
+      None => println!("No release commit to revert.")
+    }
+    for tag in &self.deleted {
+      println!("  Deleted tag {}", tag);
+    }
+    if let Some((tag, oid)) = &self.restored {
+      println!("Restored {} to {}.", tag, &oid[.. 7]);
+    }
+    Ok(())
+  }
+}
+
+pub struct PrevOutput {
+  tag: String,
+  local: Option<(String, bool)>,
+  remote: Option<Option<String>>,
+  moved: Option<Option<String>>,
+  versio: Option<String>,
+  plan: Option<String>,
+  versions: Vec<(String, String)>,
+  held: Vec<(String, Size)>
+}
+
+impl PrevOutput {
+  pub fn new(tag: String) -> PrevOutput {
+    PrevOutput {
+      tag,
+      local: None,
+      remote: None,
+      moved: None,
+      versio: None,
+      plan: None,
+      versions: Vec::new(),
+      held: Vec::new()
+    }
+  }
+
+  pub fn write_local(&mut self, oid: String, in_history: bool) { self.local = Some((oid, in_history)); }
+  pub fn write_remote(&mut self, oid: Option<String>) { self.remote = Some(oid); }
+  pub fn write_moved(&mut self, from: Option<String>) { self.moved = Some(from); }
+
+  pub fn write_message(&mut self, file: &ConfigFile, message: &PrevTagMessage) {
+    let name = |id: &ProjectId| file.get_project(id).map(|p| p.name().to_string()).unwrap_or_else(|| id.to_string());
+    self.versio = message.versio().map(|v| v.to_string());
+    self.plan = message.plan().map(|p| p.to_string());
+    self.versions = message.versions().iter().map(|(id, v)| (name(id), v.clone())).collect();
+    self.versions.sort();
+    self.held = message.held().iter().map(|(id, (size, _))| (name(id), *size)).collect();
+    self.held.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
+  }
+
+  pub fn commit(&mut self) -> Result<()> {
+    let (oid, in_history) = match &self.local {
+      Some(local) => local,
+      None => {
+        println!("No prev tag \"{}\": use \"versio prev set <ref>\" to create it.", self.tag);
+        return Ok(());
+      }
+    };
+
+    match &self.moved {
+      Some(Some(from)) if from == oid => println!("Prev tag \"{}\" is already at {}.", self.tag, &oid[.. 7]),
+      Some(Some(from)) => println!("Moved prev tag \"{}\" from {} to {}.", self.tag, &from[.. 7], &oid[.. 7]),
+      Some(None) => println!("Created prev tag \"{}\" at {}.", self.tag, &oid[.. 7]),
+      None => println!("Prev tag \"{}\" is at {}.", self.tag, &oid[.. 7])
+    }
+    if !in_history {
+      eprintln!("Warning: {} isn't in the history of HEAD.", &oid[.. 7]);
+    }
+    match &self.remote {
+      Some(Some(remote)) if remote != oid => eprintln!("Warning: the remote tag is at {}.", &remote[.. 7]),
+      Some(None) => eprintln!("Warning: the tag isn't on the remote."),
+      _ => ()
+    }
+    if let Some(versio) = &self.versio {
+      println!("  Written by versio {}", versio);
+    }
+    if let Some(plan) = &self.plan {
+      println!("  Plan hash {}", plan);
+    }
+    for (name, vers) in &self.versions {
+      println!("  {} : {}", name, vers);
+    }
+    for (name, size) in &self.held {
+      println!("  {} : held {}", name, size);
+    }
+    Ok(())
+  }
+}
+
+pub struct StatusOutput {
+  lines: Vec<StatusLine>
+}
+
+struct StatusLine {
+  name: String,
+  curt: String,
+  prev: Option<String>,
+  size: Size,
+  logged: bool
+}
+
+impl Default for StatusOutput {
+  fn default() -> StatusOutput { StatusOutput::new() }
+}
+
+impl StatusOutput {
+  pub fn new() -> StatusOutput { StatusOutput { lines: Vec::new() } }
+
+  pub fn write_status(&mut self, name: String, curt: String, prev: Option<String>, size: Size, logged: bool) {
+    self.lines.push(StatusLine { name, curt, prev, size, logged });
+  }
+
+  pub fn commit(&mut self) -> Result<()> {
+    if self.lines.is_empty() {
+      println!("(No projects)");
+      return Ok(());
+    }
+
+    let name_width = self.lines.iter().map(|l| l.name.len()).max().unwrap_or(0);
+    let curt_width = self.lines.iter().map(|l| l.curt.len()).max().unwrap_or(0);
+    let prev_width = self.lines.iter().map(|l| l.prev.as_ref().map(|p| p.len()).unwrap_or(1)).max().unwrap_or(0);
+
+    for line in &self.lines {
+      let prev = line.prev.as_deref().unwrap_or("-");
+      let logged = if line.logged { " (changelog)" } else { "" };
+      println!(
+        "{:nw$} : {:cw$} (prev {:pw$}) : {}{}",
+        line.name,
+        line.curt,
+        prev,
+        line.size,
+        logged,
+        nw = name_width,
+        cw = curt_width,
+        pw = prev_width
+      );
+    }
+    Ok(())
+  }
+}
+
+pub struct HistoryOutput {
+  notes: Vec<(String, ReleaseNote<Plan>)>
+}
+
+impl Default for HistoryOutput {
+  fn default() -> HistoryOutput { HistoryOutput::new() }
+}
+
+impl HistoryOutput {
+  pub fn new() -> HistoryOutput { HistoryOutput { notes: Vec::new() } }
+  pub fn write_notes(&mut self, notes: Vec<(String, ReleaseNote<Plan>)>) { self.notes = notes; }
+
+  pub fn commit(&mut self) -> Result<()> {
+    if self.notes.is_empty() {
+      println!("(No recorded releases)");
+      return Ok(());
+    }
+
+    for (oid, note) in &self.notes {
+      println!("{} {} by {} (versio {})", &oid[.. 7], note.timestamp(), note.operator(), note.versio());
+      let mut incrs: Vec<_> = note.plan().incrs().iter().collect();
+      incrs.sort_by_key(|(id, _)| id.to_string());
+      for (id, (size, _)) in incrs {
+        println!("  {} : {}", id, size);
+      }
+    }
+    Ok(())
+  }
+}
+
+pub struct WhyOutput {
+  plan: Option<Plan>,
+  id: Option<ProjectId>
+}
+
+impl Default for WhyOutput {
+  fn default() -> WhyOutput { WhyOutput::new() }
+}
+
+impl WhyOutput {
+  pub fn new() -> WhyOutput { WhyOutput { plan: None, id: None } }
+
+  pub fn write_why(&mut self, plan: Plan, id: ProjectId) {
+    self.plan = Some(plan);
+    self.id = Some(id);
+  }
+
+  pub fn commit(&mut self, mono: &Mono) -> Result<()> {
+    match (&self.plan, &self.id) {
+      (Some(plan), Some(id)) => {
+        let mut seen = HashSet::new();
+        println_why(plan, mono, id, 0, &mut seen)
+      }
+      _ => {
+        println!("No plan.");
+        Ok(())
+      }
+    }
+  }
+}
+
+/// Print the reasons for a project's planned size, recursing into any dependencies that cascaded their own
+/// size into it.
+fn println_why(plan: &Plan, mono: &Mono, id: &ProjectId, depth: usize, seen: &mut HashSet<ProjectId>) -> Result<()> {
+  let indent = "  ".repeat(depth);
+  let proj = mono.get_project(id)?;
+  let (size, changelog) = match plan.incrs().get(id) {
+    Some((size, changelog)) => (*size, Some(changelog)),
+    None => (Size::Empty, None)
+  };
+
+  println!("{}{} : {}", indent, proj.name(), size);
+  if !seen.insert(id.clone()) {
+    println!("{}  (already explained)", indent);
+    return Ok(());
+  }
+
+  let entries = changelog.map(|c| c.entries()).unwrap_or(&[]);
+  if entries.is_empty() {
+    println!("{}  No changes cover this project.", indent);
+  }
+
+  for entry in entries {
+    match entry {
+      ChangelogEntry::Pr(pr, size) => {
+        if !pr.commits().iter().any(|c| c.applies()) {
+          continue;
+        }
+        if pr.number() == 0 {
+          println!("{}  Other commits : {}", indent, size);
+        } else {
+          println!("{}  PR {} : {} : {}", indent, pr.number(), size, pr.title());
+        }
+        for c in pr.commits().iter().filter(|c| c.applies()) {
+          let dup = if c.duplicate() { " (duplicate)" } else { "" };
+          println!("{}    commit {} ({}){} : {}", indent, &c.oid()[.. 7], c.size(), dup, c.summary().trim());
+          for file in c.files() {
+            println!("{}      {}", indent, file);
+          }
+        }
+      }
+      ChangelogEntry::Dep(dep_id, dep_name) => {
+        let dep_size = plan.incrs().get(dep_id).map(|(s, _)| *s).unwrap_or(Size::Empty);
+        let converted = proj.depends().get(dep_id).map(|d| d.size().convert(dep_size)).unwrap_or(Size::Empty);
+        println!("{}  Depends on {} ({}) : {} -> {}", indent, dep_name, dep_id, dep_size, converted);
+        println_why(plan, mono, dep_id, depth + 2, seen)?;
+      }
+    }
+  }
+
+  Ok(())
+}
+
 pub struct ProjOutput {
   wide: bool,
   vers_only: bool,
@@ -136,6 +565,7 @@ impl ProjOutput {
   }
 }
 
+#[derive(Serialize)]
 pub struct ProjLine {
   pub id: ProjectId,
   pub name: String,
@@ -168,7 +598,8 @@ impl ProjLine {
 }
 
 pub struct DiffOutput {
-  analysis: Option<Analysis>
+  analysis: Option<Analysis>,
+  retire: bool
 }
 
 impl Default for DiffOutput {
@@ -176,26 +607,32 @@ impl Default for DiffOutput {
 }
 
 impl DiffOutput {
-  pub fn new() -> DiffOutput { DiffOutput { analysis: None } }
+  pub fn new() -> DiffOutput { DiffOutput { analysis: None, retire: false } }
 
-  pub fn write_analysis(&mut self, analysis: Analysis) -> Result<()> {
+  /// Write the analysis; if `retire` is set, removed projects are noted as retired by the next release.
+  pub fn write_analysis(&mut self, analysis: Analysis, retire: bool) -> Result<()> {
     self.analysis = Some(analysis);
+    self.retire = retire;
     Ok(())
   }
 
   pub fn commit(&mut self) -> Result<()> {
     if let Some(analysis) = &self.analysis {
-      println_analysis(analysis);
+      println_analysis(analysis, self.retire);
     }
     Ok(())
   }
 }
 
-fn println_analysis(analysis: &Analysis) {
+fn println_analysis(analysis: &Analysis, retire: bool) {
   if !analysis.older().is_empty() {
     println!("Removed projects:");
     for mark in analysis.older() {
-      println!("  {} : {}", mark.name(), mark.mark());
+      if retire {
+        println!("  {} : {} (retired by the next release)", mark.name(), mark.mark());
+      } else {
+        println!("  {} : {}", mark.name(), mark.mark());
+      }
     }
   }
 
@@ -261,6 +698,68 @@ impl FilesOutput {
   }
 }
 
+pub struct AffectedOutput {
+  projects: Vec<(ProjectId, String)>
+}
+
+impl Default for AffectedOutput {
+  fn default() -> AffectedOutput { AffectedOutput::new() }
+}
+
+impl AffectedOutput {
+  pub fn new() -> AffectedOutput { AffectedOutput { projects: Vec::new() } }
+
+  pub fn write_projects(&mut self, projects: impl Iterator<Item = Result<(ProjectId, String)>>) -> Result<()> {
+    self.projects = projects.collect::<Result<_>>()?;
+    Ok(())
+  }
+
+  pub fn commit(&mut self) -> Result<()> {
+    let val = json!(self.projects.iter().map(|(id, name)| json!({ "id": id, "name": name })).collect::<Vec<_>>());
+    println!("{}", serde_json::to_string(&val)?);
+    Ok(())
+  }
+}
+
+pub struct OwnersOutput {
+  json: bool,
+  lines: Vec<String>,
+  projects: Vec<serde_json::Value>
+}
+
+impl OwnersOutput {
+  pub fn new(json: bool) -> OwnersOutput { OwnersOutput { json, lines: Vec::new(), projects: Vec::new() } }
+
+  pub fn write_codeowners(&mut self, lines: Vec<String>) { self.lines = lines; }
+
+  pub fn write_projects<'a>(&mut self, projects: impl Iterator<Item = &'a Project>) {
+    self.projects = projects
+      .map(|p| {
+        let (includes, excludes) = p.rooted_coverage();
+        json!({
+          "id": p.id(),
+          "name": p.name(),
+          "priority": p.priority(),
+          "includes": includes,
+          "excludes": excludes,
+          "owners": p.owners()
+        })
+      })
+      .collect();
+  }
+
+  pub fn commit(&mut self) -> Result<()> {
+    if self.json {
+      println!("{}", serde_json::to_string(&self.projects)?);
+    } else {
+      for line in &self.lines {
+        println!("{}", line);
+      }
+    }
+    Ok(())
+  }
+}
+
 pub struct ChangesOutput {
   changes: Option<Changes>
 }
@@ -292,8 +791,8 @@ fn println_changes(changes: &Changes) {
     let head_oid = g.head_oid().as_ref().map(|o| o.to_string()).unwrap_or_else(|| "<not found>".to_string());
     println!("  {}: {} ({} -> {})", g.number(), g.head_ref(), g.base_oid(), head_oid);
     println!("    commits:");
-    for cmt in g.commits() {
-      println!("      {}", cmt.id());
+    for cmt in g.commit_ids() {
+      println!("      {}", cmt);
     }
     println!("    excludes:");
     for cmt in g.excludes() {
@@ -307,6 +806,90 @@ fn println_changes(changes: &Changes) {
   }
 }
 
+pub struct CompareOutput {
+  ref_a: String,
+  ref_b: String,
+  diffs: Vec<ProjectDiff>
+}
+
+struct ProjectDiff {
+  name: String,
+  size_a: Size,
+  size_b: Size,
+  removed: Vec<String>,
+  added: Vec<String>
+}
+
+impl CompareOutput {
+  pub fn new(ref_a: String, ref_b: String) -> CompareOutput { CompareOutput { ref_a, ref_b, diffs: Vec::new() } }
+
+  /// Find the projects whose size or changelog differ between the plans. Changelogs are compared by their PRs and
+  /// commit summaries, so that rebased commits still match.
+  pub fn write_compare(&mut self, file: &ConfigFile, plan_a: &Plan, plan_b: &Plan, id: Option<&ProjectId>) {
+    for proj in file.projects().iter().filter(|p| id.map(|id| p.id() == id).unwrap_or(true)) {
+      let (size_a, lines_a) = plan_lines(plan_a, proj.id());
+      let (size_b, lines_b) = plan_lines(plan_b, proj.id());
+      if size_a == size_b && lines_a == lines_b {
+        continue;
+      }
+
+      let removed = lines_a.difference(&lines_b).cloned().collect();
+      let added = lines_b.difference(&lines_a).cloned().collect();
+      self.diffs.push(ProjectDiff { name: proj.name().to_string(), size_a, size_b, removed, added });
+    }
+  }
+
+  pub fn commit(&mut self) -> Result<()> {
+    if self.diffs.is_empty() {
+      println!("No plan differences between {} and {}.", self.ref_a, self.ref_b);
+      return Ok(());
+    }
+
+    println!("Plan differences from {} to {}:", self.ref_a, self.ref_b);
+    for diff in &self.diffs {
+      if diff.size_a == diff.size_b {
+        println!("  {} : {} (changelog differs)", diff.name, diff.size_a);
+      } else {
+        println!("  {} : {} -> {}", diff.name, diff.size_a, diff.size_b);
+      }
+      for line in &diff.removed {
+        println!("    - {}", line);
+      }
+      for line in &diff.added {
+        println!("    + {}", line);
+      }
+    }
+    Ok(())
+  }
+}
+
+/// The size of a project in a plan, and a line for each entry of its changelog.
+fn plan_lines(plan: &Plan, id: &ProjectId) -> (Size, BTreeSet<String>) {
+  let (size, changelog) = match plan.incrs().get(id) {
+    Some((size, changelog)) => (*size, changelog),
+    None => return (Size::Empty, BTreeSet::new())
+  };
+
+  let mut lines = BTreeSet::new();
+  for entry in changelog.entries() {
+    match entry {
+      ChangelogEntry::Pr(pr, _) => {
+        for commit in pr.commits().iter().filter(|c| c.included()) {
+          if pr.number() == 0 {
+            lines.insert(format!("{} ({})", commit.summary(), commit.size()));
+          } else {
+            lines.insert(format!("PR {}: {} ({})", pr.number(), commit.summary(), commit.size()));
+          }
+        }
+      }
+      ChangelogEntry::Dep(_, name) => {
+        lines.insert(format!("dependency {}", name));
+      }
+    }
+  }
+  (size, lines)
+}
+
 pub struct PlanOutput {
   plan: Option<Plan>,
   id: Option<ProjectId>,
@@ -366,6 +949,9 @@ impl PlanOutput {
 
       let curt_proj = mono.get_project(id).unwrap();
       println!("{} : {}", curt_proj.name(), size);
+      if let Some(orig) = plan.info().clamped().get(id) {
+        println!("  ! Clamped from {} to max_size {}", orig, size);
+      }
 
       let curt_config = mono.config();
       let prev_config = curt_config.slice_to_prev(mono.repo())?;
@@ -380,12 +966,12 @@ impl PlanOutput {
           println!("  ! Non-parseable conventional commits: {}", failed_hashes(plan));
         } else if size != &Size::Empty {
           let target = size.apply(&prev_vers)?;
-          if Size::less_than(&curt_vers, &target)? {
-            if curt_proj.verify_restrictions(&target).is_err() {
-              println!("  ! Illegal size change for restricted project {}.", curt_proj.id());
+          let vers = if Size::less_than(&curt_vers, &target)? { &target } else { &curt_vers };
+          if let Err(e) = mono.verify_restrictions(id, vers, Some(&prev_vers), changelog) {
+            println!("  ! {}", e);
+            for e in e.iter().skip(1) {
+              println!("    {}", e);
             }
-          } else if curt_proj.verify_restrictions(&curt_vers).is_err() {
-            println!("  ! Illegal size change for restricted project {}.", curt_proj.id());
           }
         }
       }
@@ -423,25 +1009,24 @@ impl PlanOutput {
     Ok(())
   }
 
+  /// Show the PRs whose changes no project covers, which usually means a gap in the config.
   fn println_plan_ineff(&self, plan: &Plan) {
+    if self.id.is_some() || self.template.is_some() || plan.ineffective().is_empty() {
+      return;
+    }
+
+    println!("Ineffective (not covered by any project):");
     for pr in plan.ineffective() {
-      if !pr.commits().iter().any(|c| c.included()) {
-        continue;
-      }
       if pr.number() == 0 {
         println!("  Unapplied commits");
       } else {
-        println!("  Unapplied PR {}", pr.number());
+        println!("  Unapplied PR {} : {}", pr.number(), pr.title());
       }
-      for c in pr.commits().iter().filter(|c| c.included()) {
-        let symbol = if c.duplicate() {
-          "."
-        } else if c.applies() {
-          "*"
-        } else {
-          " "
-        };
-        println!("    {} commit {} ({}) : {}", symbol, &c.oid()[.. 7], c.size(), c.message());
+      for c in pr.commits() {
+        println!("    commit {} ({}) : {}", &c.oid()[.. 7], c.size(), c.summary());
+        for file in c.files() {
+          println!("      {}", file);
+        }
       }
     }
   }
@@ -466,9 +1051,11 @@ impl PlanOutput {
         .unwrap_or_else(|| panic!("No such project {}.", id));
 
       let proj = curt_config.get_project(id).ok_or_else(|| bad!("No such project ID {}", id))?;
+      let dates = proj.changelog_dates();
       let proj = ProjLine::from_version(proj, curt_vers.clone())?;
 
-      let html = construct_changelog_html(changelog, proj, &curt_vers, "".to_string(), template)?;
+      let kind = ReleaseKind::Version(&curt_vers);
+      let html = construct_changelog_html(changelog, proj, kind, "".to_string(), template, &HashMap::new(), &dates)?;
       println!("{}", html);
       break;
     }
@@ -478,7 +1065,8 @@ impl PlanOutput {
 }
 
 pub struct ReleaseOutput {
-  result: ReleaseResult
+  result: ReleaseResult,
+  tags: Vec<String>
 }
 
 impl Default for ReleaseOutput {
@@ -486,7 +1074,10 @@ impl Default for ReleaseOutput {
 }
 
 impl ReleaseOutput {
-  pub fn new() -> ReleaseOutput { ReleaseOutput { result: ReleaseResult::Empty } }
+  pub fn new() -> ReleaseOutput { ReleaseOutput { result: ReleaseResult::Empty, tags: Vec::new() } }
+
+  /// The tags that the release created or moved.
+  pub fn tags(&self) -> &[String] { &self.tags }
 
   pub fn write_empty(&mut self) -> Result<()> {
     self.result = ReleaseResult::Empty;
@@ -498,10 +1089,13 @@ impl ReleaseOutput {
   pub fn write_commit(&mut self) { self.result.append_commit(); }
   pub fn write_pause(&mut self) { self.result.append_pause(); }
   pub fn write_dry(&mut self) { self.result.append_dry(); }
+  pub fn write_off_branch(&mut self, branch: String, pre: bool) { self.result.append_off_branch(branch, pre); }
   pub fn write_wrote_changelogs(&mut self) { self.result.append_wrote_channgelogs(); }
 
-  pub fn write_changed(&mut self, name: String, prev: String, curt: String, targ: String) {
-    self.result.append_changed(name, prev, curt, targ);
+  pub fn write_images(&mut self, images: Vec<(String, String)>) {
+    for (image, tag) in images {
+      self.result.append_image(image, tag);
+    }
   }
 
   pub fn write_forward(&mut self, all: bool, name: String, prev: String, curt: String, targ: String) {
@@ -513,10 +1107,28 @@ impl ReleaseOutput {
   }
 
   pub fn write_new(&mut self, all: bool, name: String, curt: String) { self.result.append_new(all, name, curt); }
+  pub fn write_held(&mut self, name: String, curt: String, size: Size) { self.result.append_held(name, curt, size); }
+
+  pub fn write_clamped(&mut self, name: String, orig: Size, size: Size) {
+    self.result.append_clamped(name, orig, size);
+  }
+
+  pub fn write_retired(&mut self, name: String, vers: String, tag: Option<String>) {
+    self.result.append_retired(name, vers, tag);
+  }
 
   pub fn commit(&mut self) { self.result.commit(); }
 }
 
+impl Observer for ReleaseOutput {
+  fn on_project_bumped(&mut self, _id: &ProjectId, name: &str, prev: &str, curt: &str, targ: &str) {
+    self.result.append_changed(name.to_string(), prev.to_string(), curt.to_string(), targ.to_string());
+  }
+
+  fn on_tag_created(&mut self, tag: &str) { self.tags.push(tag.to_string()); }
+  fn on_branch_created(&mut self, branch: &str) { self.result.append_branch(branch.to_string()); }
+}
+
 enum ReleaseResult {
   Empty,
   Wrote(WroteReleases)
@@ -528,7 +1140,10 @@ impl ReleaseResult {
   fn append_commit(&mut self) { self.append(ReleaseEvent::Commit); }
   fn append_pause(&mut self) { self.append(ReleaseEvent::Pause); }
   fn append_dry(&mut self) { self.append(ReleaseEvent::Dry); }
+  fn append_off_branch(&mut self, branch: String, pre: bool) { self.append(ReleaseEvent::OffBranch(branch, pre)); }
   fn append_wrote_channgelogs(&mut self) { self.append(ReleaseEvent::WroteChangelogs); }
+  fn append_image(&mut self, image: String, tag: String) { self.append(ReleaseEvent::Image(image, tag)); }
+  fn append_branch(&mut self, branch: String) { self.append(ReleaseEvent::Branch(branch)); }
 
   fn append_changed(&mut self, name: String, prev: String, curt: String, targ: String) {
     self.append(ReleaseEvent::Changed(name, prev, curt, targ));
@@ -544,6 +1159,18 @@ impl ReleaseResult {
 
   fn append_new(&mut self, all: bool, name: String, curt: String) { self.append(ReleaseEvent::New(all, name, curt)); }
 
+  fn append_held(&mut self, name: String, curt: String, size: Size) {
+    self.append(ReleaseEvent::Held(name, curt, size));
+  }
+
+  fn append_clamped(&mut self, name: String, orig: Size, size: Size) {
+    self.append(ReleaseEvent::Clamped(name, orig, size));
+  }
+
+  fn append_retired(&mut self, name: String, vers: String, tag: Option<String>) {
+    self.append(ReleaseEvent::Retired(name, vers, tag));
+  }
+
   fn append(&mut self, ev: ReleaseEvent) {
     match self {
       ReleaseResult::Empty => {
@@ -586,9 +1213,15 @@ enum ReleaseEvent {
   Forward(bool, String, String, String, String),
   NoChange(bool, bool, String, Option<String>, String),
   New(bool, String, String),
+  Held(String, String, Size),
+  Clamped(String, Size, Size),
+  Retired(String, String, Option<String>),
+  Image(String, String),
+  Branch(String),
   Commit,
   Pause,
   Dry,
+  OffBranch(String, bool),
   WroteChangelogs,
   Done
 }
@@ -596,12 +1229,16 @@ enum ReleaseEvent {
 impl ReleaseEvent {
   fn commit(&mut self) {
     match self {
-      ReleaseEvent::Logged(p) => println!("Wrote changelog at {}.", p.to_string_lossy()),
-      ReleaseEvent::Done => println!("Release complete."),
-      ReleaseEvent::Commit => println!("Changes committed."),
+      ReleaseEvent::Logged(p) => chatter!("Wrote changelog at {}.", p.to_string_lossy()),
+      ReleaseEvent::Image(image, tag) => println!("  image {}:{}", image, tag),
+      ReleaseEvent::Branch(branch) => println!("  branch {}", branch),
+      ReleaseEvent::Done => chatter!("Release complete."),
+      ReleaseEvent::Commit => chatter!("Changes committed."),
       ReleaseEvent::Pause => println!("Paused for commit: use --resume to continue."),
-      ReleaseEvent::Dry => println!("Dry run: no actual changes."),
-      ReleaseEvent::WroteChangelogs => println!("Changelogs only: only changelogs written."),
+      ReleaseEvent::Dry => eprintln!("Dry run: no actual changes."),
+      ReleaseEvent::OffBranch(branch, true) => eprintln!("Off branch \"{}\": releasing prerelease versions.", branch),
+      ReleaseEvent::OffBranch(branch, false) => eprintln!("Off branch \"{}\": forcing a dry run.", branch),
+      ReleaseEvent::WroteChangelogs => chatter!("Changelogs only: only changelogs written."),
       ReleaseEvent::Changed(name, prev, curt, targ) => {
         if prev == curt {
           println!("  {} : {} -> {}", name, prev, targ);
@@ -639,6 +1276,14 @@ impl ReleaseEvent {
           println!("  {} : no change: {} is new", name, curt);
         }
       }
+      ReleaseEvent::Clamped(name, orig, size) => {
+        println!("  {} : clamped from {} to max_size {}", name, orig, size)
+      }
+      ReleaseEvent::Retired(name, vers, Some(tag)) => println!("  {} : retired at {} (tagged {})", name, vers, tag),
+      ReleaseEvent::Retired(name, vers, None) => println!("  {} : retired at {}", name, vers),
+      ReleaseEvent::Held(name, curt, size) => {
+        println!("  {} : held at {}: {} changes are below min_size", name, curt, size)
+      }
     }
   }
 }
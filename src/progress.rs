@@ -0,0 +1,36 @@
+//! Progress spinners for the slower phases of a run, so that a long scan doesn't look like a hang.
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Show progress spinners on stderr. They're still hidden if stderr isn't a terminal.
+pub fn enable_progress() { ENABLED.store(true, Ordering::SeqCst) }
+
+/// A spinner that counts the items handled in a phase, and clears itself when dropped.
+pub struct Progress {
+  bar: ProgressBar
+}
+
+impl Progress {
+  pub fn start(msg: &'static str) -> Progress {
+    let target = ProgressDrawTarget::stderr();
+    if !ENABLED.load(Ordering::SeqCst) || target.is_hidden() {
+      return Progress { bar: ProgressBar::hidden() };
+    }
+
+    let bar = ProgressBar::new_spinner();
+    bar.set_draw_target(target);
+    bar.set_style(ProgressStyle::default_spinner().template("{spinner} {msg}: {pos} ({elapsed})"));
+    bar.set_message(msg);
+    bar.enable_steady_tick(100);
+    Progress { bar }
+  }
+
+  pub fn inc(&self) { self.bar.inc(1); }
+}
+
+impl Drop for Progress {
+  fn drop(&mut self) { self.bar.finish_and_clear(); }
+}
@@ -0,0 +1,72 @@
+//! Publishing released projects to their package registries.
+
+use crate::errors::{Result, ResultExt as _};
+use crate::vcs::is_offline;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+/// The registry that a project is published to after it's tagged.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Publish {
+  Cargo,
+  Npm
+}
+
+impl Publish {
+  /// Publish the project at `root`. A version that the registry already has is skipped with a warning, so that a
+  /// resumed or repeated release doesn't fail.
+  pub fn execute(&self, root: &Option<&String>, dry_run: bool) -> Result<()> {
+    if is_offline() {
+      warn!("Offline: not publishing {}.", root.map(|r| r.as_str()).unwrap_or("."));
+      return Ok(());
+    }
+    match self {
+      Publish::Cargo => publish_cargo(root, dry_run),
+      Publish::Npm => publish_npm(root, dry_run)
+    }
+  }
+}
+
+fn publish_cargo(root: &Option<&String>, dry_run: bool) -> Result<()> {
+  let dir = root.map(|r| r.as_str()).unwrap_or(".");
+
+  // Running in the project's root publishes just that package, even if it's a member of a larger workspace.
+  let mut command = Command::new("cargo");
+  command.current_dir(dir).arg("publish");
+  if dry_run {
+    command.arg("--dry-run");
+  }
+  run_publish(command, "cargo", dir, &["already uploaded", "already exists"])
+}
+
+fn publish_npm(root: &Option<&String>, dry_run: bool) -> Result<()> {
+  let dir = root.map(|r| r.as_str()).unwrap_or(".");
+
+  // Workspace packages are published from the top, so that the workspace's own `.npmrc` and scripts apply.
+  let mut command = Command::new("npm");
+  command.arg("publish");
+  if let Some(root) = root {
+    command.args(&["--workspace", root]);
+  }
+  if dry_run {
+    command.arg("--dry-run");
+  }
+  run_publish(command, "npm", dir, &["EPUBLISHCONFLICT", "cannot publish over the previously published"])
+}
+
+fn run_publish(mut command: Command, tool: &str, dir: &str, already: &[&str]) -> Result<()> {
+  info!("Running {} publish for {}.", tool, dir);
+  let output = command.output().chain_err(|| format!("Unable to run {} publish for {}.", tool, dir))?;
+  if output.status.success() {
+    return Ok(());
+  }
+
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  if already.iter().any(|a| stderr.contains(a)) {
+    warn!("Package in {} is already published at this version: skipping.", dir);
+    return Ok(());
+  }
+  bail!("Unable to {} publish {}: {}", tool, dir, stderr.trim())
+}
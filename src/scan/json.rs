@@ -193,6 +193,7 @@ impl<'a> std::io::Read for MeteredReader<'a> {
 #[cfg(test)]
 mod test {
   use super::JsonScanner;
+  use crate::mark::NamedData;
   use crate::scan::Scanner;
 
   #[test]
@@ -271,4 +272,11 @@ mod test {
     assert_eq!("1.2.3", mark.value());
     assert_eq!(39, mark.start());
   }
+
+  #[test]
+  fn test_json_utf8_rewrite() {
+    let doc = r#"{ "nåme": "Bób", "version": "1.2.3" }"#;
+    let marked = JsonScanner::new("version").scan(NamedData::new("package.json".into(), doc.into())).unwrap();
+    assert_eq!(r#"{ "nåme": "Bób", "version": "2.0.0" }"#, marked.into_new_value("2.0.0"));
+  }
 }
@@ -13,8 +13,16 @@ use crate::mark::{Mark, MarkedData, NamedData};
 use crate::scan::parts::Part;
 use regex::Regex;
 
+/// The names of the built-in scanners, which can't be registered.
+const BUILTIN: &[&str] = &["json", "yaml", "toml", "xml"];
+
+/// Builds a custom scanner from the parts given in a version location.
+pub type ScannerFactory = fn(Vec<Part>) -> Box<dyn Scanner + Send + Sync>;
+
 pub trait Scanner {
-  fn build(parts: Vec<Part>) -> Self;
+  fn build(parts: Vec<Part>) -> Self
+  where
+    Self: Sized;
 
   fn find(&self, data: &str) -> Result<Mark>;
 
@@ -26,7 +34,36 @@ pub trait Scanner {
 
   fn scan(&self, data: NamedData) -> Result<MarkedData> {
     let mark = self.find(data.data())?;
-    Ok(data.mark(mark))
+    data.mark(mark)
+  }
+}
+
+/// Custom scanners, by name. These are given to a `Mono` when it's opened, and are used by the version locations of
+/// its configs.
+#[derive(Clone, Default)]
+pub struct Scanners {
+  custom: Vec<(String, ScannerFactory)>
+}
+
+impl Scanners {
+  pub fn new() -> Scanners { Default::default() }
+
+  /// Register a custom scanner. A version location can then use the scanner's name as a key, just like `json` or
+  /// `toml`: for example, `version: { file: "setup.cfg", ini: "metadata.version" }`.
+  pub fn register(&mut self, name: &str, factory: ScannerFactory) -> Result<()> {
+    if BUILTIN.contains(&name) {
+      bail!("Can't register built-in scanner \"{}\".", name);
+    }
+    if self.get(name).is_some() {
+      bail!("Scanner \"{}\" is already registered.", name);
+    }
+    self.custom.push((name.to_string(), factory));
+    Ok(())
+  }
+
+  /// Find a custom scanner by name.
+  pub fn get(&self, name: &str) -> Option<ScannerFactory> {
+    self.custom.iter().find(|(n, _)| n == name).map(|(_, factory)| *factory)
   }
 }
 
@@ -41,5 +78,5 @@ pub fn find_reg_data(data: &str, pattern: &str) -> Result<Mark> {
 
 pub fn scan_reg_data(data: NamedData, pattern: &str) -> Result<MarkedData> {
   let mark = find_reg_data(data.data(), pattern)?;
-  Ok(data.mark(mark))
+  data.mark(mark)
 }
@@ -131,6 +131,7 @@ impl<'de> DeserializeSeed<'de> for NthElement {
 #[cfg(test)]
 mod test {
   use super::TomlScanner;
+  use crate::mark::NamedData;
   use crate::scan::Scanner;
 
   #[test]
@@ -184,4 +185,11 @@ thing = [ "thing2", "1.2.3" ]"#;
     assert_eq!("1.2.3", mark.value());
     assert_eq!(26, mark.start());
   }
+
+  #[test]
+  fn test_toml_utf8_rewrite() {
+    let doc = "\"nåme\" = \"Bób\"\nversion = \"1.2.3\"\n";
+    let marked = TomlScanner::new("version").scan(NamedData::new("Cargo.toml".into(), doc.into())).unwrap();
+    assert_eq!("\"nåme\" = \"Bób\"\nversion = \"2.0.0\"\n", marked.into_new_value("2.0.0"));
+  }
 }
@@ -6,7 +6,6 @@ use crate::mark::Mark;
 use crate::scan::parts::ToPart;
 use crate::scan::parts::{is_match_str, IntoPartVec, Part};
 use crate::scan::Scanner;
-use error_chain::bail;
 use xmlparser::{ElementEnd, Token, Tokenizer};
 
 pub struct XmlScanner {
@@ -35,12 +34,27 @@ fn scan_xml<P: IntoPartVec>(data: &str, loc: P) -> Result<Mark> {
 
   let mut extra_depth = 0;
   let mut on_target = false;
+  let mut skipped = 0;
 
   for token in Tokenizer::from(data) {
     match token? {
       Token::ElementStart { local, .. } => {
         if extra_depth == 0 && is_match_str(local.as_str(), parts.last()) {
+          // A number after an element name picks that (zero-based) occurrence of the element.
+          let nth = match parts.len().checked_sub(2).map(|i| &parts[i]) {
+            Some(Part::Seq(n)) => Some(*n),
+            _ => None
+          };
+          if nth.map(|n| skipped < n).unwrap_or(false) {
+            skipped += 1;
+            extra_depth += 1;
+            continue;
+          }
           parts.pop();
+          if nth.is_some() {
+            parts.pop();
+          }
+          skipped = 0;
           if parts.is_empty() {
             on_target = true;
           }
@@ -72,6 +86,7 @@ fn is_ending(end: &ElementEnd) -> bool { matches!(end, ElementEnd::Close(..) | E
 #[cfg(test)]
 mod test {
   use super::XmlScanner;
+  use crate::mark::NamedData;
   use crate::scan::Scanner;
 
   #[test]
@@ -110,6 +125,20 @@ mod test {
     assert_eq!(21, mark.start());
   }
 
+  #[test]
+  fn test_xml_nth() {
+    let doc = r#"
+<deps>
+  <dep><id>a</id></dep>
+  <dep/>
+  <dep><id>c</id></dep>
+</deps>"#;
+
+    let mark = XmlScanner::new("deps.dep.2.id").find(doc).unwrap();
+    assert_eq!("c", mark.value());
+    assert!(XmlScanner::new("deps.dep.3.id").find(doc).is_err());
+  }
+
   #[test]
   fn test_xml_utf8() {
     let doc = r#"
@@ -119,4 +148,11 @@ mod test {
     assert_eq!("1.2.3", mark.value());
     assert_eq!(20, mark.start());
   }
+
+  #[test]
+  fn test_xml_utf8_rewrite() {
+    let doc = "<project><name>Bób</name><version>1.2.3</version></project>";
+    let marked = XmlScanner::new("project.version").scan(NamedData::new("pom.xml".into(), doc.into())).unwrap();
+    assert_eq!("<project><name>Bób</name><version>2.0.0</version></project>", marked.into_new_value("2.0.0"));
+  }
 }
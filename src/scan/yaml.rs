@@ -14,7 +14,6 @@ pub struct YamlScanner {
 }
 
 impl YamlScanner {
-  #[cfg(test)]
   pub fn new(target: &str) -> YamlScanner { YamlScanner { target: target.into_part_vec() } }
 
   #[cfg(test)]
@@ -203,6 +202,7 @@ enum Expect {
 #[cfg(test)]
 mod test {
   use super::{scan_yaml, YamlScanner};
+  use crate::mark::NamedData;
   use crate::scan::Scanner;
 
   #[test]
@@ -271,4 +271,11 @@ package:
     assert_eq!("0.0.6", mark.value());
     assert_eq!(31, mark.start());
   }
+
+  #[test]
+  fn test_yaml_utf8_rewrite() {
+    let doc = "nåme: \"Bób\"\nversion: 1.2.3\n";
+    let marked = YamlScanner::new("version").scan(NamedData::new("chart.yaml".into(), doc.into())).unwrap();
+    assert_eq!("nåme: \"Bób\"\nversion: 2.0.0\n", marked.into_new_value("2.0.0"));
+  }
 }
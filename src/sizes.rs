@@ -0,0 +1,353 @@
+//! The sizes of changes, and how they're found from conventional commit messages.
+//!
+//! Nothing here reads a repository or the network, so it builds without the `cli` feature (and so to wasm32): for
+//! example, to size a list of pasted commit messages in a browser.
+
+use crate::errors::{Result, ResultExt as _};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ord, Ordering};
+use std::collections::HashMap;
+use std::fmt;
+
+/// The "size" of the commit is a measure of "how much" to increment a project's version number based on the
+/// significance of its changes. There are currently six sizes from smallest to largest:
+///
+/// - **Empty**: The project was untouched, so the version will not change.
+/// - **None**: Non-altering / cosmetic changes were made. The new version of the project is operationally
+/// identical to the old version, or close enough to make no difference. The version number will not change.
+/// - **Patch**: Bugs were fixed and/or slightly-more-than-cosmetic changes were made; the new version of the
+/// project is fully backwards-compatible with the old, and probably operationally similar. The "patch" part of
+/// the version number will increment.
+/// - **Minor**: New features were added and/or other significant changes were made; the new version of the
+/// project is backwards-compatible with the old, but possibly expanded or operationally dissimilar. The "minor"
+/// part of the version number will be incremented, and the "patch" part will be reset.
+/// - **Major**: Breaking changes were made: anything from pruning APIs to a full restructuring of the code; the
+/// new version of the project is incompatible with the the old version, and can't be expected to act as a
+/// drop-in replacement. The "major" part of the version number will be incremented, and other parts reset.
+/// - **Fail**: A change occured to the project that could not be understood. No changes will be made to any
+/// version numbers; in fact, the entire process is prematurely halted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Size {
+  Fail,
+  Major,
+  Minor,
+  Patch,
+  None,
+  Empty
+}
+
+impl Size {
+  pub fn is_size(v: &str) -> bool { Size::from_str(v).is_ok() }
+  pub fn is_failure(&self) -> bool { matches!(self, Size::Fail) }
+
+  pub(crate) fn from_str(v: &str) -> Result<Size> {
+    match v {
+      "major" => Ok(Size::Major),
+      "minor" => Ok(Size::Minor),
+      "patch" => Ok(Size::Patch),
+      "none" => Ok(Size::None),
+      "empty" => Ok(Size::Empty),
+      "fail" => Ok(Size::Fail),
+      other => err!("Unknown size: {}", other)
+    }
+  }
+
+  pub fn parts(v: &str) -> Result<[u32; 3]> {
+    // Any prerelease or build suffix is ignored.
+    let core = v.split(|c| c == '-' || c == '+').next().unwrap_or(v);
+    let parts: Vec<_> = core
+      .split('.')
+      .map(|p| p.parse())
+      .collect::<std::result::Result<_, _>>()
+      .chain_err(|| format!("Couldn't split {} into parts", v))?;
+    if parts.len() != 3 {
+      return err!("Not a 3-part version: {}", v);
+    }
+    Ok([parts[0], parts[1], parts[2]])
+  }
+
+  pub fn less_than(v1: &str, v2: &str) -> Result<bool> {
+    let p1 = Size::parts(v1)?;
+    let p2 = Size::parts(v2)?;
+
+    Ok(p1[0] < p2[0] || (p1[0] == p2[0] && (p1[1] < p2[1] || (p1[1] == p2[1] && p1[2] < p2[2]))))
+  }
+
+  pub fn apply(self, v: &str) -> Result<String> {
+    let parts = Size::parts(v)?;
+
+    let newv = match self {
+      Size::Major => format!("{}.{}.{}", parts[0] + 1, 0, 0),
+      Size::Minor => format!("{}.{}.{}", parts[0], parts[1] + 1, 0),
+      Size::Patch => format!("{}.{}.{}", parts[0], parts[1], parts[2] + 1),
+      Size::None => format!("{}.{}.{}", parts[0], parts[1], parts[2]),
+      Size::Empty => format!("{}.{}.{}", parts[0], parts[1], parts[2]),
+      Size::Fail => bail!("Unparseable conventional commit encountered.")
+    };
+
+    Ok(newv)
+  }
+}
+
+impl fmt::Display for Size {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Size::Major => write!(f, "major"),
+      Size::Minor => write!(f, "minor"),
+      Size::Patch => write!(f, "patch"),
+      Size::None => write!(f, "none"),
+      Size::Fail => write!(f, "fail"),
+      Size::Empty => write!(f, "empty")
+    }
+  }
+}
+
+impl PartialOrd for Size {
+  fn partial_cmp(&self, other: &Size) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for Size {
+  fn cmp(&self, other: &Size) -> Ordering {
+    match self {
+      Size::Fail => match other {
+        Size::Fail => Ordering::Equal,
+        _ => Ordering::Greater
+      },
+      Size::Major => match other {
+        Size::Fail => Ordering::Less,
+        Size::Major => Ordering::Equal,
+        _ => Ordering::Greater
+      },
+      Size::Minor => match other {
+        Size::Major | Size::Fail => Ordering::Less,
+        Size::Minor => Ordering::Equal,
+        _ => Ordering::Greater
+      },
+      Size::Patch => match other {
+        Size::None | Size::Empty => Ordering::Greater,
+        Size::Patch => Ordering::Equal,
+        _ => Ordering::Less
+      },
+      Size::None => match other {
+        Size::Empty => Ordering::Greater,
+        Size::None => Ordering::Equal,
+        _ => Ordering::Less
+      },
+      Size::Empty => match other {
+        Size::Empty => Ordering::Equal,
+        _ => Ordering::Less
+      }
+    }
+  }
+}
+
+/// Finds a conventional commit "type" from a commit message.
+///
+/// The type can be one of the special characters "-" (no type found) or "!" ("BREAKING CHANGE:" or
+/// "BREAKING-CHANGE:" starting footer, or "!" after type/scope)
+pub fn extract_kind(message: &str) -> String {
+  let breaking_pattern =
+    Regex::new("^(?s).*?\\n\\n((BREAKING CHANGE|BREAKING-CHANGE):|.*\n(BREAKING CHANGE|BREAKING-CHANGE):)").unwrap();
+  if breaking_pattern.is_match(message) {
+    return "!".into();
+  }
+
+  match message.char_indices().find(|(_, c)| *c == ':' || *c == '\n') {
+    Some((i, c)) if c == ':' => {
+      let kind = &message[.. i].trim();
+      if kind.ends_with('!') {
+        return "!".into();
+      }
+      match kind.char_indices().find(|(_, c)| *c == '(').map(|(i, _)| i) {
+        Some(i) => {
+          let kind = &kind[0 .. i].trim();
+          if kind.ends_with('!') {
+            "!".into()
+          } else {
+            (*kind).to_lowercase()
+          }
+        }
+        None => (*kind).to_lowercase()
+      }
+    }
+    _ => "-".to_string()
+  }
+}
+
+/// A commit message that has been sized.
+#[derive(Clone, Debug, Serialize)]
+pub struct SizedMessage {
+  summary: String,
+  kind: String,
+  size: Size
+}
+
+impl SizedMessage {
+  pub fn summary(&self) -> &str { &self.summary }
+  pub fn kind(&self) -> &str { &self.kind }
+  pub fn size(&self) -> Size { self.size }
+}
+
+/// The plan for a list of commit messages, without a repository: the size of each message, and the size of the
+/// release that they make up together. The messages that change anything are its changelog.
+#[derive(Clone, Debug, Serialize)]
+pub struct MessagePlan {
+  messages: Vec<SizedMessage>,
+  size: Size
+}
+
+impl MessagePlan {
+  /// Size each message by its conventional commit type. A type that isn't in `sizes` uses the "*" catch-all, or is
+  /// an error if there is none.
+  pub fn new<'m, I: IntoIterator<Item = &'m str>>(sizes: &HashMap<String, Size>, messages: I) -> Result<MessagePlan> {
+    let messages = messages
+      .into_iter()
+      .map(|message| {
+        let message = message.trim();
+        let kind = extract_kind(message);
+        let size = sizes.get(&kind).or_else(|| sizes.get("*")).copied();
+        let size = size.ok_or_else(|| bad!("Unknown kind \"{}\".", kind))?;
+        let summary = message.lines().next().unwrap_or("").to_string();
+        Ok(SizedMessage { summary, kind, size })
+      })
+      .collect::<Result<Vec<_>>>()?;
+    let size = messages.iter().map(|m| m.size).max().unwrap_or(Size::Empty);
+    Ok(MessagePlan { messages, size })
+  }
+
+  pub fn messages(&self) -> &[SizedMessage] { &self.messages }
+  pub fn size(&self) -> Size { self.size }
+
+  /// The messages that change the version.
+  pub fn changelog(&self) -> impl Iterator<Item = &SizedMessage> + '_ {
+    self.messages.iter().filter(|m| m.size > Size::None)
+  }
+
+  /// The version that follows `current` after this plan is released.
+  pub fn next_version(&self, current: &str) -> Result<String> { self.size.apply(current) }
+}
+
+/// The sizes of the [angular](https://github.com/angular/angular/blob/master/CONTRIBUTING.md#type) commit types,
+/// as a config gets with `use_angular: true`.
+pub fn angular_sizes() -> HashMap<String, Size> {
+  let mut sizes = HashMap::new();
+  insert_angular(&mut sizes);
+  sizes
+}
+
+pub(crate) fn insert_angular(result: &mut HashMap<String, Size>) {
+  insert_if_missing(result, "!", Size::Major);
+  insert_if_missing(result, "feat", Size::Minor);
+  insert_if_missing(result, "fix", Size::Patch);
+  insert_if_missing(result, "docs", Size::None);
+  insert_if_missing(result, "style", Size::None);
+  insert_if_missing(result, "refactor", Size::None);
+  insert_if_missing(result, "perf", Size::None);
+  insert_if_missing(result, "test", Size::None);
+  insert_if_missing(result, "chore", Size::None);
+  insert_if_missing(result, "build", Size::None);
+  insert_if_missing(result, "ci", Size::None);
+}
+
+fn insert_if_missing(result: &mut HashMap<String, Size>, key: &str, val: Size) {
+  if !result.contains_key(key) {
+    result.insert(key.to_string(), val);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{angular_sizes, extract_kind, MessagePlan, Size};
+
+  #[test]
+  fn test_kind_simple() {
+    assert_eq!(&extract_kind("thing: this is thing"), "thing");
+  }
+
+  #[test]
+  fn test_kind_bang() {
+    assert_eq!(&extract_kind("thing! : this is thing"), "!");
+  }
+
+  #[test]
+  fn test_kind_paren() {
+    assert_eq!(&extract_kind("thing(scope): this is thing"), "thing");
+  }
+
+  #[test]
+  fn test_kind_complex() {
+    assert_eq!(&extract_kind("thing(scope)!: this is thing"), "!");
+  }
+
+  #[test]
+  fn test_kind_backwards() {
+    assert_eq!(&extract_kind("thing!(scope): this is thing"), "!");
+  }
+
+  #[test]
+  fn test_kind_breaking() {
+    assert_eq!(&extract_kind("thing(scope): this is thing\n\nbody\n\nBREAKING CHANGE: yup"), "!");
+  }
+
+  #[test]
+  fn test_kind_breaking_no_body() {
+    assert_eq!(&extract_kind("thing(scope): this is thing\n\nBREAKING CHANGE: yup"), "!");
+  }
+
+  #[test]
+  fn test_kind_breaking_later() {
+    assert_eq!(&extract_kind("thing(scope): this is thing\n\nbody\n\nfoot: 1\nBREAKING CHANGE: yup"), "!");
+  }
+
+  #[test]
+  fn test_kind_breaking_both() {
+    assert_eq!(&extract_kind("thing(scope)!: this is thing\n\nbody\n\nBREAKING CHANGE: yup"), "!");
+  }
+
+  #[test]
+  fn test_kind_breaking_dash() {
+    assert_eq!(&extract_kind("thing(scope): this is thing\n\nbody\n\nBREAKING-CHANGE: yup"), "!");
+  }
+
+  #[test]
+  fn test_empty() {
+    assert_eq!(&extract_kind(""), "-");
+  }
+
+  #[test]
+  fn test_unconventional() {
+    assert_eq!(&extract_kind("-"), "-");
+  }
+
+  #[test]
+  fn test_uncertain() {
+    assert_eq!(&extract_kind("ENG-123: I forgot to conventinal commit"), "eng-123");
+  }
+
+  #[test]
+  fn test_message_plan() {
+    let mut sizes = angular_sizes();
+    sizes.insert("*".into(), Size::Fail);
+    let msgs = ["docs: spelling", "feat(api): add a thing\n\nwith a body", "fix: oops"];
+    let plan = MessagePlan::new(&sizes, msgs.iter().copied()).unwrap();
+
+    assert_eq!(Size::Minor, plan.size());
+    let changes: Vec<_> = plan.changelog().map(|m| (m.summary(), m.size())).collect();
+    assert_eq!(changes, vec![("feat(api): add a thing", Size::Minor), ("fix: oops", Size::Patch)]);
+    assert_eq!("1.3.0", plan.next_version("1.2.3").unwrap());
+  }
+
+  #[test]
+  fn test_message_plan_fails() {
+    let mut sizes = angular_sizes();
+    let plan = MessagePlan::new(&sizes, vec!["feat: thing", "oops"]);
+    assert!(plan.is_err());
+
+    sizes.insert("*".into(), Size::Fail);
+    let plan = MessagePlan::new(&sizes, vec!["feat: thing", "oops"]).unwrap();
+    assert!(plan.size().is_failure());
+    assert!(plan.next_version("1.2.3").is_err());
+  }
+}
@@ -1,9 +1,15 @@
 //! The mechanisms used to read and write state, both current and historical.
 
-use crate::config::{HookSet, ProjectId};
+use crate::config::{Hook, HookSet, ProjectId};
 use crate::errors::{Result, ResultExt as _};
 use crate::git::{FromTagBuf, Repo, Slice};
 use crate::mark::{NamedData, Picker};
+use crate::mono::Changelog;
+use crate::notify::Released;
+use crate::observe::Observer;
+use crate::publish::Publish;
+use crate::sizes::Size;
+use crate::timing::Timer;
 use log::{trace, warn};
 use path_slash::{PathBufExt as _, PathExt as _};
 use regex::Regex;
@@ -25,6 +31,9 @@ pub trait FilesRead {
   fn has_file(&self, path: &Path) -> Result<bool>;
   fn read_file(&self, path: &Path) -> Result<String>;
   fn subdirs(&self, root: Option<&String>, regex: &str) -> Result<Vec<String>>;
+
+  /// The root of the working tree, if these are the files that are in it now: only then can commands read them.
+  fn working_root(&self) -> Option<&Path>;
 }
 
 impl<F: FilesRead> FilesRead for &F {
@@ -33,6 +42,7 @@ impl<F: FilesRead> FilesRead for &F {
   fn subdirs(&self, root: Option<&String>, regex: &str) -> Result<Vec<String>> {
     <F as FilesRead>::subdirs(*self, root, regex)
   }
+  fn working_root(&self) -> Option<&Path> { <F as FilesRead>::working_root(*self) }
 }
 
 pub struct CurrentState {
@@ -44,6 +54,7 @@ impl FilesRead for CurrentState {
   fn has_file(&self, path: &Path) -> Result<bool> { self.files.has_file(path) }
   fn read_file(&self, path: &Path) -> Result<String> { self.files.read_file(path) }
   fn subdirs(&self, root: Option<&String>, regex: &str) -> Result<Vec<String>> { self.files.subdirs(root, regex) }
+  fn working_root(&self) -> Option<&Path> { self.files.working_root() }
 }
 
 impl StateRead for CurrentState {
@@ -55,6 +66,61 @@ impl CurrentState {
   pub fn old_tags(&self) -> &OldTags { &self.tags }
 }
 
+/// A state held entirely in memory, so that configs and versions can be read and written without a real
+/// filesystem or git repository. Paths are relative to the (imaginary) repository root.
+#[derive(Default)]
+pub struct MemoryState {
+  files: HashMap<PathBuf, String>,
+  tags: HashMap<ProjectId, String>
+}
+
+impl FilesRead for MemoryState {
+  fn has_file(&self, path: &Path) -> Result<bool> { Ok(self.files.contains_key(path)) }
+
+  fn read_file(&self, path: &Path) -> Result<String> {
+    self.files.get(path).cloned().ok_or_else(|| bad!("No file {} in memory.", path.to_slash_lossy()))
+  }
+
+  fn subdirs(&self, root: Option<&String>, regex: &str) -> Result<Vec<String>> {
+    let filter = Regex::new(regex)?;
+    let root = root.filter(|r| r.as_str() != ".").map(PathBuf::from_slash).unwrap_or_default();
+    let mut dirs: Vec<String> = self
+      .files
+      .keys()
+      .filter_map(|path| path.strip_prefix(&root).ok())
+      .filter(|rel| rel.components().count() > 1)
+      .filter_map(|rel| rel.components().next().and_then(|c| c.as_os_str().to_str()).map(|c| c.to_string()))
+      .filter(|n| filter.is_match(n))
+      .collect();
+    dirs.sort();
+    dirs.dedup();
+    Ok(dirs)
+  }
+
+  fn working_root(&self) -> Option<&Path> { None }
+}
+
+impl StateRead for MemoryState {
+  fn latest_tag(&self, proj: &ProjectId) -> Option<&String> { self.tags.get(proj) }
+}
+
+impl MemoryState {
+  pub fn new() -> MemoryState { Default::default() }
+
+  pub fn with_file<P: AsRef<Path>, C: ToString>(mut self, path: P, content: C) -> MemoryState {
+    self.files.insert(path.as_ref().to_path_buf(), content.to_string());
+    self
+  }
+
+  pub fn with_tag<V: ToString>(mut self, proj: ProjectId, vers: V) -> MemoryState {
+    self.tags.insert(proj, vers.to_string());
+    self
+  }
+
+  pub fn files(&self) -> &HashMap<PathBuf, String> { &self.files }
+  pub fn tags(&self) -> &HashMap<ProjectId, String> { &self.tags }
+}
+
 pub struct CurrentFiles {
   root: PathBuf
 }
@@ -73,6 +139,8 @@ impl FilesRead for CurrentFiles {
       .map(|r| r.map_err(|e| e.into()))
       .collect()
   }
+
+  fn working_root(&self) -> Option<&Path> { Some(&self.root) }
 }
 
 impl CurrentFiles {
@@ -88,6 +156,7 @@ impl<'r> FilesRead for PrevState<'r> {
   fn has_file(&self, path: &Path) -> Result<bool> { self.files.has_file(path) }
   fn read_file(&self, path: &Path) -> Result<String> { self.files.read_file(path) }
   fn subdirs(&self, root: Option<&String>, regex: &str) -> Result<Vec<String>> { self.files.subdirs(root, regex) }
+  fn working_root(&self) -> Option<&Path> { None }
 }
 
 impl<'r> StateRead for PrevState<'r> {
@@ -107,6 +176,7 @@ impl<'r> FilesRead for PrevFiles<'r> {
   fn read_file(&self, path: &Path) -> Result<String> { read_from_slice(&self.slice, path) }
 
   fn subdirs(&self, root: Option<&String>, regex: &str) -> Result<Vec<String>> { self.slice.subdirs(root, regex) }
+  fn working_root(&self) -> Option<&Path> { None }
 }
 
 impl<'r> PrevFiles<'r> {
@@ -114,6 +184,7 @@ impl<'r> PrevFiles<'r> {
 
   pub fn new(slice: Slice<'r>) -> PrevFiles { PrevFiles { slice } }
   pub fn slice_to(&self, spec: FromTagBuf) -> Result<PrevFiles<'r>> { PrevFiles::from_slice(self.slice.slice(spec)) }
+  pub fn blob_id(&self, path: &str) -> Result<Option<String>> { self.slice.blob_id(path) }
 }
 
 #[derive(Debug)]
@@ -129,6 +200,7 @@ impl OldTags {
 
   pub fn latest(&self, proj: &ProjectId) -> Option<&String> { self.current.get(proj) }
   pub fn current(&self) -> &HashMap<ProjectId, String> { &self.current }
+  pub fn prev(&self) -> &HashMap<ProjectId, String> { &self.prev }
 
   pub fn slice_to_prev(&self) -> Result<OldTags> { Ok(OldTags::new(self.prev.clone(), HashMap::new())) }
 }
@@ -140,7 +212,78 @@ pub struct StateWrite {
   tag_head: Vec<String>,
   tag_commit: HashMap<String, String>,
   tag_head_or_last: Vec<(String, ProjectId)>,
-  new_tags: HashMap<ProjectId, String>
+  new_tags: HashMap<ProjectId, String>,
+  #[serde(default)]
+  bumps: Vec<BumpHooks>,
+  #[serde(default)]
+  tag_messages: HashMap<String, String>,
+  #[serde(default)]
+  held: HashMap<ProjectId, (Size, Changelog)>,
+  #[serde(default)]
+  plan_hash: Option<String>,
+  #[serde(default)]
+  branch_head_or_last: Vec<(String, ProjectId)>,
+  #[serde(default)]
+  commit_message: Option<String>,
+  #[serde(default)]
+  proj_files: Vec<(ProjectId, PathBuf)>,
+  #[serde(default)]
+  proj_commits: Vec<(ProjectId, String)>,
+  #[serde(default)]
+  alias_head_or_last: Vec<(String, ProjectId)>,
+  #[serde(default)]
+  note: Option<String>,
+  #[serde(default)]
+  released: Vec<Released>
+}
+
+/// The hooks that run after a project's version is bumped, along with the environment that they run in.
+#[derive(Deserialize, Serialize)]
+pub struct BumpHooks {
+  root: Option<String>,
+  vars: Vec<(String, String)>,
+  post_bump: Option<Hook>,
+  post_tag: Option<Hook>,
+  #[serde(default)]
+  publish: Option<Publish>
+}
+
+impl BumpHooks {
+  pub fn new(root: Option<String>, vars: Vec<(String, String)>, hooks: &HookSet) -> BumpHooks {
+    BumpHooks {
+      root,
+      vars,
+      post_bump: hooks.get("post_bump").cloned(),
+      post_tag: hooks.get("post_tag").cloned(),
+      publish: None
+    }
+  }
+
+  pub fn with_publish(mut self, publish: Option<Publish>) -> BumpHooks {
+    self.publish = publish;
+    self
+  }
+
+  fn execute_post_bump(&self) -> Result<()> {
+    match &self.post_bump {
+      Some(hook) => hook.execute(&self.root.as_ref(), &self.vars),
+      None => Ok(())
+    }
+  }
+
+  fn execute_post_tag(&self) -> Result<()> {
+    match &self.post_tag {
+      Some(hook) => hook.execute(&self.root.as_ref(), &self.vars),
+      None => Ok(())
+    }
+  }
+
+  fn execute_publish(&self) -> Result<()> {
+    match &self.publish {
+      Some(publish) => publish.execute(&self.root.as_ref(), false),
+      None => Ok(())
+    }
+  }
 }
 
 impl Default for StateWrite {
@@ -155,32 +298,108 @@ impl StateWrite {
       tag_commit: HashMap::new(),
       tag_head_or_last: Vec::new(),
       proj_writes: HashSet::new(),
-      new_tags: HashMap::new()
+      new_tags: HashMap::new(),
+      bumps: Vec::new(),
+      tag_messages: HashMap::new(),
+      held: HashMap::new(),
+      plan_hash: None,
+      branch_head_or_last: Vec::new(),
+      commit_message: None,
+      proj_files: Vec::new(),
+      proj_commits: Vec::new(),
+      alias_head_or_last: Vec::new(),
+      note: None,
+      released: Vec::new()
+    }
+  }
+
+  pub fn add_bump(&mut self, bump: BumpHooks) { self.bumps.push(bump); }
+
+  /// Hold back the release of a project whose changes are smaller than its `min_size`: its size and changelog are
+  /// kept in the prev tag, to be released along with later changes.
+  pub fn hold(&mut self, proj_id: &ProjectId, size: Size, changelog: Changelog) {
+    self.held.insert(proj_id.clone(), (size, changelog));
+  }
+
+  /// Record the hash of the plan being released, to be kept in the prev tag.
+  pub fn set_plan_hash(&mut self, hash: String) { self.plan_hash = Some(hash); }
+
+  /// Use this message for the release commit, in place of the default.
+  pub fn set_commit_message(&mut self, msg: String) { self.commit_message = Some(msg); }
+
+  /// Attach this note to the release commit, once the release is pushed.
+  pub fn set_note(&mut self, note: String) { self.note = Some(note); }
+
+  /// Remember the projects that this release changes, so that they can be announced even if the release is resumed.
+  pub fn set_released(&mut self, released: Vec<Released>) { self.released = released; }
+
+  /// Give the project its own commit with this message, holding just the files written for it. Projects are
+  /// committed in the order that they're given here, before the combined commit of any remaining files.
+  pub fn commit_project(&mut self, proj_id: &ProjectId, msg: String) {
+    if !self.proj_commits.iter().any(|(id, _)| id == proj_id) {
+      self.proj_commits.push((proj_id.clone(), msg));
     }
   }
 
   pub fn write_file<C: ToString>(
     &mut self, file: PathBuf, content: C, proj_id: &ProjectId, changelog: bool
   ) -> Result<()> {
+    self.proj_files.push((proj_id.clone(), file.clone()));
     self.writes.push(FileWrite::Write { path: file, val: content.to_string(), changelog });
     self.proj_writes.insert(proj_id.clone());
     Ok(())
   }
 
+  /// Run a command that writes the project's new version, in place of writing a file.
+  pub fn run_command(&mut self, hook: Hook, root: Option<String>, proj_id: &ProjectId) -> Result<()> {
+    self.writes.push(FileWrite::Run { hook, root });
+    self.proj_writes.insert(proj_id.clone());
+    Ok(())
+  }
+
   pub fn update_mark<C: ToString>(&mut self, pick: PickPath, content: C, proj_id: &ProjectId) -> Result<()> {
+    self.proj_files.push((proj_id.clone(), pick.file.clone()));
     self.writes.push(FileWrite::Update { pick, val: content.to_string() });
     self.proj_writes.insert(proj_id.clone());
     Ok(())
   }
 
-  pub fn tag_head_or_last<T: ToString>(&mut self, vers: &str, tag: T, proj: &ProjectId) -> Result<()> {
+  pub fn tag_commit<T: ToString>(&mut self, tag: T, oid: &str) {
+    self.tag_commit.insert(tag.to_string(), oid.to_string());
+  }
+
+  pub fn tag_head_or_last<T: ToString>(
+    &mut self, vers: &str, tag: T, msg: Option<String>, proj: &ProjectId
+  ) -> Result<()> {
     let tag = tag.to_string();
     trace!("head_or_last on {} tagged with {}.", proj, tag);
+    if let Some(msg) = msg {
+      self.tag_messages.insert(tag.clone(), msg);
+    }
     self.tag_head_or_last.push((tag, proj.clone()));
     self.new_tags.insert(proj.clone(), vers.to_string());
     Ok(())
   }
 
+  /// Move an alias tag to the same commit as the project's tag. Alias tags are expected to move on every release,
+  /// so they're moved even if the remote has them somewhere unexpected.
+  pub fn alias_head_or_last<T: ToString>(&mut self, tag: T, proj: &ProjectId) {
+    let tag = tag.to_string();
+    trace!("head_or_last on {} aliased as {}.", proj, tag);
+    if !self.alias_head_or_last.iter().any(|(t, _)| t == &tag) {
+      self.alias_head_or_last.push((tag, proj.clone()));
+    }
+  }
+
+  /// Create a release branch at the same commit as the project's tag, unless the branch already exists.
+  pub fn branch_head_or_last<B: ToString>(&mut self, branch: B, proj: &ProjectId) {
+    let branch = branch.to_string();
+    trace!("head_or_last on {} branched as {}.", proj, branch);
+    if !self.branch_head_or_last.iter().any(|(b, _)| b == &branch) {
+      self.branch_head_or_last.push((branch, proj.clone()));
+    }
+  }
+
   pub fn write_changelogs(&mut self) -> Result<()> {
     // TODO(later): we're probably not going to do anything else after this, but should we remove the changelogs
     // from `self.writes`, just in case?
@@ -190,30 +409,56 @@ impl StateWrite {
     Ok(())
   }
 
-  pub fn commit(&mut self, repo: &Repo, data: CommitArgs) -> Result<()> {
-    for write in &self.writes {
-      write.write()?;
+  /// Apply the file writes and new versions to an in-memory state, instead of to the filesystem and repository.
+  ///
+  /// There's no in-memory repository, so this only applies what a `StateRead` can see: the new file contents, and
+  /// the latest version of each tagged project. Everything else that `commit` does in the repository is dropped:
+  /// commits and their messages, version and alias tags, branches, the prev tag and its held changes, and all hooks
+  /// and publishing.
+  pub fn commit_to_memory(&mut self, state: &mut MemoryState) -> Result<()> {
+    let me = take(self);
+    for write in me.writes {
+      write.write_to_memory(state)?;
+    }
+    for (proj_id, vers) in me.new_tags {
+      state.tags.insert(proj_id, vers);
     }
+    Ok(())
+  }
+
+  pub fn commit(&mut self, repo: &Repo, data: CommitArgs, observer: &mut dyn Observer) -> Result<()> {
+    let _timer = Timer::start("commit/push");
+    let journal = if data.pause { None } else { repo.journal_file() };
     let did_write = !self.writes.is_empty();
-    self.writes.clear();
 
-    for proj_id in &self.proj_writes {
+    let me = take(self);
+    let prev_tag = data.prev_tag.to_string();
+    let last_commits = data.last_commits.clone();
+    let old_tags = data.old_tags.clone();
+    let mut commit_state =
+      CommitState::new(me, did_write, prev_tag, last_commits, old_tags, data.advance_prev, data.last_prev);
+    commit_state.orig_head = repo.head_commit_oid();
+    commit_state.force_tags = data.force_tags;
+
+    // Journal everything before touching any files, so that an interrupted release can be resumed or aborted.
+    commit_state.checkpoint(journal.as_deref())?;
+    commit_state.write_files(journal.as_deref())?;
+
+    for proj_id in &commit_state.write.proj_writes {
       if let Some((root, hooks)) = data.hooks.get(proj_id) {
         hooks.execute_post_write(root)?;
       }
     }
 
-    let me = take(self);
-    let prev_tag = data.prev_tag.to_string();
-    let last_commits = data.last_commits.clone();
-    let old_tags = data.old_tags.clone();
-    let mut commit_state = CommitState::new(me, did_write, prev_tag, last_commits, old_tags, data.advance_prev);
+    for bump in &commit_state.write.bumps {
+      bump.execute_post_bump()?;
+    }
 
     if data.pause {
       let file = OpenOptions::new().create(true).write(true).truncate(true).open(".versio-paused")?;
       Ok(serde_json::to_writer(file, &commit_state)?)
     } else {
-      commit_state.resume(repo)
+      commit_state.resume(repo, observer, journal.as_deref())
     }
   }
 }
@@ -224,15 +469,19 @@ pub struct CommitArgs<'a> {
   old_tags: &'a HashMap<ProjectId, String>,
   advance_prev: bool,
   hooks: &'a HashMap<ProjectId, (Option<&'a String>, &'a HookSet)>,
-  pause: bool
+  pause: bool,
+  last_prev: Option<LastPrev>,
+  force_tags: bool
 }
 
 impl<'a> CommitArgs<'a> {
+  #[allow(clippy::too_many_arguments)]
   pub fn new(
     prev_tag: &'a str, last_commits: &'a HashMap<ProjectId, String>, old_tags: &'a HashMap<ProjectId, String>,
-    advance_prev: bool, hooks: &'a HashMap<ProjectId, (Option<&'a String>, &'a HookSet)>, pause: bool
+    advance_prev: bool, hooks: &'a HashMap<ProjectId, (Option<&'a String>, &'a HookSet)>, pause: bool,
+    last_prev: Option<LastPrev>, force_tags: bool
   ) -> CommitArgs<'a> {
-    CommitArgs { prev_tag, last_commits, old_tags, advance_prev, hooks, pause }
+    CommitArgs { prev_tag, last_commits, old_tags, advance_prev, hooks, pause, last_prev, force_tags }
   }
 }
 
@@ -252,83 +501,387 @@ pub struct CommitState {
   prev_tag: String,
   last_commits: HashMap<ProjectId, String>,
   old_tags: HashMap<ProjectId, String>,
-  advance_prev: bool
+  advance_prev: bool,
+  #[serde(default)]
+  last_prev: Option<LastPrev>,
+  #[serde(default)]
+  orig_head: Option<String>,
+  #[serde(default)]
+  moved_tags: Vec<(String, Option<String>)>,
+  #[serde(default)]
+  force_tags: bool,
+  #[serde(default)]
+  created_branches: Vec<String>,
+  #[serde(default)]
+  proj_heads: HashMap<ProjectId, String>
 }
 
 impl CommitState {
   pub fn new(
     write: StateWrite, did_write: bool, prev_tag: String, last_commits: HashMap<ProjectId, String>,
-    old_tags: HashMap<ProjectId, String>, advance_prev: bool
+    old_tags: HashMap<ProjectId, String>, advance_prev: bool, last_prev: Option<LastPrev>
   ) -> CommitState {
-    CommitState { write, did_write, prev_tag, last_commits, old_tags, advance_prev }
+    CommitState {
+      write,
+      did_write,
+      prev_tag,
+      last_commits,
+      old_tags,
+      advance_prev,
+      last_prev,
+      orig_head: None,
+      moved_tags: Vec::new(),
+      force_tags: false,
+      created_branches: Vec::new(),
+      proj_heads: HashMap::new()
+    }
   }
 
-  pub fn resume(&mut self, repo: &Repo) -> Result<()> {
+  /// Finish the release: write any remaining files, then commit, tag, and push. If a journal is given, it is
+  /// updated after every step, and removed once the release is complete.
+  pub fn resume(&mut self, repo: &Repo, observer: &mut dyn Observer, journal: Option<&Path>) -> Result<()> {
+    self.write_files(journal)?;
+    self.commit_projects(repo, journal)?;
+
     if self.did_write {
-      trace!("Wrote files, so committing.");
-      repo.commit()?;
+      if self.orig_head.is_some() && repo.head_commit_oid() != self.orig_head {
+        trace!("Already committed, so pushing.");
+        repo.push_commit()?;
+      } else {
+        trace!("Wrote files, so committing.");
+        repo.commit(self.write.commit_message.as_deref())?;
+      }
+      self.did_write = false;
+      self.checkpoint(journal)?;
     } else {
       trace!("No files written, so not committing.");
     }
 
-    for tag in &self.write.tag_head {
-      repo.update_tag_head(tag)?;
+    if !self.force_tags {
+      self.verify_remote_tags(repo)?;
     }
-    self.write.tag_head.clear();
 
-    for (tag, proj_id) in &self.write.tag_head_or_last {
-      if self.write.proj_writes.contains(proj_id) {
-        repo.update_tag_head(tag)?;
-      } else if let Some(oid) = self.last_commits.get(proj_id) {
-        repo.update_tag(tag, oid)?;
+    while let Some(tag) = self.write.tag_head.first().cloned() {
+      self.record_tag(repo, &tag, journal)?;
+      repo.update_tag_head(&tag)?;
+      observer.on_tag_created(&tag);
+      self.write.tag_head.remove(0);
+      self.checkpoint(journal)?;
+    }
+
+    while let Some((tag, proj_id)) = self.write.tag_head_or_last.first().cloned() {
+      let spec = self.head_or_last(&proj_id);
+      self.record_tag(repo, &tag, journal)?;
+      match self.write.tag_messages.get(&tag) {
+        Some(msg) => repo.update_tag_anno(&tag, &spec, msg)?,
+        None => repo.update_tag(&tag, &spec)?
+      }
+      observer.on_tag_created(&tag);
+      self.write.tag_head_or_last.remove(0);
+      self.checkpoint(journal)?;
+    }
+
+    while let Some((tag, proj_id)) = self.write.alias_head_or_last.first().cloned() {
+      let spec = self.head_or_last(&proj_id);
+      self.record_tag(repo, &tag, journal)?;
+      repo.update_tag(&tag, &spec)?;
+      observer.on_tag_created(&tag);
+      self.write.alias_head_or_last.remove(0);
+      self.checkpoint(journal)?;
+    }
+
+    while let Some((branch, proj_id)) = self.write.branch_head_or_last.first().cloned() {
+      let spec = self.head_or_last(&proj_id);
+      if self.created_branches.contains(&branch) || !repo.has_branch(&branch) {
+        self.record_branch(&branch, journal)?;
+        repo.create_branch(&branch, &spec)?;
+        observer.on_branch_created(&branch);
       } else {
-        warn!("Latest commit for project {} unknown: tagging head.", proj_id);
-        repo.update_tag_head(tag)?;
+        warn!("Release branch {} already exists: leaving it alone.", branch);
       }
+      self.write.branch_head_or_last.remove(0);
+      self.checkpoint(journal)?;
     }
-    self.write.tag_head_or_last.clear();
+    self.write.tag_messages.clear();
     self.write.proj_writes.clear();
+    self.proj_heads.clear();
+
+    while let Some((tag, oid)) = self.write.tag_commit.iter().next().map(|(t, o)| (t.clone(), o.clone())) {
+      self.record_tag(repo, &tag, journal)?;
+      repo.update_tag(&tag, &oid)?;
+      observer.on_tag_created(&tag);
+      self.write.tag_commit.remove(&tag);
+      self.checkpoint(journal)?;
+    }
 
-    for (tag, oid) in &self.write.tag_commit {
-      repo.update_tag(tag, oid)?;
+    for bump in &self.write.bumps {
+      bump.execute_post_tag()?;
     }
-    self.write.tag_commit.clear();
+    for bump in &self.write.bumps {
+      bump.execute_publish()?;
+    }
+    self.write.bumps.clear();
+    self.checkpoint(journal)?;
 
     if self.advance_prev {
+      let prev_tag = self.prev_tag.clone();
+      self.record_tag(repo, &prev_tag, journal)?;
       fill_from_old(&self.old_tags, &mut self.write.new_tags);
-      let msg = serde_json::to_string(&PrevTagMessage::new(std::mem::take(&mut self.write.new_tags)))?;
-      repo.update_tag_head_anno(&self.prev_tag, &msg)?;
+      let versions = std::mem::take(&mut self.write.new_tags);
+      let held = std::mem::take(&mut self.write.held);
+      let msg = PrevTagMessage::new(versions)
+        .with_last(self.last_prev.take())
+        .with_held(held)
+        .with_plan(self.write.plan_hash.take());
+      let msg = serde_json::to_string(&msg)?;
+      repo.update_tag_head_anno(&prev_tag, &msg)?;
+    }
+
+    // The release is already pushed, so a note that can't be written isn't worth failing over.
+    if let Some(note) = self.write.note.take() {
+      if let Err(e) = repo.add_note(&note) {
+        warn!("Unable to write the release note: {}", e);
+      }
+      self.checkpoint(journal)?;
+    }
+
+    if repo.is_remote() {
+      observer.on_push();
+    }
+
+    if let Some(journal) = journal {
+      std::fs::remove_file(journal).chain_err(|| format!("Unable to remove {}.", journal.display()))?;
+    }
+
+    Ok(())
+  }
+
+  /// The projects changed by this release, to be announced once it's complete.
+  pub fn released(&self) -> &[Released] { &self.write.released }
+
+  /// Check that none of the tags we're about to write have been changed on the remote since we last saw them, as
+  /// happens when someone releases out-of-band. A remote tag that already points where we would put it is fine.
+  fn verify_remote_tags(&self, repo: &Repo) -> Result<()> {
+    if !repo.is_remote() {
+      return Ok(());
+    }
+
+    let remote_tags = repo.remote_tags()?;
+    let head_tags = self.write.tag_head.iter().map(|tag| (tag.clone(), "HEAD".to_string()));
+    let last_tags = self.write.tag_head_or_last.iter().map(|(tag, id)| (tag.clone(), self.head_or_last(id)));
+    let commit_tags = self.write.tag_commit.iter().map(|(tag, oid)| (tag.clone(), oid.clone()));
+    let prev_tag = Some((self.prev_tag.clone(), "HEAD".to_string())).filter(|_| self.advance_prev);
+
+    for (tag, spec) in head_tags.chain(last_tags).chain(commit_tags).chain(prev_tag) {
+      let remote = match remote_tags.get(&tag) {
+        Some(remote) => remote,
+        None => continue
+      };
+      let target = repo.tag_oid(&spec);
+      if Some(remote) != target.as_ref() && Some(remote) != repo.tag_oid(&tag).as_ref() {
+        bail!(
+          "Tag \"{}\" on the remote points to {}, which versio didn't expect: it may have been released out-of-band. \
+           Use --force-tags to overwrite it.",
+          tag,
+          remote
+        );
+      }
+    }
+    Ok(())
+  }
+
+  /// The commit to tag for a project: `HEAD` if the release changed its files, or else its latest commit.
+  fn head_or_last(&self, proj_id: &ProjectId) -> String {
+    if let Some(oid) = self.proj_heads.get(proj_id) {
+      oid.clone()
+    } else if self.write.proj_writes.contains(proj_id) {
+      "HEAD".to_string()
+    } else if let Some(oid) = self.last_commits.get(proj_id) {
+      oid.clone()
+    } else {
+      warn!("Latest commit for project {} unknown: tagging head.", proj_id);
+      "HEAD".to_string()
+    }
+  }
+
+  /// Undo an interrupted release: move its tags back to where they were, move back to the commit it started from,
+  /// and restore the files that the release wrote. Other uncommitted changes are kept, as are any files changed by
+  /// hooks or version commands, which the journal doesn't know about.
+  pub fn rollback(&self, repo: &Repo) -> Result<()> {
+    if let Some(orig_head) = &self.orig_head {
+      if let Some(head) = repo.head_commit_oid().filter(|head| head != orig_head) {
+        if repo.is_pushed(&head) {
+          bail!("The release commit {} was already pushed: resume the release, then revert it instead.", head);
+        }
+      }
+    }
+
+    for (tag, target) in self.moved_tags.iter().rev() {
+      repo.restore_tag(tag, target.as_deref())?;
+    }
+    for branch in self.created_branches.iter().rev() {
+      repo.delete_branch(branch)?;
     }
 
+    if let Some(orig_head) = &self.orig_head {
+      let mut files: Vec<PathBuf> = Vec::new();
+      for (_, file) in &self.write.proj_files {
+        if !files.contains(file) {
+          files.push(file.clone());
+        }
+      }
+      repo.reset_files(orig_head, &files)?;
+    }
+    Ok(())
+  }
+
+  fn write_files(&mut self, journal: Option<&Path>) -> Result<()> {
+    if self.write.writes.is_empty() {
+      return Ok(());
+    }
+    for write in &self.write.writes {
+      write.write()?;
+    }
+    self.write.writes.clear();
+    self.checkpoint(journal)
+  }
+
+  /// Make each project's own commit, if it has one, remembering the commit so that the project's tags and branches
+  /// point to it. Files that belong to no project's commit, such as those changed by hooks, are then committed
+  /// together, and everything is pushed at once.
+  fn commit_projects(&mut self, repo: &Repo, journal: Option<&Path>) -> Result<()> {
+    if !self.did_write || self.write.proj_commits.is_empty() {
+      return Ok(());
+    }
+
+    while let Some((proj_id, msg)) = self.write.proj_commits.first().cloned() {
+      let files: Vec<_> =
+        self.write.proj_files.iter().filter(|(id, _)| id == &proj_id).map(|(_, file)| file.clone()).collect();
+      trace!("Committing {} file(s) of project {}.", files.len(), proj_id);
+      repo.commit_paths(&files, &msg)?;
+      // If there was nothing left to commit, an interrupted run already committed this project.
+      if let Some(head) = repo.head_commit_oid().filter(|_| self.write.proj_writes.contains(&proj_id)) {
+        self.proj_heads.insert(proj_id, head);
+      }
+      self.write.proj_commits.remove(0);
+      self.checkpoint(journal)?;
+    }
+
+    if !repo.commit(self.write.commit_message.as_deref())? {
+      repo.push_commit()?;
+    }
+    self.did_write = false;
+    self.checkpoint(journal)
+  }
+
+  /// Remember where a tag pointed before the release first moved it.
+  fn record_tag(&mut self, repo: &Repo, tag: &str, journal: Option<&Path>) -> Result<()> {
+    if self.moved_tags.iter().any(|(t, _)| t == tag) {
+      return Ok(());
+    }
+    self.moved_tags.push((tag.to_string(), repo.tag_ref(tag)));
+    self.checkpoint(journal)
+  }
+
+  /// Remember a release branch before creating it, so that an abort can delete it again.
+  fn record_branch(&mut self, branch: &str, journal: Option<&Path>) -> Result<()> {
+    if self.created_branches.iter().any(|b| b == branch) {
+      return Ok(());
+    }
+    self.created_branches.push(branch.to_string());
+    self.checkpoint(journal)
+  }
+
+  fn checkpoint(&self, journal: Option<&Path>) -> Result<()> {
+    let journal = match journal {
+      Some(journal) => journal,
+      None => return Ok(())
+    };
+    if let Some(dir) = journal.parent() {
+      std::fs::create_dir_all(dir).chain_err(|| format!("Unable to create {}.", dir.display()))?;
+    }
+    let data = serde_json::to_string(self)?;
+    std::fs::write(journal, data).chain_err(|| format!("Unable to write {}.", journal.display()))?;
     Ok(())
   }
 }
 
+/// The JSON snapshot kept in the message of the annotated prev tag: the project versions at that point, along with
+/// the versio that wrote it and the hash of the plan that was released.
 #[derive(Deserialize, Serialize)]
 pub struct PrevTagMessage {
-  versions: HashMap<ProjectId, String>
+  versions: HashMap<ProjectId, String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  last: Option<LastPrev>,
+  #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+  held: HashMap<ProjectId, (Size, Changelog)>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  versio: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  plan: Option<String>
 }
 
 impl Default for PrevTagMessage {
-  fn default() -> PrevTagMessage { PrevTagMessage { versions: HashMap::new() } }
+  fn default() -> PrevTagMessage {
+    PrevTagMessage { versions: HashMap::new(), last: None, held: HashMap::new(), versio: None, plan: None }
+  }
 }
 
 impl PrevTagMessage {
-  pub fn new(versions: HashMap<ProjectId, String>) -> PrevTagMessage { PrevTagMessage { versions } }
+  pub fn new(versions: HashMap<ProjectId, String>) -> PrevTagMessage {
+    let versio = Some(env!("CARGO_PKG_VERSION").to_string());
+    PrevTagMessage { versions, versio, ..Default::default() }
+  }
+
+  pub fn versions(&self) -> &HashMap<ProjectId, String> { &self.versions }
+  pub fn last(&self) -> &Option<LastPrev> { &self.last }
+  pub fn versio(&self) -> Option<&str> { self.versio.as_deref() }
+  pub fn plan(&self) -> Option<&str> { self.plan.as_deref() }
+  pub fn held(&self) -> &HashMap<ProjectId, (Size, Changelog)> { &self.held }
   pub fn into_versions(self) -> HashMap<ProjectId, String> { self.versions }
+  pub fn into_held(self) -> HashMap<ProjectId, (Size, Changelog)> { self.held }
+
+  pub fn with_last(mut self, last: Option<LastPrev>) -> PrevTagMessage {
+    self.last = last;
+    self
+  }
+
+  pub fn with_held(mut self, held: HashMap<ProjectId, (Size, Changelog)>) -> PrevTagMessage {
+    self.held = held;
+    self
+  }
+
+  pub fn with_plan(mut self, plan: Option<String>) -> PrevTagMessage {
+    self.plan = plan;
+    self
+  }
+}
+
+/// Where the prev tag pointed before the latest release, so that the release can be reverted.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct LastPrev {
+  oid: String,
+  versions: HashMap<ProjectId, String>
+}
+
+impl LastPrev {
+  pub fn new(oid: String, versions: HashMap<ProjectId, String>) -> LastPrev { LastPrev { oid, versions } }
+  pub fn oid(&self) -> &str { &self.oid }
+  pub fn versions(&self) -> &HashMap<ProjectId, String> { &self.versions }
 }
 
 #[derive(Deserialize, Serialize)]
 enum FileWrite {
   Write { path: PathBuf, val: String, changelog: bool },
-  Update { pick: PickPath, val: String }
+  Update { pick: PickPath, val: String },
+  Run { hook: Hook, root: Option<String> }
 }
 
 impl FileWrite {
   pub fn is_changelog(&self) -> bool {
     match self {
       FileWrite::Write { changelog, .. } => *changelog,
-      FileWrite::Update { .. } => false
+      FileWrite::Update { .. } | FileWrite::Run { .. } => false
     }
   }
 
@@ -341,8 +894,24 @@ impl FileWrite {
       //   let mut file = OpenOptions::new().append(true).open(path)?;
       //   Ok(file.write_all(val.as_bytes())?)
       // }
-      FileWrite::Update { pick, val } => pick.write_value(val)
+      FileWrite::Update { pick, val } => pick.write_value(val),
+      FileWrite::Run { hook, root } => hook.execute(&root.as_ref(), &[])
+    }
+  }
+
+  fn write_to_memory(self, state: &mut MemoryState) -> Result<()> {
+    match self {
+      FileWrite::Write { path, val, .. } => {
+        state.files.insert(path, val);
+      }
+      FileWrite::Update { pick, val } => {
+        let data = state.read_file(&pick.file)?;
+        let data = pick.picker.scan(NamedData::new(pick.file.clone(), data))?.into_new_value(&val);
+        state.files.insert(pick.file, data);
+      }
+      FileWrite::Run { .. } => bail!("Can't run a version command in memory.")
     }
+    Ok(())
   }
 }
 
@@ -371,3 +940,111 @@ pub fn read_from_slice<P: AsRef<Path>>(slice: &Slice, path: P) -> Result<String>
   let cont: &str = std::str::from_utf8(blob.content()).chain_err(|| format!("Not UTF8 content: {}", path))?;
   Ok(cont.to_string())
 }
+
+#[cfg(test)]
+mod test {
+  use super::{CommitState, StateWrite};
+  use crate::config::ProjectId;
+  use crate::git::{do_push, Repo};
+  use crate::observe::NoObserver;
+  use crate::vcs::{VcsLevel, VcsState};
+  use git2::{Oid, Repository, Signature};
+  use std::collections::HashMap;
+  use tempfile::{tempdir, TempDir};
+
+  /// A repo with two commits and a remote, where the remote already has "v1.0.0" on the second commit.
+  fn released_out_of_band() -> (TempDir, TempDir, Oid, Oid) {
+    let remote_dir = tempdir().unwrap();
+    Repository::init_bare(remote_dir.path()).unwrap();
+    let dir = tempdir().unwrap();
+    let local = Repository::init(dir.path()).unwrap();
+    local.remote("origin", remote_dir.path().to_str().unwrap()).unwrap();
+
+    let sig = Signature::now("Tester", "tester@example.com").unwrap();
+    let tree = local.find_tree(local.index().unwrap().write_tree().unwrap()).unwrap();
+    let first = local.commit(Some("HEAD"), &sig, &sig, "feat: start", &tree, &[]).unwrap();
+    let parent = local.find_commit(first).unwrap();
+    let second = local.commit(Some("HEAD"), &sig, &sig, "fix: more", &tree, &[&parent]).unwrap();
+    local.tag_lightweight("v1.0.0", &local.find_object(second, None).unwrap(), false).unwrap();
+    do_push(&local, "origin", &["refs/tags/v1.0.0".to_string()]).unwrap();
+    local.tag_delete("v1.0.0").unwrap();
+
+    (remote_dir, dir, first, second)
+  }
+
+  fn tag_first(first: Oid, force_tags: bool) -> CommitState {
+    let mut write = StateWrite::new();
+    write.tag_commit("v1.0.0", &first.to_string());
+    let mut state = CommitState::new(write, false, "versio-prev".into(), HashMap::new(), HashMap::new(), false, None);
+    state.force_tags = force_tags;
+    state
+  }
+
+  #[test]
+  fn test_out_of_band_tag_fails() {
+    let (remote_dir, dir, first, second) = released_out_of_band();
+    let repo = Repo::open(dir.path(), VcsState::new(VcsLevel::Remote, false)).unwrap();
+
+    let err = tag_first(first, false).resume(&repo, &mut NoObserver, None).unwrap_err();
+    assert!(err.to_string().contains("--force-tags"));
+    assert!(err.to_string().contains(&second.to_string()));
+    assert!(repo.tag_oid("v1.0.0").is_none());
+    let remote = Repository::open_bare(remote_dir.path()).unwrap();
+    assert_eq!(remote.refname_to_id("refs/tags/v1.0.0").unwrap(), second);
+  }
+
+  #[test]
+  fn test_force_tags_overwrites() {
+    let (remote_dir, dir, first, _) = released_out_of_band();
+    let repo = Repo::open(dir.path(), VcsState::new(VcsLevel::Remote, false)).unwrap();
+
+    tag_first(first, true).resume(&repo, &mut NoObserver, None).unwrap();
+    assert_eq!(repo.tag_oid("v1.0.0"), Some(first.to_string()));
+    let remote = Repository::open_bare(remote_dir.path()).unwrap();
+    assert_eq!(remote.refname_to_id("refs/tags/v1.0.0").unwrap(), first);
+  }
+
+  #[test]
+  fn test_matching_remote_tag_passes() {
+    let (remote_dir, dir, _, second) = released_out_of_band();
+    let repo = Repo::open(dir.path(), VcsState::new(VcsLevel::Remote, false)).unwrap();
+
+    tag_first(second, false).resume(&repo, &mut NoObserver, None).unwrap();
+    let remote = Repository::open_bare(remote_dir.path()).unwrap();
+    assert_eq!(remote.refname_to_id("refs/tags/v1.0.0").unwrap(), second);
+  }
+
+  #[test]
+  fn test_rollback_restores_written() {
+    let (_remote_dir, dir, _, second) = released_out_of_band();
+    let repo = Repo::open(dir.path(), VcsState::new(VcsLevel::Local, false)).unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("other.txt"), "mine").unwrap();
+
+    let id = ProjectId::from_id(1);
+    let mut write = StateWrite::new();
+    write.write_file(root.join("version.txt"), "1.1.0", &id, false).unwrap();
+    let mut state = CommitState::new(write, true, "versio-prev".into(), HashMap::new(), HashMap::new(), false, None);
+    state.orig_head = Some(second.to_string());
+    state.write_files(None).unwrap();
+    repo.commit_paths(&[root.join("version.txt")], "release").unwrap();
+    assert_ne!(repo.head_commit_oid(), Some(second.to_string()));
+
+    state.rollback(&repo).unwrap();
+    assert_eq!(repo.head_commit_oid(), Some(second.to_string()));
+    assert!(!root.join("version.txt").exists());
+    assert_eq!(std::fs::read_to_string(root.join("other.txt")).unwrap(), "mine");
+  }
+
+  #[test]
+  fn test_resume_adds_note() {
+    let (_remote_dir, dir, _, second) = released_out_of_band();
+    let repo = Repo::open(dir.path(), VcsState::new(VcsLevel::Local, false)).unwrap();
+
+    let mut write = StateWrite::new();
+    write.set_note("released".into());
+    let mut state = CommitState::new(write, false, "versio-prev".into(), HashMap::new(), HashMap::new(), false, None);
+    state.resume(&repo, &mut NoObserver, None).unwrap();
+    assert_eq!(repo.notes().unwrap(), vec![(second.to_string(), "released".to_string())]);
+  }
+}
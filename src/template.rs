@@ -1,37 +1,154 @@
 //! Template and changelog management for Versio.
 
+use crate::clock;
+use crate::config::ProjectId;
 use crate::errors::Result;
 use crate::mono::{Changelog, ChangelogEntry};
 use crate::output::ProjLine;
-use chrono::prelude::Utc;
-use error_chain::bail;
+use crate::vcs::is_offline;
+use chrono::format::{Item, StrftimeItems};
+use chrono::prelude::{DateTime, FixedOffset, Local, TimeZone, Utc};
 use hyper::Client;
 use liquid::ParserBuilder;
 use path_slash::PathBufExt;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
-/// Extract everything in an old changelog between the `BEGIN CONTENT` and `END CONTENT` lines.
+/// Extract everything in an old changelog between the `BEGIN CONTENT` and `END CONTENT` lines, except for an
+/// unreleased section between `BEGIN UNRELEASED` and `END UNRELEASED` lines, which is always rewritten.
 pub fn extract_old_content(path: &Path) -> Result<String> {
   if !path.exists() {
     return Ok("".into());
   }
 
   let full_content = std::fs::read_to_string(path)?;
+  let mut unreleased = false;
   let content = full_content
     .split('\n')
     .skip_while(|l| !l.contains("### VERSIO BEGIN CONTENT ###"))
     .skip(1)
     .take_while(|l| !l.contains("### VERSIO END CONTENT ###"))
+    .filter(|l| {
+      if l.contains("### VERSIO BEGIN UNRELEASED ###") {
+        unreleased = true;
+      }
+      let keep = !unreleased;
+      if l.contains("### VERSIO END UNRELEASED ###") {
+        unreleased = false;
+      }
+      keep
+    })
     .collect::<Vec<_>>()
     .join("\n");
   Ok(content)
 }
 
+/// How dates are shown in a changelog: a `strftime`-style format (default `%Y-%m-%d`), in a zone that is `utc`
+/// (the default), `local`, or a fixed offset like `+02:00`.
+#[derive(Clone, Debug, Default)]
+pub struct DateStyle {
+  format: Option<String>,
+  zone: Option<String>
+}
+
+impl DateStyle {
+  pub fn new(format: Option<String>, zone: Option<String>) -> DateStyle { DateStyle { format, zone } }
+
+  pub fn render<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> Result<String> {
+    let format = self.format.as_deref().unwrap_or("%Y-%m-%d");
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+      bail!("Bad changelog date format \"{}\".", format);
+    }
+
+    let time = time.with_timezone(&Utc);
+    match self.zone.as_deref().unwrap_or("utc") {
+      "utc" | "UTC" => Ok(time.format(format).to_string()),
+      "local" => Ok(time.with_timezone(&Local).format(format).to_string()),
+      zone => match parse_offset(zone) {
+        Some(offset) => Ok(time.with_timezone(&offset).format(format).to_string()),
+        None => bail!("Bad changelog date zone \"{}\".", zone)
+      }
+    }
+  }
+}
+
+/// Parse a fixed offset from UTC, like `+02:00` or `-0530`.
+fn parse_offset(zone: &str) -> Option<FixedOffset> {
+  let (sign, rest) = match zone.as_bytes().first()? {
+    b'+' => (1, &zone[1 ..]),
+    b'-' => (-1, &zone[1 ..]),
+    _ => return None
+  };
+  let rest = rest.replace(':', "");
+  if rest.len() != 4 || !rest.chars().all(|c| c.is_ascii_digit()) {
+    return None;
+  }
+  let hours: i32 = rest[.. 2].parse().ok()?;
+  let minutes: i32 = rest[2 ..].parse().ok()?;
+  FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// The kind of release that a changelog entry is written for.
+#[derive(Clone, Copy)]
+pub enum ReleaseKind<'a> {
+  /// A release of a new version.
+  Version(&'a str),
+  /// Changes that are held back from release.
+  Unreleased,
+  /// The last version of a project that was removed from the config.
+  Final(&'a str)
+}
+
+impl<'a> ReleaseKind<'a> {
+  fn version(&self) -> &'a str {
+    match self {
+      ReleaseKind::Version(v) | ReleaseKind::Final(v) => v,
+      ReleaseKind::Unreleased => "Unreleased"
+    }
+  }
+}
+
+/// A hash of the content of a release's changelog section, which the builtin templates record in the section. A
+/// release that is run again (e.g. after a failed push) finds its hash already in the changelog, and so doesn't
+/// write the same section twice. Unreleased sections aren't hashed, since they are always replaced.
+pub fn section_hash(cl: &Changelog, kind: &ReleaseKind, dep_versions: &HashMap<ProjectId, String>) -> Option<String> {
+  let label = match kind {
+    ReleaseKind::Version(v) => format!("version {}", v),
+    ReleaseKind::Final(v) => format!("final {}", v),
+    ReleaseKind::Unreleased => return None
+  };
+
+  let mut hasher = Sha256::new();
+  hasher.update(label.as_bytes());
+  for entry in cl.entries() {
+    match entry {
+      ChangelogEntry::Pr(pr, size) => {
+        hasher.update(format!("\npr {} {}", pr.number(), size).as_bytes());
+        for c in pr.commits().iter().filter(|c| c.included()) {
+          hasher.update(format!("\ncommit {}", c.oid()).as_bytes());
+        }
+      }
+      ChangelogEntry::Dep(proj_id, _) => {
+        let vers = dep_versions.get(proj_id).map(|v| v.as_str()).unwrap_or("");
+        hasher.update(format!("\ndep {} {}", proj_id, vers).as_bytes());
+      }
+    }
+  }
+  Some(format!("{:x}", hasher.finalize())[.. 16].to_string())
+}
+
+/// Render a changelog. `dep_versions` holds the new versions of the projects that this one depends on, if they're
+/// known.
 pub fn construct_changelog_html(
-  cl: &Changelog, proj: ProjLine, new_vers: &str, old_content: String, tmpl: String
+  cl: &Changelog, proj: ProjLine, kind: ReleaseKind, old_content: String, tmpl: String,
+  dep_versions: &HashMap<ProjectId, String>, dates: &DateStyle
 ) -> Result<String> {
   let tmpl = ParserBuilder::with_stdlib().build()?.parse(&tmpl)?;
-  let nowymd = Utc::now().format("%Y-%m-%d").to_string();
+  let now = clock::now();
+  let nowymd = now.format("%Y-%m-%d").to_string();
+  let hash = section_hash(cl, &kind, dep_versions).unwrap_or_default();
 
   let pr_count = cl
     .entries()
@@ -43,6 +160,7 @@ pub fn construct_changelog_html(
     .count();
 
   let mut prs = Vec::new();
+  let mut sec_prs = Vec::new();
   let mut dps = Vec::new();
 
   for entry in cl.entries() {
@@ -60,7 +178,9 @@ pub fn construct_changelog_html(
             "shorthash": c.oid()[.. 7].to_string(),
             "size": c.size().to_string(),
             "summary": c.summary(),
-            "message": c.message().trim()
+            "message": c.message().trim(),
+            "security": c.security(),
+            "backport": c.backport()
           }));
         }
 
@@ -74,19 +194,27 @@ pub fn construct_changelog_html(
           format!("PR {}", pr.number())
         };
 
-        prs.push(liquid::object!({
+        let security = pr.commits().iter().any(|c| c.included() && c.security());
+        let pr = liquid::object!({
           "title": pr.title(),
           "name": pr_name,
+          "date": dates.render(pr.closed_at())?,
           "size": size.to_string(),
           "href": pr.url().as_deref().unwrap_or(""),
           "link": pr.number() > 0 && pr.url().is_some(),
-          "commits": commits
-        }));
+          "commits": commits,
+          "security": security
+        });
+        if security {
+          sec_prs.push(pr.clone());
+        }
+        prs.push(pr);
       }
       ChangelogEntry::Dep(proj_id, name) => {
         dps.push(liquid::object!({
           "id": proj_id.to_string(),
-          "name": name
+          "name": name,
+          "version": dep_versions.get(proj_id).cloned().unwrap_or_default()
         }));
       }
     }
@@ -102,10 +230,14 @@ pub fn construct_changelog_html(
       "root": proj.root.unwrap_or_else(|| "".to_string()),
     },
     "release": {
-      "date": nowymd,
+      "date": dates.render(&now)?,
       "prs": prs,
+      "security": sec_prs,
       "deps": dps,
-      "version": new_vers
+      "version": kind.version(),
+      "unreleased": matches!(kind, ReleaseKind::Unreleased),
+      "final": matches!(kind, ReleaseKind::Final(_)),
+      "hash": hash
     },
     "old_content": old_content,
     "content_marker": format!("CONTENT {}", nowymd)
@@ -114,6 +246,12 @@ pub fn construct_changelog_html(
   Ok(tmpl.render(&globals)?)
 }
 
+/// Render a report of a command's results through a user's template: the fields of `vars` are its globals.
+pub fn render_report<T: Serialize>(tmpl: &str, vars: &T) -> Result<String> {
+  let tmpl = ParserBuilder::with_stdlib().build()?.parse(tmpl)?;
+  Ok(tmpl.render(&liquid::to_object(vars)?)?)
+}
+
 pub async fn read_template(tmpl_url: &str, base_path: Option<&Path>, forward_slash: bool) -> Result<String> {
   let parts: Vec<_> = tmpl_url.splitn(2, ':').collect();
   if parts.len() > 1 {
@@ -131,6 +269,9 @@ pub async fn read_template(tmpl_url: &str, base_path: Option<&Path>, forward_sla
         }
       }
       "http" | "https" => {
+        if is_offline() {
+          bail!("Offline: unable to fetch template {}", tmpl_url);
+        }
         let resp = Client::new().get(tmpl_url.parse()?).await?;
         if !resp.status().is_success() {
           bail!("Unsuccessful request to {}: {}", tmpl_url, resp.status().as_u16());
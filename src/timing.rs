@@ -0,0 +1,35 @@
+//! Wall-clock timings of the slower phases of a run, as reported by `--timings`.
+
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+thread_local! {
+  static TIMINGS: RefCell<Vec<(&'static str, Duration)>> = RefCell::new(Vec::new());
+}
+
+/// Adds the time from its start until it's dropped to the total for its phase.
+pub struct Timer {
+  phase: &'static str,
+  start: Instant
+}
+
+impl Timer {
+  pub fn start(phase: &'static str) -> Timer { Timer { phase, start: Instant::now() } }
+}
+
+impl Drop for Timer {
+  fn drop(&mut self) { record(self.phase, self.start.elapsed()) }
+}
+
+fn record(phase: &'static str, elapsed: Duration) {
+  TIMINGS.with(|timings| {
+    let mut timings = timings.borrow_mut();
+    match timings.iter_mut().find(|(p, _)| *p == phase) {
+      Some((_, total)) => *total += elapsed,
+      None => timings.push((phase, elapsed))
+    }
+  })
+}
+
+/// The total time spent in each phase, in the order that the phases were first seen.
+pub fn totals() -> Vec<(&'static str, Duration)> { TIMINGS.with(|timings| timings.borrow().clone()) }
@@ -2,10 +2,22 @@
 
 use crate::errors::Result;
 use crate::git::Repo;
-use error_chain::bail;
-use log::debug;
+use log::{debug, warn};
 use std::cmp::{max, min};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const OFFLINE_VAR: &str = "VERSIO_OFFLINE";
+
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Never touch the network for the rest of the run: no fetch, push, API call, or other request.
+pub fn set_offline() { OFFLINE.store(true, Ordering::SeqCst) }
+
+/// If the run is offline, from `set_offline` or from `VERSIO_OFFLINE=1`.
+pub fn is_offline() -> bool {
+  OFFLINE.load(Ordering::SeqCst) || std::env::var(OFFLINE_VAR).map(|v| v == "1" || v == "true").unwrap_or(false)
+}
 
 #[derive(PartialEq, PartialOrd, Eq, Ord, Clone, Copy, Debug)]
 pub struct VcsState {
@@ -19,7 +31,7 @@ impl VcsState {
   pub fn ignore_current(&self) -> bool { self.ignore_current }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct VcsRange {
   min: VcsLevel,
   max: VcsLevel
@@ -38,7 +50,15 @@ impl VcsRange {
     VcsRange::new(max(self.min(), other.min()), min(self.max(), other.max()))
   }
 
-  pub fn detect() -> Result<VcsRange> { Ok(VcsRange::new(VcsLevel::None, Repo::detect(".")?)) }
+  /// Detect the levels that the current repo supports. When offline, nothing above `local` is supported.
+  pub fn detect() -> Result<VcsRange> {
+    let detected = Repo::detect(".")?;
+    if is_offline() {
+      Ok(VcsRange::new(VcsLevel::None, min(detected, VcsLevel::Local)))
+    } else {
+      Ok(VcsRange::new(VcsLevel::None, detected))
+    }
+  }
 
   pub fn detect_and_combine(pref: &VcsRange, reqd: &VcsRange) -> Result<VcsRange> {
     if pref.is_empty() {
@@ -59,8 +79,14 @@ impl VcsRange {
     let dctd = VcsRange::detect()?;
     let i2 = i1.intersect(&dctd);
     if i2.is_empty() {
+      if is_offline() && i1.min().is_network() {
+        bail!("Offline, but VCS {:?} is required.", i1);
+      }
       bail!("Couldn't detect {:?} with preferred {:?} required {:?}", dctd, pref, reqd);
     }
+    if is_offline() && i1.max() > i2.max() {
+      warn!("Offline: running at VCS level {:?} instead of {:?}.", i2.max(), i1.max());
+    }
 
     debug!("Combining preferred {:?}, required {:?}, detected {:?} = {:?}", pref, reqd, dctd, i2.max());
 
@@ -68,6 +94,18 @@ impl VcsRange {
   }
 }
 
+/// Parse a range as a single level ("local"), or as a minimum and maximum level ("none-local").
+impl FromStr for VcsRange {
+  type Err = crate::errors::Error;
+
+  fn from_str(v: &str) -> Result<VcsRange> {
+    match v.split_once('-') {
+      Some((lo, hi)) => Ok(VcsRange::new(lo.trim().parse()?, hi.trim().parse()?)),
+      None => Ok(VcsRange::exact(v.trim().parse()?))
+    }
+  }
+}
+
 #[derive(PartialEq, PartialOrd, Eq, Ord, Clone, Copy, Debug)]
 pub enum VcsLevel {
   None = 0,